@@ -0,0 +1,189 @@
+// Exports every server and delegation of the staking program at one slot, for airdrops and
+// audits. The output is signed with the given keypair so consumers can check who produced it.
+//
+//     snapshot_exporter <rpc_url> <keypair_path> <output_path> [json|csv]
+//
+// Writes <output_path> and <output_path>.sig, the latter holding the signer and the base58
+// signature over the exact bytes of the snapshot file.
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs;
+
+use anchor_lang::{AccountDeserialize, Discriminator};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde_json::{json, Value};
+use solana_client::rpc_client::RpcClient;
+use solana_client::rpc_request::RpcRequest;
+use solana_client::rpc_response::{OptionalContext, RpcKeyedAccount};
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::signature::{read_keypair_file, Signer};
+use staking_contract::{filters, DelegatedAccount, InfoAccount, Memcmp, ID};
+
+struct Snapshot {
+    slot: u64,
+    servers: Vec<(String, InfoAccount)>,
+    delegations: Vec<(String, DelegatedAccount)>,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 4 || args.len() > 5 {
+        return Err(
+            "usage: snapshot_exporter <rpc_url> <keypair_path> <output_path> [json|csv]".into(),
+        );
+    }
+    let format = args.get(4).map(String::as_str).unwrap_or("json");
+
+    let client = RpcClient::new_with_commitment(args[1].clone(), CommitmentConfig::finalized());
+    let signer = read_keypair_file(&args[2])?;
+
+    let snapshot = fetch_snapshot(&client)?;
+    let output = match format {
+        "json" => serde_json::to_string_pretty(&to_json(&snapshot))?,
+        "csv" => to_csv(&snapshot),
+        _ => return Err(format!("unknown format {}", format).into()),
+    };
+
+    fs::write(&args[3], &output)?;
+    let signature = signer.sign_message(output.as_bytes());
+    fs::write(
+        format!("{}.sig", args[3]),
+        format!("{} {}\n", signer.pubkey(), signature),
+    )?;
+
+    println!(
+        "slot {}: {} servers, {} delegations written to {}",
+        snapshot.slot,
+        snapshot.servers.len(),
+        snapshot.delegations.len(),
+        args[3]
+    );
+    Ok(())
+}
+
+fn fetch_snapshot(client: &RpcClient) -> Result<Snapshot, Box<dyn Error>> {
+    // Both scans must see the same bank, pin the second one to the slot of the first
+    let (slot, servers) = fetch_accounts::<InfoAccount>(client, None)?;
+    let (_, delegations) = fetch_accounts::<DelegatedAccount>(client, Some(slot))?;
+    Ok(Snapshot {
+        slot,
+        servers,
+        delegations,
+    })
+}
+
+// Current-layout accounts of type T and the slot they were read at. v1 accounts are left out,
+// they have to be migrated before they can be exported.
+fn fetch_accounts<T: AccountDeserialize + Discriminator>(
+    client: &RpcClient,
+    slot: Option<u64>,
+) -> Result<(u64, Vec<(String, T)>), Box<dyn Error>> {
+    let params = json!([
+        ID.to_string(),
+        {
+            "encoding": "base64",
+            "commitment": "finalized",
+            "withContext": true,
+            "minContextSlot": slot,
+            "filters": filters::<T>(&[]).iter().map(memcmp).collect::<Vec<_>>(),
+        }
+    ]);
+    let response: OptionalContext<Vec<RpcKeyedAccount>> =
+        client.send(RpcRequest::GetProgramAccounts, params)?;
+    let (context_slot, keyed_accounts) = match response {
+        OptionalContext::Context(response) => (response.context.slot, response.value),
+        OptionalContext::NoContext(_) => return Err("RPC node ignored withContext".into()),
+    };
+    if let Some(slot) = slot {
+        if context_slot != slot {
+            return Err(format!("bank moved from slot {} to {}, retry", slot, context_slot).into());
+        }
+    }
+
+    let mut accounts = Vec::with_capacity(keyed_accounts.len());
+    for keyed_account in keyed_accounts {
+        let data = keyed_account
+            .account
+            .data
+            .decode()
+            .ok_or("undecodable account data")?;
+        let account = T::try_deserialize(&mut data.as_slice())?;
+        accounts.push((keyed_account.pubkey, account));
+    }
+    accounts.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok((context_slot, accounts))
+}
+
+fn memcmp(filter: &Memcmp) -> Value {
+    let bytes = STANDARD.encode(&filter.bytes);
+    json!({ "memcmp": { "offset": filter.offset, "bytes": bytes, "encoding": "base64" } })
+}
+
+fn to_json(snapshot: &Snapshot) -> Value {
+    let servers: Vec<Value> = snapshot
+        .servers
+        .iter()
+        .map(|(address, server)| {
+            json!({
+                "address": address,
+                "owner": server.owner.to_string(),
+                "mint": server.mint.to_string(),
+                "name": String::from_utf8_lossy(server.name()),
+                "stake": server.stake,
+                "delegated": server.delegated,
+                "activating_stake": server.activating_stake,
+                "total": server.total,
+                "total_delegators": server.total_delegators,
+            })
+        })
+        .collect();
+    let delegations: Vec<Value> = snapshot
+        .delegations
+        .iter()
+        .map(|(address, delegation)| {
+            json!({
+                "address": address,
+                "server": delegation.delegator.to_string(),
+                "owner": delegation.owner.to_string(),
+                "mint": delegation.mint.to_string(),
+                "stake": delegation.stake,
+                "activating_stake": delegation.activating_stake,
+                "lock_until": delegation.lock_until,
+            })
+        })
+        .collect();
+
+    json!({
+        "program": ID.to_string(),
+        "slot": snapshot.slot,
+        "servers": servers,
+        "delegations": delegations,
+    })
+}
+
+// One row per position: servers with their operator stake, then delegations
+fn to_csv(snapshot: &Snapshot) -> String {
+    let mut csv = String::from("slot,kind,address,server,owner,mint,stake,activating_stake\n");
+    for (address, server) in &snapshot.servers {
+        let _ = writeln!(
+            csv,
+            "{},server,{},{},{},{},{},0",
+            snapshot.slot, address, address, server.owner, server.mint, server.stake
+        );
+    }
+    for (address, delegation) in &snapshot.delegations {
+        let _ = writeln!(
+            csv,
+            "{},delegation,{},{},{},{},{},{}",
+            snapshot.slot,
+            address,
+            delegation.delegator,
+            delegation.owner,
+            delegation.mint,
+            delegation.stake,
+            delegation.activating_stake
+        );
+    }
+    csv
+}
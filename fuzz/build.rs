@@ -0,0 +1,28 @@
+// Generates INSTRUCTIONS for the fuzz targets: the instruction names of the `#[program]` module in
+// ../main.rs, in declaration order, so instructions added to the program are fuzzed without
+// touching the targets.
+use std::env;
+use std::fs;
+use std::path::Path;
+
+fn main() {
+    let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("../main.rs");
+    println!("cargo:rerun-if-changed={}", source.display());
+    let source = fs::read_to_string(&source).expect("program source");
+
+    let (_, program) = source
+        .split_once("#[program]\n")
+        .expect("#[program] module");
+    let program = &program[..program.find("\n}\n").expect("end of the #[program] module")];
+    let names: Vec<&str> = program
+        .lines()
+        .filter_map(|line| line.strip_prefix("    pub fn "))
+        .map(|rest| rest.split(['(', '<']).next().unwrap())
+        .collect();
+    assert!(!names.is_empty(), "no instructions found");
+    // The targets pick an instruction with a u8
+    assert!(names.len() <= 256, "more instructions than a u8 index reaches");
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("instructions.rs");
+    fs::write(out, format!("const INSTRUCTIONS: &[&str] = &{:?};\n", names)).unwrap();
+}
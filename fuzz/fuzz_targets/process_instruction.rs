@@ -0,0 +1,131 @@
+// Feeds arbitrary instruction data and account lists into the program entrypoint. Any error is
+// fine, a panic (overflow, out-of-bounds slice, failed unwrap) is a finding.
+//
+//     cargo fuzz run process_instruction
+//
+// Instruction data starts with a real instruction discriminator and account data with a real
+// account discriminator most of the time, so inputs get past dispatch and into the account
+// constraints and handlers instead of failing at the first byte.
+#![no_main]
+
+use anchor_lang::solana_program::account_info::AccountInfo;
+use anchor_lang::solana_program::hash::hash;
+use anchor_lang::solana_program::pubkey::Pubkey;
+use anchor_lang::Discriminator;
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use staking_contract::{
+    pda, AllowlistEntry, ConfigAccount, DelegatedAccount, EpochSnapshot, InfoAccount, MainAccount,
+    ID, SPECIFIED_MINT,
+};
+
+const MAX_ACCOUNTS: usize = 32;
+
+// Every instruction of the program, generated by build.rs
+include!(concat!(env!("OUT_DIR"), "/instructions.rs"));
+
+#[derive(Arbitrary, Debug)]
+struct FuzzAccount {
+    key: u8,
+    owner: u8,
+    lamports: u64,
+    kind: u8, // Selects the account discriminator put in front of `data`
+    data: Vec<u8>,
+    is_signer: bool,
+    is_writable: bool,
+}
+
+#[derive(Arbitrary, Debug)]
+struct FuzzInput {
+    instruction: u8, // Index into INSTRUCTIONS, raw `args` when out of range
+    args: Vec<u8>,
+    accounts: Vec<FuzzAccount>,
+}
+
+// Addresses the constraints look for, plus a few arbitrary ones for mismatches
+fn key_pool() -> Vec<Pubkey> {
+    let mut keys = vec![
+        ID,
+        pda::main_account(&ID).0,
+        pda::config(&ID).0,
+        pda::treasury(&ID).0,
+        SPECIFIED_MINT,
+        anchor_lang::system_program::ID,
+        anchor_spl::token::ID,
+        anchor_spl::token_2022::ID,
+        anchor_spl::associated_token::ID,
+    ];
+    keys.extend((1..=4u8).map(|i| Pubkey::new_from_array([i; 32])));
+    keys
+}
+
+fn owner_pool() -> [Pubkey; 4] {
+    [
+        ID,
+        anchor_lang::system_program::ID,
+        anchor_spl::token::ID,
+        anchor_spl::token_2022::ID,
+    ]
+}
+
+fn account_data(kind: u8, data: &[u8]) -> Vec<u8> {
+    let discriminator: Option<&[u8]> = match kind % 8 {
+        0 => Some(&MainAccount::DISCRIMINATOR),
+        1 => Some(&ConfigAccount::DISCRIMINATOR),
+        2 => Some(&InfoAccount::DISCRIMINATOR),
+        3 => Some(&DelegatedAccount::DISCRIMINATOR),
+        4 => Some(&AllowlistEntry::DISCRIMINATOR),
+        5 => Some(&EpochSnapshot::DISCRIMINATOR),
+        _ => None,
+    };
+    match discriminator {
+        Some(discriminator) => [discriminator, data].concat(),
+        None => data.to_vec(),
+    }
+}
+
+fuzz_target!(|input: FuzzInput| {
+    let data = match INSTRUCTIONS.get(input.instruction as usize) {
+        Some(name) => {
+            let discriminator = hash(format!("global:{}", name).as_bytes()).to_bytes();
+            [&discriminator[..8], &input.args[..]].concat()
+        }
+        None => input.args,
+    };
+
+    let keys = key_pool();
+    let owners = owner_pool();
+    let mut storage: Vec<(Pubkey, Pubkey, u64, Vec<u8>, bool, bool)> = input
+        .accounts
+        .iter()
+        .take(MAX_ACCOUNTS)
+        .map(|account| {
+            (
+                keys[account.key as usize % keys.len()],
+                owners[account.owner as usize % owners.len()],
+                account.lamports,
+                account_data(account.kind, &account.data),
+                account.is_signer,
+                account.is_writable,
+            )
+        })
+        .collect();
+
+    let accounts: Vec<AccountInfo> = storage
+        .iter_mut()
+        .map(|(key, owner, lamports, data, is_signer, is_writable)| {
+            AccountInfo::new(
+                key,
+                *is_signer,
+                *is_writable,
+                lamports,
+                data,
+                owner,
+                *key == ID,
+                0,
+            )
+        })
+        .collect();
+
+    let _ = staking_contract::entry(&ID, &accounts, &data);
+});
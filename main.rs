@@ -1,31 +1,1371 @@
 use anchor_lang::prelude::*;
-use anchor_spl::associated_token::AssociatedToken;
-use anchor_spl::token::{CloseAccount, Mint, Token, TokenAccount, Transfer};
+use anchor_lang::Discriminator;
+use anchor_lang::error::{Error, ErrorCode};
+use anchor_spl::associated_token::{
+    get_associated_token_address_with_program_id, AssociatedToken, Create,
+};
+use anchor_spl::metadata::mpl_token_metadata::types::DataV2;
+use anchor_spl::metadata::{
+    create_metadata_accounts_v3, CreateMetadataAccountsV3, Metadata, MetadataAccount,
+};
+use anchor_spl::token::spl_token::native_mint;
+use anchor_spl::token_2022::spl_token_2022::extension::default_account_state::DefaultAccountState;
+use anchor_spl::token_2022::spl_token_2022::extension::{
+    BaseStateWithExtensions, ExtensionType, StateWithExtensions,
+};
+use anchor_spl::token_2022::spl_token_2022::state::{AccountState, Mint as MintState};
+use anchor_spl::token_interface::{
+    Burn, CloseAccount, Mint, MintTo, SyncNative, TokenAccount, TokenInterface, TransferChecked,
+};
+use solana_program::address_lookup_table::{self, state::AddressLookupTable};
+use solana_program::ed25519_program;
 use solana_program::hash::hash;
-use std::str::FromStr;
+use solana_program::keccak;
+use solana_program::log::sol_log_compute_units;
+use solana_program::instruction::{get_stack_height, Instruction, TRANSACTION_LEVEL_STACK_HEIGHT};
+use solana_program::program::{invoke, invoke_signed};
+use solana_program::secp256k1_recover::secp256k1_recover;
+use solana_program::sysvar::instructions::{
+    load_current_index_checked, load_instruction_at_checked,
+};
+use spl_account_compression::cpi::accounts::{Initialize, Modify};
+use spl_account_compression::program::SplAccountCompression;
+use spl_account_compression::Noop;
 
 declare_id!("AzqFSRjxR59LUdZcJxxmFauZhQSpxMFcmCHaKVXAEMDG");
 
 // Constants: Using static constants to improve performance and maintainability
+#[constant]
 pub const INFO_SEED: &[u8] = b"server";
+#[constant]
 pub const MAIN_SEED: &[u8] = b"main";
-pub const SPECIFIED_MINT: &str = "BPtPUxkZc1BR1uEDMUkheABh9N94PUbnXvmXRdCLECBW";
-pub const DELEGATE_MINIMUM_STAKE: u64 = 500 * 1_000_000_000;
-pub const MINIMUM_STAKE: u64 = 1000 * 1_000_000_000;
-pub const MAXIMUM_STAKE: u64 = 10000 * 1_000_000_000;
-pub const VERSION: u8 = 1;
+#[constant]
+pub const CONFIG_SEED: &[u8] = b"config";
+#[constant]
+pub const ALLOWLIST_SEED: &[u8] = b"allow";
+#[constant]
+pub const RECEIPT_SEED: &[u8] = b"receipt";
+#[constant]
+pub const TREASURY_SEED: &[u8] = b"treasury";
+#[constant]
+pub const SNAPSHOT_SEED: &[u8] = b"snapshot";
+#[constant]
+pub const TREE_SEED: &[u8] = b"tree";
+#[constant]
+pub const USAGE_SEED: &[u8] = b"usage";
+#[constant]
+pub const REVENUE_SEED: &[u8] = b"revenue";
+#[constant]
+pub const SUBSCRIPTION_SEED: &[u8] = b"subscription";
+#[constant]
+pub const TASK_SEED: &[u8] = b"task";
+#[constant]
+pub const CHANNEL_SEED: &[u8] = b"channel";
+#[constant]
+pub const WORK_SEED: &[u8] = b"work";
+#[constant]
+pub const ACTIVE_SET_SEED: &[u8] = b"active";
+#[constant]
+pub const EMITTER_SEED: &[u8] = b"emitter";
+#[constant]
+pub const ATTESTATION_SEED: &[u8] = b"attestation";
+#[constant]
+pub const FOREIGN_OWNER_SEED: &[u8] = b"foreign";
+#[constant]
+pub const BRIDGED_SEED: &[u8] = b"bridged";
+#[constant]
+pub const DISPUTE_SEED: &[u8] = b"dispute";
+#[constant]
+pub const AUDIT_LOG_SEED: &[u8] = b"audit";
+#[constant]
+pub const PERMIT_SEED: &[u8] = b"permit";
+#[constant]
+pub const REGISTRATION_SEED: &[u8] = b"registration";
+#[constant]
+pub const RECOUNT_SEED: &[u8] = b"recount";
+#[constant]
+pub const STANDING_ORDER_SEED: &[u8] = b"standing";
+#[constant]
+pub const RENEWAL_SEED: &[u8] = b"renewal";
+#[constant]
+pub const GUARDIAN_SEED: &[u8] = b"guardians";
+#[constant]
+pub const PORTFOLIO_SEED: &[u8] = b"portfolio";
+#[constant]
+pub const POOL_SEED: &[u8] = b"pool";
+#[constant]
+pub const POOL_SHARE_SEED: &[u8] = b"pool_share";
+#[constant]
+pub const HANDLE_SEED: &[u8] = b"handle";
+#[constant]
+pub const PARTNER_ADAPTER_SEED: &[u8] = b"partner_adapter";
+#[constant]
+pub const PARTNER_IMPORT_SEED: &[u8] = b"partner_import";
+#[constant]
+pub const TENURE_SEED: &[u8] = b"tenure";
+#[constant]
+pub const INTENT_SEED: &[u8] = b"intent";
+#[constant]
+pub const DAILY_STATS_SEED: &[u8] = b"daily";
+#[constant]
+pub const ARCHIVE_SEED: &[u8] = b"snapshot_archive";
+// Accounts of the Wormhole core bridge, PDAs of config.wormhole_bridge
+pub const WORMHOLE_BRIDGE_SEED: &[u8] = b"Bridge";
+pub const WORMHOLE_FEE_COLLECTOR_SEED: &[u8] = b"fee_collector";
+pub const WORMHOLE_SEQUENCE_SEED: &[u8] = b"Sequence";
+// Core bridge PostMessage instruction index, and the finalized commitment for guardians to sign at
+pub const WORMHOLE_POST_MESSAGE: u8 = 1;
+pub const WORMHOLE_FINALIZED: u8 = 1;
+// Cluster selection: mainnet by default, `devnet` for devnet builds. Devnet has no fixed staking
+// token, the deployment sets config.staking_mint before initialize_main. The program keypair, and
+// so the program id, is shared by both clusters.
+#[cfg(all(feature = "devnet", feature = "mainnet"))]
+compile_error!("the `devnet` and `mainnet` features are mutually exclusive");
+#[cfg(not(feature = "devnet"))]
+#[constant]
+pub const SPECIFIED_MINT: Pubkey =
+    solana_program::pubkey!("BPtPUxkZc1BR1uEDMUkheABh9N94PUbnXvmXRdCLECBW");
+#[cfg(feature = "devnet")]
+#[constant]
+pub const SPECIFIED_MINT: Pubkey = Pubkey::new_from_array([0; 32]);
+// Stake limits in whole tokens, converted with the decimals of the collateral mint
+#[constant]
+pub const DELEGATE_MINIMUM_STAKE_TOKENS: u64 = 500;
+#[constant]
+pub const MINIMUM_STAKE_TOKENS: u64 = 1000;
+#[constant]
+pub const MAXIMUM_STAKE_TOKENS: u64 = 10000;
+// The same limits in base units of the 9-decimal SPECIFIED_MINT. Constants only, amounts from
+// instructions go through the checked to_minimum_units.
+#[constant]
+pub const STAKING_DECIMALS: u8 = 9;
+const STAKING_UNIT: u64 = 10u64.pow(STAKING_DECIMALS as u32);
+#[constant]
+pub const DELEGATE_MINIMUM_STAKE: u64 = DELEGATE_MINIMUM_STAKE_TOKENS * STAKING_UNIT;
+#[constant]
+pub const MINIMUM_STAKE: u64 = MINIMUM_STAKE_TOKENS * STAKING_UNIT;
+#[constant]
+pub const MAXIMUM_STAKE: u64 = MAXIMUM_STAKE_TOKENS * STAKING_UNIT;
+// Service tier thresholds on the server total (staking token base units) and last reported uptime
+#[constant]
+pub const SILVER_TIER_STAKE: u64 = 20_000 * STAKING_UNIT;
+#[constant]
+pub const GOLD_TIER_STAKE: u64 = 50_000 * STAKING_UNIT;
+// Lower bounds of the MainAccount.servers_by_stake buckets past the first, on the server total in
+// staking token base units
+#[constant]
+pub const STAKE_BUCKET_BOUNDS: [u64; STAKE_BUCKETS - 1] =
+    [2_000 * STAKING_UNIT, 5_000 * STAKING_UNIT, 10_000 * STAKING_UNIT];
+#[constant]
+pub const SILVER_TIER_UPTIME_BPS: u16 = 9_500;
+#[constant]
+pub const GOLD_TIER_UPTIME_BPS: u16 = 9_900;
+// Decimals of the USD amounts in the config
+#[constant]
+pub const USD_DECIMALS: u8 = 6;
+// Pyth pull-oracle receiver, owner of the PriceUpdateV2 accounts the USD conversions read
+#[constant]
+pub const PYTH_RECEIVER_ID: Pubkey =
+    solana_program::pubkey!("rec5EKMGg6MxZYaMdyBfgwp4d5rB9T1VQH5pJv5LtFJ");
+// SPL Memo program, whose instruction right before a deposit tags it, see transaction_memo
+#[constant]
+pub const MEMO_PROGRAM_ID: Pubkey =
+    solana_program::pubkey!("MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr");
+// Layout version stored in every account, accounts from before versioning are v1
+#[constant]
+pub const VERSION: u8 = 2;
+pub const MAX_SERVERKEY_LEN: usize = 65;
+pub const MAX_NAME_LEN: usize = 32;
+pub const MAX_HANDLE_LEN: usize = 64;
+pub const MAX_MEMO_LEN: usize = 64;
+pub const MAX_ENDPOINT_LEN: usize = 64;
+pub const MAX_ICON_URI_LEN: usize = 128;
+pub const MAX_CONTACT_LEN: usize = 192;
+pub const MAX_WITHDRAWAL_ADDRESSES: usize = 4;
+// Reward distributions a server remembers, see InfoAccount::reward_per_share_at
+pub const REWARD_HISTORY_LEN: usize = 16;
+pub const MAX_GUARDIANS: usize = 7;
+pub const MAX_SERVER_SIGNERS: usize = 5;
+pub const MAX_APPROVED_MINTS: usize = 4;
+pub const STAKE_BUCKETS: usize = 4;
+pub const MAX_BATCH_DELEGATIONS: usize = 5;
+pub const MAX_PORTFOLIO_POSITIONS: usize = 5;
+pub const MAX_POOL_POSITIONS: usize = 8;
+pub const MAX_BATCH_OPS: usize = 4;
+pub const MAX_TASK_CANDIDATES: usize = 8;
+pub const MAX_CAPABILITIES: usize = 4;
+pub const MAX_BLOCKED_NAMES: usize = 16;
+pub const MAX_CPI_PROGRAMS: usize = 4;
+pub const AUDIT_LOG_LEN: usize = 64;
+pub const MAX_ACTIVE_SET: usize = 64;
+// Reputation lost per recorded service failure, see InfoAccount::reputation_bps
+#[constant]
+pub const FAILURE_REPUTATION_BPS: u16 = 500;
+// Bits of InfoAccount.capabilities, bit i has its minimum stake in config.capability_minimums[i]
+#[constant]
+pub const CAPABILITY_RELAY: u8 = 1 << 0;
+#[constant]
+pub const CAPABILITY_EXIT: u8 = 1 << 1;
+#[constant]
+pub const CAPABILITY_STORAGE: u8 = 1 << 2;
+#[constant]
+pub const CAPABILITY_COMPUTE: u8 = 1 << 3;
+pub const DELEGATED_ACCOUNT_SPACE: usize = 8 + DelegatedAccount::INIT_SPACE;
+pub const INFO_ACCOUNT_SPACE: usize = 8 + InfoAccount::INIT_SPACE;
+// Byte offsets, discriminator included, of the fields clients filter getProgramAccounts on
+pub const VERSION_OFFSET: usize = 8;
+pub const INITIALIZED_OFFSET: usize = 9;
+pub const INFO_OWNER_OFFSET: usize = 10;
+pub const INFO_MINT_OFFSET: usize = 42;
+pub const INFO_SERVERKEY_OFFSET: usize = 74;
+pub const DELEGATED_DELEGATOR_OFFSET: usize = 10;
+pub const DELEGATED_OWNER_OFFSET: usize = 42;
+pub const DELEGATED_MINT_OFFSET: usize = 74;
+pub const SNAPSHOT_EPOCH_OFFSET: usize = 9;
+pub const DAILY_STATS_DAY_OFFSET: usize = 9;
+// Discriminators of the accounts clients scan for, the first 8 bytes of their data
+pub const INFO_DISCRIMINATOR: [u8; 8] = InfoAccount::DISCRIMINATOR;
+pub const DELEGATED_DISCRIMINATOR: [u8; 8] = DelegatedAccount::DISCRIMINATOR;
+pub const SNAPSHOT_DISCRIMINATOR: [u8; 8] = EpochSnapshot::DISCRIMINATOR;
+pub const DAILY_STATS_DISCRIMINATOR: [u8; 8] = DailyStats::DISCRIMINATOR;
+#[constant]
+pub const MAX_BPS: u16 = 10_000;
+// First field of every event, raised whenever an event's payload changes so indexers can tell
+// the layouts apart
+#[constant]
+pub const EVENT_SCHEMA_VERSION: u8 = 3;
+// Cap on the entry fee servers take from each d_deposit, see set_entry_fee
+#[constant]
+pub const MAX_ENTRY_FEE_BPS: u16 = 500;
+#[constant]
+pub const REWARD_PRECISION: u128 = 1_000_000_000_000;
+// Exchange rates are staking token base units per collateral base unit, scaled by this factor
+#[constant]
+pub const RATE_PRECISION: u64 = 1_000_000_000;
+#[constant]
+pub const DEFAULT_SELF_BOND_MULTIPLE: u16 = 10;
+// Time after a lock rolled over during which disabling renewal still undoes the roll-over
+#[constant]
+pub const LOCKUP_RENEWAL_GRACE_SECS: i64 = 7 * 24 * 60 * 60;
+// Wait between announcing a withdrawal whitelist change and making it
+#[constant]
+pub const WHITELIST_CHANGE_DELAY_SECS: i64 = 3 * 24 * 60 * 60;
+// Wait between a backup key announcing a recovery and claiming the server, see claim_recovery
+#[constant]
+pub const RECOVERY_DELAY_SECS: i64 = 30 * 24 * 60 * 60;
+// Wait between guardians reaching their threshold and the new owner claiming the server
+#[constant]
+pub const GUARDIAN_RECOVERY_DELAY_SECS: i64 = 7 * 24 * 60 * 60;
+// Length of the UTC days DailyStats records are keyed by
+#[constant]
+pub const SECS_PER_DAY: i64 = 24 * 60 * 60;
+// Epochs an EpochSnapshot stays open before archive_snapshots may fold it, and the depth of
+// the archive tree
+#[constant]
+pub const SNAPSHOT_RETENTION_EPOCHS: u64 = 30;
+pub const ARCHIVE_DEPTH: usize = 32;
+
+// PDA derivations for clients and CPI callers, which depend on this crate with the `cpi` and
+// `no-entrypoint` features for the account, event and error types
+pub mod pda {
+    use super::*;
+
+    pub fn main_account(program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[MAIN_SEED], program_id)
+    }
+
+    pub fn config(program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[CONFIG_SEED], program_id)
+    }
+
+    pub fn treasury(program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[TREASURY_SEED], program_id)
+    }
+
+    // `owner` is the registrant, a server keeps its address when recovered by a backup key
+    pub fn server(owner: &Pubkey, serverkey: &[u8], program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[INFO_SEED, owner.as_ref(), hash(serverkey).as_ref()],
+            program_id,
+        )
+    }
+
+    pub fn delegation(owner: &Pubkey, server: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[INFO_SEED, owner.as_ref(), server.as_ref()], program_id)
+    }
+
+    pub fn allowlist_entry(staker: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[ALLOWLIST_SEED, staker.as_ref()], program_id)
+    }
+
+    pub fn receipt_mint(collateral_mint: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[RECEIPT_SEED, collateral_mint.as_ref()], program_id)
+    }
+
+    pub fn epoch_snapshot(epoch: u64, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[SNAPSHOT_SEED, epoch.to_le_bytes().as_ref()], program_id)
+    }
+
+    pub fn tree_authority(server: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[TREE_SEED, server.as_ref()], program_id)
+    }
+
+    pub fn subscription(client: &Pubkey, server: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[SUBSCRIPTION_SEED, client.as_ref(), server.as_ref()],
+            program_id,
+        )
+    }
+
+    pub fn channel(client: &Pubkey, server: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[CHANNEL_SEED, client.as_ref(), server.as_ref()],
+            program_id,
+        )
+    }
+
+    pub fn task(requester: &Pubkey, task_id: u64, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[TASK_SEED, requester.as_ref(), task_id.to_le_bytes().as_ref()],
+            program_id,
+        )
+    }
+
+    pub fn revenue_vault(server: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[REVENUE_SEED, server.as_ref()], program_id)
+    }
+
+    // The program's Wormhole emitter
+    pub fn emitter(program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[EMITTER_SEED], program_id)
+    }
+
+    // Wormhole message account of the `index`-th attestation of a server
+    pub fn attestation(server: &Pubkey, index: u64, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[ATTESTATION_SEED, server.as_ref(), index.to_le_bytes().as_ref()],
+            program_id,
+        )
+    }
+
+    // ProgramData account of the upgradeable loader holding the program's upgrade authority
+    pub fn program_data(program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[program_id.as_ref()],
+            &solana_program::bpf_loader_upgradeable::ID,
+        )
+    }
+
+    // Stand-in owner of the delegations of `address` on Wormhole chain `chain`
+    pub fn foreign_owner(chain: u16, address: &[u8; 32], program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[FOREIGN_OWNER_SEED, chain.to_le_bytes().as_ref(), address.as_ref()],
+            program_id,
+        )
+    }
+
+    // Replay guard of the bridged delegation message `sequence` from chain `chain`
+    pub fn bridged_delegation(chain: u16, sequence: u64, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[BRIDGED_SEED, chain.to_le_bytes().as_ref(), sequence.to_le_bytes().as_ref()],
+            program_id,
+        )
+    }
+
+    pub fn dispute(client: &Pubkey, server: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[DISPUTE_SEED, client.as_ref(), server.as_ref()], program_id)
+    }
+
+    // Recurring delegation of `owner` into `server`, also the SPL delegate of its funding account
+    pub fn standing_order(owner: &Pubkey, server: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[STANDING_ORDER_SEED, owner.as_ref(), server.as_ref()],
+            program_id,
+        )
+    }
+
+    // Auto-renewal opt-in of a delegation position, see LockupRenewal
+    pub fn lockup_renewal(delegation: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[RENEWAL_SEED, delegation.as_ref()], program_id)
+    }
+
+    // Recovery guardians of `server`, see GuardianSet
+    pub fn guardian_set(server: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[GUARDIAN_SEED, server.as_ref()], program_id)
+    }
+
+    // Portfolio of `owner`, the owner of its positions is this address
+    pub fn portfolio(owner: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[PORTFOLIO_SEED, owner.as_ref()], program_id)
+    }
+
+    // Pooled delegation of `mint`, see DelegationPool
+    pub fn pool(mint: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[POOL_SEED, mint.as_ref()], program_id)
+    }
+
+    pub fn pool_share_mint(pool: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[POOL_SHARE_SEED, pool.as_ref()], program_id)
+    }
+
+    // Registry entry of `handle`, see NodeHandle
+    pub fn handle(handle: &str, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[HANDLE_SEED, hash(handle.as_bytes()).as_ref()], program_id)
+    }
+
+    pub fn partner_adapter(partner_program: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[PARTNER_ADAPTER_SEED, partner_program.as_ref()], program_id)
+    }
+
+    pub fn partner_import(partner_position: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[PARTNER_IMPORT_SEED, partner_position.as_ref()], program_id)
+    }
+
+    pub fn tenure_credit(owner: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[TENURE_SEED, owner.as_ref()], program_id)
+    }
+
+    // One-off delegation of `owner` into `server`, also the SPL delegate of its funding account
+    pub fn delegation_intent(owner: &Pubkey, server: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[INTENT_SEED, owner.as_ref(), server.as_ref()], program_id)
+    }
+
+    pub fn recount(program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[RECOUNT_SEED], program_id)
+    }
+
+    // Stats of UTC day `day`, counted in days since the Unix epoch
+    pub fn daily_stats(day: u32, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[DAILY_STATS_SEED, day.to_le_bytes().as_ref()], program_id)
+    }
+
+    pub fn snapshot_archive(program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[ARCHIVE_SEED], program_id)
+    }
+
+    // Close record of a server, see RegistrationRecord
+    pub fn registration(owner: &Pubkey, serverkey: &[u8], program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[REGISTRATION_SEED, owner.as_ref(), hash(serverkey).as_ref()],
+            program_id,
+        )
+    }
+
+    // SPL token delegate that owners approve for deposit_with_permit
+    pub fn permit_authority(program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[PERMIT_SEED], program_id)
+    }
+
+    pub fn audit_log(program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[AUDIT_LOG_SEED], program_id)
+    }
+
+    pub fn active_set(epoch: u64, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[ACTIVE_SET_SEED, epoch.to_le_bytes().as_ref()], program_id)
+    }
+
+    pub fn epoch_work(epoch: u64, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[WORK_SEED, epoch.to_le_bytes().as_ref()], program_id)
+    }
+
+    pub fn work_credits(server: &Pubkey, epoch: u64, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[WORK_SEED, epoch.to_le_bytes().as_ref(), server.as_ref()],
+            program_id,
+        )
+    }
+
+    pub fn usage(server: &Pubkey, epoch: u64, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[USAGE_SEED, server.as_ref(), epoch.to_le_bytes().as_ref()],
+            program_id,
+        )
+    }
+}
+
+// Input limits the program enforces, in one place for clients and CPI callers to validate
+// against before sending. Stake limits are given in whole tokens and in base units of the
+// 9-decimal SPECIFIED_MINT; config.min_stake_usd and delegate_min_stake_usd replace the minimums
+// when set. Names and serverkeys are byte lengths, names count after whitespace normalization.
+pub mod limits {
+    pub use super::{
+        DELEGATE_MINIMUM_STAKE, DELEGATE_MINIMUM_STAKE_TOKENS, MAXIMUM_STAKE, MAXIMUM_STAKE_TOKENS,
+        MAX_CONTACT_LEN, MAX_ENDPOINT_LEN, MAX_ENTRY_FEE_BPS, MAX_HANDLE_LEN, MAX_ICON_URI_LEN,
+        MAX_MEMO_LEN, MAX_NAME_LEN, MAX_SERVERKEY_LEN, MINIMUM_STAKE, MINIMUM_STAKE_TOKENS,
+        STAKING_DECIMALS,
+    };
+}
+
+// A getProgramAccounts memcmp filter, `bytes` has to match the account data at `offset`
+#[cfg(not(target_os = "solana"))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Memcmp {
+    pub offset: usize,
+    pub bytes: Vec<u8>,
+}
+
+// Filters selecting the current-layout accounts of type T, narrowed down by `fields` at the
+// *_OFFSET constants, e.g. the servers of an owner:
+//
+//     filters::<InfoAccount>(&[(INFO_OWNER_OFFSET, owner.as_ref())])
+#[cfg(not(target_os = "solana"))]
+pub fn filters<T: Discriminator>(fields: &[(usize, &[u8])]) -> Vec<Memcmp> {
+    let mut filters = vec![
+        Memcmp {
+            offset: 0,
+            bytes: T::DISCRIMINATOR.to_vec(),
+        },
+        Memcmp {
+            offset: VERSION_OFFSET,
+            bytes: vec![VERSION],
+        },
+    ];
+    filters.extend(fields.iter().map(|&(offset, bytes)| Memcmp {
+        offset,
+        bytes: bytes.to_vec(),
+    }));
+    filters
+}
+
+// On-chain helpers for programs that gate their own instructions on staking state
+#[cfg(feature = "cpi")]
+pub mod query {
+    use super::*;
+
+    // CPI into get_stake_of and read its return data. `program` must be this program, the
+    // accounts are checked by the callee, so a forged server or position is rejected there.
+    pub fn stake_of<'info>(
+        program: AccountInfo<'info>,
+        info_account: AccountInfo<'info>,
+        delegated_account: Option<AccountInfo<'info>>,
+        owner: Pubkey,
+    ) -> Result<StakeOf> {
+        require_keys_eq!(program.key(), ID, ErrorCode::InvalidProgramId);
+        let ctx = CpiContext::new(
+            program,
+            cpi::accounts::GetStakeOf {
+                info_account,
+                delegated_account,
+            },
+        );
+        Ok(cpi::get_stake_of(ctx, owner)?.get())
+    }
+
+    // CPI into get_pool_balance, see PoolBalance. Deposits and withdrawals go through
+    // cpi::pool_deposit, cpi::pool_withdraw and cpi::pool_withdraw_assets, whose return data
+    // holds the shares or base units that moved.
+    pub fn pool_balance<'info>(
+        program: AccountInfo<'info>,
+        pool: AccountInfo<'info>,
+        share_mint: AccountInfo<'info>,
+        share_account: Option<AccountInfo<'info>>,
+    ) -> Result<PoolBalance> {
+        require_keys_eq!(program.key(), ID, ErrorCode::InvalidProgramId);
+        let ctx = CpiContext::new(
+            program,
+            cpi::accounts::GetPoolBalance {
+                pool,
+                share_mint,
+                share_account,
+            },
+        );
+        Ok(cpi::get_pool_balance(ctx)?.get())
+    }
+}
+
+// Instruction builders for off-chain Rust tooling. Every PDA and ATA is derived from the owner, the
+// serverkey or server address and the collateral mint; optional accounts a deployment doesn't use
+// are left out, which Anchor encodes as the program id.
+#[cfg(not(target_os = "solana"))]
+pub mod client {
+    use super::*;
+    use anchor_lang::{InstructionData, ToAccountMetas};
+    use solana_program::instruction::{AccountMeta, Instruction};
+
+    fn build(accounts: impl ToAccountMetas, data: impl InstructionData) -> Instruction {
+        Instruction {
+            program_id: ID,
+            accounts: accounts.to_account_metas(None),
+            data: data.data(),
+        }
+    }
+
+    fn ata(authority: &Pubkey, mint: &Pubkey, token_program: &Pubkey) -> Pubkey {
+        get_associated_token_address_with_program_id(authority, mint, token_program)
+    }
+
+    #[cfg(feature = "event-cpi")]
+    fn event_authority() -> Pubkey {
+        Pubkey::find_program_address(&[b"__event_authority"], &ID).0
+    }
+
+    // Treasury token account, passed when the matching protocol fee is on
+    fn treasury_token_account(
+        protocol_fees: bool,
+        mint: &Pubkey,
+        token_program: &Pubkey,
+    ) -> Option<Pubkey> {
+        protocol_fees.then(|| ata(&pda::treasury(&ID).0, mint, token_program))
+    }
+
+    pub fn add_server_ix(
+        owner: Pubkey,
+        serverkey: Vec<u8>,
+        server_name: String,
+        amount: u64,
+        mint: Pubkey,
+        token_program: Pubkey,
+    ) -> Instruction {
+        let info_account = pda::server(&owner, &serverkey, &ID).0;
+        build(
+            accounts::AddServer {
+                main_account: pda::main_account(&ID).0,
+                config: pda::config(&ID).0,
+                gate_account: None,
+                price_update: None,
+                license_token_account: None,
+                license_metadata: None,
+                registration_record: Some(pda::registration(&owner, &serverkey, &ID).0),
+                info_account,
+                sender_token_account: ata(&owner, &mint, &token_program),
+                vault: ata(&info_account, &mint, &token_program),
+                mint,
+                bond_mint: None,
+                bond_sender_token_account: None,
+                bond_vault: None,
+                owner,
+                payer: owner,
+                token_program,
+                associated_token_program: anchor_spl::associated_token::ID,
+                system_program: anchor_lang::system_program::ID,
+                #[cfg(feature = "event-cpi")]
+                event_authority: event_authority(),
+                #[cfg(feature = "event-cpi")]
+                program: ID,
+            },
+            instruction::AddServer {
+                serverkey,
+                server_name,
+                amount,
+                nonce: None,
+            },
+        )
+    }
+
+    pub fn deposit_ix(
+        owner: Pubkey,
+        serverkey: &[u8],
+        amount: u64,
+        mint: Pubkey,
+        token_program: Pubkey,
+    ) -> Instruction {
+        let info_account = pda::server(&owner, serverkey, &ID).0;
+        build(
+            accounts::Deposit {
+                main_account: pda::main_account(&ID).0,
+                config: pda::config(&ID).0,
+                gate_account: None,
+                info_account,
+                vault: ata(&info_account, &mint, &token_program),
+                mint,
+                sender_token_account: ata(&owner, &mint, &token_program),
+                instructions: None,
+                owner,
+                token_program,
+                associated_token_program: anchor_spl::associated_token::ID,
+                system_program: anchor_lang::system_program::ID,
+                #[cfg(feature = "event-cpi")]
+                event_authority: event_authority(),
+                #[cfg(feature = "event-cpi")]
+                program: ID,
+            },
+            instruction::Deposit { amount },
+        )
+    }
+
+    pub fn withdraw_ix(
+        owner: Pubkey,
+        serverkey: &[u8],
+        amount: u64,
+        close_vault: bool,
+        mint: Pubkey,
+        token_program: Pubkey,
+        protocol_fees: bool,
+    ) -> Instruction {
+        let info_account = pda::server(&owner, serverkey, &ID).0;
+        build(
+            accounts::Withdraw {
+                main_account: pda::main_account(&ID).0,
+                config: pda::config(&ID).0,
+                info_account,
+                vault: ata(&info_account, &mint, &token_program),
+                receipt_token_account: ata(&owner, &mint, &token_program),
+                destination: None,
+                mint,
+                treasury: pda::treasury(&ID).0,
+                treasury_token_account: treasury_token_account(
+                    protocol_fees,
+                    &mint,
+                    &token_program,
+                ),
+                instructions: None,
+                owner,
+                token_program,
+                associated_token_program: anchor_spl::associated_token::ID,
+                system_program: anchor_lang::system_program::ID,
+                #[cfg(feature = "event-cpi")]
+                event_authority: event_authority(),
+                #[cfg(feature = "event-cpi")]
+                program: ID,
+            },
+            instruction::Withdraw {
+                amount,
+                close_vault,
+            },
+        )
+    }
+
+    // `receipts` passes the receipt token accounts, required while config.receipt_tokens is on
+    #[allow(clippy::too_many_arguments)]
+    pub fn d_deposit_ix(
+        owner: Pubkey,
+        server: Pubkey,
+        amount: u64,
+        referrer: Option<Pubkey>,
+        lockup: LockupTier,
+        mint: Pubkey,
+        token_program: Pubkey,
+        receipts: bool,
+    ) -> Instruction {
+        let delegated_account = pda::delegation(&owner, &server, &ID).0;
+        let receipt_mint = receipt_mint_address(&mint, &ID);
+        build(
+            accounts::DelegatedDeposit {
+                main_account: pda::main_account(&ID).0,
+                config: pda::config(&ID).0,
+                gate_account: None,
+                price_update: None,
+                info_account: server,
+                delegated_account,
+                vault: ata(&delegated_account, &mint, &token_program),
+                mint,
+                sender_token_account: ata(&owner, &mint, &token_program),
+                operator_token_account: None,
+                receipt_mint: receipts.then_some(receipt_mint),
+                receipt_holder_account: receipts
+                    .then(|| ata(&owner, &receipt_mint, &token_program)),
+                instructions: None,
+                owner,
+                payer: owner,
+                token_program,
+                associated_token_program: anchor_spl::associated_token::ID,
+                system_program: anchor_lang::system_program::ID,
+                #[cfg(feature = "event-cpi")]
+                event_authority: event_authority(),
+                #[cfg(feature = "event-cpi")]
+                program: ID,
+            },
+            instruction::DDeposit {
+                amount,
+                referrer,
+                lockup,
+            },
+        )
+    }
+
+    // One d_deposit_many entry per (server, amount) pair, their triplets go in the remaining
+    // accounts
+    pub fn d_deposit_many_ix(
+        owner: Pubkey,
+        deposits: &[(Pubkey, u64)],
+        lockup: LockupTier,
+        mint: Pubkey,
+        token_program: Pubkey,
+        receipts: bool,
+    ) -> Instruction {
+        let receipt_mint = receipt_mint_address(&mint, &ID);
+        let mut ix = build(
+            accounts::DelegatedDepositMany {
+                main_account: pda::main_account(&ID).0,
+                config: pda::config(&ID).0,
+                gate_account: None,
+                price_update: None,
+                mint,
+                sender_token_account: ata(&owner, &mint, &token_program),
+                receipt_mint: receipts.then_some(receipt_mint),
+                receipt_holder_account: receipts
+                    .then(|| ata(&owner, &receipt_mint, &token_program)),
+                owner,
+                payer: owner,
+                token_program,
+                associated_token_program: anchor_spl::associated_token::ID,
+                system_program: anchor_lang::system_program::ID,
+                #[cfg(feature = "event-cpi")]
+                event_authority: event_authority(),
+                #[cfg(feature = "event-cpi")]
+                program: ID,
+            },
+            instruction::DDepositMany {
+                amounts: deposits.iter().map(|(_, amount)| *amount).collect(),
+                lockup,
+            },
+        );
+        for (server, _) in deposits {
+            let delegated_account = pda::delegation(&owner, server, &ID).0;
+            ix.accounts.extend([
+                AccountMeta::new(*server, false),
+                AccountMeta::new(ata(&delegated_account, &mint, &token_program), false),
+                AccountMeta::new(delegated_account, false),
+            ]);
+        }
+        ix
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn d_withdraw_ix(
+        owner: Pubkey,
+        server: Pubkey,
+        amount: u64,
+        close_vault: bool,
+        mint: Pubkey,
+        token_program: Pubkey,
+        receipts: bool,
+        protocol_fees: bool,
+    ) -> Instruction {
+        let delegated_account = pda::delegation(&owner, &server, &ID).0;
+        let receipt_mint = receipt_mint_address(&mint, &ID);
+        build(
+            accounts::DelegatedWithdraw {
+                main_account: pda::main_account(&ID).0,
+                config: pda::config(&ID).0,
+                info_account: server,
+                delegated_account,
+                lockup_renewal: pda::lockup_renewal(&delegated_account, &ID).0,
+                vault: ata(&delegated_account, &mint, &token_program),
+                receipt_token_account: ata(&owner, &mint, &token_program),
+                mint,
+                receipt_mint: receipts.then_some(receipt_mint),
+                receipt_holder_account: receipts
+                    .then(|| ata(&owner, &receipt_mint, &token_program)),
+                treasury: pda::treasury(&ID).0,
+                treasury_token_account: treasury_token_account(
+                    protocol_fees,
+                    &mint,
+                    &token_program,
+                ),
+                owner,
+                payer: owner,
+                token_program,
+                associated_token_program: anchor_spl::associated_token::ID,
+                system_program: anchor_lang::system_program::ID,
+                #[cfg(feature = "event-cpi")]
+                event_authority: event_authority(),
+                #[cfg(feature = "event-cpi")]
+                program: ID,
+            },
+            instruction::DWithdraw {
+                amount,
+                close_vault,
+            },
+        )
+    }
+
+    pub fn claim_rewards_ix(
+        owner: Pubkey,
+        server: Pubkey,
+        mint: Pubkey,
+        token_program: Pubkey,
+        protocol_fees: bool,
+    ) -> Instruction {
+        let main_account = pda::main_account(&ID).0;
+        build(
+            accounts::ClaimRewards {
+                main_account,
+                config: pda::config(&ID).0,
+                info_account: server,
+                delegated_account: pda::delegation(&owner, &server, &ID).0,
+                reward_vault: ata(&main_account, &mint, &token_program),
+                receipt_token_account: ata(&owner, &mint, &token_program),
+                mint,
+                treasury: pda::treasury(&ID).0,
+                treasury_token_account: treasury_token_account(
+                    protocol_fees,
+                    &mint,
+                    &token_program,
+                ),
+                owner,
+                payer: owner,
+                token_program,
+                associated_token_program: anchor_spl::associated_token::ID,
+                system_program: anchor_lang::system_program::ID,
+            },
+            instruction::ClaimRewards {},
+        )
+    }
+
+    pub fn initialize_main_ix(
+        owner: Pubkey,
+        with_config: bool,
+        mint: Pubkey,
+        decimals: u8,
+    ) -> Instruction {
+        build(
+            accounts::InitializeMain {
+                main_account: pda::main_account(&ID).0,
+                config: with_config.then(|| pda::config(&ID).0),
+                mint,
+                owner,
+                system_program: anchor_lang::system_program::ID,
+            },
+            instruction::InitializeMain { decimals },
+        )
+    }
+
+    pub fn initialize_config_ix(admin: Pubkey) -> Instruction {
+        build(
+            accounts::InitializeConfig {
+                config: pda::config(&ID).0,
+                program: ID,
+                program_data: pda::program_data(&ID).0,
+                admin,
+                system_program: anchor_lang::system_program::ID,
+            },
+            instruction::InitializeConfig {},
+        )
+    }
+
+    pub fn update_config_ix(admin: Pubkey, params: ConfigParams) -> Instruction {
+        build(
+            accounts::UpdateConfig {
+                config: pda::config(&ID).0,
+                main_account: pda::main_account(&ID).0,
+                audit_log: None,
+                admin,
+            },
+            instruction::UpdateConfig { params },
+        )
+    }
+
+    pub fn remove_server_ix(
+        owner: Pubkey,
+        serverkey: &[u8],
+        mint: Pubkey,
+        token_program: Pubkey,
+    ) -> Instruction {
+        let info_account = pda::server(&owner, serverkey, &ID).0;
+        build(
+            accounts::RemoveServer {
+                main_account: pda::main_account(&ID).0,
+                config: pda::config(&ID).0,
+                info_account,
+                registration_record: pda::registration(&owner, serverkey, &ID).0,
+                vault: Some(ata(&info_account, &mint, &token_program)),
+                mint,
+                instructions: None,
+                owner,
+                token_program,
+                associated_token_program: anchor_spl::associated_token::ID,
+                system_program: anchor_lang::system_program::ID,
+                #[cfg(feature = "event-cpi")]
+                event_authority: event_authority(),
+                #[cfg(feature = "event-cpi")]
+                program: ID,
+            },
+            instruction::RemoveServer {},
+        )
+    }
+
+    pub fn d_remove_ix(
+        owner: Pubkey,
+        server: Pubkey,
+        mint: Pubkey,
+        token_program: Pubkey,
+    ) -> Instruction {
+        let delegated_account = pda::delegation(&owner, &server, &ID).0;
+        build(
+            accounts::RemoveDelegatedAccount {
+                main_account: pda::main_account(&ID).0,
+                info_account: server,
+                delegated_account,
+                vault: Some(ata(&delegated_account, &mint, &token_program)),
+                mint,
+                owner,
+                token_program,
+                associated_token_program: anchor_spl::associated_token::ID,
+                system_program: anchor_lang::system_program::ID,
+                #[cfg(feature = "event-cpi")]
+                event_authority: event_authority(),
+                #[cfg(feature = "event-cpi")]
+                program: ID,
+            },
+            instruction::DRemove {},
+        )
+    }
+
+    pub fn fund_rewards_ix(
+        funder: Pubkey,
+        server: Pubkey,
+        amount: u64,
+        mint: Pubkey,
+        token_program: Pubkey,
+    ) -> Instruction {
+        let main_account = pda::main_account(&ID).0;
+        build(
+            accounts::FundRewards {
+                main_account,
+                info_account: server,
+                reward_vault: ata(&main_account, &mint, &token_program),
+                mint,
+                sender_token_account: ata(&funder, &mint, &token_program),
+                funder,
+                token_program,
+                associated_token_program: anchor_spl::associated_token::ID,
+                system_program: anchor_lang::system_program::ID,
+            },
+            instruction::FundRewards { amount },
+        )
+    }
+
+    pub fn settle_lockup_ix(delegation_owner: Pubkey, server: Pubkey) -> Instruction {
+        let delegated_account = pda::delegation(&delegation_owner, &server, &ID).0;
+        build(
+            accounts::SettleLockup {
+                main_account: pda::main_account(&ID).0,
+                info_account: server,
+                delegated_account,
+                lockup_renewal: pda::lockup_renewal(&delegated_account, &ID).0,
+            },
+            instruction::SettleLockup {},
+        )
+    }
+
+    pub fn link_delegation_ix(
+        server: Pubkey,
+        delegated_account: Pubkey,
+        tail: Option<Pubkey>,
+    ) -> Instruction {
+        build(
+            accounts::LinkDelegation {
+                info_account: server,
+                delegated_account,
+                tail,
+            },
+            instruction::LinkDelegation {},
+        )
+    }
+
+    pub fn unlink_delegation_ix(
+        caller: Pubkey,
+        server: Pubkey,
+        delegated_account: Pubkey,
+        prev: Option<Pubkey>,
+        next: Option<Pubkey>,
+    ) -> Instruction {
+        build(
+            accounts::UnlinkDelegation {
+                info_account: server,
+                delegated_account,
+                prev,
+                next,
+                caller,
+            },
+            instruction::UnlinkDelegation {},
+        )
+    }
+
+    pub fn announce_exit_ix(owner: Pubkey, serverkey: &[u8]) -> Instruction {
+        build(
+            accounts::ServerExit {
+                config: pda::config(&ID).0,
+                info_account: pda::server(&owner, serverkey, &ID).0,
+                owner,
+            },
+            instruction::AnnounceExit {},
+        )
+    }
+
+    pub fn return_delegation_ix(
+        payer: Pubkey,
+        delegation_owner: Pubkey,
+        server: Pubkey,
+        mint: Pubkey,
+        token_program: Pubkey,
+    ) -> Instruction {
+        let main_account = pda::main_account(&ID).0;
+        let delegated_account = pda::delegation(&delegation_owner, &server, &ID).0;
+        build(
+            accounts::ReturnDelegation {
+                main_account,
+                config: pda::config(&ID).0,
+                info_account: server,
+                delegated_account,
+                vault: Some(ata(&delegated_account, &mint, &token_program)),
+                reward_vault: ata(&main_account, &mint, &token_program),
+                delegation_owner,
+                delegation_owner_token_account: ata(&delegation_owner, &mint, &token_program),
+                mint,
+                payer,
+                token_program,
+                associated_token_program: anchor_spl::associated_token::ID,
+                system_program: anchor_lang::system_program::ID,
+                #[cfg(feature = "event-cpi")]
+                event_authority: event_authority(),
+                #[cfg(feature = "event-cpi")]
+                program: ID,
+            },
+            instruction::ReturnDelegation {},
+        )
+    }
+}
+
+// Localnet harness for scenario tests, see test_support.rs
+#[cfg(feature = "test-support")]
+pub mod test_support;
+
+// Decoding of this program's events for indexers, from transaction logs (`emit!`) or from the
+// instruction data of the self-CPI (`emit_cpi!`, event-cpi feature)
+#[cfg(not(target_os = "solana"))]
+pub mod events {
+    use super::*;
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+
+    macro_rules! program_events {
+        ($($name:ident),* $(,)?) => {
+            pub enum ProgramEvent {
+                $($name($name),)*
+            }
+
+            impl ProgramEvent {
+                // Event discriminator followed by the Borsh-serialized event
+                pub fn decode(data: &[u8]) -> Option<Self> {
+                    if data.len() < 8 {
+                        return None;
+                    }
+                    let mut payload = &data[8..];
+                    $(
+                        if data[..8] == $name::DISCRIMINATOR {
+                            return $name::deserialize(&mut payload).ok().map(Self::$name);
+                        }
+                    )*
+                    None
+                }
+            }
+        };
+    }
+
+    program_events!(
+        ServerAdded,
+        ServerUpdated,
+        ServerRemoved,
+        DelegateMinimumUpdated,
+        DelegatedRemoved,
+        TokenDeposited,
+        TokenDelegatedDeposited,
+        TokenWithdrawn,
+        DelegatedTokenWithdrawn,
+        DelegationActivated,
+        ServerFlagged,
+        AutoCompoundUpdated,
+        RewardsFunded,
+        RewardsClaimed,
+        RewardsCranked,
+        ReferralRewardsClaimed,
+        DonationReceived,
+        AllowlistUpdated,
+        LstDeposited,
+        LstWithdrawn,
+        BondDeposited,
+        BondWithdrawn,
+        MainAccountInitialized,
+        ConfigUpdated,
+        ConfigValueChanged,
+        ConfigAddressChanged,
+        ConfigFlagToggled,
+        ConfigDataChanged,
+        MintApproved,
+        ExchangeRateUpdated,
+        ReceiptMintInitialized,
+        ProtocolFeeCollected,
+        TreasuryTransferred,
+        DustSwept,
+        AccountResized,
+        AccountMigrated,
+        EpochSnapshotTaken,
+        LookupTableExtended,
+        DelegationTreeInitialized,
+        CompressedDelegationWritten,
+        AccountingReconciled,
+        UsageRecorded,
+        RevenuePaid,
+        RevenueSettled,
+        CommissionUpdated,
+        ServerHeartbeat,
+        SubscriptionOpened,
+        SubscriptionSettled,
+        SubscriptionClosed,
+        TaskCommitted,
+        TaskAssigned,
+        ChannelOpened,
+        ChannelAccepted,
+        ChannelClaimed,
+        ChannelCloseRequested,
+        ChannelFinalized,
+        WorkCreditsReported,
+        EpochRewardsFunded,
+        WorkRewardsDistributed,
+        ServiceFailurePenalized,
+        CapabilitiesUpdated,
+        PricingUpdated,
+        EndpointBound,
+        ActiveSetUpdated,
+        StakeAttested,
+        BridgedDelegationCredited,
+        BridgedWithdrawalPaid,
+        StablePaymentReceived,
+        DisputeOpened,
+        DisputeResolved,
+        AccountCollected,
+        ExcessSwept,
+        DelegationRecovered,
+        DelegationReturned,
+        NonceUsed,
+        TotalsRecounted,
+        DepositFunded,
+        StandingOrderCreated,
+        StandingOrderExecuted,
+        StandingOrderCancelled,
+        LockupRenewalUpdated,
+        LockupRenewed,
+        LockupSettled,
+        DelegationListUpdated,
+        WhitelistChangeAnnounced,
+        WithdrawalWhitelistSet,
+        BackupKeySet,
+        RecoveryAnnounced,
+        RecoveryCancelled,
+        OwnershipRecovered,
+        GuardiansSet,
+        GuardianRecoveryApproved,
+        GuardianRecoveryCancelled,
+        PortfolioCreated,
+        PortfolioRebalanced,
+        PoolInitialized,
+        PoolDeposited,
+        PoolWithdrawn,
+        PoolSynced,
+        VaultCreated,
+        VaultClosed,
+        HandleSet,
+        IconUriUpdated,
+        ContactUpdated,
+        SoftwareVersionReported,
+        PositionMigrated,
+        PartnerAdapterUpdated,
+        PartnerPositionImported,
+        TenureCreditClaimed,
+        SignerSetUpdated,
+        OperatorKeySet,
+        DelegationIntentCreated,
+        DelegationIntentExecuted,
+        DelegationIntentCancelled,
+        ExitAnnounced,
+        ExitCancelled,
+        EntryFeeUpdated,
+        EntryFeePaid,
+        EpochSummary,
+        DailyStatsUpdated,
+        SnapshotArchived,
+    );
+
+    // Events logged by this program in a transaction's log messages. Only `Program data:` lines
+    // written while this program is the innermost running program are decoded, so events of
+    // other programs with a colliding discriminator are skipped.
+    pub fn parse_logs(logs: &[String]) -> Vec<ProgramEvent> {
+        let program_id = ID.to_string();
+        let mut stack: Vec<&str> = Vec::new();
+        let mut events = Vec::new();
+
+        for log in logs {
+            if let Some(rest) = log.strip_prefix("Program data: ") {
+                if stack.last() == Some(&program_id.as_str()) {
+                    if let Some(event) = STANDARD
+                        .decode(rest)
+                        .ok()
+                        .and_then(|data| ProgramEvent::decode(&data))
+                    {
+                        events.push(event);
+                    }
+                }
+            } else if let Some(rest) = log.strip_prefix("Program ") {
+                let mut parts = rest.split(' ');
+                match (parts.next(), parts.next()) {
+                    (Some(id), Some("invoke")) => stack.push(id),
+                    (Some(_), Some("success")) | (Some(_), Some("failed:")) => {
+                        stack.pop();
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        events
+    }
+
+    // Event carried by the data of an inner instruction to this program, as emitted by emit_cpi!
+    pub fn parse_cpi_event(ix_data: &[u8]) -> Option<ProgramEvent> {
+        ix_data
+            .strip_prefix(anchor_lang::event::EVENT_IX_TAG_LE)
+            .and_then(ProgramEvent::decode)
+    }
+}
 
 #[program]
 mod staking_contract {
     use super::*;
 
-    pub fn initialize_main(ctx: Context<InitializeMain>) -> Result<()> {
-        let main_account = &mut ctx.accounts.main_account;
-        require!(!main_account.initialized, CustomError::AlreadyInitialized);
-        main_account.initialized = true;
+    pub fn initialize_main(ctx: Context<InitializeMain>, decimals: u8) -> Result<()> {
+        let main_account = &mut ctx.accounts.main_account.load_init()?;
+        require!(main_account.initialized == 0, CustomError::AlreadyInitialized);
+        main_account.initialized = 1;
+        main_account.version = VERSION;
+
+        // The staking token is always the first approved collateral mint
+        let staking_mint = ctx
+            .accounts
+            .config
+            .as_ref()
+            .map_or(SPECIFIED_MINT, |config| config.staking_mint());
+        require!(
+            staking_mint != Pubkey::default(),
+            CustomError::StakingMintNotSet
+        );
+        require_keys_eq!(
+            ctx.accounts.mint.key(),
+            staking_mint,
+            CustomError::InvalidMint
+        );
+        // Checked like the mints approve_mint adds later
+        check_mint_extensions(&ctx.accounts.mint.to_account_info())?;
+        // Stake limits and rates assume the decimals the deployment was configured for
+        require!(
+            ctx.accounts.mint.decimals == decimals,
+            CustomError::MintDecimalsMismatch
+        );
+        main_account.add_mint(MintTotal::new(
+            staking_mint,
+            CollateralClass::Primary,
+            decimals,
+            RATE_PRECISION,
+            Clock::get()?.unix_timestamp,
+        ))?;
 
+        let clock = Clock::get()?;
         emit!(MainAccountInitialized {
+            schema_version: EVENT_SCHEMA_VERSION,
             admin: ctx.accounts.owner.key(),
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
         });
 
         Ok(())
@@ -36,795 +1376,18561 @@ mod staking_contract {
         serverkey: Vec<u8>,
         server_name: String,
         amount: u64,
+        nonce: Option<u64>,
     ) -> Result<()> {
         // Validate input parameters
-        if server_name.len() > 32 {
-            return Err(CustomError::NameTooLong.into());
-        }
+        let server_name = normalize_name(&server_name, &ctx.accounts.config)?;
 
-        if serverkey.len() > 65 {
+        // Registration farms run by other programs have to go through a signed top-level call
+        require!(
+            !ctx.accounts.config.direct_registration
+                || get_stack_height() == TRANSACTION_LEVEL_STACK_HEIGHT,
+            CustomError::CpiNotAllowed
+        );
+
+        if serverkey.len() > MAX_SERVERKEY_LEN {
             return Err(ProgramError::InvalidArgument.into()); // Return error for invalid data length
         }
 
+        check_gate(
+            &ctx.accounts.config,
+            ctx.accounts.gate_account.as_ref(),
+            &ctx.accounts.owner.key(),
+        )?;
+
         // Safe mathematical operations
-        let amount_in_minimum_units = amount
-            .checked_mul(1_000_000_000)
-            .ok_or(CustomError::NumberOverflow)?;
+        let amount_in_minimum_units = to_minimum_units(amount, ctx.accounts.mint.decimals)?;
+        let mint = ctx.accounts.mint.key();
 
-        if amount_in_minimum_units < MINIMUM_STAKE || amount_in_minimum_units > MAXIMUM_STAKE {
+        // The main account is loaded once for both the limit check and the totals
+        let main_account = &mut ctx.accounts.main_account.load_mut()?;
+
+        // Limits are expressed in the staking token, other mints are converted at their oracle rate
+        main_account.check_rate_age(
+            &mint,
+            ctx.accounts.config.max_rate_age_secs,
+            Clock::get()?.unix_timestamp,
+        )?;
+        let value = main_account.to_common_units(&mint, amount_in_minimum_units)?;
+        let minimum = stake_minimum(
+            MINIMUM_STAKE,
+            ctx.accounts.config.min_stake_usd,
+            &ctx.accounts.config,
+            ctx.accounts.price_update.as_ref(),
+        )?;
+        if value < minimum || value > MAXIMUM_STAKE {
             return Err(CustomError::MoreThan1000FewerThan10000.into());
         }
 
-        let main_account = &mut ctx.accounts.main_account;
         let info_account = &mut ctx.accounts.info_account;
 
         // If it's a new account, increase total users and set owner
         if !info_account.initialized {
-            main_account.total_users += 1;
+            check_license(
+                &ctx.accounts.config,
+                ctx.accounts.license_token_account.as_ref(),
+                ctx.accounts.license_metadata.as_ref(),
+                &ctx.accounts.owner.key(),
+            )?;
+            check_registration_cooldown(
+                &ctx.accounts.config,
+                ctx.accounts.registration_record.as_ref(),
+                ctx.program_id,
+            )?;
+            main_account.add_user(true)?;
+            info_account.version = VERSION;
             info_account.owner = ctx.accounts.owner.key(); // Set to caller's public key
-            info_account.name = server_name.clone(); // Store name
-            info_account.serverkey = serverkey.clone();
+            info_account.set_name(&server_name)?; // Store name
+            info_account.set_serverkey(&serverkey);
+            info_account.serverkey_hash = hash(&serverkey).to_bytes(); // Seed of this PDA, see AddServer
+            info_account.bump = ctx.bumps.info_account;
+            info_account.mint = mint; // Collateral mint is fixed for the server
+            info_account.registration_nonce = nonce.unwrap_or(0);
             info_account.initialized = true; // Mark account as initialized
+
+            // Dual-token mode: the bond is posted together with the registration
+            let config = &ctx.accounts.config;
+            if config.bond_mint != Pubkey::default() {
+                let bond_mint = ctx.accounts.bond_mint.as_ref().ok_or(CustomError::BondRequired)?;
+                let bond_sender = ctx
+                    .accounts
+                    .bond_sender_token_account
+                    .as_ref()
+                    .ok_or(CustomError::BondRequired)?;
+                let bond_vault = ctx.accounts.bond_vault.as_ref().ok_or(CustomError::BondRequired)?;
+
+                require_keys_eq!(bond_mint.key(), config.bond_mint, CustomError::InvalidMint);
+                require_keys_eq!(
+                    bond_vault.key(),
+                    get_associated_token_address_with_program_id(
+                        &info_account.key(),
+                        &config.bond_mint,
+                        &ctx.accounts.token_program.key(),
+                    ),
+                    CustomError::InvalidBondVault
+                );
+
+                anchor_spl::token_interface::transfer_checked(
+                    CpiContext::new(
+                        ctx.accounts.token_program.to_account_info(),
+                        TransferChecked {
+                            from: bond_sender.to_account_info(),
+                            mint: bond_mint.to_account_info(),
+                            to: bond_vault.to_account_info(),
+                            authority: ctx.accounts.owner.to_account_info(),
+                        },
+                    ),
+                    config.bond_minimum,
+                    bond_mint.decimals,
+                )?;
+
+                info_account.bond = config.bond_minimum;
+                main_account.add_self_stake(&config.bond_mint, config.bond_minimum)?;
+            }
         } else {
             require!(
                 info_account.owner == ctx.accounts.owner.key(),
                 CustomError::InfoAlreadyInitialized
             );
+            require_keys_eq!(info_account.mint, mint, CustomError::InvalidMint);
+
+            // A wallet resubmitting a registration that already landed, not a top-up
+            if nonce.is_some_and(|nonce| nonce != 0 && nonce == info_account.registration_nonce) {
+                return Ok(());
+            }
         }
 
+        open_vault(
+            &ctx.accounts.vault,
+            info_account.to_account_info(),
+            &ctx.accounts.mint,
+            ctx.accounts.payer.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.associated_token_program.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+        )?;
+
         // Transfer xxx tokens to PDA's TokenAccount
-        anchor_spl::token::transfer(
+        anchor_spl::token_interface::transfer_checked(
             CpiContext::new(
                 ctx.accounts.token_program.to_account_info(),
-                Transfer {
+                TransferChecked {
                     from: ctx.accounts.sender_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
                     to: ctx.accounts.vault.to_account_info(),
                     authority: ctx.accounts.owner.to_account_info(),
                 },
             ),
             amount_in_minimum_units,
+            ctx.accounts.mint.decimals,
         )?;
 
         info_account.stake += amount_in_minimum_units;
         info_account.total += amount_in_minimum_units;
-        main_account.total_stake += amount_in_minimum_units;
+        main_account.add_self_stake(&info_account.mint, amount_in_minimum_units)?;
+        info_account.refresh_stake(main_account)?;
+        info_account.count_deposit(main_account, amount_in_minimum_units)?;
 
         // Record event
-        emit!(ServerAdded {
+        let clock = Clock::get()?;
+        info_account.last_active_epoch = clock.epoch;
+        let event = ServerAdded {
+            schema_version: EVENT_SCHEMA_VERSION,
             owner: ctx.accounts.owner.key(),
-            name: server_name,
+            name: info_account.name,
             amount: amount_in_minimum_units,
-            serverkey: serverkey,
-        });
+            serverkey: info_account.serverkey,
+            serverkey_len: info_account.serverkey_len,
+            total_stake: main_account.total_stake,
+            total_users: main_account.total_users,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        };
+        #[cfg(feature = "event-cpi")]
+        emit_cpi!(event);
+        emit!(event);
 
         Ok(())
     }
 
+    // Register a server with SOL collateral: the lamports are wrapped into the owner's wSOL
+    // account first, so deployments that approved the native mint don't need a separate wrap step
+    pub fn add_server_sol(
+        ctx: Context<AddServer>,
+        serverkey: Vec<u8>,
+        server_name: String,
+        amount: u64,
+        nonce: Option<u64>,
+    ) -> Result<()> {
+        require_keys_eq!(
+            ctx.accounts.mint.key(),
+            native_mint::ID,
+            CustomError::NotNativeMint
+        );
+        let lamports = to_minimum_units(amount, ctx.accounts.mint.decimals)?;
+        wrap_sol(
+            ctx.accounts.owner.to_account_info(),
+            ctx.accounts.sender_token_account.to_account_info(),
+            lamports,
+            ctx.accounts.system_program.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+        )?;
+        add_server(ctx, serverkey, server_name, amount, nonce)
+    }
+
     // Update server name
-    pub fn update_server(ctx: Context<UpdateServer>, new_name: String) -> Result<()> {
-        let info_account = &mut ctx.accounts.info_account;
+    pub fn update_server(ctx: Context<UpdateServerName>, new_name: String) -> Result<()> {
+        let name = normalize_name(&new_name, &ctx.accounts.config)?;
+
+        // Only an actual rename pays config.name_change_fee, resubmitting the same name is free
+        let fee = if name.as_bytes() != ctx.accounts.info_account.name() {
+            ctx.accounts
+                .main_account
+                .load()?
+                .from_common_units(&ctx.accounts.mint.key(), ctx.accounts.config.name_change_fee)?
+        } else {
+            0
+        };
+        if fee > 0 {
+            let owner_token_account = ctx
+                .accounts
+                .owner_token_account
+                .as_ref()
+                .ok_or(CustomError::NameFeeAccountsRequired)?;
+            collect_fee(
+                ctx.accounts.token_program.to_account_info(),
+                owner_token_account.to_account_info(),
+                &ctx.accounts.mint,
+                ctx.accounts.treasury_token_account.as_ref(),
+                ctx.accounts.owner.to_account_info(),
+                &[],
+                fee,
+            )?;
+        }
 
-        info_account.name = new_name.clone();
+        let info_account = &mut ctx.accounts.info_account;
+        info_account.set_name(&name)?;
 
-        emit!(ServerUpdated {
+        let clock = Clock::get()?;
+        let event = ServerUpdated {
+            schema_version: EVENT_SCHEMA_VERSION,
             owner: ctx.accounts.owner.key(),
-            name: new_name,
+            name: info_account.name,
             amount: info_account.stake,
-            serverkey: (*info_account.serverkey.clone()).to_vec(),
-        });
+            serverkey: info_account.serverkey,
+            serverkey_len: info_account.serverkey_len,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        };
+        #[cfg(feature = "event-cpi")]
+        emit_cpi!(event);
+        emit!(event);
 
         Ok(())
     }
 
-    // Remove node
-    pub fn remove_server(ctx: Context<RemoveServer>) -> Result<()> {
-        let main_account = &mut ctx.accounts.main_account;
-        let owner = ctx.accounts.owner.key();
-
-        let seeds = &[
-            INFO_SEED,
-            owner.as_ref(),
-            &hash(ctx.accounts.info_account.serverkey.as_ref()).to_bytes(),
-            &[ctx.bumps.info_account], // Use vault's seeds and bump
-        ];
-
-        anchor_spl::token::close_account(CpiContext::new_with_signer(
-            ctx.accounts.token_program.to_account_info(),
-            CloseAccount {
-                account: ctx.accounts.vault.to_account_info(),
-                destination: ctx.accounts.owner.to_account_info(),
-                authority: ctx.accounts.info_account.to_account_info(),
-            },
-            &[&seeds[..]], // PDA's seeds for signature
-        ))?;
+    // Claim a unique handle such as "tokyo-relay-01.aeronyx" for the server (owner). Unlike the
+    // display name it is first come, first served, see check_handle for the accepted form.
+    pub fn claim_handle(ctx: Context<ClaimHandle>, handle: String) -> Result<()> {
+        check_handle(&handle)?;
 
-        main_account.total_users -= 1;
+        let node_handle = &mut ctx.accounts.node_handle;
+        node_handle.version = VERSION;
+        node_handle.server = ctx.accounts.info_account.key();
+        node_handle.handle_hash = hash(handle.as_bytes()).to_bytes();
+        node_handle.handle[..handle.len()].copy_from_slice(handle.as_bytes());
+        node_handle.handle_len = handle.len() as u8;
+        node_handle.bump = ctx.bumps.node_handle;
 
-        emit!(ServerRemoved {
-            owner,
-            name: ctx.accounts.info_account.name.clone(),
-            serverkey: ctx.accounts.info_account.serverkey.clone(),
+        let clock = Clock::get()?;
+        emit!(HandleSet {
+            schema_version: EVENT_SCHEMA_VERSION,
+            node_handle: node_handle.key(),
+            handle: node_handle.handle,
+            handle_len: node_handle.handle_len,
+            server: node_handle.server,
+            owner: ctx.accounts.owner.key(),
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
         });
+
         Ok(())
     }
 
-    pub fn d_remove(ctx: Context<RemoveDelegatedAccount>) -> Result<()> {
-        let main_account = &mut ctx.accounts.main_account;
-        let info_account = &mut ctx.accounts.info_account;
-        let owner = ctx.accounts.owner.key();
-
-        let binding = info_account.key();
+    // Point the handle at another server. Both owners sign, which is the same key when an
+    // operator moves the handle between its own servers.
+    pub fn transfer_handle(ctx: Context<TransferHandle>) -> Result<()> {
+        let node_handle = &mut ctx.accounts.node_handle;
+        node_handle.server = ctx.accounts.new_info_account.key();
 
-        let seeds = &[
-            INFO_SEED,
-            owner.as_ref(),
-            binding.as_ref(),
-            &[ctx.bumps.delegated_account], // Use vault's seeds and bump
-        ];
+        let clock = Clock::get()?;
+        emit!(HandleSet {
+            schema_version: EVENT_SCHEMA_VERSION,
+            node_handle: node_handle.key(),
+            handle: node_handle.handle,
+            handle_len: node_handle.handle_len,
+            server: node_handle.server,
+            owner: ctx.accounts.new_owner.key(),
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
 
-        anchor_spl::token::close_account(CpiContext::new_with_signer(
-            ctx.accounts.token_program.to_account_info(),
-            CloseAccount {
-                account: ctx.accounts.vault.to_account_info(),
-                destination: ctx.accounts.owner.to_account_info(),
-                authority: ctx.accounts.delegated_account.to_account_info(),
-            },
-            &[&seeds[..]], // PDA's seeds for signature
-        ))?;
+        Ok(())
+    }
 
-        main_account.total_users -= 1;
-        info_account.total_delegators -= 1;
+    // Give the handle up, the rent goes to the signer. That is the server owner, or anyone once
+    // the server has been removed, so handles of closed servers don't stay taken.
+    pub fn release_handle(ctx: Context<ReleaseHandle>) -> Result<()> {
+        let server = &ctx.accounts.info_account;
+        if !server.data_is_empty() {
+            let info_account = Account::<InfoAccount>::try_from(server)?;
+            require_keys_eq!(
+                info_account.owner,
+                ctx.accounts.signer.key(),
+                CustomError::Unauthorized
+            );
+        }
 
-        emit!(DelegatedRemoved {
-            owner,
-            delegator: info_account.key(),
+        let node_handle = &ctx.accounts.node_handle;
+        let clock = Clock::get()?;
+        emit!(HandleSet {
+            schema_version: EVENT_SCHEMA_VERSION,
+            node_handle: node_handle.key(),
+            handle: node_handle.handle,
+            handle_len: node_handle.handle_len,
+            server: Pubkey::default(),
+            owner: ctx.accounts.signer.key(),
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
         });
+
         Ok(())
     }
 
-    // Deposit stake amount
-    pub fn deposit(ctx: Context<Deposit>, amount: u64) -> Result<()> {
-        let main_account = &mut ctx.accounts.main_account;
+    // Raise the minimum delegation accepted by this server, never below the network minimum
+    pub fn set_delegate_minimum(ctx: Context<UpdateServer>, amount: u64) -> Result<()> {
         let info_account = &mut ctx.accounts.info_account;
+        let amount_in_minimum_units =
+            info_account.set_delegate_minimum(amount, ctx.accounts.mint.decimals)?;
+
+        let clock = Clock::get()?;
+        emit!(DelegateMinimumUpdated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: ctx.accounts.owner.key(),
+            server: info_account.key(),
+            amount: amount_in_minimum_units,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
 
-        // require!(amount > 0, CustomError::InsufficientFunds);
+        Ok(())
+    }
 
-        // Safe mathematical operations
-        let amount_in_minimum_units = amount
-            .checked_mul(1_000_000_000)
+    // Start the WHITELIST_CHANGE_DELAY_SECS wait before set_withdrawal_whitelist may replace a
+    // whitelist. A stolen key can announce too, the delay is the owner's time to withdraw to a
+    // whitelisted account first. Announcing again restarts the wait.
+    pub fn announce_whitelist_change(ctx: Context<UpdateServer>) -> Result<()> {
+        let clock = Clock::get()?;
+        let info_account = &mut ctx.accounts.info_account;
+        info_account.whitelist_unlocks_at = clock
+            .unix_timestamp
+            .checked_add(WHITELIST_CHANGE_DELAY_SECS)
             .ok_or(CustomError::NumberOverflow)?;
 
-        // Check if it exceeds the maximum stake limit
+        emit!(WhitelistChangeAnnounced {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: ctx.accounts.owner.key(),
+            server: info_account.key(),
+            unlocks_at: info_account.whitelist_unlocks_at,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Restrict withdraw to these token accounts. Setting the first whitelist takes effect right
+    // away, replacing or clearing one needs an announced change whose delay has passed.
+    pub fn set_withdrawal_whitelist(
+        ctx: Context<UpdateServer>,
+        addresses: Vec<Pubkey>,
+    ) -> Result<()> {
         require!(
-            info_account.stake + amount_in_minimum_units <= MAXIMUM_STAKE,
-            CustomError::ExceedsMaxStakeLimit
+            addresses.len() <= MAX_WITHDRAWAL_ADDRESSES,
+            CustomError::TooManyWithdrawalAddresses
         );
+        let clock = Clock::get()?;
+        let info_account = &mut ctx.accounts.info_account;
+        if info_account.has_withdrawal_whitelist() {
+            require!(
+                info_account.whitelist_unlocks_at != 0
+                    && clock.unix_timestamp >= info_account.whitelist_unlocks_at,
+                CustomError::WhitelistLocked
+            );
+        }
 
-        anchor_spl::token::transfer(
-            CpiContext::new(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.sender_token_account.to_account_info(),
-                    to: ctx.accounts.vault.to_account_info(),
-                    authority: ctx.accounts.owner.to_account_info(),
-                },
-            ),
-            amount_in_minimum_units,
-        )?;
-
-        info_account.stake += amount_in_minimum_units;
-        info_account.total += amount_in_minimum_units;
-        main_account.total_stake += amount_in_minimum_units;
+        let mut whitelist = [Pubkey::default(); MAX_WITHDRAWAL_ADDRESSES];
+        whitelist[..addresses.len()].copy_from_slice(&addresses);
+        info_account.withdrawal_whitelist = whitelist;
+        info_account.whitelist_unlocks_at = 0;
 
-        // Record event
-        emit!(TokenDeposited {
+        emit!(WithdrawalWhitelistSet {
+            schema_version: EVENT_SCHEMA_VERSION,
             owner: ctx.accounts.owner.key(),
-            name: info_account.name.clone(),
-            amount: info_account.stake,
+            server: info_account.key(),
+            addresses,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
         });
 
         Ok(())
     }
 
-    pub fn d_deposit(ctx: Context<DelegatedDeposit>, amount: u64) -> Result<()> {
-        let main_account = &mut ctx.accounts.main_account;
+    // Name a key that can take the server over after RECOVERY_DELAY_SECS if the owner key is
+    // lost, Pubkey::default() removes it. Also cancels a pending recovery.
+    pub fn set_backup_key(ctx: Context<UpdateServer>, backup_key: Pubkey) -> Result<()> {
+        require_keys_neq!(
+            backup_key,
+            ctx.accounts.owner.key(),
+            CustomError::InvalidArgument
+        );
         let info_account = &mut ctx.accounts.info_account;
-        let delegated_account = &mut ctx.accounts.delegated_account;
+        info_account.backup_key = backup_key;
+        info_account.recovery_announced_at = 0;
 
-        if !delegated_account.initialized {
-            main_account.total_users += 1;
-            info_account.total_delegators += 1;
-            delegated_account.owner = ctx.accounts.owner.key();
-            delegated_account.delegator = info_account.key();
-            delegated_account.initialized = true; // Mark account as initialized
-        } else {
-            require!(
-                delegated_account.owner == ctx.accounts.owner.key(),
-                CustomError::DelegateAlreadyInitialized
-            );
-        }
+        let clock = Clock::get()?;
+        emit!(BackupKeySet {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: ctx.accounts.owner.key(),
+            server: info_account.key(),
+            backup_key,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
 
-        // Safe mathematical operations
-        let amount_in_minimum_units = amount
-            .checked_mul(1_000_000_000)
-            .ok_or(CustomError::NumberOverflow)?;
+        Ok(())
+    }
 
-        if amount_in_minimum_units < DELEGATE_MINIMUM_STAKE
-            || delegated_account.stake + amount_in_minimum_units > MAXIMUM_STAKE
-        {
-            return Err(CustomError::DelegateExceedsMaxStakeLimit.into());
-        }
+    // Start a recovery (backup key). The owner can stop it with cancel_recovery until it is
+    // claimed, which is the point of the delay.
+    pub fn announce_recovery(ctx: Context<Recovery>) -> Result<()> {
+        let clock = Clock::get()?;
+        let info_account = &mut ctx.accounts.info_account;
+        info_account.recovery_announced_at = clock.unix_timestamp;
 
-        anchor_spl::token::transfer(
-            CpiContext::new(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.sender_token_account.to_account_info(),
-                    to: ctx.accounts.vault.to_account_info(),
-                    authority: ctx.accounts.owner.to_account_info(),
-                },
-            ),
-            amount_in_minimum_units,
-        )?;
+        emit!(RecoveryAnnounced {
+            schema_version: EVENT_SCHEMA_VERSION,
+            server: info_account.key(),
+            owner: info_account.owner,
+            backup_key: info_account.backup_key,
+            claimable_at: clock
+                .unix_timestamp
+                .checked_add(RECOVERY_DELAY_SECS)
+                .ok_or(CustomError::NumberOverflow)?,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
 
-        delegated_account.stake += amount_in_minimum_units;
-        info_account.total += amount_in_minimum_units;
-        main_account.total_stake += amount_in_minimum_units;
+        Ok(())
+    }
 
-        // Record event
-        emit!(TokenDelegatedDeposited {
+    // Give notice that the server is leaving. From then on it takes no new delegations, and once
+    // config.exit_notice_secs has passed the owner may withdraw below MINIMUM_STAKE while
+    // delegations are still open. Announcing again restarts the notice.
+    pub fn announce_exit(ctx: Context<ServerExit>) -> Result<()> {
+        let clock = Clock::get()?;
+        let info_account = &mut ctx.accounts.info_account;
+        info_account.exit_announced_at = clock.unix_timestamp;
+
+        emit!(ExitAnnounced {
+            schema_version: EVENT_SCHEMA_VERSION,
             owner: ctx.accounts.owner.key(),
-            delegator: info_account.key(),
-            delegator_owner: info_account.owner.key(),
-            amount: info_account.stake,
+            server: info_account.key(),
+            withdrawable_at: clock
+                .unix_timestamp
+                .saturating_add(ctx.accounts.config.exit_notice_secs),
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
         });
 
         Ok(())
     }
 
-    // Withdraw stake amount
-    pub fn withdraw(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
-        let main_account = &mut ctx.accounts.main_account;
+    pub fn cancel_exit(ctx: Context<ServerExit>) -> Result<()> {
         let info_account = &mut ctx.accounts.info_account;
-        let owner = ctx.accounts.owner.key();
+        require!(info_account.exit_announced_at != 0, CustomError::NoExitAnnounced);
+        info_account.exit_announced_at = 0;
+
+        let clock = Clock::get()?;
+        emit!(ExitCancelled {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: ctx.accounts.owner.key(),
+            server: info_account.key(),
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
 
-        let amount_in_minimum_units = amount * 1_000_000_000; // Convert amount to minimum units
+        Ok(())
+    }
 
+    pub fn cancel_recovery(ctx: Context<UpdateServer>) -> Result<()> {
+        let info_account = &mut ctx.accounts.info_account;
         require!(
-            amount_in_minimum_units <= info_account.stake,
-            CustomError::InsufficientFunds
+            info_account.recovery_announced_at != 0,
+            CustomError::NoPendingRecovery
         );
+        info_account.recovery_announced_at = 0;
 
-        let serverkey = &info_account.serverkey;
+        let clock = Clock::get()?;
+        emit!(RecoveryCancelled {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: ctx.accounts.owner.key(),
+            server: info_account.key(),
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
 
-        // Transfer xxx tokens from PDA TokenAccount to user's TokenAccount
-        let seeds = &[
-            INFO_SEED,
-            owner.as_ref(),
-            &hash(serverkey.as_ref()).to_bytes(),
-            &[ctx.bumps.info_account], // Use vault's seeds and bump
-        ];
+        Ok(())
+    }
 
-        anchor_spl::token::transfer(
-            CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.vault.to_account_info(),
-                    to: ctx.accounts.receipt_token_account.to_account_info(),
-                    authority: ctx.accounts.info_account.to_account_info(), // Use vault as authority
-                },
-                &[&seeds[..]], // PDA's seeds
-            ),
-            amount_in_minimum_units,
-        )?;
+    // Make the backup key the owner of the server and its stake once the announced delay has
+    // passed. The withdrawal whitelist is cleared, it may only list accounts of the lost key.
+    pub fn claim_recovery(ctx: Context<Recovery>) -> Result<()> {
+        let clock = Clock::get()?;
+        let info_account = &mut ctx.accounts.info_account;
+        require!(
+            info_account.recovery_announced_at != 0,
+            CustomError::NoPendingRecovery
+        );
+        require!(
+            clock.unix_timestamp
+                >= info_account
+                    .recovery_announced_at
+                    .saturating_add(RECOVERY_DELAY_SECS),
+            CustomError::RecoveryPending
+        );
 
-        ctx.accounts.info_account.stake -= amount_in_minimum_units;
-        ctx.accounts.info_account.total -= amount_in_minimum_units;
-        main_account.total_stake -= amount_in_minimum_units;
+        let previous_owner = info_account.owner;
+        let backup_key = info_account.backup_key;
+        info_account.reassign_owner(backup_key, clock.unix_timestamp);
 
-        // Record event
-        emit!(TokenWithdrawn {
-            owner: ctx.accounts.owner.key(),
-            name: ctx.accounts.info_account.name.clone(),
-            amount: ctx.accounts.info_account.stake,
+        emit!(OwnershipRecovered {
+            schema_version: EVENT_SCHEMA_VERSION,
+            server: info_account.key(),
+            previous_owner,
+            owner: info_account.owner,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
         });
 
         Ok(())
     }
 
-    pub fn d_withdraw(ctx: Context<DelegatedWithdraw>, amount: u64) -> Result<()> {
-        let main_account = &mut ctx.accounts.main_account;
-        let info_account = &mut ctx.accounts.info_account;
-        let delegated_account = &mut ctx.accounts.delegated_account;
+    // Require `threshold` of `signers` on withdrawals and removal, an empty set goes back to the
+    // owner key alone. Replacing a set needs the threshold of the current one. The signatures are
+    // collected from the owner and the signer accounts in remaining_accounts.
+    pub fn set_signer_set(
+        ctx: Context<UpdateServer>,
+        signers: Vec<Pubkey>,
+        threshold: u8,
+    ) -> Result<()> {
         let owner = ctx.accounts.owner.key();
-
-        let amount_in_minimum_units = amount * 1_000_000_000; // Convert amount to minimum units
+        check_signer_set(&ctx.accounts.info_account, &owner, ctx.remaining_accounts)?;
 
         require!(
-            amount_in_minimum_units <= delegated_account.stake,
-            CustomError::InsufficientFunds
+            signers.len() <= MAX_SERVER_SIGNERS
+                && threshold as usize <= signers.len()
+                && (threshold >= 1 || signers.is_empty()),
+            CustomError::InvalidSignerSet
         );
+        for (i, signer) in signers.iter().enumerate() {
+            require!(
+                *signer != Pubkey::default() && !signers[..i].contains(signer),
+                CustomError::InvalidSignerSet
+            );
+        }
 
-        let binding = info_account.key();
+        let info_account = &mut ctx.accounts.info_account;
+        info_account.signers = [Pubkey::default(); MAX_SERVER_SIGNERS];
+        info_account.signers[..signers.len()].copy_from_slice(&signers);
+        info_account.signer_count = signers.len() as u8;
+        info_account.signer_threshold = threshold;
 
-        let seeds = &[
-            INFO_SEED,
-            owner.as_ref(),
-            binding.as_ref(),
-            &[ctx.bumps.delegated_account], // Use vault's seeds and bump
-        ];
+        let clock = Clock::get()?;
+        emit!(SignerSetUpdated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            server: info_account.key(),
+            owner,
+            signers,
+            threshold,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
 
-        anchor_spl::token::transfer(
-            CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.vault.to_account_info(),
-                    to: ctx.accounts.receipt_token_account.to_account_info(),
-                    authority: delegated_account.to_account_info(),
-                },
-                &[&seeds[..]],
-            ),
-            amount_in_minimum_units,
-        )?;
+        Ok(())
+    }
+
+    // Name the guardians that can jointly hand the server to a new owner, `threshold` of them
+    // have to agree on it. Replacing the set drops any approvals in progress.
+    pub fn set_guardians(
+        ctx: Context<SetGuardians>,
+        guardians: Vec<Pubkey>,
+        threshold: u8,
+    ) -> Result<()> {
+        require!(
+            !guardians.is_empty()
+                && guardians.len() <= MAX_GUARDIANS
+                && threshold >= 1
+                && threshold as usize <= guardians.len(),
+            CustomError::InvalidGuardianSet
+        );
+        let owner = ctx.accounts.owner.key();
+        for (i, guardian) in guardians.iter().enumerate() {
+            require!(
+                *guardian != Pubkey::default()
+                    && *guardian != owner
+                    && !guardians[..i].contains(guardian),
+                CustomError::InvalidGuardianSet
+            );
+        }
 
-        info_account.total -= amount_in_minimum_units;
-        delegated_account.stake -= amount_in_minimum_units;
-        main_account.total_stake -= amount_in_minimum_units;
+        let guardian_set = &mut ctx.accounts.guardian_set;
+        guardian_set.version = VERSION;
+        guardian_set.server = ctx.accounts.info_account.key();
+        guardian_set.guardians = [Pubkey::default(); MAX_GUARDIANS];
+        guardian_set.guardians[..guardians.len()].copy_from_slice(&guardians);
+        guardian_set.guardian_count = guardians.len() as u8;
+        guardian_set.threshold = threshold;
+        guardian_set.clear_votes();
+        guardian_set.bump = ctx.bumps.guardian_set;
 
-        // Record event
-        emit!(DelegatedTokenWithdrawn {
-            owner: owner.key(),
-            delegator: info_account.key(),
-            delegator_owner: info_account.owner.key(),
-            amount: delegated_account.stake,
+        let clock = Clock::get()?;
+        emit!(GuardiansSet {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner,
+            server: guardian_set.server,
+            guardians,
+            threshold,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
         });
 
         Ok(())
     }
 
-}
+    // Close the guardian set, which has to happen before remove_server to get the rent back
+    pub fn remove_guardians(ctx: Context<RemoveGuardians>) -> Result<()> {
+        let clock = Clock::get()?;
+        emit!(GuardiansSet {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: ctx.accounts.owner.key(),
+            server: ctx.accounts.info_account.key(),
+            guardians: Vec::new(),
+            threshold: 0,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
 
-#[derive(Accounts)]
-pub struct InitializeMain<'info> {
-    #[account(
-        init,
-        payer = owner,
-        space = 8 + 8 + 4 +1, 
-        seeds = [MAIN_SEED], 
-        bump
-    )]
-    pub main_account: Account<'info, MainAccount>,
-    #[account(mut)]
-    pub owner: Signer<'info>,
-    pub system_program: Program<'info, System>,
-}
+        Ok(())
+    }
 
-#[derive(Accounts)]
-#[instruction(serverkey: Vec<u8>)]
-pub struct AddServer<'info> {
-    #[account(mut)]
-    pub main_account: Account<'info, MainAccount>,
+    // A guardian's vote for `new_owner`, Pubkey::default() withdraws it. Once `threshold`
+    // guardians vote for the same key the GUARDIAN_RECOVERY_DELAY_SECS wait starts, and it
+    // starts over if the votes later settle on a different key.
+    pub fn approve_guardian_recovery(ctx: Context<GuardianVote>, new_owner: Pubkey) -> Result<()> {
+        let clock = Clock::get()?;
+        let guardian_set = &mut ctx.accounts.guardian_set;
+        let index = guardian_set
+            .guardian_index(&ctx.accounts.guardian.key())
+            .ok_or(CustomError::NotAGuardian)?;
+        guardian_set.votes[index] = new_owner;
 
-    // PDA account for storing data
-    #[account(
-        init_if_needed,
-        payer = owner,
-        space = 8 + 1 + 32 + 8 + 4 + 32 + 69,
-        seeds = [
-            INFO_SEED,        // seed prefix
-            owner.key().as_ref(), // Use caller's public key as seed
-            &hash(serverkey.as_ref()).to_bytes(),
-        ],
-        bump
-    )]
-    pub info_account: Account<'info, InfoAccount>, // PDA for storing name
+        if guardian_set.proposed_owner != Pubkey::default()
+            && guardian_set.approvals(&guardian_set.proposed_owner) < guardian_set.threshold
+        {
+            guardian_set.proposed_owner = Pubkey::default();
+            guardian_set.approved_at = 0;
+        }
+        if new_owner != Pubkey::default()
+            && new_owner != guardian_set.proposed_owner
+            && guardian_set.approvals(&new_owner) >= guardian_set.threshold
+        {
+            guardian_set.proposed_owner = new_owner;
+            guardian_set.approved_at = clock.unix_timestamp;
+        }
 
-    // Transfer account
-    #[account(
-        mut,
-        associated_token::mint = mint,
-        associated_token::authority = owner,
-        associated_token::token_program = token_program,
-    )]
-    pub sender_token_account: Account<'info, TokenAccount>,
+        emit!(GuardianRecoveryApproved {
+            schema_version: EVENT_SCHEMA_VERSION,
+            server: guardian_set.server,
+            guardian: ctx.accounts.guardian.key(),
+            new_owner,
+            approvals: guardian_set.approvals(&new_owner),
+            proposed_owner: guardian_set.proposed_owner,
+            approved_at: guardian_set.approved_at,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
 
-    // PDA account for staking in contract
-    #[account(
-        init_if_needed,  
-        payer = owner,
-        associated_token::mint = mint,         // Specified token type
-        associated_token::authority = info_account,         // Manager (can be other account, here is PDA account)
-        associated_token::token_program = token_program,
-    )]
-    pub vault: Account<'info, TokenAccount>,
+        Ok(())
+    }
 
-    // Hardcoded specified token Mint address
-    #[account(
-        address = Pubkey::from_str(SPECIFIED_MINT).unwrap() @ CustomError::InvalidMint
-    )]
-    pub mint: Account<'info, Mint>, // Specified token mint address
+    // Owner veto, clears every guardian's vote
+    pub fn cancel_guardian_recovery(ctx: Context<UpdateGuardians>) -> Result<()> {
+        let guardian_set = &mut ctx.accounts.guardian_set;
+        require!(
+            guardian_set.votes.iter().any(|vote| *vote != Pubkey::default()),
+            CustomError::NoPendingRecovery
+        );
+        guardian_set.clear_votes();
 
-    #[account(mut)]
-    pub owner: Signer<'info>,
+        let clock = Clock::get()?;
+        emit!(GuardianRecoveryCancelled {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: ctx.accounts.owner.key(),
+            server: guardian_set.server,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
 
-    // Token Program
-    pub token_program: Program<'info, Token>,
+        Ok(())
+    }
 
-    // Associated Token Program
-    pub associated_token_program: Program<'info, AssociatedToken>,
+    // Hand the server to the owner the guardians approved, once the wait has passed. Signed by
+    // the new owner so a mistyped key can't end up owning it.
+    pub fn claim_guardian_recovery(ctx: Context<GuardianRecovery>) -> Result<()> {
+        let clock = Clock::get()?;
+        let guardian_set = &mut ctx.accounts.guardian_set;
+        require!(guardian_set.approved_at != 0, CustomError::NoPendingRecovery);
+        require!(
+            clock.unix_timestamp
+                >= guardian_set
+                    .approved_at
+                    .saturating_add(GUARDIAN_RECOVERY_DELAY_SECS),
+            CustomError::RecoveryPending
+        );
+        guardian_set.clear_votes();
 
-    // System Program
-    pub system_program: Program<'info, System>,
-}
+        let info_account = &mut ctx.accounts.info_account;
+        let previous_owner = info_account.owner;
+        info_account.reassign_owner(ctx.accounts.new_owner.key(), clock.unix_timestamp);
 
-#[derive(Accounts)]
-pub struct UpdateServer<'info> {
-    #[account(
-        mut,
-        has_one = owner
-    )]
-    pub info_account: Account<'info, InfoAccount>, // For updating name
-    pub owner: Signer<'info>,
-}
+        emit!(OwnershipRecovered {
+            schema_version: EVENT_SCHEMA_VERSION,
+            server: info_account.key(),
+            previous_owner,
+            owner: info_account.owner,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Operator commission on the revenue share of the server's delegators
+    pub fn set_commission(ctx: Context<UpdateServer>, commission_bps: u16) -> Result<()> {
+        require!(commission_bps <= MAX_BPS, CustomError::InvalidBasisPoints);
+        let info_account = &mut ctx.accounts.info_account;
+        info_account.commission_bps = commission_bps;
+
+        let clock = Clock::get()?;
+        emit!(CommissionUpdated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: ctx.accounts.owner.key(),
+            server: info_account.key(),
+            commission_bps,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // One-off fee on new delegations, taken from each d_deposit and paid to the owner's token
+    // account, as an alternative to or alongside the commission. Applies to later deposits only.
+    pub fn set_entry_fee(ctx: Context<UpdateServer>, entry_fee_bps: u16) -> Result<()> {
+        require!(entry_fee_bps <= MAX_ENTRY_FEE_BPS, CustomError::InvalidBasisPoints);
+        let info_account = &mut ctx.accounts.info_account;
+        info_account.entry_fee_bps = entry_fee_bps;
+
+        let clock = Clock::get()?;
+        emit!(EntryFeeUpdated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: ctx.accounts.owner.key(),
+            server: info_account.key(),
+            entry_fee_bps,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Name the hot key that may run the server day to day (heartbeats, endpoint, pricing, icon and
+    // contact) while the owner key stays offline. Pubkey::default() removes it.
+    pub fn set_operator_key(ctx: Context<UpdateServer>, operator_key: Pubkey) -> Result<()> {
+        let info_account = &mut ctx.accounts.info_account;
+        info_account.operator_key = operator_key;
+
+        let clock = Clock::get()?;
+        emit!(OperatorKeySet {
+            schema_version: EVENT_SCHEMA_VERSION,
+            server: info_account.key(),
+            owner: ctx.accounts.owner.key(),
+            operator_key,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Liveness signal of the server, subscriptions only pay for time covered by heartbeats
+    pub fn heartbeat(ctx: Context<OperateServer>) -> Result<()> {
+        let clock = Clock::get()?;
+        ctx.accounts.info_account.last_heartbeat = clock.unix_timestamp;
+        ctx.accounts.info_account.last_active_epoch = clock.epoch;
+
+        emit!(ServerHeartbeat {
+            schema_version: EVENT_SCHEMA_VERSION,
+            server: ctx.accounts.info_account.key(),
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Post the server's stake as a Wormhole message so programs on other chains can verify it
+    // through the guardians (permissionless). After the first attestation the total must have
+    // moved by config.attestation_threshold_bps since the last one. The payer covers the bridge
+    // fee and the message account rent.
+    pub fn attest_stake(ctx: Context<AttestStake>) -> Result<()> {
+        let config = &ctx.accounts.config;
+        require!(config.wormhole_bridge != Pubkey::default(), CustomError::AttestationsDisabled);
+        let info_account = &mut ctx.accounts.info_account;
+        if info_account.attestations > 0 {
+            let change = info_account.total.abs_diff(info_account.attested_total);
+            let threshold =
+                fee_amount(info_account.attested_total, config.attestation_threshold_bps)?;
+            require!(change > 0 && change >= threshold, CustomError::StakeChangeTooSmall);
+        }
+
+        let clock = Clock::get()?;
+        let attestation = StakeAttestation {
+            version: VERSION,
+            server: info_account.key(),
+            owner: info_account.owner,
+            serverkey_hash: info_account.serverkey_hash,
+            mint: info_account.mint,
+            stake: info_account.stake,
+            delegated: info_account.delegated,
+            total: info_account.total,
+            tier: info_account.tier,
+            epoch: clock.epoch,
+            slot: clock.slot,
+        };
+        let mut payload = Vec::new();
+        attestation.serialize(&mut payload)?;
+
+        // BridgeData: guardian_set_index u32, last_lamports u64, guardian_set_expiration_time
+        // u32, fee u64
+        let fee = {
+            let data = ctx.accounts.wormhole_bridge.try_borrow_data()?;
+            let bytes = data.get(16..24).ok_or(CustomError::InvalidAccountData)?;
+            u64::from_le_bytes(bytes.try_into().unwrap())
+        };
+        if fee > 0 {
+            anchor_lang::system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    anchor_lang::system_program::Transfer {
+                        from: ctx.accounts.payer.to_account_info(),
+                        to: ctx.accounts.wormhole_fee_collector.to_account_info(),
+                    },
+                ),
+                fee,
+            )?;
+        }
+
+        let index = info_account.attestations;
+        let mut data = vec![WORMHOLE_POST_MESSAGE];
+        (index as u32, payload, WORMHOLE_FINALIZED).serialize(&mut data)?;
+        let ix = Instruction {
+            program_id: config.wormhole_bridge,
+            accounts: vec![
+                AccountMeta::new(ctx.accounts.wormhole_bridge.key(), false),
+                AccountMeta::new(ctx.accounts.wormhole_message.key(), true),
+                AccountMeta::new_readonly(ctx.accounts.wormhole_emitter.key(), true),
+                AccountMeta::new(ctx.accounts.wormhole_sequence.key(), false),
+                AccountMeta::new(ctx.accounts.payer.key(), true),
+                AccountMeta::new(ctx.accounts.wormhole_fee_collector.key(), false),
+                AccountMeta::new_readonly(ctx.accounts.clock.key(), false),
+                AccountMeta::new_readonly(ctx.accounts.rent.key(), false),
+                AccountMeta::new_readonly(ctx.accounts.system_program.key(), false),
+            ],
+            data,
+        };
+        let server = info_account.key();
+        let index_bytes = index.to_le_bytes();
+        invoke_signed(
+            &ix,
+            &[
+                ctx.accounts.wormhole_bridge.to_account_info(),
+                ctx.accounts.wormhole_message.to_account_info(),
+                ctx.accounts.wormhole_emitter.to_account_info(),
+                ctx.accounts.wormhole_sequence.to_account_info(),
+                ctx.accounts.payer.to_account_info(),
+                ctx.accounts.wormhole_fee_collector.to_account_info(),
+                ctx.accounts.clock.to_account_info(),
+                ctx.accounts.rent.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+                ctx.accounts.wormhole_program.to_account_info(),
+            ],
+            &[
+                &[
+                    ATTESTATION_SEED,
+                    server.as_ref(),
+                    index_bytes.as_ref(),
+                    &[ctx.bumps.wormhole_message],
+                ],
+                &[EMITTER_SEED, &[ctx.bumps.wormhole_emitter]],
+            ],
+        )?;
+
+        info_account.attested_total = info_account.total;
+        info_account.attestations += 1;
+
+        emit!(StakeAttested {
+            schema_version: EVENT_SCHEMA_VERSION,
+            server,
+            index,
+            message: ctx.accounts.wormhole_message.key(),
+            total: attestation.total,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Advertise the server's network address. The serverkey itself must sign endpoint_message,
+    // see verify_serverkey_signature.
+    pub fn bind_endpoint(
+        ctx: Context<BindEndpoint>,
+        endpoint: String,
+        signature: Vec<u8>,
+    ) -> Result<()> {
+        require!(endpoint.len() <= MAX_ENDPOINT_LEN, CustomError::EndpointTooLong);
+
+        let info_account = &mut ctx.accounts.info_account;
+        verify_serverkey_signature(
+            info_account,
+            ctx.accounts.instructions.as_ref(),
+            &endpoint_message(&info_account.key(), &endpoint),
+            &signature,
+        )?;
+
+        info_account.endpoint = [0; MAX_ENDPOINT_LEN];
+        info_account.endpoint[..endpoint.len()].copy_from_slice(endpoint.as_bytes());
+        info_account.endpoint_len = endpoint.len() as u8;
+
+        let clock = Clock::get()?;
+        emit!(EndpointBound {
+            schema_version: EVENT_SCHEMA_VERSION,
+            server: info_account.key(),
+            endpoint,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Record the software version the node runs. The serverkey signs version_message for the
+    // current epoch, see verify_serverkey_signature, so the report comes from the node itself and
+    // anyone can submit it. Below config.min_software_version the server earns no work credits.
+    pub fn report_version(
+        ctx: Context<ReportVersion>,
+        version: SoftwareVersion,
+        signature: Vec<u8>,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        let info_account = &mut ctx.accounts.info_account;
+        verify_serverkey_signature(
+            info_account,
+            ctx.accounts.instructions.as_ref(),
+            &version_message(&info_account.key(), &version, clock.epoch),
+            &signature,
+        )?;
+
+        info_account.software_version = version;
+        info_account.version_reported_at = clock.unix_timestamp;
+
+        emit!(SoftwareVersionReported {
+            schema_version: EVENT_SCHEMA_VERSION,
+            server: info_account.key(),
+            version,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Publish the server's prices, `per_gb` and `per_hour` in base units of the server's mint
+    pub fn update_pricing(ctx: Context<OperateServer>, per_gb: u64, per_hour: u64) -> Result<()> {
+        let clock = Clock::get()?;
+        let info_account = &mut ctx.accounts.info_account;
+        info_account.pricing = Pricing {
+            per_gb,
+            per_hour,
+            updated_at: clock.unix_timestamp,
+        };
+
+        emit!(PricingUpdated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            server: info_account.key(),
+            mint: info_account.mint,
+            per_gb,
+            per_hour,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Set the server's icon for delegation UIs, an empty `icon_uri` removes it. Servers
+    // registered before this field existed need resize_account first.
+    pub fn set_icon_uri(ctx: Context<OperateServer>, icon_uri: String) -> Result<()> {
+        check_icon_uri(&icon_uri)?;
+
+        let info_account = &mut ctx.accounts.info_account;
+        info_account.icon_uri = [0; MAX_ICON_URI_LEN];
+        info_account.icon_uri[..icon_uri.len()].copy_from_slice(icon_uri.as_bytes());
+        info_account.icon_uri_len = icon_uri.len() as u8;
+
+        let clock = Clock::get()?;
+        emit!(IconUriUpdated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            server: info_account.key(),
+            icon_uri,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Store the operator's contact details, encrypted off-chain to config.contact_key. The bytes
+    // are not inspected, an empty `contact` removes them. Servers registered before this field
+    // existed need resize_account first.
+    pub fn set_contact(ctx: Context<OperateServer>, contact: Vec<u8>) -> Result<()> {
+        require!(contact.len() <= MAX_CONTACT_LEN, CustomError::ContactTooLong);
+
+        let info_account = &mut ctx.accounts.info_account;
+        info_account.contact = [0; MAX_CONTACT_LEN];
+        info_account.contact[..contact.len()].copy_from_slice(&contact);
+        info_account.contact_len = contact.len() as u8;
+
+        let clock = Clock::get()?;
+        emit!(ContactUpdated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            server: info_account.key(),
+            contact_len: info_account.contact_len,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Declare the services the server offers, CAPABILITY_* flags. Each flag requires the server's
+    // total stake, valued in the staking token, to meet its config.capability_minimums entry.
+    pub fn set_capabilities(ctx: Context<SetCapabilities>, capabilities: u8) -> Result<()> {
+        require!(capabilities >> MAX_CAPABILITIES == 0, CustomError::InvalidArgument);
+        let info_account = &mut ctx.accounts.info_account;
+        let value = ctx
+            .accounts
+            .main_account
+            .load()?
+            .to_common_units(&info_account.mint, info_account.total)?;
+        require!(
+            ctx.accounts.config.capabilities_met(value) & capabilities == capabilities,
+            CustomError::CapabilityStakeTooLow
+        );
+        info_account.capabilities = capabilities;
+
+        let clock = Clock::get()?;
+        emit!(CapabilitiesUpdated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: ctx.accounts.owner.key(),
+            server: info_account.key(),
+            capabilities,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Remove node
+    pub fn remove_server(ctx: Context<RemoveServer>) -> Result<()> {
+        check_cpi_origin(&ctx.accounts.config, ctx.accounts.instructions.as_ref())?;
+        check_signer_set(
+            &ctx.accounts.info_account,
+            &ctx.accounts.owner.key(),
+            ctx.remaining_accounts,
+        )?;
+
+        let main_account = &mut ctx.accounts.main_account.load_mut()?;
+        let owner = ctx.accounts.owner.key();
+
+        let seed_owner = ctx.accounts.info_account.seed_owner();
+        let seeds = &[
+            INFO_SEED,
+            seed_owner.as_ref(),
+            ctx.accounts.info_account.serverkey_hash.as_ref(),
+            &[ctx.bumps.info_account], // Use vault's seeds and bump
+        ];
+
+        let clock = Clock::get()?;
+        // Already gone when the last withdrawal closed it
+        if let Some(vault) = &ctx.accounts.vault {
+            anchor_spl::token_interface::close_account(CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                CloseAccount {
+                    account: vault.to_account_info(),
+                    destination: ctx.accounts.owner.to_account_info(),
+                    authority: ctx.accounts.info_account.to_account_info(),
+                },
+                &[&seeds[..]], // PDA's seeds for signature
+            ))?;
+            emit!(VaultClosed {
+                schema_version: EVENT_SCHEMA_VERSION,
+                vault: vault.key(),
+                authority: ctx.accounts.info_account.key(),
+                rent_destination: owner,
+                slot: clock.slot,
+                unix_timestamp: clock.unix_timestamp,
+            });
+        }
+
+        main_account.remove_user(true);
+        main_account.move_stake_bucket(ctx.accounts.info_account.stake_bucket, 0);
+
+        let record = &mut ctx.accounts.registration_record;
+        record.version = VERSION;
+        record.closed_at = clock.unix_timestamp;
+        record.closes += 1;
+        record.bump = ctx.bumps.registration_record;
+
+        let event = ServerRemoved {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner,
+            name: ctx.accounts.info_account.name,
+            serverkey: ctx.accounts.info_account.serverkey,
+            serverkey_len: ctx.accounts.info_account.serverkey_len,
+            total_stake: main_account.total_stake,
+            total_users: main_account.total_users,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        };
+        #[cfg(feature = "event-cpi")]
+        emit_cpi!(event);
+        emit!(event);
+        Ok(())
+    }
+
+    pub fn d_remove(ctx: Context<RemoveDelegatedAccount>) -> Result<()> {
+        let main_account = &mut ctx.accounts.main_account.load_mut()?;
+        let info_account = &mut ctx.accounts.info_account;
+        let owner = ctx.accounts.owner.key();
+
+        let binding = info_account.key();
+
+        let seeds = &[
+            INFO_SEED,
+            owner.as_ref(),
+            binding.as_ref(),
+            &[ctx.bumps.delegated_account], // Use vault's seeds and bump
+        ];
+
+        let clock = Clock::get()?;
+        // Already gone when the last withdrawal closed it
+        if let Some(vault) = &ctx.accounts.vault {
+            anchor_spl::token_interface::close_account(CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                CloseAccount {
+                    account: vault.to_account_info(),
+                    destination: ctx.accounts.owner.to_account_info(),
+                    authority: ctx.accounts.delegated_account.to_account_info(),
+                },
+                &[&seeds[..]], // PDA's seeds for signature
+            ))?;
+            emit!(VaultClosed {
+                schema_version: EVENT_SCHEMA_VERSION,
+                vault: vault.key(),
+                authority: ctx.accounts.delegated_account.key(),
+                rent_destination: owner,
+                slot: clock.slot,
+                unix_timestamp: clock.unix_timestamp,
+            });
+        }
+
+        main_account.remove_user(false);
+        info_account.remove_delegator();
+
+        let event = DelegatedRemoved {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner,
+            delegator: info_account.key(),
+            total_stake: main_account.total_stake,
+            total_users: main_account.total_users,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        };
+        #[cfg(feature = "event-cpi")]
+        emit_cpi!(event);
+        emit!(event);
+        Ok(())
+    }
+
+    // Return a delegation to its owner so the server can be removed (server owner): the whole
+    // stake and the pending rewards go to the delegator's wallet, the position and its vault are
+    // closed with the rent going to the delegator. Locked delegations have to run out their
+    // lockup first. Positions with receipts outstanding or unclaimed referral rewards can't be
+    // returned this way, their owner or referrer has to act first.
+    pub fn force_return_delegation(ctx: Context<ForceReturnDelegation>) -> Result<()> {
+        let clock = Clock::get()?;
+        let accounts = &mut ctx.accounts;
+        let (amount, rewards) = return_position(
+            &accounts.main_account,
+            ctx.bumps.main_account,
+            &mut accounts.info_account,
+            &mut accounts.delegated_account,
+            &mut accounts.vault,
+            &accounts.reward_vault,
+            accounts.delegation_owner.to_account_info(),
+            &accounts.delegation_owner_token_account,
+            &accounts.mint,
+            accounts.token_program.to_account_info(),
+            &clock,
+        )?;
+
+        let main_account = ctx.accounts.main_account.load()?;
+        let event = DelegationReturned {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: ctx.accounts.delegation_owner.key(),
+            server: ctx.accounts.info_account.key(),
+            amount,
+            rewards,
+            total_stake: main_account.total_stake,
+            total_users: main_account.total_users,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        };
+        #[cfg(feature = "event-cpi")]
+        emit_cpi!(event);
+        emit!(event);
+        Ok(())
+    }
+
+    // Same as force_return_delegation for anyone once the server's exit notice has run out, so a
+    // leaving operator's closure doesn't wait on every delegator. Lockups still hold. The caller
+    // pays for the delegator's token account when it doesn't exist.
+    pub fn return_delegation(ctx: Context<ReturnDelegation>) -> Result<()> {
+        let clock = Clock::get()?;
+        let accounts = &mut ctx.accounts;
+        let announced_at = accounts.info_account.exit_announced_at;
+        require!(announced_at != 0, CustomError::NoExitAnnounced);
+        require!(
+            clock.unix_timestamp >= announced_at.saturating_add(accounts.config.exit_notice_secs),
+            CustomError::ExitNoticeRequired
+        );
+
+        let (amount, rewards) = return_position(
+            &accounts.main_account,
+            ctx.bumps.main_account,
+            &mut accounts.info_account,
+            &mut accounts.delegated_account,
+            &mut accounts.vault,
+            &accounts.reward_vault,
+            accounts.delegation_owner.to_account_info(),
+            &accounts.delegation_owner_token_account,
+            &accounts.mint,
+            accounts.token_program.to_account_info(),
+            &clock,
+        )?;
+
+        let main_account = ctx.accounts.main_account.load()?;
+        let event = DelegationReturned {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: ctx.accounts.delegation_owner.key(),
+            server: ctx.accounts.info_account.key(),
+            amount,
+            rewards,
+            total_stake: main_account.total_stake,
+            total_users: main_account.total_users,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        };
+        #[cfg(feature = "event-cpi")]
+        emit_cpi!(event);
+        emit!(event);
+        Ok(())
+    }
+
+    // Close an abandoned server or delegation account (permissionless): the delegation when one
+    // is passed, the server otherwise. The account must hold nothing and have been idle for
+    // config.gc_inactive_epochs. The caller gets config.gc_reward_bps of its rent, the owner the
+    // rest and the vault rent.
+    pub fn gc_account(ctx: Context<GcAccount>) -> Result<()> {
+        let config = &ctx.accounts.config;
+        require!(config.gc_inactive_epochs > 0, CustomError::GcDisabled);
+
+        let main_account = &mut ctx.accounts.main_account.load_mut()?;
+        let info_account = &mut ctx.accounts.info_account;
+        let server = info_account.key();
+        let clock = Clock::get()?;
+
+        let (account, owner, reward) = match &ctx.accounts.delegated_account {
+            Some(delegated_account) => {
+                require!(
+                    delegated_account.stake == 0
+                        && delegated_account.activating_stake == 0
+                        && delegated_account.pending_rewards == 0
+                        && delegated_account.referral_pending == 0
+                        && delegated_account.receipt_minted == 0,
+                    CustomError::NonZeroBalance
+                );
+                // Deposits and flags are the only activity a delegation records
+                let last_active = delegated_account
+                    .activation_epoch
+                    .max(delegated_account.last_flag_epoch.unwrap_or(0));
+                require!(
+                    clock.epoch >= last_active.saturating_add(config.gc_inactive_epochs),
+                    CustomError::AccountStillActive
+                );
+                require!(
+                    !info_account.lists(&delegated_account.key(), delegated_account),
+                    CustomError::DelegationLinked
+                );
+                require_keys_eq!(
+                    ctx.accounts.owner.key(),
+                    delegated_account.owner,
+                    CustomError::Unauthorized
+                );
+
+                let owner = delegated_account.owner;
+                let seeds = &[
+                    INFO_SEED,
+                    owner.as_ref(),
+                    server.as_ref(),
+                    &[delegated_account.bump],
+                ];
+                let reward = collect_rent(
+                    delegated_account.to_account_info(),
+                    &ctx.accounts.vault,
+                    &info_account.mint,
+                    ctx.accounts.owner.to_account_info(),
+                    ctx.accounts.caller.to_account_info(),
+                    ctx.accounts.token_program.to_account_info(),
+                    &[&seeds[..]],
+                    config.gc_reward_bps,
+                )?;
+                delegated_account.close(ctx.accounts.owner.to_account_info())?;
+
+                main_account.remove_user(false);
+                info_account.remove_delegator();
+                (delegated_account.key(), owner, reward)
+            }
+            None => {
+                require!(
+                    info_account.total == 0
+                        && info_account.stake == 0
+                        && info_account.activating_stake == 0
+                        && info_account.total_delegators == 0
+                        && info_account.restaked == 0
+                        && info_account.bond == 0
+                        && info_account.reward_dust == 0
+                        && info_account.revenue_unreleased == 0
+                        && info_account.revenue_released == 0
+                        && info_account.vesting_amount == info_account.vesting_claimed
+                        && info_account.revenue_disputed == 0,
+                    CustomError::NonZeroBalance
+                );
+                require!(
+                    clock.epoch
+                        >= info_account.last_active_epoch.saturating_add(config.gc_inactive_epochs),
+                    CustomError::AccountStillActive
+                );
+                require_keys_eq!(
+                    ctx.accounts.owner.key(),
+                    info_account.owner,
+                    CustomError::Unauthorized
+                );
+
+                let owner = info_account.owner;
+                let seed_owner = info_account.seed_owner();
+                let seeds = &[
+                    INFO_SEED,
+                    seed_owner.as_ref(),
+                    info_account.serverkey_hash.as_ref(),
+                    &[info_account.bump],
+                ];
+                let reward = collect_rent(
+                    info_account.to_account_info(),
+                    &ctx.accounts.vault,
+                    &info_account.mint,
+                    ctx.accounts.owner.to_account_info(),
+                    ctx.accounts.caller.to_account_info(),
+                    ctx.accounts.token_program.to_account_info(),
+                    &[&seeds[..]],
+                    config.gc_reward_bps,
+                )?;
+                info_account.close(ctx.accounts.owner.to_account_info())?;
+
+                main_account.remove_user(true);
+                main_account.move_stake_bucket(info_account.stake_bucket, 0);
+                (server, owner, reward)
+            }
+        };
+
+        let event = AccountCollected {
+            schema_version: EVENT_SCHEMA_VERSION,
+            account,
+            owner,
+            caller: ctx.accounts.caller.key(),
+            reward,
+            total_stake: main_account.total_stake,
+            total_users: main_account.total_users,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        };
+        #[cfg(feature = "event-cpi")]
+        emit_cpi!(event);
+        emit!(event);
+        Ok(())
+    }
+
+    // Exit a delegation whose server account is closed or replaced, using only the
+    // delegation and its vault. The whole vault goes back to the owner and the position is
+    // closed, rewards still pending on it are lost with the server's reward vault.
+    pub fn recover_delegation(ctx: Context<RecoverDelegation>) -> Result<()> {
+        require!(
+            server_orphaned(&ctx.accounts.info_account, ctx.program_id)?,
+            CustomError::ServerNotOrphaned
+        );
+
+        let delegated_account = &mut ctx.accounts.delegated_account;
+        let owner = ctx.accounts.owner.key();
+        let server = ctx.accounts.info_account.key();
+        let seeds = &[
+            INFO_SEED,
+            owner.as_ref(),
+            server.as_ref(),
+            &[delegated_account.bump],
+        ];
+
+        // Receipts are a claim on this stake as well, they have to come back first
+        if delegated_account.receipt_minted > 0 {
+            let receipt_mint = ctx
+                .accounts
+                .receipt_mint
+                .as_ref()
+                .ok_or(CustomError::ReceiptAccountsRequired)?;
+            let receipt_holder = ctx
+                .accounts
+                .receipt_holder_account
+                .as_ref()
+                .ok_or(CustomError::ReceiptAccountsRequired)?;
+            require_keys_eq!(
+                receipt_mint.key(),
+                receipt_mint_address(&ctx.accounts.mint.key(), ctx.program_id),
+                CustomError::InvalidReceiptMint
+            );
+            anchor_spl::token_interface::burn(
+                CpiContext::new(
+                    ctx.accounts.token_program.to_account_info(),
+                    Burn {
+                        mint: receipt_mint.to_account_info(),
+                        from: receipt_holder.to_account_info(),
+                        authority: ctx.accounts.owner.to_account_info(),
+                    },
+                ),
+                delegated_account.receipt_minted,
+            )?;
+            delegated_account.receipt_minted = 0;
+        }
+
+        // Already gone when the last withdrawal closed it
+        let mut amount = 0;
+        if let Some(vault) = &mut ctx.accounts.vault {
+            amount = vault.amount;
+            if amount > 0 {
+                anchor_spl::token_interface::transfer_checked(
+                    CpiContext::new_with_signer(
+                        ctx.accounts.token_program.to_account_info(),
+                        TransferChecked {
+                            from: vault.to_account_info(),
+                            mint: ctx.accounts.mint.to_account_info(),
+                            to: ctx.accounts.receipt_token_account.to_account_info(),
+                            authority: delegated_account.to_account_info(),
+                        },
+                        &[&seeds[..]],
+                    ),
+                    amount,
+                    ctx.accounts.mint.decimals,
+                )?;
+            }
+            close_empty_vault(
+                vault,
+                ctx.accounts.owner.to_account_info(),
+                delegated_account.to_account_info(),
+                ctx.accounts.token_program.to_account_info(),
+                &[&seeds[..]],
+            )?;
+        }
+
+        // Queued stake never reached the totals, the active stake did
+        let main_account = &mut ctx.accounts.main_account.load_mut()?;
+        main_account.sub_stake(&delegated_account.mint, delegated_account.stake)?;
+        main_account.count_withdrawal(&delegated_account.mint, amount)?;
+        main_account.remove_user(false);
+
+        let clock = Clock::get()?;
+        let event = DelegationRecovered {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner,
+            server,
+            amount,
+            forfeited_rewards: delegated_account.pending_rewards,
+            total_stake: main_account.total_stake,
+            total_users: main_account.total_users,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        };
+        #[cfg(feature = "event-cpi")]
+        emit_cpi!(event);
+        emit!(event);
+        Ok(())
+    }
+
+    // Deposit stake amount
+    pub fn deposit(ctx: Context<Deposit>, amount: u64) -> Result<()> {
+        // Safe mathematical operations
+        let amount_in_minimum_units = to_minimum_units(amount, ctx.accounts.mint.decimals)?;
+        deposit_raw(ctx, amount_in_minimum_units)
+    }
+
+    // Add SOL collateral (in lamports) to a server whose mint is wSOL
+    pub fn deposit_sol(ctx: Context<Deposit>, lamports: u64) -> Result<()> {
+        require_keys_eq!(
+            ctx.accounts.mint.key(),
+            native_mint::ID,
+            CustomError::NotNativeMint
+        );
+        wrap_sol(
+            ctx.accounts.owner.to_account_info(),
+            ctx.accounts.sender_token_account.to_account_info(),
+            lamports,
+            ctx.accounts.system_program.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+        )?;
+        deposit_raw(ctx, lamports)
+    }
+
+    // Same as deposit, with the amount already in base units of the mint
+    pub fn deposit_raw(ctx: Context<Deposit>, amount_in_minimum_units: u64) -> Result<()> {
+        let event = deposit_stake(ctx.accounts, amount_in_minimum_units)?;
+        #[cfg(feature = "event-cpi")]
+        emit_cpi!(event);
+        emit!(event);
+
+        Ok(())
+    }
+
+    // Routine server maintenance in one instruction: runs up to MAX_BATCH_OPS operations in
+    // order, each with the checks and the event of its standalone instruction
+    pub fn batch(ctx: Context<Deposit>, ops: Vec<BatchOp>) -> Result<()> {
+        require!(
+            !ops.is_empty() && ops.len() <= MAX_BATCH_OPS,
+            CustomError::InvalidBatchSize
+        );
+
+        for op in ops {
+            match op {
+                BatchOp::Deposit { amount } => {
+                    let amount_in_minimum_units =
+                        to_minimum_units(amount, ctx.accounts.mint.decimals)?;
+                    let event = deposit_stake(ctx.accounts, amount_in_minimum_units)?;
+                    #[cfg(feature = "event-cpi")]
+                    emit_cpi!(event);
+                    emit!(event);
+                }
+                BatchOp::UpdateName { name } => {
+                    // Batches carry no treasury accounts, paid renames go through update_server
+                    require!(
+                        ctx.accounts.config.name_change_fee == 0,
+                        CustomError::NameFeeAccountsRequired
+                    );
+                    let info_account = &mut ctx.accounts.info_account;
+                    info_account.set_name(&normalize_name(&name, &ctx.accounts.config)?)?;
+
+                    let clock = Clock::get()?;
+                    let event = ServerUpdated {
+                        schema_version: EVENT_SCHEMA_VERSION,
+                        owner: ctx.accounts.owner.key(),
+                        name: info_account.name,
+                        amount: info_account.stake,
+                        serverkey: info_account.serverkey,
+                        serverkey_len: info_account.serverkey_len,
+                        slot: clock.slot,
+                        unix_timestamp: clock.unix_timestamp,
+                    };
+                    #[cfg(feature = "event-cpi")]
+                    emit_cpi!(event);
+                    emit!(event);
+                }
+                BatchOp::SetDelegateMinimum { amount } => {
+                    let info_account = &mut ctx.accounts.info_account;
+                    let amount_in_minimum_units = info_account
+                        .set_delegate_minimum(amount, ctx.accounts.mint.decimals)?;
+
+                    let clock = Clock::get()?;
+                    emit!(DelegateMinimumUpdated {
+                        schema_version: EVENT_SCHEMA_VERSION,
+                        owner: ctx.accounts.owner.key(),
+                        server: info_account.key(),
+                        amount: amount_in_minimum_units,
+                        slot: clock.slot,
+                        unix_timestamp: clock.unix_timestamp,
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Deposit submitted and paid for by a relayer, authorized by the owner's signature over
+    // `permit_message` in an Ed25519 program instruction placed right before this one. The owner
+    // approves the permit authority PDA as delegate of its token account once, with the usual SPL
+    // approve, and the tokens are drawn through that allowance. Each permit carries the server's
+    // next nonce and an expiry (unix timestamp), so it can be used once and only in time.
+    pub fn deposit_with_permit(
+        ctx: Context<DepositWithPermit>,
+        amount: u64,
+        nonce: u64,
+        expiry: i64,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        require!(clock.unix_timestamp <= expiry, CustomError::PermitExpired);
+
+        let owner = ctx.accounts.owner.key();
+        let server = ctx.accounts.info_account.key();
+        verify_ed25519_ix(
+            &ctx.accounts.instructions,
+            &owner,
+            &permit_message(&server, amount, nonce, expiry),
+        )?;
+        consume_nonce(server, owner, &mut ctx.accounts.info_account.nonce, nonce)?;
+
+        check_gate(
+            &ctx.accounts.config,
+            ctx.accounts.gate_account.as_ref(),
+            &owner,
+        )?;
+
+        let main_account = &mut ctx.accounts.main_account.load_mut()?;
+        let info_account = &mut ctx.accounts.info_account;
+        let amount_in_minimum_units = to_minimum_units(amount, ctx.accounts.mint.decimals)?;
+        check_deposit(
+            main_account,
+            info_account,
+            &ctx.accounts.config,
+            amount_in_minimum_units,
+        )?;
+
+        open_vault(
+            &ctx.accounts.vault,
+            info_account.to_account_info(),
+            &ctx.accounts.mint,
+            ctx.accounts.relayer.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.associated_token_program.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+        )?;
+        let seeds = &[PERMIT_SEED, &[ctx.bumps.permit_authority]];
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.sender_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.vault.to_account_info(),
+                    authority: ctx.accounts.permit_authority.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            amount_in_minimum_units,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        // The instruction before this one is the Ed25519 permit, there is no room for a memo
+        let event = credit_self_stake(
+            main_account,
+            info_account,
+            owner,
+            amount_in_minimum_units,
+            [0; MAX_MEMO_LEN],
+        )?;
+        #[cfg(feature = "event-cpi")]
+        emit_cpi!(event);
+        emit!(event);
+
+        Ok(())
+    }
+
+    // Fund the self-stake of `beneficiary`'s server, for example from a treasury or an employer.
+    // The tokens come from the funder, the stake belongs to the server owner as with deposit.
+    pub fn deposit_for(ctx: Context<DepositFor>, beneficiary: Pubkey, amount: u64) -> Result<()> {
+        check_gate(
+            &ctx.accounts.config,
+            ctx.accounts.gate_account.as_ref(),
+            &beneficiary,
+        )?;
+
+        let main_account = &mut ctx.accounts.main_account.load_mut()?;
+        let info_account = &mut ctx.accounts.info_account;
+        let amount_in_minimum_units = to_minimum_units(amount, ctx.accounts.mint.decimals)?;
+        check_deposit(
+            main_account,
+            info_account,
+            &ctx.accounts.config,
+            amount_in_minimum_units,
+        )?;
+
+        open_vault(
+            &ctx.accounts.vault,
+            info_account.to_account_info(),
+            &ctx.accounts.mint,
+            ctx.accounts.funder.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.associated_token_program.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+        )?;
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.sender_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.vault.to_account_info(),
+                    authority: ctx.accounts.funder.to_account_info(),
+                },
+            ),
+            amount_in_minimum_units,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        let event = credit_self_stake(
+            main_account,
+            info_account,
+            beneficiary,
+            amount_in_minimum_units,
+            transaction_memo(ctx.accounts.instructions.as_ref())?,
+        )?;
+        #[cfg(feature = "event-cpi")]
+        emit_cpi!(event);
+        emit!(event);
+        emit!(DepositFunded {
+            schema_version: EVENT_SCHEMA_VERSION,
+            funder: ctx.accounts.funder.key(),
+            beneficiary,
+            account: info_account.key(),
+            amount: amount_in_minimum_units,
+            slot: event.slot,
+            unix_timestamp: event.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn d_deposit(
+        ctx: Context<DelegatedDeposit>,
+        amount: u64,
+        referrer: Option<Pubkey>,
+        lockup: LockupTier,
+    ) -> Result<()> {
+        // Safe mathematical operations
+        let amount_in_minimum_units = to_minimum_units(amount, ctx.accounts.mint.decimals)?;
+        d_deposit_raw(ctx, amount_in_minimum_units, referrer, lockup)
+    }
+
+    // Same as d_deposit, with the amount already in base units of the mint
+    pub fn d_deposit_raw(
+        ctx: Context<DelegatedDeposit>,
+        amount_in_minimum_units: u64,
+        referrer: Option<Pubkey>,
+        lockup: LockupTier,
+    ) -> Result<()> {
+        log_compute_units(&ctx.accounts.config, "d_deposit: start");
+        check_gate(
+            &ctx.accounts.config,
+            ctx.accounts.gate_account.as_ref(),
+            &ctx.accounts.owner.key(),
+        )?;
+
+        let clock = Clock::get()?;
+        let main_account = &mut ctx.accounts.main_account.load_mut()?;
+        let info_account = &mut ctx.accounts.info_account;
+        let delegated_account = &mut ctx.accounts.delegated_account;
+
+        // The entry fee comes off the deposit, only the rest is staked
+        let entry_fee = fee_amount(amount_in_minimum_units, info_account.entry_fee_bps)?;
+        let amount_in_minimum_units = amount_in_minimum_units - entry_fee;
+
+        let created = record_delegation(
+            main_account,
+            info_account,
+            delegated_account,
+            &ctx.accounts.config,
+            ctx.accounts.price_update.as_ref(),
+            ctx.accounts.owner.key(),
+            amount_in_minimum_units,
+            ctx.accounts.mint.decimals,
+            lockup,
+            &clock,
+        )?;
+
+        // The referrer is fixed on the first deposit, together with the share in force at that time
+        if created {
+            delegated_account.bump = ctx.bumps.delegated_account;
+            if let Some(referrer) = referrer {
+                require!(
+                    referrer != ctx.accounts.owner.key(),
+                    CustomError::InvalidReferrer
+                );
+                delegated_account.referrer = referrer;
+                delegated_account.referral_bps = ctx.accounts.config.referral_bps;
+            }
+        }
+
+        log_compute_units(&ctx.accounts.config, "d_deposit: recorded");
+
+        open_vault(
+            &ctx.accounts.vault,
+            delegated_account.to_account_info(),
+            &ctx.accounts.mint,
+            ctx.accounts.payer.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.associated_token_program.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+        )?;
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.sender_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.vault.to_account_info(),
+                    authority: ctx.accounts.owner.to_account_info(),
+                },
+            ),
+            amount_in_minimum_units,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        if entry_fee > 0 {
+            let operator_token_account = ctx
+                .accounts
+                .operator_token_account
+                .as_ref()
+                .ok_or(CustomError::OperatorTokenAccountRequired)?;
+            anchor_spl::token_interface::transfer_checked(
+                CpiContext::new(
+                    ctx.accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.sender_token_account.to_account_info(),
+                        mint: ctx.accounts.mint.to_account_info(),
+                        to: operator_token_account.to_account_info(),
+                        authority: ctx.accounts.owner.to_account_info(),
+                    },
+                ),
+                entry_fee,
+                ctx.accounts.mint.decimals,
+            )?;
+            info_account.entry_fees_collected = info_account
+                .entry_fees_collected
+                .checked_add(entry_fee)
+                .ok_or(CustomError::NumberOverflow)?;
+
+            emit!(EntryFeePaid {
+                schema_version: EVENT_SCHEMA_VERSION,
+                owner: ctx.accounts.owner.key(),
+                server: info_account.key(),
+                fee: entry_fee,
+                entry_fee_bps: info_account.entry_fee_bps,
+                slot: clock.slot,
+                unix_timestamp: clock.unix_timestamp,
+            });
+        }
+
+        if ctx.accounts.config.receipt_tokens {
+            mint_receipt(
+                ctx.accounts.receipt_mint.as_ref(),
+                ctx.accounts.receipt_holder_account.as_ref(),
+                &ctx.accounts.mint.key(),
+                ctx.accounts.token_program.to_account_info(),
+                ctx.program_id,
+                amount_in_minimum_units,
+            )?;
+            ctx.accounts.delegated_account.receipt_minted += amount_in_minimum_units;
+        }
+
+        log_compute_units(&ctx.accounts.config, "d_deposit: transferred");
+
+        // Record event
+        let event = TokenDelegatedDeposited {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: ctx.accounts.owner.key(),
+            delegator: info_account.key(),
+            delegator_owner: info_account.owner.key(),
+            amount: amount_in_minimum_units,
+            stake: ctx.accounts.delegated_account.stake,
+            activating_stake: ctx.accounts.delegated_account.activating_stake,
+            server_total: info_account.total,
+            server_seq: info_account.next_event_seq(),
+            memo: transaction_memo(ctx.accounts.instructions.as_ref())?,
+            total_stake: main_account.total_stake,
+            total_users: main_account.total_users,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        };
+        #[cfg(feature = "event-cpi")]
+        emit_cpi!(event);
+        emit!(event);
+
+        Ok(())
+    }
+
+    // Delegate on behalf of `beneficiary`: the funder pays the tokens and any rent, the position
+    // and its receipt tokens belong to the beneficiary, who alone can withdraw. No referrer is
+    // recorded, that choice is left to the beneficiary's own first deposit.
+    pub fn d_deposit_for(
+        ctx: Context<DelegatedDepositFor>,
+        beneficiary: Pubkey,
+        amount: u64,
+        lockup: LockupTier,
+    ) -> Result<()> {
+        check_gate(
+            &ctx.accounts.config,
+            ctx.accounts.gate_account.as_ref(),
+            &beneficiary,
+        )?;
+
+        let clock = Clock::get()?;
+        let amount_in_minimum_units = to_minimum_units(amount, ctx.accounts.mint.decimals)?;
+        let main_account = &mut ctx.accounts.main_account.load_mut()?;
+        let info_account = &mut ctx.accounts.info_account;
+        let delegated_account = &mut ctx.accounts.delegated_account;
+
+        let created = record_delegation(
+            main_account,
+            info_account,
+            delegated_account,
+            &ctx.accounts.config,
+            ctx.accounts.price_update.as_ref(),
+            beneficiary,
+            amount_in_minimum_units,
+            ctx.accounts.mint.decimals,
+            lockup,
+            &clock,
+        )?;
+        if created {
+            delegated_account.bump = ctx.bumps.delegated_account;
+        }
+
+        open_vault(
+            &ctx.accounts.vault,
+            delegated_account.to_account_info(),
+            &ctx.accounts.mint,
+            ctx.accounts.funder.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.associated_token_program.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+        )?;
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.sender_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.vault.to_account_info(),
+                    authority: ctx.accounts.funder.to_account_info(),
+                },
+            ),
+            amount_in_minimum_units,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        if ctx.accounts.config.receipt_tokens {
+            mint_receipt(
+                ctx.accounts.receipt_mint.as_ref(),
+                ctx.accounts.receipt_holder_account.as_ref(),
+                &ctx.accounts.mint.key(),
+                ctx.accounts.token_program.to_account_info(),
+                ctx.program_id,
+                amount_in_minimum_units,
+            )?;
+            ctx.accounts.delegated_account.receipt_minted += amount_in_minimum_units;
+        }
+
+        let event = TokenDelegatedDeposited {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: beneficiary,
+            delegator: info_account.key(),
+            delegator_owner: info_account.owner.key(),
+            amount: amount_in_minimum_units,
+            stake: ctx.accounts.delegated_account.stake,
+            activating_stake: ctx.accounts.delegated_account.activating_stake,
+            server_total: info_account.total,
+            server_seq: info_account.next_event_seq(),
+            memo: transaction_memo(ctx.accounts.instructions.as_ref())?,
+            total_stake: main_account.total_stake,
+            total_users: main_account.total_users,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        };
+        #[cfg(feature = "event-cpi")]
+        emit_cpi!(event);
+        emit!(event);
+        emit!(DepositFunded {
+            schema_version: EVENT_SCHEMA_VERSION,
+            funder: ctx.accounts.funder.key(),
+            beneficiary,
+            account: ctx.accounts.delegated_account.key(),
+            amount: amount_in_minimum_units,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Set up a recurring delegation of `amount` whole tokens every `interval_secs` into an
+    // existing position, drawn from `funding_account` through an SPL approval of the order PDA.
+    // The first execution is due right away. Recreating a cancelled order is allowed.
+    pub fn create_standing_order(
+        ctx: Context<CreateStandingOrder>,
+        amount: u64,
+        interval_secs: i64,
+        lockup: LockupTier,
+    ) -> Result<()> {
+        require!(amount > 0, CustomError::InvalidArgument);
+        require!(interval_secs > 0, CustomError::InvalidArgument);
+
+        let clock = Clock::get()?;
+        let order = &mut ctx.accounts.standing_order;
+        order.version = VERSION;
+        order.owner = ctx.accounts.owner.key();
+        order.server = ctx.accounts.info_account.key();
+        order.funding_account = ctx.accounts.funding_account.key();
+        order.amount = to_minimum_units(amount, ctx.accounts.mint.decimals)?;
+        order.interval_secs = interval_secs;
+        order.next_execution = clock.unix_timestamp;
+        order.lockup = lockup;
+        order.executions = 0;
+        order.bump = ctx.bumps.standing_order;
+
+        emit!(StandingOrderCreated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: order.owner,
+            server: order.server,
+            amount: order.amount,
+            interval_secs,
+            next_execution: order.next_execution,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Run a due standing order (permissionless). Fails without changing anything when the
+    // funding account's allowance or balance is short, the order then waits for the owner.
+    pub fn execute_standing_order(ctx: Context<ExecuteStandingOrder>) -> Result<()> {
+        let clock = Clock::get()?;
+        let order = &mut ctx.accounts.standing_order;
+        require!(
+            clock.unix_timestamp >= order.next_execution,
+            CustomError::StandingOrderNotDue
+        );
+        order.advance(clock.unix_timestamp)?;
+        let owner = order.owner;
+        let amount = order.amount;
+
+        check_gate(
+            &ctx.accounts.config,
+            ctx.accounts.gate_account.as_ref(),
+            &owner,
+        )?;
+
+        let main_account = &mut ctx.accounts.main_account.load_mut()?;
+        let info_account = &mut ctx.accounts.info_account;
+        record_delegation(
+            main_account,
+            info_account,
+            &mut ctx.accounts.delegated_account,
+            &ctx.accounts.config,
+            ctx.accounts.price_update.as_ref(),
+            owner,
+            amount,
+            ctx.accounts.mint.decimals,
+            order.lockup,
+            &clock,
+        )?;
+
+        let server = info_account.key();
+        let seeds = &[
+            STANDING_ORDER_SEED,
+            owner.as_ref(),
+            server.as_ref(),
+            &[order.bump],
+        ];
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.funding_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.vault.to_account_info(),
+                    authority: order.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            amount,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        if ctx.accounts.config.receipt_tokens {
+            mint_receipt(
+                ctx.accounts.receipt_mint.as_ref(),
+                ctx.accounts.receipt_holder_account.as_ref(),
+                &ctx.accounts.mint.key(),
+                ctx.accounts.token_program.to_account_info(),
+                ctx.program_id,
+                amount,
+            )?;
+            ctx.accounts.delegated_account.receipt_minted += amount;
+        }
+
+        let event = TokenDelegatedDeposited {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner,
+            delegator: server,
+            delegator_owner: info_account.owner.key(),
+            amount,
+            stake: ctx.accounts.delegated_account.stake,
+            activating_stake: ctx.accounts.delegated_account.activating_stake,
+            server_total: info_account.total,
+            server_seq: info_account.next_event_seq(),
+            memo: [0; MAX_MEMO_LEN],
+            total_stake: main_account.total_stake,
+            total_users: main_account.total_users,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        };
+        #[cfg(feature = "event-cpi")]
+        emit_cpi!(event);
+        emit!(event);
+        emit!(StandingOrderExecuted {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner,
+            server,
+            keeper: ctx.accounts.keeper.key(),
+            amount,
+            executions: order.executions,
+            next_execution: order.next_execution,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Stop a standing order and get its rent back. Revoking the SPL approval stops it too.
+    pub fn cancel_standing_order(ctx: Context<CancelStandingOrder>) -> Result<()> {
+        let order = &ctx.accounts.standing_order;
+        let clock = Clock::get()?;
+        emit!(StandingOrderCancelled {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: order.owner,
+            server: order.server,
+            executions: order.executions,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Sign up for a delegation of `amount` whole tokens that the server or a keeper carries out
+    // later, until `expires_at`. Like a standing order it draws from `funding_account` through an
+    // SPL approval of the intent PDA, but it runs once and may open the position.
+    pub fn create_delegation_intent(
+        ctx: Context<CreateDelegationIntent>,
+        amount: u64,
+        expires_at: i64,
+        lockup: LockupTier,
+    ) -> Result<()> {
+        require!(amount > 0, CustomError::InvalidArgument);
+        let clock = Clock::get()?;
+        require!(expires_at > clock.unix_timestamp, CustomError::IntentExpired);
+
+        let intent = &mut ctx.accounts.delegation_intent;
+        intent.version = VERSION;
+        intent.owner = ctx.accounts.owner.key();
+        intent.server = ctx.accounts.info_account.key();
+        intent.funding_account = ctx.accounts.funding_account.key();
+        intent.amount = to_minimum_units(amount, ctx.accounts.mint.decimals)?;
+        intent.expires_at = expires_at;
+        intent.lockup = lockup;
+        intent.bump = ctx.bumps.delegation_intent;
+
+        emit!(DelegationIntentCreated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: intent.owner,
+            server: intent.server,
+            amount: intent.amount,
+            expires_at,
+            lockup,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Carry out a delegation intent before it expires (permissionless). The executor pays the
+    // rent of a new position and its vault, the intent's rent goes back to the owner.
+    pub fn execute_delegation_intent(ctx: Context<ExecuteDelegationIntent>) -> Result<()> {
+        let clock = Clock::get()?;
+        let intent = &ctx.accounts.delegation_intent;
+        require!(clock.unix_timestamp < intent.expires_at, CustomError::IntentExpired);
+        let owner = intent.owner;
+        let amount = intent.amount;
+
+        check_gate(
+            &ctx.accounts.config,
+            ctx.accounts.gate_account.as_ref(),
+            &owner,
+        )?;
+
+        let main_account = &mut ctx.accounts.main_account.load_mut()?;
+        let info_account = &mut ctx.accounts.info_account;
+        let delegated_account = &mut ctx.accounts.delegated_account;
+        let created = record_delegation(
+            main_account,
+            info_account,
+            delegated_account,
+            &ctx.accounts.config,
+            ctx.accounts.price_update.as_ref(),
+            owner,
+            amount,
+            ctx.accounts.mint.decimals,
+            intent.lockup,
+            &clock,
+        )?;
+        if created {
+            delegated_account.bump = ctx.bumps.delegated_account;
+        }
+
+        open_vault(
+            &ctx.accounts.vault,
+            delegated_account.to_account_info(),
+            &ctx.accounts.mint,
+            ctx.accounts.executor.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.associated_token_program.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+        )?;
+        let server = info_account.key();
+        let seeds = &[INTENT_SEED, owner.as_ref(), server.as_ref(), &[intent.bump]];
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.funding_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.vault.to_account_info(),
+                    authority: intent.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            amount,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        if ctx.accounts.config.receipt_tokens {
+            mint_receipt(
+                ctx.accounts.receipt_mint.as_ref(),
+                ctx.accounts.receipt_holder_account.as_ref(),
+                &ctx.accounts.mint.key(),
+                ctx.accounts.token_program.to_account_info(),
+                ctx.program_id,
+                amount,
+            )?;
+            ctx.accounts.delegated_account.receipt_minted += amount;
+        }
+
+        let event = TokenDelegatedDeposited {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner,
+            delegator: server,
+            delegator_owner: info_account.owner.key(),
+            amount,
+            stake: ctx.accounts.delegated_account.stake,
+            activating_stake: ctx.accounts.delegated_account.activating_stake,
+            server_total: info_account.total,
+            server_seq: info_account.next_event_seq(),
+            memo: [0; MAX_MEMO_LEN],
+            total_stake: main_account.total_stake,
+            total_users: main_account.total_users,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        };
+        #[cfg(feature = "event-cpi")]
+        emit_cpi!(event);
+        emit!(event);
+        emit!(DelegationIntentExecuted {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner,
+            server,
+            executor: ctx.accounts.executor.key(),
+            amount,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Withdraw a delegation intent and get its rent back. Revoking the SPL approval stops it too.
+    pub fn cancel_delegation_intent(ctx: Context<CancelDelegationIntent>) -> Result<()> {
+        let intent = &ctx.accounts.delegation_intent;
+        let clock = Clock::get()?;
+        emit!(DelegationIntentCancelled {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: intent.owner,
+            server: intent.server,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Credit a delegation made on another chain. `posted_vaa` is the core bridge account of a
+    // guardian-verified message from config.bridge_emitter carrying a BridgedDelegationMessage;
+    // the position is owned by the foreign address's ForeignOwner account. The tokens come from
+    // `funder`, normally the relayer that redeemed the matching token bridge transfer. Each
+    // message can be credited once.
+    pub fn d_deposit_bridged(
+        ctx: Context<DelegatedDepositBridged>,
+        sequence: u64,
+        foreign_owner: [u8; 32],
+    ) -> Result<()> {
+        let config = &ctx.accounts.config;
+        require!(
+            config.bridge_emitter != [0; 32] && config.wormhole_bridge != Pubkey::default(),
+            CustomError::BridgedDepositsDisabled
+        );
+        let (emitter_chain, emitter, vaa_sequence, payload) = parse_posted_vaa(
+            &ctx.accounts.posted_vaa.try_borrow_data()?,
+            BRIDGE_DELEGATION_MESSAGE,
+        )?;
+        require!(
+            emitter_chain == config.bridge_emitter_chain
+                && emitter == config.bridge_emitter
+                && vaa_sequence == sequence,
+            CustomError::InvalidVaa
+        );
+        let message = BridgedDelegationMessage::try_from_slice(&payload)
+            .map_err(|_| error!(CustomError::InvalidVaa))?;
+        require!(
+            message.server == ctx.accounts.info_account.key()
+                && message.foreign_owner == foreign_owner,
+            CustomError::InvalidVaa
+        );
+
+        let foreign_owner_account = &mut ctx.accounts.foreign_owner_account;
+        if foreign_owner_account.version == 0 {
+            foreign_owner_account.version = VERSION;
+            foreign_owner_account.emitter_chain = emitter_chain;
+            foreign_owner_account.address = foreign_owner;
+            foreign_owner_account.bump = ctx.bumps.foreign_owner_account;
+        }
+        let owner = foreign_owner_account.key();
+        check_gate(config, ctx.accounts.gate_account.as_ref(), &owner)?;
+
+        let clock = Clock::get()?;
+        let main_account = &mut ctx.accounts.main_account.load_mut()?;
+        let info_account = &mut ctx.accounts.info_account;
+        let delegated_account = &mut ctx.accounts.delegated_account;
+        // No receipt tokens, there is no Solana wallet to hold them
+        let created = record_delegation(
+            main_account,
+            info_account,
+            delegated_account,
+            config,
+            ctx.accounts.price_update.as_ref(),
+            owner,
+            message.amount,
+            ctx.accounts.mint.decimals,
+            message.lockup,
+            &clock,
+        )?;
+        if created {
+            delegated_account.bump = ctx.bumps.delegated_account;
+        }
+
+        open_vault(
+            &ctx.accounts.vault,
+            delegated_account.to_account_info(),
+            &ctx.accounts.mint,
+            ctx.accounts.payer.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.associated_token_program.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+        )?;
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.sender_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.vault.to_account_info(),
+                    authority: ctx.accounts.funder.to_account_info(),
+                },
+            ),
+            message.amount,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        let bridged = &mut ctx.accounts.bridged_delegation;
+        bridged.version = VERSION;
+        bridged.emitter_chain = emitter_chain;
+        bridged.sequence = sequence;
+        bridged.server = info_account.key();
+        bridged.owner = owner;
+        bridged.amount = message.amount;
+        bridged.bump = ctx.bumps.bridged_delegation;
+
+        emit!(TokenDelegatedDeposited {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner,
+            delegator: info_account.key(),
+            delegator_owner: info_account.owner.key(),
+            amount: message.amount,
+            stake: delegated_account.stake,
+            activating_stake: delegated_account.activating_stake,
+            server_total: info_account.total,
+            server_seq: info_account.next_event_seq(),
+            memo: [0; MAX_MEMO_LEN],
+            total_stake: main_account.total_stake,
+            total_users: main_account.total_users,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+        emit!(BridgedDelegationCredited {
+            schema_version: EVENT_SCHEMA_VERSION,
+            server: info_account.key(),
+            emitter_chain,
+            sequence,
+            foreign_owner,
+            owner,
+            amount: message.amount,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Withdraw from a bridged delegation. `posted_vaa` carries a BridgedWithdrawalMessage from
+    // config.bridge_emitter, which the foreign owner signs on its chain. The stake and the
+    // pending rewards go to the token account of the message's `recipient`, e.g. a relayer
+    // bridging them back. Each message can be used once, and shares the replay guard of
+    // d_deposit_bridged since the emitter numbers all its messages in one sequence.
+    pub fn d_withdraw_bridged(
+        ctx: Context<DelegatedWithdrawBridged>,
+        sequence: u64,
+        foreign_owner: [u8; 32],
+    ) -> Result<()> {
+        let config = &ctx.accounts.config;
+        require!(
+            config.bridge_emitter != [0; 32] && config.wormhole_bridge != Pubkey::default(),
+            CustomError::BridgedDepositsDisabled
+        );
+        let (emitter_chain, emitter, vaa_sequence, payload) = parse_posted_vaa(
+            &ctx.accounts.posted_vaa.try_borrow_data()?,
+            BRIDGE_WITHDRAWAL_MESSAGE,
+        )?;
+        require!(
+            emitter_chain == config.bridge_emitter_chain
+                && emitter == config.bridge_emitter
+                && vaa_sequence == sequence,
+            CustomError::InvalidVaa
+        );
+        let message = BridgedWithdrawalMessage::try_from_slice(&payload)
+            .map_err(|_| error!(CustomError::InvalidVaa))?;
+        require!(
+            message.server == ctx.accounts.info_account.key()
+                && message.foreign_owner == foreign_owner,
+            CustomError::InvalidVaa
+        );
+        require_keys_eq!(
+            ctx.accounts.recipient_token_account.owner,
+            message.recipient,
+            CustomError::InvalidAccountData
+        );
+
+        // The main account signs the reward transfer below, so it can't stay borrowed across it
+        let clock = Clock::get()?;
+        let info_account = &mut ctx.accounts.info_account;
+        let delegated_account = &mut ctx.accounts.delegated_account;
+        {
+            let main_account = &mut ctx.accounts.main_account.load_mut()?;
+            record_withdrawal(
+                main_account,
+                info_account,
+                delegated_account,
+                message.amount,
+                &clock,
+            )?;
+        }
+
+        let owner = ctx.accounts.foreign_owner_account.key();
+        let server = info_account.key();
+        let seeds = &[INFO_SEED, owner.as_ref(), server.as_ref(), &[delegated_account.bump]];
+        let fee = fee_amount(message.amount, config.withdraw_fee_bps)?;
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.vault.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.recipient_token_account.to_account_info(),
+                    authority: delegated_account.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            message.amount - fee,
+            ctx.accounts.mint.decimals,
+        )?;
+        collect_fee(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.vault.to_account_info(),
+            &ctx.accounts.mint,
+            ctx.accounts.treasury_token_account.as_ref(),
+            delegated_account.to_account_info(),
+            &[&seeds[..]],
+            fee,
+        )?;
+
+        // There is no Solana wallet to claim the rewards later, they leave with the stake
+        let rewards = delegated_account.pending_rewards;
+        if rewards > 0 {
+            delegated_account.pending_rewards = 0;
+            let main_seeds = &[MAIN_SEED, &[ctx.bumps.main_account]];
+            let reward_fee = fee_amount(rewards, config.reward_fee_bps)?;
+            anchor_spl::token_interface::transfer_checked(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.reward_vault.to_account_info(),
+                        mint: ctx.accounts.mint.to_account_info(),
+                        to: ctx.accounts.recipient_token_account.to_account_info(),
+                        authority: ctx.accounts.main_account.to_account_info(),
+                    },
+                    &[&main_seeds[..]],
+                ),
+                rewards - reward_fee,
+                ctx.accounts.mint.decimals,
+            )?;
+            collect_fee(
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.reward_vault.to_account_info(),
+                &ctx.accounts.mint,
+                ctx.accounts.treasury_token_account.as_ref(),
+                ctx.accounts.main_account.to_account_info(),
+                &[&main_seeds[..]],
+                reward_fee,
+            )?;
+        }
+
+        let bridged = &mut ctx.accounts.bridged_delegation;
+        bridged.version = VERSION;
+        bridged.emitter_chain = emitter_chain;
+        bridged.sequence = sequence;
+        bridged.server = server;
+        bridged.owner = owner;
+        bridged.amount = message.amount;
+        bridged.bump = ctx.bumps.bridged_delegation;
+
+        let main_account = ctx.accounts.main_account.load()?;
+        emit!(DelegatedTokenWithdrawn {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner,
+            delegator: server,
+            delegator_owner: info_account.owner.key(),
+            amount: message.amount,
+            fee,
+            stake: delegated_account.stake,
+            activating_stake: delegated_account.activating_stake,
+            server_total: info_account.total,
+            server_seq: info_account.next_event_seq(),
+            total_stake: main_account.total_stake,
+            total_users: main_account.total_users,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+        emit!(BridgedWithdrawalPaid {
+            schema_version: EVENT_SCHEMA_VERSION,
+            server,
+            emitter_chain,
+            sequence,
+            foreign_owner,
+            owner,
+            recipient: message.recipient,
+            amount: message.amount,
+            rewards,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Delegate into several servers at once, remaining_accounts holds one
+    // (info_account, vault, delegated_account) triplet per entry of `amounts`
+    pub fn d_deposit_many<'info>(
+        ctx: Context<'_, '_, 'info, 'info, DelegatedDepositMany<'info>>,
+        amounts: Vec<u64>,
+        lockup: LockupTier,
+    ) -> Result<()> {
+        require!(
+            !amounts.is_empty() && amounts.len() <= MAX_BATCH_DELEGATIONS,
+            CustomError::InvalidBatchSize
+        );
+        require!(
+            ctx.remaining_accounts.len() == amounts.len() * 3,
+            CustomError::InvalidBatchSize
+        );
+
+        check_gate(
+            &ctx.accounts.config,
+            ctx.accounts.gate_account.as_ref(),
+            &ctx.accounts.owner.key(),
+        )?;
+
+        let clock = Clock::get()?;
+        let owner = ctx.accounts.owner.key();
+        let mint = ctx.accounts.mint.key();
+
+        for (amount, triplet) in amounts.iter().zip(ctx.remaining_accounts.chunks(3)) {
+            let (info_info, vault_info, delegated_info) = (&triplet[0], &triplet[1], &triplet[2]);
+
+            let mut info_account = Account::<InfoAccount>::try_from(info_info)?;
+            require_keys_eq!(info_account.mint, mint, CustomError::InvalidMint);
+
+            let (mut delegated_account, delegated_bump) = batch_position(
+                &owner,
+                &info_account,
+                delegated_info,
+                vault_info,
+                &ctx.accounts.mint,
+                ctx.accounts.payer.to_account_info(),
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.associated_token_program.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+                ctx.program_id,
+            )?;
+
+            let amount_in_minimum_units = to_minimum_units(*amount, ctx.accounts.mint.decimals)?;
+
+            let created = record_delegation(
+                &mut ctx.accounts.main_account.load_mut()?,
+                &mut info_account,
+                &mut delegated_account,
+                &ctx.accounts.config,
+                ctx.accounts.price_update.as_ref(),
+                owner,
+                amount_in_minimum_units,
+                ctx.accounts.mint.decimals,
+                lockup,
+                &clock,
+            )?;
+            if created {
+                delegated_account.bump = delegated_bump;
+            }
+
+            anchor_spl::token_interface::transfer_checked(
+                CpiContext::new(
+                    ctx.accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.sender_token_account.to_account_info(),
+                        mint: ctx.accounts.mint.to_account_info(),
+                        to: vault_info.clone(),
+                        authority: ctx.accounts.owner.to_account_info(),
+                    },
+                ),
+                amount_in_minimum_units,
+                ctx.accounts.mint.decimals,
+            )?;
+
+            if ctx.accounts.config.receipt_tokens {
+                mint_receipt(
+                    ctx.accounts.receipt_mint.as_ref(),
+                    ctx.accounts.receipt_holder_account.as_ref(),
+                    &mint,
+                    ctx.accounts.token_program.to_account_info(),
+                    ctx.program_id,
+                    amount_in_minimum_units,
+                )?;
+                delegated_account.receipt_minted += amount_in_minimum_units;
+            }
+
+            let server_seq = info_account.next_event_seq();
+            info_account.exit(ctx.program_id)?;
+            delegated_account.exit(ctx.program_id)?;
+
+            let main_account = ctx.accounts.main_account.load()?;
+            let event = TokenDelegatedDeposited {
+                schema_version: EVENT_SCHEMA_VERSION,
+                owner,
+                delegator: info_account.key(),
+                delegator_owner: info_account.owner.key(),
+                amount: amount_in_minimum_units,
+                stake: delegated_account.stake,
+                activating_stake: delegated_account.activating_stake,
+                server_total: info_account.total,
+                server_seq,
+                memo: [0; MAX_MEMO_LEN],
+                total_stake: main_account.total_stake,
+                total_users: main_account.total_users,
+                slot: clock.slot,
+                unix_timestamp: clock.unix_timestamp,
+            };
+            #[cfg(feature = "event-cpi")]
+            emit_cpi!(event);
+            emit!(event);
+        }
+
+        Ok(())
+    }
+
+    pub fn create_portfolio(ctx: Context<CreatePortfolio>) -> Result<()> {
+        let portfolio = &mut ctx.accounts.portfolio;
+        portfolio.version = VERSION;
+        portfolio.owner = ctx.accounts.owner.key();
+        portfolio.bump = ctx.bumps.portfolio;
+
+        let clock = Clock::get()?;
+        emit!(PortfolioCreated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: portfolio.owner,
+            portfolio: portfolio.key(),
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Split `amount` across servers by `distribution`, in basis points adding up to MAX_BPS.
+    // remaining_accounts holds one (info_account, vault, delegated_account) triplet per weight,
+    // the positions belong to the portfolio PDA and are opened on first use. Rounding dust goes
+    // to the last weighted server. Portfolio positions don't mint receipts and compound their
+    // rewards through crank_rewards, the portfolio can't sign a claim.
+    pub fn portfolio_deposit<'info>(
+        ctx: Context<'_, '_, 'info, 'info, PortfolioDeposit<'info>>,
+        amount: u64,
+        distribution: Vec<u16>,
+        lockup: LockupTier,
+    ) -> Result<()> {
+        require!(
+            !distribution.is_empty() && distribution.len() <= MAX_PORTFOLIO_POSITIONS,
+            CustomError::InvalidBatchSize
+        );
+        require!(
+            ctx.remaining_accounts.len() == distribution.len() * 3,
+            CustomError::InvalidBatchSize
+        );
+        require!(
+            distribution.iter().map(|bps| *bps as u64).sum::<u64>() == MAX_BPS as u64,
+            CustomError::InvalidDistribution
+        );
+
+        check_gate(
+            &ctx.accounts.config,
+            ctx.accounts.gate_account.as_ref(),
+            &ctx.accounts.owner.key(),
+        )?;
+
+        let clock = Clock::get()?;
+        let owner = ctx.accounts.owner.key();
+        let portfolio_key = ctx.accounts.portfolio.key();
+        let mint = ctx.accounts.mint.key();
+        let total = to_minimum_units(amount, ctx.accounts.mint.decimals)?;
+        let last = distribution.iter().rposition(|bps| *bps > 0).unwrap_or_default();
+        let mut allocated = 0u64;
+
+        for (i, (bps, triplet)) in
+            distribution.iter().zip(ctx.remaining_accounts.chunks(3)).enumerate()
+        {
+            if *bps == 0 {
+                continue;
+            }
+            let (info_info, vault_info, delegated_info) = (&triplet[0], &triplet[1], &triplet[2]);
+
+            let mut info_account = Account::<InfoAccount>::try_from(info_info)?;
+            require_keys_eq!(info_account.mint, mint, CustomError::InvalidMint);
+            // record_delegation only sees the portfolio as owner
+            require_keys_neq!(owner, info_account.owner, CustomError::SelfDelegation);
+
+            let share = if i == last {
+                total - allocated
+            } else {
+                fee_amount(total, *bps)? // Same basis-point share as a fee
+            };
+            allocated += share;
+
+            let (mut delegated_account, delegated_bump) = batch_position(
+                &portfolio_key,
+                &info_account,
+                delegated_info,
+                vault_info,
+                &ctx.accounts.mint,
+                ctx.accounts.payer.to_account_info(),
+                ctx.accounts.token_program.to_account_info(),
+                ctx.accounts.associated_token_program.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+                ctx.program_id,
+            )?;
+
+            let created = record_delegation(
+                &mut ctx.accounts.main_account.load_mut()?,
+                &mut info_account,
+                &mut delegated_account,
+                &ctx.accounts.config,
+                ctx.accounts.price_update.as_ref(),
+                portfolio_key,
+                share,
+                ctx.accounts.mint.decimals,
+                lockup,
+                &clock,
+            )?;
+            if created {
+                delegated_account.bump = delegated_bump;
+                delegated_account.auto_compound = true;
+                ctx.accounts.portfolio.add_position(info_account.key())?;
+            }
+
+            anchor_spl::token_interface::transfer_checked(
+                CpiContext::new(
+                    ctx.accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.sender_token_account.to_account_info(),
+                        mint: ctx.accounts.mint.to_account_info(),
+                        to: vault_info.clone(),
+                        authority: ctx.accounts.owner.to_account_info(),
+                    },
+                ),
+                share,
+                ctx.accounts.mint.decimals,
+            )?;
+
+            let server_seq = info_account.next_event_seq();
+            info_account.exit(ctx.program_id)?;
+            delegated_account.exit(ctx.program_id)?;
+
+            let main_account = ctx.accounts.main_account.load()?;
+            let event = TokenDelegatedDeposited {
+                schema_version: EVENT_SCHEMA_VERSION,
+                owner: portfolio_key,
+                delegator: info_account.key(),
+                delegator_owner: info_account.owner.key(),
+                amount: share,
+                stake: delegated_account.stake,
+                activating_stake: delegated_account.activating_stake,
+                server_total: info_account.total,
+                server_seq,
+                memo: [0; MAX_MEMO_LEN],
+                total_stake: main_account.total_stake,
+                total_users: main_account.total_users,
+                slot: clock.slot,
+                unix_timestamp: clock.unix_timestamp,
+            };
+            #[cfg(feature = "event-cpi")]
+            emit_cpi!(event);
+            emit!(event);
+        }
+
+        Ok(())
+    }
+
+    // Withdraw from one portfolio position to the owner's wallet, in base units. With
+    // `close_position` the emptied position and its vault are closed and the server leaves the
+    // portfolio, pending rewards have to be cranked into the stake first.
+    pub fn portfolio_withdraw(
+        ctx: Context<PortfolioWithdraw>,
+        amount_in_minimum_units: u64,
+        close_position: bool,
+    ) -> Result<()> {
+        let main_account = &mut ctx.accounts.main_account.load_mut()?;
+        let info_account = &mut ctx.accounts.info_account;
+        let delegated_account = &mut ctx.accounts.delegated_account;
+        let portfolio_key = ctx.accounts.portfolio.key();
+
+        let clock = Clock::get()?;
+        record_withdrawal(
+            main_account,
+            info_account,
+            delegated_account,
+            amount_in_minimum_units,
+            &clock,
+        )?;
+
+        let info_key = info_account.key();
+        let seeds = &[
+            INFO_SEED,
+            portfolio_key.as_ref(),
+            info_key.as_ref(),
+            &[delegated_account.bump],
+        ];
+        let fee = fee_amount(amount_in_minimum_units, ctx.accounts.config.withdraw_fee_bps)?;
+
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.vault.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.receipt_token_account.to_account_info(),
+                    authority: delegated_account.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            amount_in_minimum_units - fee,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        collect_fee(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.vault.to_account_info(),
+            &ctx.accounts.mint,
+            ctx.accounts.treasury_token_account.as_ref(),
+            delegated_account.to_account_info(),
+            &[&seeds[..]],
+            fee,
+        )?;
+
+        let event = DelegatedTokenWithdrawn {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: portfolio_key,
+            delegator: info_key,
+            delegator_owner: info_account.owner,
+            amount: amount_in_minimum_units,
+            fee,
+            stake: delegated_account.stake,
+            activating_stake: delegated_account.activating_stake,
+            server_total: info_account.total,
+            server_seq: info_account.next_event_seq(),
+            total_stake: main_account.total_stake,
+            total_users: main_account.total_users,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        };
+        #[cfg(feature = "event-cpi")]
+        emit_cpi!(event);
+        emit!(event);
+
+        if close_position {
+            require!(
+                delegated_account.stake == 0
+                    && delegated_account.activating_stake == 0
+                    && delegated_account.pending_rewards == 0,
+                CustomError::NonZeroBalance
+            );
+            require!(
+                !info_account.lists(&delegated_account.key(), delegated_account),
+                CustomError::DelegationLinked
+            );
+            close_empty_vault(
+                &mut ctx.accounts.vault,
+                ctx.accounts.owner.to_account_info(),
+                delegated_account.to_account_info(),
+                ctx.accounts.token_program.to_account_info(),
+                &[&seeds[..]],
+            )?;
+            delegated_account.close(ctx.accounts.owner.to_account_info())?;
+            main_account.remove_user(false);
+            info_account.remove_delegator();
+            ctx.accounts.portfolio.remove_position(&info_key);
+
+            let event = DelegatedRemoved {
+                schema_version: EVENT_SCHEMA_VERSION,
+                owner: portfolio_key,
+                delegator: info_key,
+                total_stake: main_account.total_stake,
+                total_users: main_account.total_users,
+                slot: clock.slot,
+                unix_timestamp: clock.unix_timestamp,
+            };
+            #[cfg(feature = "event-cpi")]
+            emit_cpi!(event);
+            emit!(event);
+        }
+
+        Ok(())
+    }
+
+    // Move stake between the portfolio's positions until they match `weights`, basis points in
+    // the order of portfolio.servers. remaining_accounts holds the (info_account, vault,
+    // delegated_account) triplet of every position in that order. Tokens go vault to vault
+    // without a fee. Stake taken from a position must be unlocked, and moved stake activates in
+    // the next epoch like any new delegation.
+    pub fn rebalance<'info>(
+        ctx: Context<'_, '_, 'info, 'info, Rebalance<'info>>,
+        weights: Vec<u16>,
+    ) -> Result<()> {
+        let portfolio = &ctx.accounts.portfolio;
+        let count = portfolio.position_count as usize;
+        require!(
+            count > 0 && weights.len() == count && ctx.remaining_accounts.len() == count * 3,
+            CustomError::InvalidBatchSize
+        );
+        require!(
+            weights.iter().map(|bps| *bps as u64).sum::<u64>() == MAX_BPS as u64,
+            CustomError::InvalidDistribution
+        );
+
+        let clock = Clock::get()?;
+        let main_account = &mut ctx.accounts.main_account.load_mut()?;
+        let portfolio_key = portfolio.key();
+        let mint = ctx.accounts.mint.key();
+
+        let mut positions = Vec::with_capacity(count);
+        let triplets = ctx.remaining_accounts.chunks(3);
+        for (server, triplet) in portfolio.positions().iter().zip(triplets) {
+            let (info_info, vault_info, delegated_info) = (&triplet[0], &triplet[1], &triplet[2]);
+            require_keys_eq!(info_info.key(), *server, CustomError::InvalidBatchAccount);
+            let mut info_account = Account::<InfoAccount>::try_from(info_info)?;
+            require_keys_eq!(info_account.mint, mint, CustomError::InvalidMint);
+
+            let (delegated_key, _) = Pubkey::find_program_address(
+                &[INFO_SEED, portfolio_key.as_ref(), server.as_ref()],
+                ctx.program_id,
+            );
+            require_keys_eq!(delegated_info.key(), delegated_key, CustomError::InvalidBatchAccount);
+            require_keys_eq!(
+                vault_info.key(),
+                get_associated_token_address_with_program_id(
+                    &delegated_key,
+                    &mint,
+                    &ctx.accounts.token_program.key(),
+                ),
+                CustomError::InvalidBatchAccount
+            );
+            let mut delegated_account = Account::<DelegatedAccount>::try_from(delegated_info)?;
+
+            activate_delegation(
+                main_account,
+                &mut info_account,
+                &mut delegated_account,
+                clock.epoch,
+            )?;
+            positions.push((info_account, vault_info, delegated_account));
+        }
+
+        let balances: Vec<u64> = positions
+            .iter()
+            .map(|(_, _, delegation)| delegation.stake + delegation.activating_stake)
+            .collect();
+        let total: u64 = balances.iter().sum();
+        let mut targets = Vec::with_capacity(count);
+        for bps in &weights[..count - 1] {
+            targets.push(fee_amount(total, *bps)?); // Same basis-point share as a fee
+        }
+        targets.push(total - targets.iter().sum::<u64>());
+
+        // Record both sides first, then pair the surpluses with the deficits for the transfers
+        let mut surpluses = Vec::new();
+        let mut deficits = Vec::new();
+        for (i, (info_account, _, delegated_account)) in positions.iter_mut().enumerate() {
+            if balances[i] > targets[i] {
+                let amount = balances[i] - targets[i];
+                record_withdrawal(main_account, info_account, delegated_account, amount, &clock)?;
+                surpluses.push((i, amount));
+            } else if balances[i] < targets[i] {
+                let amount = targets[i] - balances[i];
+                require!(
+                    main_account.to_common_units(&mint, targets[i])? <= MAXIMUM_STAKE,
+                    CustomError::DelegateExceedsMaxStakeLimit
+                );
+                queue_delegation(
+                    main_account,
+                    info_account,
+                    delegated_account,
+                    &ctx.accounts.config,
+                    amount,
+                    LockupTier::None,
+                    &clock,
+                )?;
+                deficits.push((i, amount));
+            }
+        }
+
+        let mut moved = 0u64;
+        let mut deficit_iter = deficits.into_iter();
+        let mut deficit = deficit_iter.next();
+        for (from, mut surplus) in surpluses {
+            while surplus > 0 {
+                let (to, needed) = deficit.as_mut().ok_or(CustomError::NumberOverflow)?;
+                let amount = surplus.min(*needed);
+                let (info_account, vault_info, delegated_account) = &positions[from];
+                let info_key = info_account.key();
+                let seeds = &[
+                    INFO_SEED,
+                    portfolio_key.as_ref(),
+                    info_key.as_ref(),
+                    &[delegated_account.bump],
+                ];
+                anchor_spl::token_interface::transfer_checked(
+                    CpiContext::new_with_signer(
+                        ctx.accounts.token_program.to_account_info(),
+                        TransferChecked {
+                            from: (*vault_info).clone(),
+                            mint: ctx.accounts.mint.to_account_info(),
+                            to: positions[*to].1.clone(),
+                            authority: delegated_account.to_account_info(),
+                        },
+                        &[&seeds[..]],
+                    ),
+                    amount,
+                    ctx.accounts.mint.decimals,
+                )?;
+
+                surplus -= amount;
+                *needed -= amount;
+                moved += amount;
+                if *needed == 0 {
+                    deficit = deficit_iter.next();
+                }
+            }
+        }
+
+        for (info_account, _, delegated_account) in &positions {
+            info_account.exit(ctx.program_id)?;
+            delegated_account.exit(ctx.program_id)?;
+        }
+
+        emit!(PortfolioRebalanced {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: ctx.accounts.owner.key(),
+            portfolio: portfolio_key,
+            weights,
+            moved,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Open the delegation pool of a primary mint (admin). `manager` spreads the pooled tokens
+    // over Gold tier servers with pool_delegate and pool_undelegate.
+    pub fn initialize_pool(ctx: Context<InitializePool>, manager: Pubkey) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        pool.version = VERSION;
+        pool.mint = ctx.accounts.mint.key();
+        pool.share_mint = ctx.accounts.share_mint.key();
+        pool.manager = manager;
+        pool.bump = ctx.bumps.pool;
+
+        let clock = Clock::get()?;
+        emit!(PoolInitialized {
+            schema_version: EVENT_SCHEMA_VERSION,
+            pool: pool.key(),
+            mint: pool.mint,
+            share_mint: pool.share_mint,
+            manager,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+        emit!(VaultCreated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            vault: ctx.accounts.pool_vault.key(),
+            authority: pool.key(),
+            mint: pool.mint,
+            payer: ctx.accounts.admin.key(),
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Buy pool shares at the price of the last sync_pool, any amount in base units. There is no
+    // delegate minimum, the pool delegates the aggregate. Returns the shares minted.
+    pub fn pool_deposit(ctx: Context<PoolDeposit>, amount_in_minimum_units: u64) -> Result<u64> {
+        check_gate(
+            &ctx.accounts.config,
+            ctx.accounts.gate_account.as_ref(),
+            &ctx.accounts.owner.key(),
+        )?;
+
+        let pool = &mut ctx.accounts.pool;
+        let shares = pool.shares_for(amount_in_minimum_units, ctx.accounts.share_mint.supply)?;
+        require!(shares > 0, CustomError::InvalidArgument);
+
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.sender_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.pool_vault.to_account_info(),
+                    authority: ctx.accounts.owner.to_account_info(),
+                },
+            ),
+            amount_in_minimum_units,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        let mint = pool.mint;
+        let seeds = &[POOL_SEED, mint.as_ref(), &[pool.bump]];
+        anchor_spl::token_interface::mint_to(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                MintTo {
+                    mint: ctx.accounts.share_mint.to_account_info(),
+                    to: ctx.accounts.share_account.to_account_info(),
+                    authority: pool.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            shares,
+        )?;
+        pool.idle = pool
+            .idle
+            .checked_add(amount_in_minimum_units)
+            .ok_or(CustomError::NumberOverflow)?;
+
+        let clock = Clock::get()?;
+        emit!(PoolDeposited {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: ctx.accounts.owner.key(),
+            pool: pool.key(),
+            amount: amount_in_minimum_units,
+            shares,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(shares)
+    }
+
+    // Redeem pool shares for tokens out of the pool's undelegated balance. When that is short the
+    // manager has to pool_undelegate first. Returns the base units paid out.
+    pub fn pool_withdraw(ctx: Context<PoolWithdraw>, shares: u64) -> Result<u64> {
+        let amount = ctx
+            .accounts
+            .pool
+            .assets_for(shares, ctx.accounts.share_mint.supply)?;
+        redeem_pool_shares(ctx.accounts, shares, amount)?;
+        Ok(amount)
+    }
+
+    // Withdraw exactly `amount_in_minimum_units` from the pool, burning the shares that cost
+    // rounded up. Returns the shares burned.
+    pub fn pool_withdraw_assets(
+        ctx: Context<PoolWithdraw>,
+        amount_in_minimum_units: u64,
+    ) -> Result<u64> {
+        let shares = ctx
+            .accounts
+            .pool
+            .shares_to_withdraw(amount_in_minimum_units, ctx.accounts.share_mint.supply)?;
+        redeem_pool_shares(ctx.accounts, shares, amount_in_minimum_units)?;
+        Ok(shares)
+    }
+
+    // Pool position of a share holder and the pool totals behind the share price, for CPI
+    // callers, see `query::pool_balance`
+    pub fn get_pool_balance(ctx: Context<GetPoolBalance>) -> Result<PoolBalance> {
+        let pool = &ctx.accounts.pool;
+        let share_supply = ctx.accounts.share_mint.supply;
+        let (owner, shares) = match &ctx.accounts.share_account {
+            Some(account) => (account.owner, account.amount),
+            None => (Pubkey::default(), 0),
+        };
+        let assets = pool.assets_for(shares, share_supply)?;
+
+        Ok(PoolBalance {
+            pool: pool.key(),
+            mint: pool.mint,
+            share_mint: pool.share_mint,
+            total_assets: pool.total_assets()?,
+            share_supply,
+            idle: pool.idle,
+            synced_at: pool.synced_at,
+            owner,
+            shares,
+            assets,
+            max_withdraw: assets.min(pool.idle),
+        })
+    }
+
+    // Delegate pooled tokens into a Gold tier server (manager). The position belongs to the pool
+    // PDA and compounds its rewards through crank_rewards.
+    pub fn pool_delegate(ctx: Context<PoolDelegate>, amount_in_minimum_units: u64) -> Result<()> {
+        require!(
+            ctx.accounts.info_account.tier == ServiceTier::Gold,
+            CustomError::PoolServerNotEligible
+        );
+        let pool = &mut ctx.accounts.pool;
+        require!(
+            amount_in_minimum_units <= pool.idle,
+            CustomError::PoolLiquidityInsufficient
+        );
+
+        let clock = Clock::get()?;
+        let info_account = &mut ctx.accounts.info_account;
+        let delegated_account = &mut ctx.accounts.delegated_account;
+        let created = record_delegation(
+            &mut ctx.accounts.main_account.load_mut()?,
+            info_account,
+            delegated_account,
+            &ctx.accounts.config,
+            ctx.accounts.price_update.as_ref(),
+            pool.key(),
+            amount_in_minimum_units,
+            ctx.accounts.mint.decimals,
+            LockupTier::None,
+            &clock,
+        )?;
+        if created {
+            delegated_account.bump = ctx.bumps.delegated_account;
+            delegated_account.auto_compound = true;
+            pool.add_position(info_account.key())?;
+        }
+
+        open_vault(
+            &ctx.accounts.vault,
+            delegated_account.to_account_info(),
+            &ctx.accounts.mint,
+            ctx.accounts.manager.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.associated_token_program.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+        )?;
+        let mint = pool.mint;
+        let seeds = &[POOL_SEED, mint.as_ref(), &[pool.bump]];
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.pool_vault.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.vault.to_account_info(),
+                    authority: pool.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            amount_in_minimum_units,
+            ctx.accounts.mint.decimals,
+        )?;
+        pool.idle -= amount_in_minimum_units;
+        pool.delegated = pool
+            .delegated
+            .checked_add(amount_in_minimum_units)
+            .ok_or(CustomError::NumberOverflow)?;
+
+        let main_account = ctx.accounts.main_account.load()?;
+        let event = TokenDelegatedDeposited {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: pool.key(),
+            delegator: info_account.key(),
+            delegator_owner: info_account.owner,
+            amount: amount_in_minimum_units,
+            stake: delegated_account.stake,
+            activating_stake: delegated_account.activating_stake,
+            server_total: info_account.total,
+            server_seq: info_account.next_event_seq(),
+            memo: [0; MAX_MEMO_LEN],
+            total_stake: main_account.total_stake,
+            total_users: main_account.total_users,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        };
+        #[cfg(feature = "event-cpi")]
+        emit_cpi!(event);
+        emit!(event);
+
+        Ok(())
+    }
+
+    // Bring delegated tokens back to the pool's undelegated balance (manager), less the withdraw
+    // fee a delegator would pay
+    pub fn pool_undelegate(
+        ctx: Context<PoolUndelegate>,
+        amount_in_minimum_units: u64,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        let pool = &mut ctx.accounts.pool;
+        let info_account = &mut ctx.accounts.info_account;
+        let delegated_account = &mut ctx.accounts.delegated_account;
+        record_withdrawal(
+            &mut ctx.accounts.main_account.load_mut()?,
+            info_account,
+            delegated_account,
+            amount_in_minimum_units,
+            &clock,
+        )?;
+
+        let pool_key = pool.key();
+        let info_key = info_account.key();
+        let seeds = &[
+            INFO_SEED,
+            pool_key.as_ref(),
+            info_key.as_ref(),
+            &[delegated_account.bump],
+        ];
+        let fee = fee_amount(amount_in_minimum_units, ctx.accounts.config.withdraw_fee_bps)?;
+
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.vault.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.pool_vault.to_account_info(),
+                    authority: delegated_account.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            amount_in_minimum_units - fee,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        collect_fee(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.vault.to_account_info(),
+            &ctx.accounts.mint,
+            ctx.accounts.treasury_token_account.as_ref(),
+            delegated_account.to_account_info(),
+            &[&seeds[..]],
+            fee,
+        )?;
+
+        pool.idle = pool
+            .idle
+            .checked_add(amount_in_minimum_units - fee)
+            .ok_or(CustomError::NumberOverflow)?;
+        // Compounded rewards are only counted from the next sync_pool
+        pool.delegated = pool.delegated.saturating_sub(amount_in_minimum_units);
+
+        let main_account = ctx.accounts.main_account.load()?;
+        let event = DelegatedTokenWithdrawn {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: pool_key,
+            delegator: info_key,
+            delegator_owner: info_account.owner,
+            amount: amount_in_minimum_units,
+            fee,
+            stake: delegated_account.stake,
+            activating_stake: delegated_account.activating_stake,
+            server_total: info_account.total,
+            server_seq: info_account.next_event_seq(),
+            total_stake: main_account.total_stake,
+            total_users: main_account.total_users,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        };
+        #[cfg(feature = "event-cpi")]
+        emit_cpi!(event);
+        emit!(event);
+
+        Ok(())
+    }
+
+    // Recount the pool's delegated stake, compounded rewards included, from its positions
+    // (permissionless). remaining_accounts holds the delegated account of every position in the
+    // order of pool.servers. Sets the share price until the next sync.
+    pub fn sync_pool<'info>(ctx: Context<'_, '_, 'info, 'info, SyncPool<'info>>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        require!(
+            ctx.remaining_accounts.len() == pool.position_count as usize,
+            CustomError::InvalidBatchSize
+        );
+
+        let pool_key = pool.key();
+        let mut delegated = 0u64;
+        for (server, delegated_info) in pool.positions().iter().zip(ctx.remaining_accounts) {
+            let (expected, _) = Pubkey::find_program_address(
+                &[INFO_SEED, pool_key.as_ref(), server.as_ref()],
+                ctx.program_id,
+            );
+            require_keys_eq!(delegated_info.key(), expected, CustomError::InvalidBatchAccount);
+            let delegation = Account::<DelegatedAccount>::try_from(delegated_info)?;
+            delegated = delegated
+                .checked_add(delegation.stake + delegation.activating_stake)
+                .ok_or(CustomError::NumberOverflow)?;
+        }
+
+        let clock = Clock::get()?;
+        pool.delegated = delegated;
+        pool.synced_at = clock.unix_timestamp;
+
+        emit!(PoolSynced {
+            schema_version: EVENT_SCHEMA_VERSION,
+            pool: pool_key,
+            idle: pool.idle,
+            delegated,
+            share_supply: ctx.accounts.share_mint.supply,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Withdraw stake amount. With `close_vault` the emptied vault is closed and its rent refunded
+    // to the owner, a later deposit recreates it.
+    pub fn withdraw(ctx: Context<Withdraw>, amount: u64, close_vault: bool) -> Result<()> {
+        let amount_in_minimum_units = to_minimum_units(amount, ctx.accounts.mint.decimals)?;
+        withdraw_raw(ctx, amount_in_minimum_units, close_vault)
+    }
+
+    // Same as withdraw, with the amount already in base units of the mint
+    pub fn withdraw_raw(
+        ctx: Context<Withdraw>,
+        amount_in_minimum_units: u64,
+        close_vault: bool,
+    ) -> Result<()> {
+        log_compute_units(&ctx.accounts.config, "withdraw: start");
+        check_cpi_origin(&ctx.accounts.config, ctx.accounts.instructions.as_ref())?;
+        check_signer_set(
+            &ctx.accounts.info_account,
+            &ctx.accounts.owner.key(),
+            ctx.remaining_accounts,
+        )?;
+
+        let main_account = &mut ctx.accounts.main_account.load_mut()?;
+        let info_account = &mut ctx.accounts.info_account;
+
+        require!(
+            !info_account.withdrawals_frozen(Clock::get()?.unix_timestamp),
+            CustomError::WithdrawalsFrozen
+        );
+        require!(
+            amount_in_minimum_units <= info_account.stake,
+            CustomError::InsufficientFunds
+        );
+        require!(
+            amount_in_minimum_units <= info_account.stake - info_account.channel_locked,
+            CustomError::ChannelCollateralLocked
+        );
+
+        let serverkey_hash = info_account.serverkey_hash;
+
+        // Transfer xxx tokens from PDA TokenAccount to user's TokenAccount
+        let seed_owner = info_account.seed_owner();
+        let seeds = &[
+            INFO_SEED,
+            seed_owner.as_ref(),
+            serverkey_hash.as_ref(),
+            &[ctx.bumps.info_account], // Use vault's seeds and bump
+        ];
+
+        log_compute_units(&ctx.accounts.config, "withdraw: checked");
+
+        let fee = fee_amount(amount_in_minimum_units, ctx.accounts.config.withdraw_fee_bps)?;
+
+        // The owner's own account, unless a whitelist restricts where stake can go
+        let destination = if info_account.has_withdrawal_whitelist() {
+            let destination = ctx
+                .accounts
+                .destination
+                .as_ref()
+                .ok_or(CustomError::WithdrawalAddressNotAllowed)?;
+            require!(
+                info_account.withdrawal_whitelist.contains(&destination.key()),
+                CustomError::WithdrawalAddressNotAllowed
+            );
+            destination.to_account_info()
+        } else {
+            ctx.accounts.receipt_token_account.to_account_info()
+        };
+
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.vault.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: destination,
+                    authority: ctx.accounts.info_account.to_account_info(), // Use vault as authority
+                },
+                &[&seeds[..]], // PDA's seeds
+            ),
+            amount_in_minimum_units - fee,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        collect_fee(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.vault.to_account_info(),
+            &ctx.accounts.mint,
+            ctx.accounts.treasury_token_account.as_ref(),
+            ctx.accounts.info_account.to_account_info(),
+            &[&seeds[..]],
+            fee,
+        )?;
+
+        if close_vault {
+            close_empty_vault(
+                &mut ctx.accounts.vault,
+                ctx.accounts.owner.to_account_info(),
+                ctx.accounts.info_account.to_account_info(),
+                ctx.accounts.token_program.to_account_info(),
+                &[&seeds[..]],
+            )?;
+        }
+
+        ctx.accounts.info_account.stake -= amount_in_minimum_units;
+        ctx.accounts.info_account.total -= amount_in_minimum_units;
+        main_account.sub_self_stake(&ctx.accounts.info_account.mint, amount_in_minimum_units)?;
+        ctx.accounts.info_account.refresh_stake(main_account)?;
+        ctx.accounts.info_account.count_withdrawal(main_account, amount_in_minimum_units)?;
+
+        // The operator can't pull self-stake out from under its delegations
+        ctx.accounts
+            .info_account
+            .check_self_bond(ctx.accounts.config.self_bond_multiple)?;
+
+        // Nor leave them on a server under the minimum without the exit notice
+        let clock = Clock::get()?;
+        ctx.accounts.info_account.check_exit_notice(
+            main_account,
+            ctx.accounts.config.exit_notice_secs,
+            clock.unix_timestamp,
+        )?;
+
+        log_compute_units(&ctx.accounts.config, "withdraw: transferred");
+
+        // Record event
+        ctx.accounts.info_account.last_active_epoch = clock.epoch;
+        let event = TokenWithdrawn {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: ctx.accounts.owner.key(),
+            name: ctx.accounts.info_account.name,
+            amount: amount_in_minimum_units,
+            fee,
+            stake: ctx.accounts.info_account.stake,
+            server_total: ctx.accounts.info_account.total,
+            server_seq: ctx.accounts.info_account.next_event_seq(),
+            total_stake: main_account.total_stake,
+            total_users: main_account.total_users,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        };
+        #[cfg(feature = "event-cpi")]
+        emit_cpi!(event);
+        emit!(event);
+
+        Ok(())
+    }
+
+    // `close_vault` closes the emptied vault like in withdraw
+    pub fn d_withdraw(
+        ctx: Context<DelegatedWithdraw>,
+        amount: u64,
+        close_vault: bool,
+    ) -> Result<()> {
+        let amount_in_minimum_units = to_minimum_units(amount, ctx.accounts.mint.decimals)?;
+        d_withdraw_raw(ctx, amount_in_minimum_units, close_vault)
+    }
+
+    // Same as d_withdraw, with the amount already in base units of the mint
+    pub fn d_withdraw_raw(
+        ctx: Context<DelegatedWithdraw>,
+        amount_in_minimum_units: u64,
+        close_vault: bool,
+    ) -> Result<()> {
+        log_compute_units(&ctx.accounts.config, "d_withdraw: start");
+        let main_account = &mut ctx.accounts.main_account.load_mut()?;
+        let info_account = &mut ctx.accounts.info_account;
+        let delegated_account = &mut ctx.accounts.delegated_account;
+        let owner = ctx.accounts.owner.key();
+
+        let clock = Clock::get()?;
+        record_withdrawal(
+            main_account,
+            info_account,
+            delegated_account,
+            amount_in_minimum_units,
+            &clock,
+        )?;
+        log_compute_units(&ctx.accounts.config, "d_withdraw: recorded");
+
+        let binding = info_account.key();
+
+        let seeds = &[
+            INFO_SEED,
+            owner.as_ref(),
+            binding.as_ref(),
+            &[ctx.bumps.delegated_account], // Use vault's seeds and bump
+        ];
+
+        let fee = fee_amount(amount_in_minimum_units, ctx.accounts.config.withdraw_fee_bps)?;
+
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.vault.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.receipt_token_account.to_account_info(),
+                    authority: delegated_account.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            amount_in_minimum_units - fee,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        collect_fee(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.vault.to_account_info(),
+            &ctx.accounts.mint,
+            ctx.accounts.treasury_token_account.as_ref(),
+            delegated_account.to_account_info(),
+            &[&seeds[..]],
+            fee,
+        )?;
+
+        if close_vault {
+            close_empty_vault(
+                &mut ctx.accounts.vault,
+                ctx.accounts.owner.to_account_info(),
+                delegated_account.to_account_info(),
+                ctx.accounts.token_program.to_account_info(),
+                &[&seeds[..]],
+            )?;
+        }
+
+        // Receipts minted for this position have to be returned, positions opened before
+        // receipts were enabled only burn what they received
+        let burn_amount = amount_in_minimum_units.min(delegated_account.receipt_minted);
+        if burn_amount > 0 {
+            let receipt_mint = ctx
+                .accounts
+                .receipt_mint
+                .as_ref()
+                .ok_or(CustomError::ReceiptAccountsRequired)?;
+            let receipt_holder = ctx
+                .accounts
+                .receipt_holder_account
+                .as_ref()
+                .ok_or(CustomError::ReceiptAccountsRequired)?;
+            require_keys_eq!(
+                receipt_mint.key(),
+                receipt_mint_address(&ctx.accounts.mint.key(), ctx.program_id),
+                CustomError::InvalidReceiptMint
+            );
+
+            anchor_spl::token_interface::burn(
+                CpiContext::new(
+                    ctx.accounts.token_program.to_account_info(),
+                    Burn {
+                        mint: receipt_mint.to_account_info(),
+                        from: receipt_holder.to_account_info(),
+                        authority: ctx.accounts.owner.to_account_info(),
+                    },
+                ),
+                burn_amount,
+            )?;
+            delegated_account.receipt_minted -= burn_amount;
+        }
+
+        log_compute_units(&ctx.accounts.config, "d_withdraw: transferred");
+
+        // Record event
+        let event = DelegatedTokenWithdrawn {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: owner.key(),
+            delegator: info_account.key(),
+            delegator_owner: info_account.owner.key(),
+            amount: amount_in_minimum_units,
+            fee,
+            stake: delegated_account.stake,
+            activating_stake: delegated_account.activating_stake,
+            server_total: info_account.total,
+            server_seq: info_account.next_event_seq(),
+            total_stake: main_account.total_stake,
+            total_users: main_account.total_users,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        };
+        #[cfg(feature = "event-cpi")]
+        emit_cpi!(event);
+        emit!(event);
+
+        Ok(())
+    }
+
+    // Give a server a concurrent Merkle tree for compressed delegations. The client allocates
+    // `merkle_tree` for the chosen depth and buffer size, owned by the compression program.
+    pub fn init_delegation_tree(
+        ctx: Context<InitDelegationTree>,
+        max_depth: u32,
+        max_buffer_size: u32,
+    ) -> Result<()> {
+        require_keys_eq!(
+            ctx.accounts.info_account.delegation_tree,
+            Pubkey::default(),
+            CustomError::DelegationTreeExists
+        );
+
+        let info_key = ctx.accounts.info_account.key();
+        let seeds = &[TREE_SEED, info_key.as_ref(), &[ctx.bumps.tree_authority]];
+        spl_account_compression::cpi::init_empty_merkle_tree(
+            CpiContext::new_with_signer(
+                ctx.accounts.compression_program.to_account_info(),
+                Initialize {
+                    merkle_tree: ctx.accounts.merkle_tree.to_account_info(),
+                    authority: ctx.accounts.tree_authority.to_account_info(),
+                    noop: ctx.accounts.noop_program.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            max_depth,
+            max_buffer_size,
+        )?;
+        ctx.accounts.info_account.delegation_tree = ctx.accounts.merkle_tree.key();
+
+        let clock = Clock::get()?;
+        emit!(DelegationTreeInitialized {
+            schema_version: EVENT_SCHEMA_VERSION,
+            server: info_key,
+            merkle_tree: ctx.accounts.merkle_tree.key(),
+            max_depth,
+            max_buffer_size,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Delegate through a leaf of the server's delegation tree instead of a position PDA, the
+    // stake is pooled in one vault per server. `position` is the caller's current leaf, None to
+    // open a new one, with its proof path in remaining_accounts. Compressed positions don't
+    // take referrals or mint receipts.
+    pub fn d_deposit_compressed<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CompressedDelegatedDeposit<'info>>,
+        amount: u64,
+        lockup: LockupTier,
+        position: Option<CompressedDelegation>,
+    ) -> Result<()> {
+        check_gate(
+            &ctx.accounts.config,
+            ctx.accounts.gate_account.as_ref(),
+            &ctx.accounts.owner.key(),
+        )?;
+
+        let clock = Clock::get()?;
+        let owner = ctx.accounts.owner.key();
+        let amount_in_minimum_units = to_minimum_units(amount, ctx.accounts.mint.decimals)?;
+        let mut delegation = compressed_position(position.as_ref(), &owner)?;
+
+        record_delegation(
+            &mut ctx.accounts.main_account.load_mut()?,
+            &mut ctx.accounts.info_account,
+            &mut delegation,
+            &ctx.accounts.config,
+            ctx.accounts.price_update.as_ref(),
+            owner,
+            amount_in_minimum_units,
+            ctx.accounts.mint.decimals,
+            lockup,
+            &clock,
+        )?;
+
+        open_vault(
+            &ctx.accounts.pool_vault,
+            ctx.accounts.tree_authority.to_account_info(),
+            &ctx.accounts.mint,
+            ctx.accounts.payer.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.associated_token_program.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+        )?;
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.sender_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.pool_vault.to_account_info(),
+                    authority: ctx.accounts.owner.to_account_info(),
+                },
+            ),
+            amount_in_minimum_units,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        let info_key = ctx.accounts.info_account.key();
+        let seeds = &[TREE_SEED, info_key.as_ref(), &[ctx.bumps.tree_authority]];
+        write_compressed_delegation(
+            CpiContext::new_with_signer(
+                ctx.accounts.compression_program.to_account_info(),
+                Modify {
+                    merkle_tree: ctx.accounts.merkle_tree.to_account_info(),
+                    authority: ctx.accounts.tree_authority.to_account_info(),
+                    noop: ctx.accounts.noop_program.to_account_info(),
+                },
+                &[&seeds[..]],
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            &mut ctx.accounts.info_account,
+            position.as_ref(),
+            &delegation,
+        )?;
+
+        let main_account = ctx.accounts.main_account.load()?;
+        emit!(TokenDelegatedDeposited {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner,
+            delegator: info_key,
+            delegator_owner: ctx.accounts.info_account.owner,
+            amount: amount_in_minimum_units,
+            stake: delegation.stake,
+            activating_stake: delegation.activating_stake,
+            server_total: ctx.accounts.info_account.total,
+            server_seq: ctx.accounts.info_account.next_event_seq(),
+            memo: [0; MAX_MEMO_LEN],
+            total_stake: main_account.total_stake,
+            total_users: main_account.total_users,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Withdraw from a compressed position, see d_deposit_compressed
+    pub fn d_withdraw_compressed<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CompressedDelegatedWithdraw<'info>>,
+        amount: u64,
+        position: CompressedDelegation,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        let amount_in_minimum_units = to_minimum_units(amount, ctx.accounts.mint.decimals)?;
+        let mut delegation = compressed_position(Some(&position), &ctx.accounts.owner.key())?;
+
+        record_withdrawal(
+            &mut ctx.accounts.main_account.load_mut()?,
+            &mut ctx.accounts.info_account,
+            &mut delegation,
+            amount_in_minimum_units,
+            &clock,
+        )?;
+
+        let info_key = ctx.accounts.info_account.key();
+        let seeds = &[TREE_SEED, info_key.as_ref(), &[ctx.bumps.tree_authority]];
+        let fee = fee_amount(amount_in_minimum_units, ctx.accounts.config.withdraw_fee_bps)?;
+
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.pool_vault.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.receipt_token_account.to_account_info(),
+                    authority: ctx.accounts.tree_authority.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            amount_in_minimum_units - fee,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        collect_fee(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.pool_vault.to_account_info(),
+            &ctx.accounts.mint,
+            ctx.accounts.treasury_token_account.as_ref(),
+            ctx.accounts.tree_authority.to_account_info(),
+            &[&seeds[..]],
+            fee,
+        )?;
+
+        write_compressed_delegation(
+            CpiContext::new_with_signer(
+                ctx.accounts.compression_program.to_account_info(),
+                Modify {
+                    merkle_tree: ctx.accounts.merkle_tree.to_account_info(),
+                    authority: ctx.accounts.tree_authority.to_account_info(),
+                    noop: ctx.accounts.noop_program.to_account_info(),
+                },
+                &[&seeds[..]],
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            &mut ctx.accounts.info_account,
+            Some(&position),
+            &delegation,
+        )?;
+
+        let main_account = ctx.accounts.main_account.load()?;
+        emit!(DelegatedTokenWithdrawn {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: ctx.accounts.owner.key(),
+            delegator: info_key,
+            delegator_owner: ctx.accounts.info_account.owner,
+            amount: amount_in_minimum_units,
+            fee,
+            stake: delegation.stake,
+            activating_stake: delegation.activating_stake,
+            server_total: ctx.accounts.info_account.total,
+            server_seq: ctx.accounts.info_account.next_event_seq(),
+            total_stake: main_account.total_stake,
+            total_users: main_account.total_users,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Claim the rewards of a compressed position, see d_deposit_compressed
+    pub fn claim_rewards_compressed<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ClaimCompressedRewards<'info>>,
+        position: CompressedDelegation,
+    ) -> Result<()> {
+        let mut delegation = compressed_position(Some(&position), &ctx.accounts.owner.key())?;
+        delegation.settle_rewards(ctx.accounts.info_account.acc_reward_per_share)?;
+
+        let amount = delegation.pending_rewards;
+        require!(amount > 0, CustomError::NothingToClaim);
+        delegation.pending_rewards = 0;
+
+        let seeds = &[MAIN_SEED, &[ctx.bumps.main_account]];
+        let fee = fee_amount(amount, ctx.accounts.config.reward_fee_bps)?;
+
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.reward_vault.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.receipt_token_account.to_account_info(),
+                    authority: ctx.accounts.main_account.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            amount - fee,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        collect_fee(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.reward_vault.to_account_info(),
+            &ctx.accounts.mint,
+            ctx.accounts.treasury_token_account.as_ref(),
+            ctx.accounts.main_account.to_account_info(),
+            &[&seeds[..]],
+            fee,
+        )?;
+
+        let info_key = ctx.accounts.info_account.key();
+        let tree_seeds = &[TREE_SEED, info_key.as_ref(), &[ctx.bumps.tree_authority]];
+        write_compressed_delegation(
+            CpiContext::new_with_signer(
+                ctx.accounts.compression_program.to_account_info(),
+                Modify {
+                    merkle_tree: ctx.accounts.merkle_tree.to_account_info(),
+                    authority: ctx.accounts.tree_authority.to_account_info(),
+                    noop: ctx.accounts.noop_program.to_account_info(),
+                },
+                &[&tree_seeds[..]],
+            )
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec()),
+            &mut ctx.accounts.info_account,
+            Some(&position),
+            &delegation,
+        )?;
+
+        let clock = Clock::get()?;
+        emit!(RewardsClaimed {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: ctx.accounts.owner.key(),
+            delegator: info_key,
+            amount,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Read-only network totals with the derived metrics explorers show, returned as return data
+    pub fn get_network_stats(ctx: Context<GetNetworkStats>) -> Result<NetworkStats> {
+        let main_account = ctx.accounts.main_account.load()?;
+        let total_servers = main_account.total_servers;
+
+        let average_stake_per_server = if total_servers > 0 {
+            main_account.total_stake / total_servers as u64
+        } else {
+            0
+        };
+        let delegation_ratio_bps = if main_account.total_stake > 0 {
+            (main_account.total_delegated as u128 * MAX_BPS as u128
+                / main_account.total_stake as u128) as u16
+        } else {
+            0
+        };
+
+        Ok(NetworkStats {
+            total_stake: main_account.total_stake,
+            total_self_stake: main_account.total_self_stake,
+            total_delegated: main_account.total_delegated,
+            total_users: main_account.total_users,
+            total_servers,
+            total_delegations: main_account.total_users.saturating_sub(total_servers),
+            average_stake_per_server,
+            delegation_ratio_bps,
+        })
+    }
+
+    // Read-only summary of a server for simulateTransaction callers, returned as return data.
+    // Pass `delegated_account` to also get the rewards that position could claim now.
+    pub fn get_server_info(ctx: Context<GetServerInfo>) -> Result<ServerInfo> {
+        let main_account = &ctx.accounts.main_account.load()?;
+        let info_account = &ctx.accounts.info_account;
+
+        let self_stake = main_account.to_common_units(&info_account.mint, info_account.stake)?;
+        let status = if info_account.stake == 0 {
+            ServerStatus::Inactive
+        } else if self_stake < MINIMUM_STAKE {
+            ServerStatus::BelowMinimum
+        } else if info_account
+            .check_self_bond(ctx.accounts.config.self_bond_multiple)
+            .is_err()
+        {
+            ServerStatus::OverBonded
+        } else {
+            ServerStatus::Active
+        };
+
+        let total_value = main_account.to_common_units(&info_account.mint, info_account.total)?;
+        let capabilities =
+            info_account.capabilities & ctx.accounts.config.capabilities_met(total_value);
+
+        let pending_rewards = match &ctx.accounts.delegated_account {
+            Some(delegated_account) => {
+                let mut position = (**delegated_account).clone();
+                position.settle_rewards(info_account.acc_reward_per_share)?;
+                position.pending_rewards
+            }
+            None => 0,
+        };
+
+        Ok(ServerInfo {
+            server: info_account.key(),
+            owner: info_account.owner,
+            mint: info_account.mint,
+            stake: info_account.stake,
+            delegated: info_account.delegated,
+            total: info_account.total,
+            activating_stake: info_account.activating_stake,
+            total_delegators: info_account.total_delegators,
+            vault_balance: ctx.accounts.vault.as_ref().map_or(0, |vault| vault.amount),
+            status,
+            complaints: info_account.complaints,
+            pending_rewards,
+            capabilities,
+            tier: info_account.tier,
+            pricing: info_account.pricing,
+        })
+    }
+
+    // Stake `owner` holds in `info_account`: the operator stake for the server owner, the
+    // delegated position otherwise. Meant for CPI callers, see `query::stake_of`.
+    pub fn get_stake_of(ctx: Context<GetStakeOf>, owner: Pubkey) -> Result<StakeOf> {
+        let info_account = &ctx.accounts.info_account;
+
+        let (stake, activating_stake, lock_until) = if owner == info_account.owner {
+            (info_account.stake, 0, 0)
+        } else {
+            match &ctx.accounts.delegated_account {
+                Some(delegated_account) => (
+                    delegated_account.stake,
+                    delegated_account.activating_stake,
+                    delegated_account.lock_until,
+                ),
+                None => (0, 0, 0),
+            }
+        };
+
+        Ok(StakeOf {
+            server: info_account.key(),
+            owner,
+            mint: info_account.mint,
+            stake,
+            activating_stake,
+            lock_until,
+            server_total: info_account.total,
+        })
+    }
+
+    // Preflight for deposit: runs its checks for `owner` without moving tokens and returns the
+    // error code the deposit would fail with, so wallets can explain it before signing
+    pub fn validate_deposit(
+        ctx: Context<ValidateDeposit>,
+        owner: Pubkey,
+        amount: u64,
+    ) -> Result<PreflightResult> {
+        let accounts = &ctx.accounts;
+        let check = (|| -> Result<()> {
+            require_keys_eq!(accounts.info_account.owner, owner, ErrorCode::ConstraintHasOne);
+            check_gate(&accounts.config, accounts.gate_account.as_ref(), &owner)?;
+
+            let amount_in_minimum_units = to_minimum_units(amount, accounts.mint.decimals)?;
+            check_sender_balance(
+                &accounts.sender_token_account,
+                &accounts.mint.key(),
+                amount_in_minimum_units,
+            )?;
+            check_deposit(
+                &*accounts.main_account.load()?,
+                &accounts.info_account,
+                &accounts.config,
+                amount_in_minimum_units,
+            )
+        })();
+
+        Ok(check.into())
+    }
+
+    // Preflight for d_deposit, see validate_deposit. The delegation bookkeeping runs on copies of
+    // the accounts, `delegated_account` is left out before the first delegation to the server.
+    pub fn validate_d_deposit(
+        ctx: Context<ValidateDelegatedDeposit>,
+        owner: Pubkey,
+        amount: u64,
+        lockup: LockupTier,
+    ) -> Result<PreflightResult> {
+        let accounts = &ctx.accounts;
+        let check = (|| -> Result<()> {
+            check_gate(&accounts.config, accounts.gate_account.as_ref(), &owner)?;
+
+            let amount_in_minimum_units = to_minimum_units(amount, accounts.mint.decimals)?;
+            check_sender_balance(
+                &accounts.sender_token_account,
+                &accounts.mint.key(),
+                amount_in_minimum_units,
+            )?;
+
+            let mut main_account = *accounts.main_account.load()?;
+            let mut info_account = accounts.info_account.clone();
+            let mut delegated_account = accounts
+                .delegated_account
+                .as_ref()
+                .map(|delegated_account| (**delegated_account).clone())
+                .unwrap_or_default();
+            record_delegation(
+                &mut main_account,
+                &mut info_account,
+                &mut delegated_account,
+                &accounts.config,
+                accounts.price_update.as_ref(),
+                owner,
+                amount_in_minimum_units,
+                accounts.mint.decimals,
+                lockup,
+                &Clock::get()?,
+            )?;
+            Ok(())
+        })();
+
+        Ok(check.into())
+    }
+
+    // Preview of a deposit or withdrawal of `amount` whole tokens by `owner`, as return data: the
+    // base units moved, the withdrawal fee and the balances left behind. The instruction's checks
+    // run on copies of the accounts and fail the quote the same way, validate_deposit and
+    // validate_d_deposit report them as codes instead.
+    pub fn quote(
+        ctx: Context<GetQuote>,
+        owner: Pubkey,
+        kind: QuoteKind,
+        amount: u64,
+    ) -> Result<Quote> {
+        let accounts = &ctx.accounts;
+        let amount_in_minimum_units = to_minimum_units(amount, accounts.mint.decimals)?;
+        let fee = match kind {
+            QuoteKind::Deposit { .. } if owner == accounts.info_account.owner => 0,
+            QuoteKind::Deposit { .. } => {
+                fee_amount(amount_in_minimum_units, accounts.info_account.entry_fee_bps)?
+            }
+            QuoteKind::Withdraw => {
+                fee_amount(amount_in_minimum_units, accounts.config.withdraw_fee_bps)?
+            }
+        };
+
+        let mut main_account = *accounts.main_account.load()?;
+        let mut info_account = accounts.info_account.clone();
+        let (stake, activating_stake, lock_until) = if owner == info_account.owner {
+            match kind {
+                QuoteKind::Deposit { .. } => {
+                    check_deposit(
+                        &main_account,
+                        &info_account,
+                        &accounts.config,
+                        amount_in_minimum_units,
+                    )?;
+                    info_account.stake += amount_in_minimum_units;
+                    info_account.total += amount_in_minimum_units;
+                }
+                QuoteKind::Withdraw => {
+                    require!(
+                        amount_in_minimum_units <= info_account.stake,
+                        CustomError::InsufficientFunds
+                    );
+                    require!(
+                        amount_in_minimum_units <= info_account.stake - info_account.channel_locked,
+                        CustomError::ChannelCollateralLocked
+                    );
+                    info_account.stake -= amount_in_minimum_units;
+                    info_account.total -= amount_in_minimum_units;
+                    info_account.check_self_bond(accounts.config.self_bond_multiple)?;
+                    info_account.check_exit_notice(
+                        &main_account,
+                        accounts.config.exit_notice_secs,
+                        Clock::get()?.unix_timestamp,
+                    )?;
+                }
+            }
+            (info_account.stake, 0, 0)
+        } else {
+            let mut delegated_account = accounts
+                .delegated_account
+                .as_ref()
+                .map(|delegated_account| (**delegated_account).clone())
+                .unwrap_or_default();
+            let clock = Clock::get()?;
+            match kind {
+                QuoteKind::Deposit { lockup } => {
+                    record_delegation(
+                        &mut main_account,
+                        &mut info_account,
+                        &mut delegated_account,
+                        &accounts.config,
+                        accounts.price_update.as_ref(),
+                        owner,
+                        amount_in_minimum_units - fee,
+                        accounts.mint.decimals,
+                        lockup,
+                        &clock,
+                    )?;
+                }
+                QuoteKind::Withdraw => record_withdrawal(
+                    &mut main_account,
+                    &mut info_account,
+                    &mut delegated_account,
+                    amount_in_minimum_units,
+                    &clock,
+                )?,
+            }
+            (
+                delegated_account.stake,
+                delegated_account.activating_stake,
+                delegated_account.lock_until,
+            )
+        };
+
+        Ok(Quote {
+            server: info_account.key(),
+            owner,
+            mint: info_account.mint,
+            amount: amount_in_minimum_units,
+            fee,
+            net_amount: amount_in_minimum_units - fee,
+            stake,
+            activating_stake,
+            lock_until,
+            server_total: info_account.total,
+        })
+    }
+
+    // Every pre-check of a deposit or withdrawal of `amount` whole tokens by `owner`, run on
+    // copies of the accounts. Where validate_deposit and validate_d_deposit stop at the first
+    // failure, the return data lists each rule the operation would break, in the order the
+    // instruction checks them. The sender's balance is only checked when its account is passed.
+    pub fn diagnose(
+        ctx: Context<Diagnose>,
+        owner: Pubkey,
+        operation: QuoteKind,
+        amount: u64,
+    ) -> Result<Diagnosis> {
+        let accounts = &ctx.accounts;
+        let config = &accounts.config;
+        let main_account = accounts.main_account.load()?;
+        let info_account = &accounts.info_account;
+        let delegated_account = accounts
+            .delegated_account
+            .as_ref()
+            .map(|delegated_account| (**delegated_account).clone())
+            .unwrap_or_default();
+        let clock = Clock::get()?;
+        let mut diagnosis = Diagnosis::default();
+
+        // The other rules need the amount in base units
+        let amount_in_minimum_units = match to_minimum_units(amount, accounts.mint.decimals) {
+            Ok(amount_in_minimum_units) => amount_in_minimum_units,
+            Err(error) => {
+                diagnosis.check(Err(error));
+                return Ok(diagnosis);
+            }
+        };
+
+        let mut after = (**info_account).clone();
+        match operation {
+            QuoteKind::Deposit { .. } => {
+                diagnosis.check(check_gate(config, accounts.gate_account.as_ref(), &owner));
+                if let Some(sender_token_account) = &accounts.sender_token_account {
+                    diagnosis.check(check_sender_balance(
+                        sender_token_account,
+                        &accounts.mint.key(),
+                        amount_in_minimum_units,
+                    ));
+                }
+                if owner == info_account.owner {
+                    diagnosis.check(check_deposit(
+                        &main_account,
+                        info_account,
+                        config,
+                        amount_in_minimum_units,
+                    ));
+                } else {
+                    let fee = fee_amount(amount_in_minimum_units, info_account.entry_fee_bps)?;
+                    let net_amount = amount_in_minimum_units - fee;
+                    diagnosis.require(
+                        info_account.exit_announced_at == 0,
+                        CustomError::ServerExiting,
+                    );
+                    diagnosis.check(check_delegation_limits(
+                        &main_account,
+                        info_account,
+                        &delegated_account,
+                        config,
+                        accounts.price_update.as_ref(),
+                        net_amount,
+                        accounts.mint.decimals,
+                    ));
+                    after.activating_stake = after.activating_stake.saturating_add(net_amount);
+                    diagnosis.check(after.check_self_bond(config.self_bond_multiple));
+                }
+            }
+            QuoteKind::Withdraw if owner == info_account.owner => {
+                diagnosis.require(
+                    amount_in_minimum_units <= info_account.stake,
+                    CustomError::InsufficientFunds,
+                );
+                diagnosis.require(
+                    amount_in_minimum_units > info_account.stake
+                        || amount_in_minimum_units
+                            <= info_account.stake - info_account.channel_locked,
+                    CustomError::ChannelCollateralLocked,
+                );
+                let removed = amount_in_minimum_units.min(after.stake);
+                after.stake -= removed;
+                after.total -= removed;
+                diagnosis.check(after.check_self_bond(config.self_bond_multiple));
+                diagnosis.check(after.check_exit_notice(
+                    &main_account,
+                    config.exit_notice_secs,
+                    clock.unix_timestamp,
+                ));
+            }
+            QuoteKind::Withdraw => {
+                diagnosis.require(
+                    amount_in_minimum_units
+                        <= delegated_account.stake + delegated_account.activating_stake,
+                    CustomError::InsufficientFunds,
+                );
+                diagnosis.require(
+                    clock.unix_timestamp >= delegated_account.lock_until,
+                    CustomError::StakeLocked,
+                );
+            }
+        }
+
+        Ok(diagnosis)
+    }
+
+    // Rebuild the global counters and per-mint stake totals from the server accounts (admin
+    // only), over as many calls as needed. remaining_accounts holds server accounts in strictly
+    // increasing address order, continuing after the previous page; `restart` starts a new pass.
+    // Delegations are counted through their server's total_delegators, so compressed ones are
+    // included, and run reconcile first on servers whose own totals may have drifted. Stake that
+    // moves on an already counted server during the pass is missed, so a pass should be finished
+    // quickly and then applied with finish_recount. v1 server accounts have to be migrated first.
+    pub fn recount<'info>(
+        ctx: Context<'_, '_, 'info, 'info, RecountTotals<'info>>,
+        restart: bool,
+    ) -> Result<()> {
+        let recount = &mut ctx.accounts.recount;
+        if restart || recount.version == 0 {
+            recount.set_inner(Recount {
+                version: VERSION,
+                bump: ctx.bumps.recount,
+                ..Default::default()
+            });
+        }
+
+        let main_account = ctx.accounts.main_account.load()?;
+        for account in ctx.remaining_accounts {
+            // Strictly increasing addresses across pages, so no server is counted twice
+            require!(account.key() > recount.cursor, CustomError::InvalidBatchAccount);
+            recount.cursor = account.key();
+
+            let server = Account::<InfoAccount>::try_from(account)?;
+            let index = main_account
+                .approved_mints()
+                .iter()
+                .position(|entry| entry.mint == server.mint)
+                .ok_or(CustomError::InvalidMint)?;
+
+            recount.servers = recount
+                .servers
+                .checked_add(1)
+                .ok_or(CustomError::NumberOverflow)?;
+            recount.users = recount
+                .users
+                .checked_add(1)
+                .and_then(|users| users.checked_add(server.total_delegators))
+                .ok_or(CustomError::NumberOverflow)?;
+            recount.self_stake[index] = recount.self_stake[index]
+                .checked_add(server.stake)
+                .ok_or(CustomError::NumberOverflow)?;
+            recount.total_stake[index] = recount.total_stake[index]
+                .checked_add(server.total)
+                .ok_or(CustomError::NumberOverflow)?;
+        }
+
+        Ok(())
+    }
+
+    // Write a finished recount pass into the main account and close it (admin only)
+    pub fn finish_recount(ctx: Context<FinishRecount>) -> Result<()> {
+        let main_account = &mut ctx.accounts.main_account.load_mut()?;
+        main_account.apply_recount(&ctx.accounts.recount)?;
+
+        let clock = Clock::get()?;
+        emit!(TotalsRecounted {
+            schema_version: EVENT_SCHEMA_VERSION,
+            total_users: main_account.total_users,
+            total_servers: main_account.total_servers,
+            total_stake: main_account.total_stake,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Permissionless audit of a server's counters. remaining_accounts holds a (delegated_account,
+    // vault) pair for every delegation of the server, ordered by delegated_account address. The
+    // position records are the reference: `total`, `delegated` and `activating_stake` are
+    // recomputed from them and fixed when they drifted, and vaults holding less than their record
+    // are reported as a shortfall.
+    pub fn reconcile<'info>(ctx: Context<'_, '_, 'info, 'info, Reconcile<'info>>) -> Result<()> {
+        let info_account = &mut ctx.accounts.info_account;
+        let info_key = info_account.key();
+        // Compressed positions have no account to read, only servers without them can be audited
+        require!(
+            info_account.delegation_leaves == 0,
+            CustomError::ReconcileUnsupported
+        );
+        require!(
+            ctx.remaining_accounts.len() == info_account.total_delegators as usize * 2,
+            CustomError::InvalidBatchSize
+        );
+
+        let mut shortfall = info_account.stake.saturating_sub(ctx.accounts.vault.amount);
+        let mut delegated: u64 = 0;
+        let mut activating: u64 = 0;
+        let mut previous = Pubkey::default();
+
+        for pair in ctx.remaining_accounts.chunks(2) {
+            let (delegated_info, vault_info) = (&pair[0], &pair[1]);
+
+            // Strictly increasing addresses, so no position is counted twice
+            require!(
+                delegated_info.key() > previous,
+                CustomError::InvalidBatchAccount
+            );
+            previous = delegated_info.key();
+
+            let delegated_account = Account::<DelegatedAccount>::try_from(delegated_info)?;
+            require_keys_eq!(
+                delegated_account.delegator,
+                info_key,
+                CustomError::InvalidBatchAccount
+            );
+            require_keys_eq!(
+                vault_info.key(),
+                get_associated_token_address_with_program_id(
+                    &delegated_info.key(),
+                    &info_account.mint,
+                    &ctx.accounts.token_program.key(),
+                ),
+                CustomError::InvalidBatchAccount
+            );
+            let vault = InterfaceAccount::<TokenAccount>::try_from(vault_info)?;
+
+            let position = delegated_account.stake + delegated_account.activating_stake;
+            shortfall = shortfall
+                .checked_add(position.saturating_sub(vault.amount))
+                .ok_or(CustomError::NumberOverflow)?;
+            delegated = delegated
+                .checked_add(delegated_account.stake)
+                .ok_or(CustomError::NumberOverflow)?;
+            activating = activating
+                .checked_add(delegated_account.activating_stake)
+                .ok_or(CustomError::NumberOverflow)?;
+        }
+
+        let recorded_total = info_account.total;
+        let computed_total = info_account
+            .stake
+            .checked_add(delegated)
+            .ok_or(CustomError::NumberOverflow)?;
+
+        if computed_total != recorded_total {
+            let main_account = &mut ctx.accounts.main_account.load_mut()?;
+            if computed_total > recorded_total {
+                main_account.add_stake(&info_account.mint, computed_total - recorded_total)?;
+            } else {
+                main_account.sub_stake(&info_account.mint, recorded_total - computed_total)?;
+            }
+        }
+        info_account.total = computed_total;
+        info_account.delegated = delegated;
+        info_account.activating_stake = activating;
+        info_account.refresh_stake(&mut ctx.accounts.main_account.load_mut()?)?;
+
+        let clock = Clock::get()?;
+        emit!(AccountingReconciled {
+            schema_version: EVENT_SCHEMA_VERSION,
+            server: info_key,
+            recorded_total,
+            computed_total,
+            vault_shortfall: shortfall,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Stake-backed complaint from a delegator, limited to one per delegation per epoch
+    pub fn flag_server(ctx: Context<FlagServer>, reason_code: u8) -> Result<()> {
+        let info_account = &mut ctx.accounts.info_account;
+        let delegated_account = &mut ctx.accounts.delegated_account;
+        let epoch = Clock::get()?.epoch;
+
+        require!(delegated_account.stake > 0, CustomError::NoActiveStake);
+        require!(
+            delegated_account.last_flag_epoch != Some(epoch),
+            CustomError::AlreadyFlaggedThisEpoch
+        );
+
+        delegated_account.last_flag_epoch = Some(epoch);
+        info_account.complaints = info_account
+            .complaints
+            .checked_add(1)
+            .ok_or(CustomError::NumberOverflow)?;
+
+        let clock = Clock::get()?;
+        emit!(ServerFlagged {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: ctx.accounts.owner.key(),
+            server: info_account.key(),
+            reason_code,
+            stake: delegated_account.stake,
+            complaints: info_account.complaints,
+            epoch,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn initialize_config(ctx: Context<InitializeConfig>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.version = VERSION;
+        config.admin = ctx.accounts.admin.key();
+        config.self_bond_multiple = DEFAULT_SELF_BOND_MULTIPLE;
+
+        let clock = Clock::get()?;
+        emit!(ConfigUpdated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            admin: config.admin,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Admin-only update of the tunable protocol parameters, unset fields are left untouched
+    pub fn update_config(ctx: Context<UpdateConfig>, params: ConfigParams) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        let clock = Clock::get()?;
+        let changes = ConfigChanges {
+            admin: config.admin,
+            clock: &clock,
+        };
+
+        if let Some(referral_bps) = params.referral_bps {
+            require!(referral_bps <= MAX_BPS, CustomError::InvalidBasisPoints);
+            changes.set(&mut config.referral_bps, referral_bps, ConfigParam::ReferralBps);
+        }
+        if let Some(self_bond_multiple) = params.self_bond_multiple {
+            changes.set(
+                &mut config.self_bond_multiple,
+                self_bond_multiple,
+                ConfigParam::SelfBondMultiple,
+            );
+        }
+        if let Some(gate_program) = params.gate_program {
+            changes.set(&mut config.gate_program, gate_program, ConfigParam::GateProgram);
+        }
+        if let Some(withdraw_fee_bps) = params.withdraw_fee_bps {
+            require!(withdraw_fee_bps <= MAX_BPS, CustomError::InvalidBasisPoints);
+            changes.set(
+                &mut config.withdraw_fee_bps,
+                withdraw_fee_bps,
+                ConfigParam::WithdrawFeeBps,
+            );
+        }
+        if let Some(reward_fee_bps) = params.reward_fee_bps {
+            require!(reward_fee_bps <= MAX_BPS, CustomError::InvalidBasisPoints);
+            changes.set(&mut config.reward_fee_bps, reward_fee_bps, ConfigParam::RewardFeeBps);
+        }
+        if let Some(rate_oracle) = params.rate_oracle {
+            changes.set(&mut config.rate_oracle, rate_oracle, ConfigParam::RateOracle);
+        }
+        if let Some(receipt_tokens) = params.receipt_tokens {
+            changes.set(&mut config.receipt_tokens, receipt_tokens, ConfigParam::ReceiptTokens);
+        }
+        if let Some(staking_mint) = params.staking_mint {
+            // initialize_main approved it as the primary mint, the two have to stay the same
+            require!(ctx.accounts.main_account.data_is_empty(), CustomError::StakingMintFixed);
+            changes.set(&mut config.staking_mint, staking_mint, ConfigParam::StakingMint);
+        }
+        if let Some(usage_oracle) = params.usage_oracle {
+            changes.set(&mut config.usage_oracle, usage_oracle, ConfigParam::UsageOracle);
+        }
+        if let Some(revenue_stream_secs) = params.revenue_stream_secs {
+            changes.set(
+                &mut config.revenue_stream_secs,
+                revenue_stream_secs,
+                ConfigParam::RevenueStreamSecs,
+            );
+        }
+        if let Some(heartbeat_timeout_secs) = params.heartbeat_timeout_secs {
+            changes.set(
+                &mut config.heartbeat_timeout_secs,
+                heartbeat_timeout_secs,
+                ConfigParam::HeartbeatTimeoutSecs,
+            );
+        }
+        if let Some(channel_challenge_secs) = params.channel_challenge_secs {
+            changes.set(
+                &mut config.channel_challenge_secs,
+                channel_challenge_secs,
+                ConfigParam::ChannelChallengeSecs,
+            );
+        }
+        if let Some(failure_penalty_bps) = params.failure_penalty_bps {
+            require!(failure_penalty_bps <= MAX_BPS, CustomError::InvalidBasisPoints);
+            changes.set(
+                &mut config.failure_penalty_bps,
+                failure_penalty_bps,
+                ConfigParam::FailurePenaltyBps,
+            );
+        }
+        if let Some(capability_minimums) = params.capability_minimums {
+            changes.set(
+                &mut config.capability_minimums,
+                capability_minimums,
+                ConfigParam::CapabilityMinimums,
+            );
+        }
+        if let Some(active_set_size) = params.active_set_size {
+            require!(
+                active_set_size as usize <= MAX_ACTIVE_SET,
+                CustomError::InvalidArgument
+            );
+            changes.set(&mut config.active_set_size, active_set_size, ConfigParam::ActiveSetSize);
+        }
+        if let Some(active_set_rotation_bps) = params.active_set_rotation_bps {
+            require!(active_set_rotation_bps <= MAX_BPS, CustomError::InvalidBasisPoints);
+            changes.set(
+                &mut config.active_set_rotation_bps,
+                active_set_rotation_bps,
+                ConfigParam::ActiveSetRotationBps,
+            );
+        }
+        if let Some(jail_cooldown_epochs) = params.jail_cooldown_epochs {
+            changes.set(
+                &mut config.jail_cooldown_epochs,
+                jail_cooldown_epochs,
+                ConfigParam::JailCooldownEpochs,
+            );
+        }
+        if let Some(wormhole_bridge) = params.wormhole_bridge {
+            changes.set(&mut config.wormhole_bridge, wormhole_bridge, ConfigParam::WormholeBridge);
+        }
+        if let Some(attestation_threshold_bps) = params.attestation_threshold_bps {
+            require!(attestation_threshold_bps <= MAX_BPS, CustomError::InvalidBasisPoints);
+            changes.set(
+                &mut config.attestation_threshold_bps,
+                attestation_threshold_bps,
+                ConfigParam::AttestationThresholdBps,
+            );
+        }
+        if let Some(bridge_emitter_chain) = params.bridge_emitter_chain {
+            changes.set(
+                &mut config.bridge_emitter_chain,
+                bridge_emitter_chain,
+                ConfigParam::BridgeEmitterChain,
+            );
+        }
+        if let Some(bridge_emitter) = params.bridge_emitter {
+            changes.set(&mut config.bridge_emitter, bridge_emitter, ConfigParam::BridgeEmitter);
+        }
+        if let Some(stable_mint) = params.stable_mint {
+            changes.set(&mut config.stable_mint, stable_mint, ConfigParam::StableMint);
+        }
+        if let Some(price_feed_id) = params.price_feed_id {
+            changes.set(&mut config.price_feed_id, price_feed_id, ConfigParam::PriceFeedId);
+        }
+        if let Some(max_price_age_secs) = params.max_price_age_secs {
+            changes.set(
+                &mut config.max_price_age_secs,
+                max_price_age_secs,
+                ConfigParam::MaxPriceAgeSecs,
+            );
+        }
+        if let Some(min_stake_usd) = params.min_stake_usd {
+            changes.set(&mut config.min_stake_usd, min_stake_usd, ConfigParam::MinStakeUsd);
+        }
+        if let Some(delegate_min_stake_usd) = params.delegate_min_stake_usd {
+            changes.set(
+                &mut config.delegate_min_stake_usd,
+                delegate_min_stake_usd,
+                ConfigParam::DelegateMinStakeUsd,
+            );
+        }
+        if let Some(revenue_vesting_cliff_secs) = params.revenue_vesting_cliff_secs {
+            changes.set(
+                &mut config.revenue_vesting_cliff_secs,
+                revenue_vesting_cliff_secs,
+                ConfigParam::RevenueVestingCliffSecs,
+            );
+        }
+        if let Some(revenue_vesting_secs) = params.revenue_vesting_secs {
+            changes.set(
+                &mut config.revenue_vesting_secs,
+                revenue_vesting_secs,
+                ConfigParam::RevenueVestingSecs,
+            );
+        }
+        if let Some(arbiter) = params.arbiter {
+            changes.set(&mut config.arbiter, arbiter, ConfigParam::Arbiter);
+        }
+        if let Some(dispute_timeout_secs) = params.dispute_timeout_secs {
+            changes.set(
+                &mut config.dispute_timeout_secs,
+                dispute_timeout_secs,
+                ConfigParam::DisputeTimeoutSecs,
+            );
+        }
+        if let Some(gc_inactive_epochs) = params.gc_inactive_epochs {
+            changes.set(
+                &mut config.gc_inactive_epochs,
+                gc_inactive_epochs,
+                ConfigParam::GcInactiveEpochs,
+            );
+        }
+        if let Some(gc_reward_bps) = params.gc_reward_bps {
+            require!(gc_reward_bps <= MAX_BPS, CustomError::InvalidBasisPoints);
+            changes.set(&mut config.gc_reward_bps, gc_reward_bps, ConfigParam::GcRewardBps);
+        }
+        if let Some(blocked_name_hashes) = params.blocked_name_hashes {
+            changes.set(
+                &mut config.blocked_name_hashes,
+                blocked_name_hashes,
+                ConfigParam::BlockedNameHashes,
+            );
+        }
+        if let Some(cpi_guard) = params.cpi_guard {
+            changes.set(&mut config.cpi_guard, cpi_guard, ConfigParam::CpiGuard);
+        }
+        if let Some(cpi_allowed_programs) = params.cpi_allowed_programs {
+            changes.set(
+                &mut config.cpi_allowed_programs,
+                cpi_allowed_programs,
+                ConfigParam::CpiAllowedPrograms,
+            );
+        }
+        if let Some(direct_registration) = params.direct_registration {
+            changes.set(
+                &mut config.direct_registration,
+                direct_registration,
+                ConfigParam::DirectRegistration,
+            );
+        }
+        if let Some(license_collection) = params.license_collection {
+            changes.set(
+                &mut config.license_collection,
+                license_collection,
+                ConfigParam::LicenseCollection,
+            );
+        }
+        if let Some(registration_cooldown_secs) = params.registration_cooldown_secs {
+            changes.set(
+                &mut config.registration_cooldown_secs,
+                registration_cooldown_secs,
+                ConfigParam::RegistrationCooldownSecs,
+            );
+        }
+        if let Some(contact_key) = params.contact_key {
+            changes.set(&mut config.contact_key, contact_key, ConfigParam::ContactKey);
+        }
+        if let Some(min_software_version) = params.min_software_version {
+            changes.set(
+                &mut config.min_software_version,
+                min_software_version,
+                ConfigParam::MinSoftwareVersion,
+            );
+        }
+        if let Some(legacy_program) = params.legacy_program {
+            changes.set(&mut config.legacy_program, legacy_program, ConfigParam::LegacyProgram);
+        }
+        if let Some(tenure_root) = params.tenure_root {
+            changes.set(&mut config.tenure_root, tenure_root, ConfigParam::TenureRoot);
+        }
+        if let Some(name_change_fee) = params.name_change_fee {
+            changes.set(&mut config.name_change_fee, name_change_fee, ConfigParam::NameChangeFee);
+        }
+        if let Some(exit_notice_secs) = params.exit_notice_secs {
+            require!(exit_notice_secs >= 0, CustomError::InvalidArgument);
+            changes.set(
+                &mut config.exit_notice_secs,
+                exit_notice_secs,
+                ConfigParam::ExitNoticeSecs,
+            );
+        }
+        if let Some(compute_telemetry) = params.compute_telemetry {
+            require!(
+                !compute_telemetry || cfg!(feature = "devnet"),
+                CustomError::InvalidArgument
+            );
+            changes.set(
+                &mut config.compute_telemetry,
+                compute_telemetry,
+                ConfigParam::ComputeTelemetry,
+            );
+        }
+        if let Some(max_rate_age_secs) = params.max_rate_age_secs {
+            changes.set(
+                &mut config.max_rate_age_secs,
+                max_rate_age_secs,
+                ConfigParam::MaxRateAgeSecs,
+            );
+        }
+        if let Some(bond_mint) = params.bond_mint {
+            changes.set(&mut config.bond_mint, bond_mint, ConfigParam::BondMint);
+        }
+        if let Some(bond_minimum) = params.bond_minimum {
+            changes.set(&mut config.bond_minimum, bond_minimum, ConfigParam::BondMinimum);
+        }
+        if let Some(bond_maximum) = params.bond_maximum {
+            changes.set(&mut config.bond_maximum, bond_maximum, ConfigParam::BondMaximum);
+        }
+        require!(
+            config.bond_maximum == 0 || config.bond_minimum <= config.bond_maximum,
+            CustomError::BondOutOfRange
+        );
+
+        record_audit(
+            config,
+            ctx.accounts.audit_log.as_ref(),
+            AuditAction::ConfigUpdated,
+            config.admin,
+            config.key(),
+            &clock,
+        )?;
+        emit!(ConfigUpdated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            admin: config.admin,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Create the audit log ring buffer (admin only). Once it exists, config changes, mint
+    // approvals, treasury transfers, allowlist changes, service-failure penalties and dispute
+    // rulings can't run without appending to it.
+    pub fn initialize_audit_log(ctx: Context<InitializeAuditLog>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        let clock = Clock::get()?;
+        let changes = ConfigChanges {
+            admin: config.admin,
+            clock: &clock,
+        };
+        changes.set(&mut config.audit_log, true, ConfigParam::AuditLog);
+
+        ctx.accounts.audit_log.load_init()?.push(AuditEntry::new(
+            AuditAction::AuditLogInitialized,
+            config.admin,
+            ctx.accounts.audit_log.key(),
+            &clock,
+        ));
+
+        Ok(())
+    }
+
+    // Add liquid staking tokens (mSOL, jitoSOL, ...) as extra collateral of a server. They sit in
+    // their own vault and keep earning Solana staking yield. Amount is in base units of the LST.
+    pub fn deposit_lst(ctx: Context<DepositLst>, amount: u64) -> Result<()> {
+        let main_account = &mut ctx.accounts.main_account.load_mut()?;
+        let info_account = &mut ctx.accounts.info_account;
+        let mint = ctx.accounts.mint.key();
+
+        // A server holds a single LST at a time, switching is possible once the vault is emptied
+        if info_account.restaked == 0 {
+            info_account.restaked_mint = mint;
+        }
+        require_keys_eq!(info_account.restaked_mint, mint, CustomError::InvalidMint);
+
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.sender_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.lst_vault.to_account_info(),
+                    authority: ctx.accounts.owner.to_account_info(),
+                },
+            ),
+            amount,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        info_account.restaked = info_account
+            .restaked
+            .checked_add(amount)
+            .ok_or(CustomError::NumberOverflow)?;
+        main_account.add_self_stake(&mint, amount)?;
+
+        let clock = Clock::get()?;
+        emit!(LstDeposited {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: ctx.accounts.owner.key(),
+            server: info_account.key(),
+            mint,
+            amount,
+            restaked: info_account.restaked,
+            total_stake: main_account.total_stake,
+            total_users: main_account.total_users,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn withdraw_lst(ctx: Context<WithdrawLst>, amount: u64) -> Result<()> {
+        let main_account = &mut ctx.accounts.main_account.load_mut()?;
+        let owner = ctx.accounts.owner.key();
+
+        require!(
+            !ctx.accounts.info_account.withdrawals_frozen(Clock::get()?.unix_timestamp),
+            CustomError::WithdrawalsFrozen
+        );
+        require!(
+            amount <= ctx.accounts.info_account.restaked,
+            CustomError::InsufficientFunds
+        );
+
+        let seed_owner = ctx.accounts.info_account.seed_owner();
+        let seeds = &[
+            INFO_SEED,
+            seed_owner.as_ref(),
+            ctx.accounts.info_account.serverkey_hash.as_ref(),
+            &[ctx.bumps.info_account],
+        ];
+
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.lst_vault.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.receipt_token_account.to_account_info(),
+                    authority: ctx.accounts.info_account.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            amount,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        let info_account = &mut ctx.accounts.info_account;
+        info_account.restaked -= amount;
+        main_account.sub_self_stake(&info_account.restaked_mint, amount)?;
+
+        let clock = Clock::get()?;
+        emit!(LstWithdrawn {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner,
+            server: info_account.key(),
+            mint: info_account.restaked_mint,
+            amount,
+            restaked: info_account.restaked,
+            total_stake: main_account.total_stake,
+            total_users: main_account.total_users,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Top up the secondary bond of a server, amount in base units of the bond mint
+    pub fn deposit_bond(ctx: Context<DepositBond>, amount: u64) -> Result<()> {
+        let config = &ctx.accounts.config;
+        let main_account = &mut ctx.accounts.main_account.load_mut()?;
+        let info_account = &mut ctx.accounts.info_account;
+
+        let bond = info_account
+            .bond
+            .checked_add(amount)
+            .ok_or(CustomError::NumberOverflow)?;
+        require!(
+            config.bond_maximum == 0 || bond <= config.bond_maximum,
+            CustomError::BondOutOfRange
+        );
+
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.sender_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.bond_vault.to_account_info(),
+                    authority: ctx.accounts.owner.to_account_info(),
+                },
+            ),
+            amount,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        info_account.bond = bond;
+        main_account.add_self_stake(&config.bond_mint, amount)?;
+
+        let clock = Clock::get()?;
+        emit!(BondDeposited {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: ctx.accounts.owner.key(),
+            server: info_account.key(),
+            amount,
+            bond,
+            total_stake: main_account.total_stake,
+            total_users: main_account.total_users,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // The bond can't go below the minimum while the server still has self-stake
+    pub fn withdraw_bond(ctx: Context<WithdrawBond>, amount: u64) -> Result<()> {
+        let owner = ctx.accounts.owner.key();
+        let info_account = &ctx.accounts.info_account;
+        check_signer_set(info_account, &owner, ctx.remaining_accounts)?;
+        require!(
+            !info_account.withdrawals_frozen(Clock::get()?.unix_timestamp),
+            CustomError::WithdrawalsFrozen
+        );
+
+        let bond = info_account
+            .bond
+            .checked_sub(amount)
+            .ok_or(CustomError::InsufficientFunds)?;
+        require!(
+            info_account.stake == 0 || bond >= ctx.accounts.config.bond_minimum,
+            CustomError::BondOutOfRange
+        );
+
+        let seed_owner = info_account.seed_owner();
+        let seeds = &[
+            INFO_SEED,
+            seed_owner.as_ref(),
+            info_account.serverkey_hash.as_ref(),
+            &[ctx.bumps.info_account],
+        ];
+
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.bond_vault.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.receipt_token_account.to_account_info(),
+                    authority: info_account.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            amount,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        let info_account = &mut ctx.accounts.info_account;
+        info_account.bond = bond;
+        ctx.accounts
+            .main_account
+            .load_mut()?
+            .sub_self_stake(&ctx.accounts.config.bond_mint, amount)?;
+
+        let clock = Clock::get()?;
+        let main_account = ctx.accounts.main_account.load()?;
+        emit!(BondWithdrawn {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner,
+            server: info_account.key(),
+            amount,
+            bond,
+            total_stake: main_account.total_stake,
+            total_users: main_account.total_users,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Grow a server or delegation account so fields added in later releases fit, without closing
+    // and recreating it. The account is handled raw because an old layout may no longer
+    // deserialize into the current struct. Callable by the account owner or the admin, who pays
+    // the extra rent. The main account can be grown as well, by the admin only.
+    pub fn resize_account(ctx: Context<ResizeAccount>, new_space: u32) -> Result<()> {
+        let account = ctx.accounts.account.to_account_info();
+        let authority = ctx.accounts.authority.key();
+
+        require_keys_eq!(*account.owner, *ctx.program_id, CustomError::InvalidAccountData);
+        {
+            let data = account.try_borrow_data()?;
+            require!(data.len() > 8, CustomError::InvalidAccountData);
+
+            let owner = if data[..8] == MainAccount::DISCRIMINATOR {
+                // No version byte up front, v1 is told apart by its size as in migrate_main_to_v2
+                require!(data.len() != MAIN_ACCOUNT_V1_SPACE, CustomError::AccountNotMigrated);
+                ctx.accounts.config.admin
+            } else {
+                require!(data[VERSION_OFFSET] == VERSION, CustomError::AccountNotMigrated);
+                let owner_offset = if data[..8] == InfoAccount::DISCRIMINATOR {
+                    INFO_OWNER_OFFSET
+                } else if data[..8] == DelegatedAccount::DISCRIMINATOR {
+                    DELEGATED_OWNER_OFFSET
+                } else {
+                    return err!(CustomError::InvalidAccountData);
+                };
+                require!(data.len() >= owner_offset + 32, CustomError::InvalidAccountData);
+                Pubkey::try_from(&data[owner_offset..owner_offset + 32]).unwrap()
+            };
+
+            require!(
+                authority == owner || authority == ctx.accounts.config.admin,
+                CustomError::Unauthorized
+            );
+        }
+
+        let old_space = account.data_len();
+        let new_space = new_space as usize;
+        grow_account(
+            &account,
+            ctx.accounts.authority.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            new_space,
+        )?;
+
+        let clock = Clock::get()?;
+        emit!(AccountResized {
+            schema_version: EVENT_SCHEMA_VERSION,
+            account: account.key(),
+            authority,
+            old_space: old_space as u32,
+            new_space: new_space as u32,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Lazily upgrade the main account from the v1 layout (Borsh, no mint table) to the current
+    // zero-copy layout, carrying its totals over to the staking token entry
+    pub fn migrate_main_to_v2(ctx: Context<MigrateAccount>) -> Result<()> {
+        let account = ctx.accounts.account.to_account_info();
+        let (expected, _) = Pubkey::find_program_address(&[MAIN_SEED], ctx.program_id);
+        require_keys_eq!(account.key(), expected, CustomError::InvalidAccountData);
+
+        let v1 = {
+            let data = account.try_borrow_data()?;
+            require_keys_eq!(*account.owner, *ctx.program_id, CustomError::InvalidAccountData);
+            require!(
+                data.len() >= 8 && data[..8] == MainAccount::DISCRIMINATOR,
+                CustomError::InvalidAccountData
+            );
+            // The main account has no `initialized` flag up front, v1 is told apart by its size
+            require!(
+                data.len() == MAIN_ACCOUNT_V1_SPACE,
+                CustomError::AlreadyMigrated
+            );
+            MainAccountV1::deserialize(&mut &data[8..])?
+        };
+
+        grow_account(
+            &account,
+            ctx.accounts.payer.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            8 + std::mem::size_of::<MainAccount>(),
+        )?;
+
+        let mut data = account.try_borrow_mut_data()?;
+        data[8..].fill(0);
+        let main_account: &mut MainAccount =
+            bytemuck::from_bytes_mut(&mut data[8..8 + std::mem::size_of::<MainAccount>()]);
+        main_account.version = VERSION;
+        main_account.initialized = v1.initialized as u8;
+        main_account.total_users = v1.total_users;
+
+        // The mint isn't passed in, its decimals stay unrecorded
+        let mut entry = MintTotal::new(
+            SPECIFIED_MINT,
+            CollateralClass::Primary,
+            0,
+            RATE_PRECISION,
+            Clock::get()?.unix_timestamp,
+        );
+        // v1 kept no split: everything counts as delegated until migrate_info_to_v2 moves each
+        // operator's own stake over
+        entry.total_stake = v1.total_stake;
+        main_account.add_mint(entry)?;
+        main_account.refresh_total_stake()?;
+
+        let clock = Clock::get()?;
+        emit!(AccountMigrated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            account: account.key(),
+            version: VERSION,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Lazily upgrade a server account from the v1 layout. v1 delegations carry no lockup, so the
+    // delegated weight is simply the delegated stake.
+    pub fn migrate_info_to_v2(ctx: Context<MigrateInfoAccount>) -> Result<()> {
+        let account = ctx.accounts.account.to_account_info();
+
+        let v1 = {
+            let data = account.try_borrow_data()?;
+            check_v1_account(&account, &data, &InfoAccount::DISCRIMINATOR, ctx.program_id)?;
+            InfoAccountV1::deserialize(&mut &data[8..])?
+        };
+        require!(
+            v1.serverkey.len() <= MAX_SERVERKEY_LEN,
+            CustomError::InvalidAccountData
+        );
+
+        let serverkey_hash = hash(&v1.serverkey).to_bytes();
+        let (expected, bump) = Pubkey::find_program_address(
+            &[INFO_SEED, v1.owner.as_ref(), serverkey_hash.as_ref()],
+            ctx.program_id,
+        );
+        require_keys_eq!(account.key(), expected, CustomError::InvalidAccountData);
+
+        // migrate_main_to_v2 counted all stake as delegated, move the operator's share over
+        let mint = SPECIFIED_MINT;
+        let main_account = &mut ctx.accounts.main_account.load_mut()?;
+        main_account.add_self_stake(&mint, v1.stake)?;
+        main_account.sub_stake(&mint, v1.stake)?;
+        // v1 only counted users, servers are counted as they migrate
+        main_account.total_servers = main_account
+            .total_servers
+            .checked_add(1)
+            .ok_or(CustomError::NumberOverflow)?;
+
+        let mut info_account = InfoAccount {
+            version: VERSION,
+            initialized: v1.initialized,
+            owner: v1.owner,
+            stake: v1.stake,
+            total: v1.total,
+            total_delegators: v1.total_delegators,
+            serverkey: [0; MAX_SERVERKEY_LEN],
+            serverkey_len: 0,
+            serverkey_hash,
+            bump,
+            name: [0; MAX_NAME_LEN],
+            name_len: 0,
+            acc_reward_per_share: 0,
+            delegate_minimum: 0,
+            delegated_weight: v1.total - v1.stake,
+            activating_stake: 0,
+            complaints: 0,
+            mint,
+            restaked_mint: Pubkey::default(),
+            restaked: 0,
+            bond: 0,
+            reward_dust: 0,
+            delegated: v1.total - v1.stake,
+            delegation_tree: Pubkey::default(),
+            delegation_leaves: 0,
+            revenue_unreleased: 0,
+            revenue_released: 0,
+            revenue_updated_at: 0,
+            revenue_stream_end: 0,
+            revenue_total: 0,
+            commission_bps: 0,
+            last_heartbeat: 0,
+            channel_locked: 0,
+            service_failures: 0,
+            last_failure_epoch: 0,
+            capabilities: 0,
+            uptime_bps: 0,
+            uptime_epoch: 0,
+            tier: ServiceTier::None,
+            pricing: Pricing::default(),
+            endpoint: [0; MAX_ENDPOINT_LEN],
+            endpoint_len: 0,
+            attested_total: 0,
+            attestations: 0,
+            vesting_amount: 0,
+            vesting_claimed: 0,
+            vesting_start: 0,
+            vesting_cliff_end: 0,
+            vesting_end: 0,
+            revenue_disputed: 0,
+            last_active_epoch: Clock::get()?.epoch,
+            nonce: 0,
+            withdrawal_whitelist: [Pubkey::default(); MAX_WITHDRAWAL_ADDRESSES],
+            whitelist_unlocks_at: 0,
+            registrant: Pubkey::default(),
+            backup_key: Pubkey::default(),
+            recovery_announced_at: 0,
+            icon_uri: [0; MAX_ICON_URI_LEN],
+            icon_uri_len: 0,
+            contact: [0; MAX_CONTACT_LEN],
+            contact_len: 0,
+            software_version: SoftwareVersion::default(),
+            version_reported_at: 0,
+            registration_nonce: 0,
+            signers: [Pubkey::default(); MAX_SERVER_SIGNERS],
+            signer_count: 0,
+            signer_threshold: 0,
+            operator_key: Pubkey::default(),
+            exit_announced_at: 0,
+            entry_fee_bps: 0,
+            entry_fees_collected: 0,
+            stake_bucket: 0,
+            total_deposited: 0,
+            total_withdrawn: 0,
+            withdrawals_frozen_until: 0,
+            reward_history: [RewardCheckpoint::default(); REWARD_HISTORY_LEN],
+            reward_history_next: 0,
+            delegations_head: Pubkey::default(),
+            delegations_tail: Pubkey::default(),
+            linked_delegations: 0,
+            event_seq: 0,
+        };
+        info_account.set_serverkey(&v1.serverkey);
+        info_account.set_name(&v1.name)?;
+
+        grow_account(
+            &account,
+            ctx.accounts.payer.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            INFO_ACCOUNT_SPACE,
+        )?;
+        info_account.try_serialize(&mut &mut account.try_borrow_mut_data()?[..])?;
+
+        let clock = Clock::get()?;
+        emit!(AccountMigrated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            account: account.key(),
+            version: VERSION,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Lazily upgrade a delegation from the v1 layout, after its server. The server's reward
+    // accumulator starts from zero at migration and already counts this stake, so a zero
+    // reward debt is exact.
+    pub fn migrate_delegated_to_v2(ctx: Context<MigrateDelegatedAccount>) -> Result<()> {
+        let account = ctx.accounts.account.to_account_info();
+        let info_account = &ctx.accounts.info_account;
+        require!(
+            info_account.version == VERSION,
+            CustomError::AccountNotMigrated
+        );
+
+        let v1 = {
+            let data = account.try_borrow_data()?;
+            check_v1_account(&account, &data, &DelegatedAccount::DISCRIMINATOR, ctx.program_id)?;
+            DelegatedAccountV1::deserialize(&mut &data[8..])?
+        };
+        require_keys_eq!(v1.delegator, info_account.key(), CustomError::InvalidAccountData);
+
+        let (expected, bump) = Pubkey::find_program_address(
+            &[INFO_SEED, v1.owner.as_ref(), info_account.key().as_ref()],
+            ctx.program_id,
+        );
+        require_keys_eq!(account.key(), expected, CustomError::InvalidAccountData);
+
+        let delegated_account = DelegatedAccount {
+            version: VERSION,
+            initialized: v1.initialized,
+            delegator: v1.delegator,
+            owner: v1.owner,
+            stake: v1.stake,
+            mint: info_account.mint,
+            bump,
+            ..Default::default()
+        };
+
+        grow_account(
+            &account,
+            ctx.accounts.payer.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            DELEGATED_ACCOUNT_SPACE,
+        )?;
+        delegated_account.try_serialize(&mut &mut account.try_borrow_mut_data()?[..])?;
+
+        let clock = Clock::get()?;
+        emit!(AccountMigrated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            account: account.key(),
+            version: VERSION,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Move a delegation over from the previous deployment at config.legacy_program. The owner's
+    // signature is forwarded to the legacy d_withdraw, with remaining_accounts as its account list,
+    // and the position must come out of it empty with its stake in the owner's token account. v1
+    // positions hold no lockup or activation timestamps and were active there, so the stake is
+    // recreated as active stake instead of being queued for the next epoch.
+    pub fn migrate_from_v0<'info>(
+        ctx: Context<'_, '_, 'info, 'info, MigrateFromV0<'info>>,
+    ) -> Result<()> {
+        let legacy_program = ctx.accounts.legacy_program.key();
+        let legacy_server = ctx.accounts.legacy_server.to_account_info();
+        let legacy_position = ctx.accounts.legacy_position.to_account_info();
+        let owner = ctx.accounts.owner.key();
+
+        let server_v1 = {
+            let data = legacy_server.try_borrow_data()?;
+            check_v1_account(&legacy_server, &data, &InfoAccount::DISCRIMINATOR, &legacy_program)?;
+            InfoAccountV1::deserialize(&mut &data[8..])?
+        };
+        let serverkey_hash = hash(&server_v1.serverkey).to_bytes();
+        let (expected, _) = Pubkey::find_program_address(
+            &[INFO_SEED, server_v1.owner.as_ref(), serverkey_hash.as_ref()],
+            &legacy_program,
+        );
+        require_keys_eq!(legacy_server.key(), expected, CustomError::InvalidAccountData);
+
+        // The operator must have registered the same serverkey here
+        require!(
+            ctx.accounts.info_account.owner == server_v1.owner
+                && ctx.accounts.info_account.serverkey() == &server_v1.serverkey[..],
+            CustomError::InvalidAccountData
+        );
+
+        let position_v1 = legacy_position_v1(&legacy_position, &legacy_program)?;
+        require_keys_eq!(position_v1.owner, owner, CustomError::Unauthorized);
+        require_keys_eq!(
+            position_v1.delegator,
+            legacy_server.key(),
+            CustomError::InvalidAccountData
+        );
+        let (expected, _) = Pubkey::find_program_address(
+            &[INFO_SEED, owner.as_ref(), legacy_server.key().as_ref()],
+            &legacy_program,
+        );
+        require_keys_eq!(legacy_position.key(), expected, CustomError::InvalidAccountData);
+        require!(
+            position_v1.initialized && position_v1.stake > 0,
+            CustomError::InsufficientFunds
+        );
+
+        // v1 withdrawals take whole tokens, and v1 only ever deposited whole tokens
+        let amount = position_v1.stake;
+        let decimals = ctx.accounts.mint.decimals;
+        let whole = amount / 10u64.pow(decimals as u32);
+        require!(
+            to_minimum_units(whole, decimals)? == amount,
+            CustomError::InvalidAccountData
+        );
+
+        let balance = ctx.accounts.owner_token_account.amount;
+        let mut data = hash(b"global:d_withdraw").to_bytes()[..8].to_vec();
+        whole.serialize(&mut data)?;
+        let ix = Instruction {
+            program_id: legacy_program,
+            accounts: ctx
+                .remaining_accounts
+                .iter()
+                .map(|account| AccountMeta {
+                    pubkey: account.key(),
+                    is_signer: account.is_signer,
+                    is_writable: account.is_writable,
+                })
+                .collect(),
+            data,
+        };
+        let mut account_infos = ctx.remaining_accounts.to_vec();
+        account_infos.push(ctx.accounts.legacy_program.to_account_info());
+        invoke(&ix, &account_infos)?;
+
+        // Closed or left empty, either way nothing can be withdrawn from it a second time
+        if legacy_position.lamports() > 0 {
+            let after = legacy_position_v1(&legacy_position, &legacy_program)?;
+            require!(after.stake == 0, CustomError::LegacyPositionNotEmptied);
+        }
+        ctx.accounts.owner_token_account.reload()?;
+        require!(
+            ctx.accounts.owner_token_account.amount >= balance + amount,
+            CustomError::LegacyPositionNotEmptied
+        );
+
+        let clock = Clock::get()?;
+        let main_account = &mut ctx.accounts.main_account.load_mut()?;
+        let info_account = &mut ctx.accounts.info_account;
+        let delegated_account = &mut ctx.accounts.delegated_account;
+
+        let created = record_delegation(
+            main_account,
+            info_account,
+            delegated_account,
+            &ctx.accounts.config,
+            ctx.accounts.price_update.as_ref(),
+            owner,
+            amount,
+            decimals,
+            LockupTier::None,
+            &clock,
+        )?;
+        if created {
+            delegated_account.bump = ctx.bumps.delegated_account;
+        }
+        // Already active in the old deployment
+        delegated_account.activation_epoch = clock.epoch;
+        activate_delegation(main_account, info_account, delegated_account, clock.epoch)?;
+
+        open_vault(
+            &ctx.accounts.vault,
+            delegated_account.to_account_info(),
+            &ctx.accounts.mint,
+            ctx.accounts.payer.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.associated_token_program.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+        )?;
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.owner_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.vault.to_account_info(),
+                    authority: ctx.accounts.owner.to_account_info(),
+                },
+            ),
+            amount,
+            decimals,
+        )?;
+
+        emit!(PositionMigrated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            legacy_position: legacy_position.key(),
+            delegated_account: delegated_account.key(),
+            owner,
+            delegator: info_account.key(),
+            amount,
+            stake: delegated_account.stake,
+            total_stake: main_account.total_stake,
+            total_users: main_account.total_users,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Approve or update the adapter for a partner staking program (admin only). It describes where
+    // the owner and the amount sit in the partner's position accounts. Partners fund the bonus by
+    // transferring to the adapter vault, opened here.
+    pub fn set_partner_adapter(
+        ctx: Context<SetPartnerAdapter>,
+        partner_program: Pubkey,
+        params: PartnerAdapterParams,
+    ) -> Result<()> {
+        require!(
+            params.owner_offset as usize >= 8 && params.amount_offset as usize >= 8,
+            CustomError::InvalidArgument
+        );
+        // An unlocked credit could be withdrawn as soon as the partner stake moved on
+        require!(params.lockup != LockupTier::None, CustomError::InvalidArgument);
+
+        let adapter = &mut ctx.accounts.partner_adapter;
+        adapter.version = VERSION;
+        adapter.partner_program = partner_program;
+        adapter.mint = ctx.accounts.mint.key();
+        adapter.position_discriminator = params.position_discriminator;
+        adapter.owner_offset = params.owner_offset;
+        adapter.amount_offset = params.amount_offset;
+        adapter.bonus_bps = params.bonus_bps;
+        adapter.max_credit = params.max_credit;
+        adapter.enabled = params.enabled;
+        adapter.bump = ctx.bumps.partner_adapter;
+        adapter.lockup = params.lockup;
+
+        open_vault(
+            &ctx.accounts.adapter_vault,
+            adapter.to_account_info(),
+            &ctx.accounts.mint,
+            ctx.accounts.admin.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.associated_token_program.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+        )?;
+
+        let clock = Clock::get()?;
+        record_audit(
+            &ctx.accounts.config,
+            ctx.accounts.audit_log.as_ref(),
+            AuditAction::PartnerAdapterUpdated,
+            ctx.accounts.admin.key(),
+            partner_program,
+            &clock,
+        )?;
+        emit!(PartnerAdapterUpdated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            adapter: adapter.key(),
+            partner_program,
+            mint: adapter.mint,
+            bonus_bps: adapter.bonus_bps,
+            max_credit: adapter.max_credit,
+            enabled: adapter.enabled,
+            lockup: adapter.lockup,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Credit a staker of a partner program with a delegation here, funded from the adapter vault.
+    // The partner position is read straight from the partner program's account, and the credit is
+    // its amount weighted by the adapter's bonus_bps, up to max_credit, and locked in the adapter's
+    // lockup tier. Once per partner position.
+    pub fn import_partner_position(ctx: Context<ImportPartnerPosition>) -> Result<()> {
+        let adapter = &ctx.accounts.partner_adapter;
+        require!(adapter.enabled, CustomError::PartnerAdapterDisabled);
+        let owner = ctx.accounts.owner.key();
+
+        let partner_stake = {
+            let position = ctx.accounts.partner_position.to_account_info();
+            require_keys_eq!(
+                *position.owner,
+                adapter.partner_program,
+                CustomError::InvalidPartnerPosition
+            );
+            let data = position.try_borrow_data()?;
+            let owner_at = adapter.owner_offset as usize;
+            let amount_at = adapter.amount_offset as usize;
+            require!(
+                data.get(..8) == Some(&adapter.position_discriminator[..])
+                    && data.get(owner_at..owner_at + 32) == Some(owner.as_ref()),
+                CustomError::InvalidPartnerPosition
+            );
+            let amount = data
+                .get(amount_at..amount_at + 8)
+                .ok_or(CustomError::InvalidPartnerPosition)?;
+            u64::from_le_bytes(amount.try_into().unwrap())
+        };
+
+        let weighted = partner_stake as u128 * adapter.bonus_bps as u128 / MAX_BPS as u128;
+        let credit = u64::try_from(weighted)
+            .map_err(|_| CustomError::NumberOverflow)?
+            .min(adapter.max_credit);
+        require!(credit > 0, CustomError::InvalidPartnerPosition);
+        require!(
+            credit <= ctx.accounts.adapter_vault.amount,
+            CustomError::InsufficientFunds
+        );
+
+        let clock = Clock::get()?;
+        let main_account = &mut ctx.accounts.main_account.load_mut()?;
+        let info_account = &mut ctx.accounts.info_account;
+        let delegated_account = &mut ctx.accounts.delegated_account;
+
+        let created = record_delegation(
+            main_account,
+            info_account,
+            delegated_account,
+            &ctx.accounts.config,
+            ctx.accounts.price_update.as_ref(),
+            owner,
+            credit,
+            ctx.accounts.mint.decimals,
+            adapter.lockup,
+            &clock,
+        )?;
+        if created {
+            delegated_account.bump = ctx.bumps.delegated_account;
+        }
+
+        open_vault(
+            &ctx.accounts.vault,
+            delegated_account.to_account_info(),
+            &ctx.accounts.mint,
+            ctx.accounts.payer.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.associated_token_program.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+        )?;
+        let partner_program = adapter.partner_program;
+        let seeds = &[PARTNER_ADAPTER_SEED, partner_program.as_ref(), &[adapter.bump]];
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.adapter_vault.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.vault.to_account_info(),
+                    authority: ctx.accounts.partner_adapter.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            credit,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        let adapter = &mut ctx.accounts.partner_adapter;
+        adapter.total_credited = adapter
+            .total_credited
+            .checked_add(credit)
+            .ok_or(CustomError::NumberOverflow)?;
+
+        let import = &mut ctx.accounts.partner_import;
+        import.version = VERSION;
+        import.adapter = adapter.key();
+        import.owner = owner;
+        import.partner_position = ctx.accounts.partner_position.key();
+        import.partner_stake = partner_stake;
+        import.credited = credit;
+        import.delegated_account = delegated_account.key();
+        import.unix_timestamp = clock.unix_timestamp;
+        import.bump = ctx.bumps.partner_import;
+
+        emit!(PartnerPositionImported {
+            schema_version: EVENT_SCHEMA_VERSION,
+            adapter: adapter.key(),
+            owner,
+            partner_position: import.partner_position,
+            delegator: info_account.key(),
+            partner_stake,
+            credited: credit,
+            total_stake: main_account.total_stake,
+            total_users: main_account.total_users,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // One-time loyalty credit for stakers active before the rewards system launched. The entry
+    // (owner, points, multiplier) must be a leaf of config.tenure_root, the snapshot tree the
+    // admin publishes, see tenure_leaf. Points are base units of the staking token, paid out of
+    // the treasury with the multiplier applied.
+    pub fn claim_tenure_credit(
+        ctx: Context<ClaimTenureCredit>,
+        points: u64,
+        multiplier_bps: u16,
+        proof: Vec<[u8; 32]>,
+    ) -> Result<()> {
+        let root = ctx.accounts.config.tenure_root;
+        require!(root != [0; 32], CustomError::TenureClaimsClosed);
+
+        let owner = ctx.accounts.owner.key();
+        require!(
+            verify_merkle_proof(&proof, root, tenure_leaf(&owner, points, multiplier_bps)),
+            CustomError::InvalidMerkleProof
+        );
+
+        let credited = u64::try_from(points as u128 * multiplier_bps as u128 / MAX_BPS as u128)
+            .map_err(|_| CustomError::NumberOverflow)?;
+        require!(
+            credited <= ctx.accounts.treasury_token_account.amount,
+            CustomError::InsufficientFunds
+        );
+        let seeds = &[TREASURY_SEED, &[ctx.bumps.treasury]];
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.treasury_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.owner_token_account.to_account_info(),
+                    authority: ctx.accounts.treasury.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            credited,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        let clock = Clock::get()?;
+        let credit = &mut ctx.accounts.tenure_credit;
+        credit.version = VERSION;
+        credit.owner = owner;
+        credit.points = points;
+        credit.multiplier_bps = multiplier_bps;
+        credit.root = root;
+        credit.claimed_at = clock.unix_timestamp;
+        credit.credited = credited;
+        credit.bump = ctx.bumps.tenure_credit;
+
+        emit!(TenureCreditClaimed {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner,
+            points,
+            multiplier_bps,
+            credited,
+            root,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Permissionless crank recording the global totals for the current epoch. The snapshot PDA is
+    // created here, so every epoch is written exactly once. When the previous epoch's snapshot is
+    // passed as well, the activity between the two is emitted as an EpochSummary.
+    pub fn epoch_snapshot(ctx: Context<TakeEpochSnapshot>, epoch: u64) -> Result<()> {
+        let clock = Clock::get()?;
+        require!(epoch == clock.epoch, CustomError::InvalidEpoch);
+
+        let main_account = ctx.accounts.main_account.load()?;
+        let snapshot = &mut ctx.accounts.snapshot;
+        snapshot.version = VERSION;
+        snapshot.epoch = epoch;
+        snapshot.total_stake = main_account.total_stake;
+        snapshot.total_self_stake = main_account.total_self_stake;
+        snapshot.total_delegated = main_account.total_delegated;
+        snapshot.total_users = main_account.total_users;
+        snapshot.slot = clock.slot;
+        snapshot.unix_timestamp = clock.unix_timestamp;
+        snapshot.bump = ctx.bumps.snapshot;
+        snapshot.total_deposited = main_account.total_deposited;
+        snapshot.total_withdrawn = main_account.total_withdrawn;
+        snapshot.servers_opened = main_account.servers_opened;
+        snapshot.servers_closed = main_account.servers_closed;
+
+        emit!(EpochSnapshotTaken {
+            schema_version: EVENT_SCHEMA_VERSION,
+            epoch,
+            total_stake: snapshot.total_stake,
+            total_delegated: snapshot.total_delegated,
+            total_users: snapshot.total_users,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        if let Some(previous) = &ctx.accounts.previous_snapshot {
+            emit!(EpochSummary {
+                schema_version: EVENT_SCHEMA_VERSION,
+                epoch: previous.epoch,
+                inflow: snapshot.total_deposited.saturating_sub(previous.total_deposited),
+                outflow: snapshot.total_withdrawn.saturating_sub(previous.total_withdrawn),
+                new_servers: snapshot.servers_opened.saturating_sub(previous.servers_opened),
+                closed_servers: snapshot.servers_closed.saturating_sub(previous.servers_closed),
+                slot: clock.slot,
+                unix_timestamp: clock.unix_timestamp,
+            });
+        }
+
+        Ok(())
+    }
+
+    // Permissionless crank keeping a per-UTC-day time series explorers can chart. The day's first
+    // call opens its record with the total stake and the change since the previous day's record,
+    // passed as `previous_day`. Servers passed in remaining_accounts, in strictly increasing
+    // address order over all of the day's calls, are counted as active when they are online.
+    pub fn update_daily_stats<'info>(
+        ctx: Context<'_, '_, 'info, 'info, UpdateDailyStats<'info>>,
+        day: u32,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        require!(
+            day as i64 == clock.unix_timestamp / SECS_PER_DAY,
+            CustomError::InvalidDay
+        );
+
+        let stats = &mut ctx.accounts.daily_stats;
+        if stats.version == 0 {
+            let total_stake = ctx.accounts.main_account.load()?.total_stake;
+            let previous = ctx
+                .accounts
+                .previous_day
+                .as_ref()
+                .map_or(total_stake, |previous| previous.total_stake);
+            stats.set_inner(DailyStats {
+                version: VERSION,
+                day,
+                total_stake,
+                stake_delta: i64::try_from(total_stake as i128 - previous as i128)
+                    .map_err(|_| CustomError::NumberOverflow)?,
+                opened_at: clock.unix_timestamp,
+                bump: ctx.bumps.daily_stats,
+                ..Default::default()
+            });
+        }
+
+        for account in ctx.remaining_accounts {
+            require!(account.key() > stats.cursor, CustomError::InvalidBatchAccount);
+            stats.cursor = account.key();
+
+            let server = Account::<InfoAccount>::try_from(account)?;
+            if server_online(&server, &ctx.accounts.config, clock.unix_timestamp) {
+                stats.active_servers = stats
+                    .active_servers
+                    .checked_add(1)
+                    .ok_or(CustomError::NumberOverflow)?;
+            }
+        }
+
+        emit!(DailyStatsUpdated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            day,
+            total_stake: stats.total_stake,
+            stake_delta: stats.stake_delta,
+            active_servers: stats.active_servers,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Fold epoch snapshots at least SNAPSHOT_RETENTION_EPOCHS old into the archive's Merkle root
+    // and close them, their rent going to the admin (admin only). Snapshots are passed writable
+    // in remaining_accounts, in increasing epoch order after the archive's last_epoch. Each one
+    // becomes the next leaf, snapshot_leaf of its data, and SnapshotArchived carries the leaves
+    // so proofs against the root can be rebuilt off-chain.
+    pub fn archive_snapshots<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ArchiveSnapshots<'info>>,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        let admin = ctx.accounts.admin.to_account_info();
+        let archive = &mut ctx.accounts.archive;
+        if archive.version == 0 {
+            archive.version = VERSION;
+            archive.bump = ctx.bumps.archive;
+        }
+
+        for account in ctx.remaining_accounts {
+            // Read raw, snapshots taken before the lifetime counters have the shorter layout
+            let (epoch, leaf) = {
+                let data = account.try_borrow_data()?;
+                require_keys_eq!(*account.owner, *ctx.program_id, CustomError::InvalidBatchAccount);
+                require!(
+                    data.len() >= SNAPSHOT_EPOCH_OFFSET + 8
+                        && data[..8] == SNAPSHOT_DISCRIMINATOR,
+                    CustomError::InvalidBatchAccount
+                );
+                let epoch = &data[SNAPSHOT_EPOCH_OFFSET..SNAPSHOT_EPOCH_OFFSET + 8];
+                let epoch = u64::from_le_bytes(epoch.try_into().unwrap());
+                (epoch, snapshot_leaf(&data))
+            };
+            require_keys_eq!(
+                account.key(),
+                pda::epoch_snapshot(epoch, ctx.program_id).0,
+                CustomError::InvalidBatchAccount
+            );
+            require!(
+                archive.count == 0 || epoch > archive.last_epoch,
+                CustomError::InvalidEpoch
+            );
+            require!(
+                epoch.saturating_add(SNAPSHOT_RETENTION_EPOCHS) <= clock.epoch,
+                CustomError::SnapshotTooRecent
+            );
+
+            let index = archive.count;
+            archive.append(leaf)?;
+            if index == 0 {
+                archive.first_epoch = epoch;
+            }
+            archive.last_epoch = epoch;
+
+            **admin.try_borrow_mut_lamports()? += account.lamports();
+            **account.try_borrow_mut_lamports()? = 0;
+            account.assign(&anchor_lang::system_program::ID);
+            account.realloc(0, false)?;
+
+            emit!(SnapshotArchived {
+                schema_version: EVENT_SCHEMA_VERSION,
+                epoch,
+                index,
+                leaf,
+                root: archive.root,
+                slot: clock.slot,
+                unix_timestamp: clock.unix_timestamp,
+            });
+        }
+
+        Ok(())
+    }
+
+    // Create an address lookup table holding the program's common accounts, so batch
+    // instructions such as d_deposit_many fit in a v0 transaction. The admin is its authority.
+    pub fn create_lookup_table(ctx: Context<ManageLookupTable>, recent_slot: u64) -> Result<()> {
+        let (ix, table) = address_lookup_table::instruction::create_lookup_table(
+            ctx.accounts.admin.key(),
+            ctx.accounts.admin.key(),
+            recent_slot,
+        );
+        require_keys_eq!(
+            ctx.accounts.lookup_table.key(),
+            table,
+            CustomError::InvalidLookupTable
+        );
+        invoke(
+            &ix,
+            &[
+                ctx.accounts.lookup_table.to_account_info(),
+                ctx.accounts.admin.to_account_info(),
+                ctx.accounts.admin.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+            ],
+        )?;
+
+        extend_lookup_table_with(ctx.accounts, ctx.program_id, &[])
+    }
+
+    // Append the common accounts missing from an existing table, e.g. mints approved since
+    pub fn extend_lookup_table(ctx: Context<ManageLookupTable>) -> Result<()> {
+        let lookup_table = ctx.accounts.lookup_table.to_account_info();
+        require_keys_eq!(
+            *lookup_table.owner,
+            address_lookup_table::program::ID,
+            CustomError::InvalidLookupTable
+        );
+        let existing = {
+            let data = lookup_table.try_borrow_data()?;
+            AddressLookupTable::deserialize(&data)
+                .map_err(|_| CustomError::InvalidLookupTable)?
+                .addresses
+                .to_vec()
+        };
+
+        extend_lookup_table_with(ctx.accounts, ctx.program_id, &existing)
+    }
+
+    // Spend protocol fees held by the treasury, gated by the config admin
+    pub fn treasury_transfer(ctx: Context<TreasuryTransfer>, amount: u64) -> Result<()> {
+        require!(
+            amount <= ctx.accounts.treasury_token_account.amount,
+            CustomError::InsufficientFunds
+        );
+
+        let seeds = &[TREASURY_SEED, &[ctx.bumps.treasury]];
+
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.treasury_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.destination_token_account.to_account_info(),
+                    authority: ctx.accounts.treasury.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            amount,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        let clock = Clock::get()?;
+        record_audit(
+            &ctx.accounts.config,
+            ctx.accounts.audit_log.as_ref(),
+            AuditAction::TreasuryTransfer,
+            ctx.accounts.admin.key(),
+            ctx.accounts.destination_token_account.key(),
+            &clock,
+        )?;
+        emit!(TreasuryTransferred {
+            schema_version: EVENT_SCHEMA_VERSION,
+            admin: ctx.accounts.admin.key(),
+            mint: ctx.accounts.mint.key(),
+            destination: ctx.accounts.destination_token_account.key(),
+            amount,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Permissionless: move a server's unclaimable reward rounding dust into the treasury
+    pub fn treasury_sweep_dust(ctx: Context<TreasurySweepDust>) -> Result<()> {
+        let amount = ctx.accounts.info_account.reward_dust;
+        require!(amount > 0, CustomError::NothingToClaim);
+        ctx.accounts.info_account.reward_dust = 0;
+
+        let seeds = &[MAIN_SEED, &[ctx.bumps.main_account]];
+
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.reward_vault.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.treasury_token_account.to_account_info(),
+                    authority: ctx.accounts.main_account.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            amount,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        let clock = Clock::get()?;
+        emit!(DustSwept {
+            schema_version: EVENT_SCHEMA_VERSION,
+            server: ctx.accounts.info_account.key(),
+            mint: ctx.accounts.mint.key(),
+            amount,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Permissionless: move tokens sent straight into a server or delegation vault, beyond the
+    // stake recorded for it, out of the vault. The delegation vault is swept when one is passed,
+    // the server vault otherwise. The excess goes to the server's delegators through the reward
+    // pool, or back to the server owner while the server has no delegated stake.
+    pub fn sweep_excess(ctx: Context<SweepExcess>) -> Result<()> {
+        let info_account = &mut ctx.accounts.info_account;
+        let info_key = info_account.key();
+
+        let (authority, tracked, owner, seed, bump) = match &ctx.accounts.delegated_account {
+            Some(delegated_account) => (
+                delegated_account.to_account_info(),
+                delegated_account.stake + delegated_account.activating_stake,
+                delegated_account.owner,
+                info_key.to_bytes(),
+                delegated_account.bump,
+            ),
+            None => (
+                info_account.to_account_info(),
+                info_account.stake,
+                info_account.seed_owner(),
+                info_account.serverkey_hash,
+                info_account.bump,
+            ),
+        };
+        require_keys_eq!(
+            ctx.accounts.vault.key(),
+            get_associated_token_address_with_program_id(
+                authority.key,
+                &info_account.mint,
+                &ctx.accounts.token_program.key(),
+            ),
+            CustomError::InvalidAccountData
+        );
+        let amount = ctx.accounts.vault.amount.saturating_sub(tracked);
+        require!(amount > 0, CustomError::NothingToClaim);
+
+        let to_rewards = info_account.delegated_weight > 0;
+        let destination = if to_rewards {
+            ctx.accounts.reward_vault.as_ref()
+        } else {
+            ctx.accounts.owner_token_account.as_ref()
+        }
+        .ok_or(CustomError::SweepDestinationRequired)?;
+
+        let seeds = &[INFO_SEED, owner.as_ref(), seed.as_ref(), &[bump]];
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.vault.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: destination.to_account_info(),
+                    authority,
+                },
+                &[&seeds[..]],
+            ),
+            amount,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        let clock = Clock::get()?;
+        if to_rewards {
+            info_account.distribute_rewards(amount, clock.unix_timestamp)?;
+        }
+
+        emit!(ExcessSwept {
+            schema_version: EVENT_SCHEMA_VERSION,
+            server: info_key,
+            vault: ctx.accounts.vault.key(),
+            amount,
+            to_rewards,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Create the receipt mint (stAERO for the staking token) of a collateral mint. The mint is a
+    // PDA that is its own mint authority, so only this program can mint or sign for it.
+    pub fn initialize_receipt_mint(ctx: Context<InitializeReceiptMint>) -> Result<()> {
+        let clock = Clock::get()?;
+        emit!(ReceiptMintInitialized {
+            schema_version: EVENT_SCHEMA_VERSION,
+            mint: ctx.accounts.mint.key(),
+            receipt_mint: ctx.accounts.receipt_mint.key(),
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Attach Token Metadata to a receipt mint so wallets and marketplaces can display it.
+    // The receipt mint PDA signs as both mint and update authority.
+    pub fn set_receipt_metadata(
+        ctx: Context<SetReceiptMetadata>,
+        name: String,
+        symbol: String,
+        uri: String,
+    ) -> Result<()> {
+        let mint = ctx.accounts.mint.key();
+        let seeds = &[RECEIPT_SEED, mint.as_ref(), &[ctx.bumps.receipt_mint]];
+
+        create_metadata_accounts_v3(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_metadata_program.to_account_info(),
+                CreateMetadataAccountsV3 {
+                    metadata: ctx.accounts.metadata.to_account_info(),
+                    mint: ctx.accounts.receipt_mint.to_account_info(),
+                    mint_authority: ctx.accounts.receipt_mint.to_account_info(),
+                    payer: ctx.accounts.admin.to_account_info(),
+                    update_authority: ctx.accounts.receipt_mint.to_account_info(),
+                    system_program: ctx.accounts.system_program.to_account_info(),
+                    rent: ctx.accounts.rent.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            DataV2 {
+                name,
+                symbol,
+                uri,
+                seller_fee_basis_points: 0,
+                creators: None,
+                collection: None,
+                uses: None,
+            },
+            false,
+            true,
+            None,
+        )?;
+
+        Ok(())
+    }
+
+    // Accept another collateral mint, either as server stake or as an additional class.
+    // `decimals` is what the admin expects the mint to have, a mismatch is rejected.
+    pub fn approve_mint(
+        ctx: Context<ApproveMint>,
+        class: CollateralClass,
+        decimals: u8,
+    ) -> Result<()> {
+        check_mint_extensions(&ctx.accounts.mint.to_account_info())?;
+        require!(
+            ctx.accounts.mint.decimals == decimals,
+            CustomError::MintDecimalsMismatch
+        );
+
+        let main_account = &mut ctx.accounts.main_account.load_mut()?;
+        let mint = ctx.accounts.mint.key();
+
+        require!(
+            !main_account.is_mint_approved(&mint),
+            CustomError::MintAlreadyApproved
+        );
+
+        // No stake is accepted for the mint until the rate oracle publishes its exchange rate
+        main_account.add_mint(MintTotal::new(mint, class, decimals, 0, 0))?;
+
+        let clock = Clock::get()?;
+        record_audit(
+            &ctx.accounts.config,
+            ctx.accounts.audit_log.as_ref(),
+            AuditAction::MintApproved,
+            ctx.accounts.admin.key(),
+            mint,
+            &clock,
+        )?;
+        emit!(MintApproved {
+            schema_version: EVENT_SCHEMA_VERSION,
+            admin: ctx.accounts.admin.key(),
+            mint,
+            class,
+            decimals,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Publish the exchange rate of a collateral mint into the staking token, signed by the rate oracle
+    pub fn update_exchange_rate(
+        ctx: Context<UpdateExchangeRate>,
+        mint: Pubkey,
+        rate: u64,
+    ) -> Result<()> {
+        require!(rate > 0, CustomError::InvalidExchangeRate);
+
+        let now = Clock::get()?.unix_timestamp;
+        let main_account = &mut ctx.accounts.main_account.load_mut()?;
+        // The staking token is the first approved mint, its rate is fixed at RATE_PRECISION
+        require!(
+            mint != main_account.approved_mints()[0].mint,
+            CustomError::InvalidExchangeRate
+        );
+        main_account.set_rate(&mint, rate, now)?;
+
+        let clock = Clock::get()?;
+        emit!(ExchangeRateUpdated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            mint,
+            rate,
+            total_stake: main_account.total_stake,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Approve a staker in the program's own allowlist, used when the gate program is this program
+    pub fn add_to_allowlist(ctx: Context<AddToAllowlist>, staker: Pubkey) -> Result<()> {
+        let entry = &mut ctx.accounts.allowlist_entry;
+        entry.version = VERSION;
+        entry.staker = staker;
+        entry.approved_by = ctx.accounts.admin.key();
+
+        let clock = Clock::get()?;
+        record_audit(
+            &ctx.accounts.config,
+            ctx.accounts.audit_log.as_ref(),
+            AuditAction::AllowlistAdded,
+            ctx.accounts.admin.key(),
+            staker,
+            &clock,
+        )?;
+        emit!(AllowlistUpdated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            staker,
+            allowed: true,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn remove_from_allowlist(ctx: Context<RemoveFromAllowlist>) -> Result<()> {
+        let clock = Clock::get()?;
+        record_audit(
+            &ctx.accounts.config,
+            ctx.accounts.audit_log.as_ref(),
+            AuditAction::AllowlistRemoved,
+            ctx.accounts.admin.key(),
+            ctx.accounts.allowlist_entry.staker,
+            &clock,
+        )?;
+        emit!(AllowlistUpdated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            staker: ctx.accounts.allowlist_entry.staker,
+            allowed: false,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Add rewards for the delegators of a server, shared pro-rata by reward weight
+    pub fn fund_rewards(ctx: Context<FundRewards>, amount: u64) -> Result<()> {
+        let info_account = &mut ctx.accounts.info_account;
+
+        let amount_in_minimum_units = to_minimum_units(amount, ctx.accounts.mint.decimals)?;
+
+        // Rewards are shared by lockup-weighted delegated stake
+        let delegated_weight = info_account.delegated_weight;
+        require!(delegated_weight > 0, CustomError::NoDelegatedStake);
+
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.sender_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.reward_vault.to_account_info(),
+                    authority: ctx.accounts.funder.to_account_info(),
+                },
+            ),
+            amount_in_minimum_units,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        let clock = Clock::get()?;
+        info_account.distribute_rewards(amount_in_minimum_units, clock.unix_timestamp)?;
+
+        emit!(RewardsFunded {
+            schema_version: EVENT_SCHEMA_VERSION,
+            funder: ctx.accounts.funder.key(),
+            server: info_account.key(),
+            amount: amount_in_minimum_units,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Anyone can donate to the delegators of `info_account`, or spread a donation over several
+    // servers by passing their info accounts (writable) in remaining_accounts. The donation is
+    // split by delegated reward weight and credited to the accumulators, so it raises the APR.
+    pub fn donate<'info>(
+        ctx: Context<'_, '_, 'info, 'info, FundRewards<'info>>,
+        amount: u64,
+    ) -> Result<()> {
+        let amount_in_minimum_units = to_minimum_units(amount, ctx.accounts.mint.decimals)?;
+        require!(amount_in_minimum_units > 0, CustomError::InsufficientFunds);
+        require!(
+            ctx.remaining_accounts.len() < MAX_BATCH_DELEGATIONS,
+            CustomError::InvalidBatchSize
+        );
+
+        let mint = ctx.accounts.mint.key();
+        let mut others = Vec::with_capacity(ctx.remaining_accounts.len());
+        for info_info in ctx.remaining_accounts.iter() {
+            require!(info_info.is_writable, CustomError::InvalidBatchAccount);
+            require_keys_neq!(
+                info_info.key(),
+                ctx.accounts.info_account.key(),
+                CustomError::InvalidBatchAccount
+            );
+            let info_account = Account::<InfoAccount>::try_from(info_info)?;
+            require_keys_eq!(info_account.mint, mint, CustomError::InvalidMint);
+            others.push(info_account);
+        }
+
+        let total_weight = others
+            .iter()
+            .map(|info| info.delegated_weight as u128)
+            .sum::<u128>()
+            + ctx.accounts.info_account.delegated_weight as u128;
+        require!(total_weight > 0, CustomError::NoDelegatedStake);
+
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.sender_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.reward_vault.to_account_info(),
+                    authority: ctx.accounts.funder.to_account_info(),
+                },
+            ),
+            amount_in_minimum_units,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        let clock = Clock::get()?;
+        let mut remaining = amount_in_minimum_units;
+        for info_account in others.iter_mut() {
+            let share = (amount_in_minimum_units as u128 * info_account.delegated_weight as u128
+                / total_weight) as u64;
+            if share > 0 {
+                info_account.distribute_rewards(share, clock.unix_timestamp)?;
+                remaining -= share;
+            }
+            info_account.exit(ctx.program_id)?;
+        }
+
+        // The primary server takes its share plus the rounding remainder of the split
+        let info_account = &mut ctx.accounts.info_account;
+        if info_account.delegated_weight > 0 {
+            info_account.distribute_rewards(remaining, clock.unix_timestamp)?;
+        } else {
+            info_account.reward_dust += remaining;
+        }
+
+        emit!(DonationReceived {
+            schema_version: EVENT_SCHEMA_VERSION,
+            donor: ctx.accounts.funder.key(),
+            server: info_account.key(),
+            servers: others.len() as u8 + 1,
+            amount: amount_in_minimum_units,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn claim_rewards(ctx: Context<ClaimRewards>) -> Result<()> {
+        let info_account = &ctx.accounts.info_account;
+        let delegated_account = &mut ctx.accounts.delegated_account;
+
+        delegated_account.settle_rewards(info_account.acc_reward_per_share)?;
+
+        let amount = delegated_account.pending_rewards;
+        require!(amount > 0, CustomError::NothingToClaim);
+        delegated_account.pending_rewards = 0;
+
+        let seeds = &[MAIN_SEED, &[ctx.bumps.main_account]];
+        let fee = fee_amount(amount, ctx.accounts.config.reward_fee_bps)?;
+
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.reward_vault.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.receipt_token_account.to_account_info(),
+                    authority: ctx.accounts.main_account.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            amount - fee,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        collect_fee(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.reward_vault.to_account_info(),
+            &ctx.accounts.mint,
+            ctx.accounts.treasury_token_account.as_ref(),
+            ctx.accounts.main_account.to_account_info(),
+            &[&seeds[..]],
+            fee,
+        )?;
+
+        let clock = Clock::get()?;
+        emit!(RewardsClaimed {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: ctx.accounts.owner.key(),
+            delegator: info_account.key(),
+            amount,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn set_auto_compound(ctx: Context<UpdateDelegation>, enabled: bool) -> Result<()> {
+        let delegated_account = &mut ctx.accounts.delegated_account;
+        delegated_account.auto_compound = enabled;
+
+        let clock = Clock::get()?;
+        emit!(AutoCompoundUpdated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: ctx.accounts.owner.key(),
+            delegated_account: delegated_account.key(),
+            enabled,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Opt a locked position into auto-renewal at its current tier, see LockupRenewal
+    pub fn enable_lockup_renewal(ctx: Context<EnableLockupRenewal>) -> Result<()> {
+        let clock = Clock::get()?;
+        let delegated_account = &ctx.accounts.delegated_account;
+        require!(
+            delegated_account.lockup != LockupTier::None
+                && clock.unix_timestamp < delegated_account.lock_until,
+            CustomError::NoActiveLockup
+        );
+
+        let renewal = &mut ctx.accounts.lockup_renewal;
+        renewal.version = VERSION;
+        renewal.delegation = delegated_account.key();
+        renewal.lockup = delegated_account.lockup;
+        renewal.renewed_from = 0;
+        renewal.renewed_until = 0;
+        renewal.bump = ctx.bumps.lockup_renewal;
+
+        emit!(LockupRenewalUpdated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: ctx.accounts.owner.key(),
+            delegated_account: delegated_account.key(),
+            enabled: true,
+            lockup: renewal.lockup,
+            lock_until: delegated_account.lock_until,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Opt out of auto-renewal. Within LOCKUP_RENEWAL_GRACE_SECS of a roll-over the lock goes back
+    // to the expiry it rolled over from. A roll-over nobody cranked and whose grace window has
+    // passed is applied first, so leaving late can't skip a term.
+    pub fn disable_lockup_renewal(ctx: Context<DisableLockupRenewal>) -> Result<()> {
+        let clock = Clock::get()?;
+        let now = clock.unix_timestamp;
+        let info_account = &mut ctx.accounts.info_account;
+        let delegated_account = &mut ctx.accounts.delegated_account;
+        let renewal = &mut ctx.accounts.lockup_renewal;
+
+        if renewal.renewed_until == delegated_account.lock_until
+            && now < renewal.renewed_from.saturating_add(LOCKUP_RENEWAL_GRACE_SECS)
+        {
+            // The boost ends lazily, the next settlement of the position drops it
+            delegated_account.lock_until = renewal.renewed_from;
+        } else if now >= delegated_account
+            .lock_until
+            .saturating_add(LOCKUP_RENEWAL_GRACE_SECS)
+        {
+            delegated_account.settle_rewards(info_account.acc_reward_per_share)?;
+            let old_weight = delegated_account.reward_weight();
+            renewal.roll_over(delegated_account, now)?;
+            info_account.update_delegated_weight(old_weight, delegated_account.reward_weight())?;
+            delegated_account.checkpoint_rewards(info_account.acc_reward_per_share)?;
+        }
+
+        emit!(LockupRenewalUpdated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: ctx.accounts.owner.key(),
+            delegated_account: delegated_account.key(),
+            enabled: false,
+            lockup: delegated_account.lockup,
+            lock_until: delegated_account.lock_until,
+            slot: clock.slot,
+            unix_timestamp: now,
+        });
+
+        Ok(())
+    }
+
+    // Roll an expired lock with renewal on over into its next term (permissionless), so the
+    // boost carries on without a gap. Keepers run it before crank_rewards.
+    pub fn renew_lockup(ctx: Context<RenewLockup>) -> Result<()> {
+        let clock = Clock::get()?;
+        let info_account = &mut ctx.accounts.info_account;
+        let delegated_account = &mut ctx.accounts.delegated_account;
+
+        delegated_account.settle_rewards(info_account.acc_reward_per_share)?;
+        let old_weight = delegated_account.reward_weight();
+        require!(
+            ctx.accounts
+                .lockup_renewal
+                .roll_over(delegated_account, clock.unix_timestamp)?,
+            CustomError::StakeLocked
+        );
+        info_account.update_delegated_weight(old_weight, delegated_account.reward_weight())?;
+        delegated_account.checkpoint_rewards(info_account.acc_reward_per_share)?;
+
+        emit!(LockupRenewed {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: delegated_account.owner,
+            delegated_account: delegated_account.key(),
+            lockup: delegated_account.lockup,
+            lock_until: delegated_account.lock_until,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Drop the boost of a lock that ran out (permissionless), so the server stops counting it in
+    // delegated_weight. Positions with renewal on go through renew_lockup instead.
+    pub fn settle_lockup(ctx: Context<SettleLockup>) -> Result<()> {
+        let clock = Clock::get()?;
+        let info_account = &mut ctx.accounts.info_account;
+        let delegated_account = &mut ctx.accounts.delegated_account;
+        require!(
+            delegated_account.lockup != LockupTier::None,
+            CustomError::InvalidArgument
+        );
+        require!(
+            clock.unix_timestamp >= delegated_account.lock_until,
+            CustomError::StakeLocked
+        );
+
+        {
+            let main_account = &mut ctx.accounts.main_account.load_mut()?;
+            activate_delegation(main_account, info_account, delegated_account, clock.epoch)?;
+        }
+        let old_weight = delegated_account.reward_weight();
+        delegated_account.settle_expired_lockup(info_account, clock.unix_timestamp)?;
+        info_account.update_delegated_weight(old_weight, delegated_account.reward_weight())?;
+
+        emit!(LockupSettled {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: delegated_account.owner,
+            delegated_account: delegated_account.key(),
+            lock_until: delegated_account.lock_until,
+            old_weight,
+            new_weight: delegated_account.reward_weight(),
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Append a delegation to its server's list (permissionless), so the server's delegations can
+    // be walked from delegations_head along `next`, on-chain or by sequential reads, instead of
+    // filtering all program accounts. Positions join through this crank rather than when opened,
+    // the ones opened before the list existed after resize_account. `tail` is the current
+    // delegations_tail, None while the list is empty.
+    pub fn link_delegation(ctx: Context<LinkDelegation>) -> Result<()> {
+        let info_account = &mut ctx.accounts.info_account;
+        let delegated_account = &mut ctx.accounts.delegated_account;
+        let key = delegated_account.key();
+        require!(
+            !info_account.lists(&key, delegated_account),
+            CustomError::DelegationLinked
+        );
+
+        match &mut ctx.accounts.tail {
+            Some(tail) => {
+                require_keys_eq!(
+                    tail.key(),
+                    info_account.delegations_tail,
+                    CustomError::InvalidAccountData
+                );
+                tail.next = key;
+                delegated_account.prev = tail.key();
+            }
+            None => {
+                require!(
+                    info_account.delegations_tail == Pubkey::default(),
+                    CustomError::InvalidAccountData
+                );
+                info_account.delegations_head = key;
+            }
+        }
+        info_account.delegations_tail = key;
+        info_account.linked_delegations = info_account
+            .linked_delegations
+            .checked_add(1)
+            .ok_or(CustomError::NumberOverflow)?;
+
+        let clock = Clock::get()?;
+        emit!(DelegationListUpdated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            server: info_account.key(),
+            delegated_account: key,
+            linked: true,
+            prev: delegated_account.prev,
+            next: delegated_account.next,
+            linked_delegations: info_account.linked_delegations,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Take a delegation out of its server's list, joining its neighbours. A listed position has
+    // to be unlinked before it can close. Its owner and the server owner may always do it, anyone
+    // once the position is empty or the server announced its exit. `prev` and `next` are the
+    // position's neighbours, None at the ends of the list.
+    pub fn unlink_delegation(ctx: Context<UnlinkDelegation>) -> Result<()> {
+        let info_account = &mut ctx.accounts.info_account;
+        let delegated_account = &mut ctx.accounts.delegated_account;
+        let key = delegated_account.key();
+        require!(
+            info_account.lists(&key, delegated_account),
+            CustomError::DelegationNotLinked
+        );
+        let caller = ctx.accounts.caller.key();
+        require!(
+            caller == delegated_account.owner
+                || caller == info_account.owner
+                || info_account.exit_announced_at != 0
+                || (delegated_account.stake == 0 && delegated_account.activating_stake == 0),
+            CustomError::Unauthorized
+        );
+
+        let (prev, next) = (delegated_account.prev, delegated_account.next);
+        match &mut ctx.accounts.prev {
+            Some(account) => {
+                require_keys_eq!(account.key(), prev, CustomError::InvalidAccountData);
+                account.next = next;
+            }
+            None => {
+                require_keys_eq!(prev, Pubkey::default(), CustomError::InvalidAccountData);
+                info_account.delegations_head = next;
+            }
+        }
+        match &mut ctx.accounts.next {
+            Some(account) => {
+                require_keys_eq!(account.key(), next, CustomError::InvalidAccountData);
+                account.prev = prev;
+            }
+            None => {
+                require_keys_eq!(next, Pubkey::default(), CustomError::InvalidAccountData);
+                info_account.delegations_tail = prev;
+            }
+        }
+        delegated_account.prev = Pubkey::default();
+        delegated_account.next = Pubkey::default();
+        info_account.linked_delegations = info_account.linked_delegations.saturating_sub(1);
+
+        let clock = Clock::get()?;
+        emit!(DelegationListUpdated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            server: info_account.key(),
+            delegated_account: key,
+            linked: false,
+            prev,
+            next,
+            linked_delegations: info_account.linked_delegations,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Permissionless reward crank: settles a delegation and restakes the rewards when the
+    // delegator opted into auto-compounding, otherwise they stay claimable
+    pub fn crank_rewards(ctx: Context<CrankRewards>) -> Result<()> {
+        let info_account = &mut ctx.accounts.info_account;
+        let delegated_account = &mut ctx.accounts.delegated_account;
+
+        // The main account signs the restake transfer below, so it can't stay borrowed across it
+        let maximum = {
+            let main_account = &mut ctx.accounts.main_account.load_mut()?;
+            activate_delegation(main_account, info_account, delegated_account, Clock::get()?.epoch)?;
+            main_account.from_common_units(&info_account.mint, MAXIMUM_STAKE)?
+        };
+
+        // Drop the boost of a lock that ran out since the position was last touched
+        let old_weight = delegated_account.reward_weight();
+        delegated_account.settle_expired_lockup(info_account, Clock::get()?.unix_timestamp)?;
+
+        // Restake only what still fits under the position limit
+        let restake = if delegated_account.auto_compound {
+            delegated_account
+                .pending_rewards
+                .min(maximum.saturating_sub(delegated_account.stake))
+        } else {
+            0
+        };
+
+        if restake > 0 {
+            let seeds = &[MAIN_SEED, &[ctx.bumps.main_account]];
+
+            anchor_spl::token_interface::transfer_checked(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.reward_vault.to_account_info(),
+                        mint: ctx.accounts.mint.to_account_info(),
+                        to: ctx.accounts.vault.to_account_info(),
+                        authority: ctx.accounts.main_account.to_account_info(),
+                    },
+                    &[&seeds[..]],
+                ),
+                restake,
+                ctx.accounts.mint.decimals,
+            )?;
+
+            delegated_account.pending_rewards -= restake;
+            delegated_account.stake += restake;
+            info_account.total += restake;
+            info_account.delegated += restake;
+            let main_account = &mut ctx.accounts.main_account.load_mut()?;
+            main_account.add_stake(&info_account.mint, restake)?;
+            info_account.refresh_stake(main_account)?;
+        }
+
+        info_account.update_delegated_weight(old_weight, delegated_account.reward_weight())?;
+        delegated_account.checkpoint_rewards(info_account.acc_reward_per_share)?;
+
+        let clock = Clock::get()?;
+        emit!(RewardsCranked {
+            schema_version: EVENT_SCHEMA_VERSION,
+            owner: delegated_account.owner,
+            delegator: info_account.key(),
+            restaked: restake,
+            claimable: delegated_account.pending_rewards,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // The referrer collects its share of the rewards earned by a referred delegation
+    pub fn claim_referral_rewards(ctx: Context<ClaimReferralRewards>) -> Result<()> {
+        let info_account = &ctx.accounts.info_account;
+        let delegated_account = &mut ctx.accounts.delegated_account;
+
+        delegated_account.settle_rewards(info_account.acc_reward_per_share)?;
+
+        let amount = delegated_account.referral_pending;
+        require!(amount > 0, CustomError::NothingToClaim);
+        delegated_account.referral_pending = 0;
+
+        let seeds = &[MAIN_SEED, &[ctx.bumps.main_account]];
+        let fee = fee_amount(amount, ctx.accounts.config.reward_fee_bps)?;
+
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.reward_vault.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.receipt_token_account.to_account_info(),
+                    authority: ctx.accounts.main_account.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            amount - fee,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        collect_fee(
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.reward_vault.to_account_info(),
+            &ctx.accounts.mint,
+            ctx.accounts.treasury_token_account.as_ref(),
+            ctx.accounts.main_account.to_account_info(),
+            &[&seeds[..]],
+            fee,
+        )?;
+
+        let clock = Clock::get()?;
+        emit!(ReferralRewardsClaimed {
+            schema_version: EVENT_SCHEMA_VERSION,
+            referrer: ctx.accounts.referrer.key(),
+            delegated_account: delegated_account.key(),
+            amount,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Usage of a server over a finished epoch, attested by the usage oracle. Every server and
+    // epoch gets one usage account, written either here or by submit_usage_proof.
+    pub fn report_usage(ctx: Context<ReportUsage>, epoch: u64, report: UsageReport) -> Result<()> {
+        let bump = ctx.bumps.usage;
+        write_usage(
+            &mut ctx.accounts.usage,
+            &mut ctx.accounts.info_account,
+            &ctx.accounts.main_account.load()?,
+            epoch,
+            &report,
+            ctx.accounts.oracle.key(),
+            bump,
+        )
+    }
+
+    // Same record submitted by the server owner, carrying the usage oracle's signature over
+    // `usage_message` in an Ed25519 program instruction placed right before this one
+    pub fn submit_usage_proof(
+        ctx: Context<SubmitUsageProof>,
+        epoch: u64,
+        report: UsageReport,
+    ) -> Result<()> {
+        let usage_oracle = ctx.accounts.config.usage_oracle;
+        require!(usage_oracle != Pubkey::default(), CustomError::Unauthorized);
+
+        let server = ctx.accounts.info_account.key();
+        verify_ed25519_ix(
+            &ctx.accounts.instructions,
+            &usage_oracle,
+            &usage_message(&server, epoch, &report)?,
+        )?;
+
+        let bump = ctx.bumps.usage;
+        write_usage(
+            &mut ctx.accounts.usage,
+            &mut ctx.accounts.info_account,
+            &ctx.accounts.main_account.load()?,
+            epoch,
+            &report,
+            usage_oracle,
+            bump,
+        )
+    }
+
+    // Build the active set of `epoch` during the epoch before it (permissionless, may take several
+    // calls). Servers passed in remaining_accounts are scored by total stake (in the staking
+    // token) x reputation and the best config.active_set_size are kept. Members of the previous
+    // epoch's set hold at most 1 - config.active_set_rotation_bps of the slots, so the rest
+    // rotates to other servers every epoch.
+    pub fn select_active_set<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SelectActiveSet<'info>>,
+        epoch: u64,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        require!(epoch == clock.epoch + 1, CustomError::InvalidEpoch);
+        let size = ctx.accounts.config.active_set_size as usize;
+        require!(size > 0, CustomError::ActiveSetDisabled);
+        require!(
+            ctx.remaining_accounts.len() <= MAX_BATCH_DELEGATIONS,
+            CustomError::InvalidBatchSize
+        );
+
+        let config = &ctx.accounts.config;
+        let active_set = &mut ctx.accounts.active_set;
+        if active_set.version == 0 {
+            active_set.version = VERSION;
+            active_set.epoch = epoch;
+            active_set.size = size as u16;
+            active_set.bump = ctx.bumps.active_set;
+            // Servers of the previous set keep at most the slots not reserved for rotation
+            let reserved = fee_amount(size as u64, config.active_set_rotation_bps)? as u16;
+            active_set.max_incumbents = size as u16 - reserved;
+        }
+        let previous_set = &ctx.accounts.previous_set;
+        let previous_set = if previous_set.data_is_empty() {
+            None
+        } else {
+            Some(Account::<ActiveSet>::try_from(&previous_set.to_account_info())?)
+        };
+
+        let main_account = ctx.accounts.main_account.load()?;
+        let mut added = 0u8;
+        for info_info in ctx.remaining_accounts.iter() {
+            let info_account = Account::<InfoAccount>::try_from(info_info)?;
+            // Only servers in their own right: enough self stake and online
+            if info_account.tier == ServiceTier::None
+                || !server_online(&info_account, config, clock.unix_timestamp)
+            {
+                continue;
+            }
+            // Penalized servers sit out config.jail_cooldown_epochs before they can return
+            if info_account.service_failures > 0
+                && info_account.last_failure_epoch + config.jail_cooldown_epochs >= epoch
+            {
+                continue;
+            }
+            let total = main_account.to_common_units(&info_account.mint, info_account.total)?;
+            let score = (total as u128 * info_account.reputation_bps() as u128
+                / MAX_BPS as u128) as u64;
+            let incumbent = previous_set
+                .as_ref()
+                .is_some_and(|previous_set| previous_set.contains(&info_info.key()));
+            if active_set.insert(info_info.key(), score, incumbent) {
+                added += 1;
+            }
+        }
+
+        emit!(ActiveSetUpdated {
+            schema_version: EVENT_SCHEMA_VERSION,
+            epoch,
+            added,
+            count: active_set.count,
+            incumbents: active_set.incumbents,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Work credits of a server for the epoch that just ended, from the usage oracle. Reports are
+    // taken during the following epoch, after which the epoch's reward pool is split by credits.
+    pub fn report_work_credits(
+        ctx: Context<ReportWorkCredits>,
+        epoch: u64,
+        credits: u64,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        require!(epoch + 1 == clock.epoch, CustomError::InvalidEpoch);
+        // Work rewards are paid in the staking token
+        require_keys_eq!(
+            ctx.accounts.info_account.mint,
+            ctx.accounts.main_account.load()?.staking_mint(),
+            CustomError::InvalidMint
+        );
+
+        // Outdated nodes earn nothing until they upgrade and report the new version
+        require!(
+            ctx.accounts.info_account.software_version >= ctx.accounts.config.min_software_version,
+            CustomError::SoftwareOutdated
+        );
+
+        // With active sets enabled, only servers of the epoch's set earn work rewards
+        if ctx.accounts.config.active_set_size > 0 {
+            let active_set = ctx
+                .accounts
+                .active_set
+                .as_ref()
+                .ok_or(CustomError::NotInActiveSet)?;
+            require!(
+                active_set.contains(&ctx.accounts.info_account.key()),
+                CustomError::NotInActiveSet
+            );
+        }
+
+        let epoch_work = &mut ctx.accounts.epoch_work;
+        if epoch_work.version == 0 {
+            epoch_work.version = VERSION;
+            epoch_work.epoch = epoch;
+            epoch_work.bump = ctx.bumps.epoch_work;
+        }
+        epoch_work.total_credits = epoch_work
+            .total_credits
+            .checked_add(credits)
+            .ok_or(CustomError::NumberOverflow)?;
+        epoch_work.servers += 1;
+
+        let work_credits = &mut ctx.accounts.work_credits;
+        work_credits.version = VERSION;
+        work_credits.server = ctx.accounts.info_account.key();
+        work_credits.epoch = epoch;
+        work_credits.credits = credits;
+        work_credits.distributed = false;
+        work_credits.bump = ctx.bumps.work_credits;
+
+        emit!(WorkCreditsReported {
+            schema_version: EVENT_SCHEMA_VERSION,
+            server: work_credits.server,
+            epoch,
+            credits,
+            total_credits: epoch_work.total_credits,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Add staking tokens to an epoch's work reward pool, until its distribution starts
+    pub fn fund_epoch_rewards(
+        ctx: Context<FundEpochRewards>,
+        epoch: u64,
+        amount: u64,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        require!(clock.epoch <= epoch + 1, CustomError::InvalidEpoch);
+        require_keys_eq!(
+            ctx.accounts.mint.key(),
+            ctx.accounts.main_account.load()?.staking_mint(),
+            CustomError::InvalidMint
+        );
+
+        let amount_in_minimum_units = to_minimum_units(amount, ctx.accounts.mint.decimals)?;
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.sender_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.reward_vault.to_account_info(),
+                    authority: ctx.accounts.funder.to_account_info(),
+                },
+            ),
+            amount_in_minimum_units,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        let epoch_work = &mut ctx.accounts.epoch_work;
+        if epoch_work.version == 0 {
+            epoch_work.version = VERSION;
+            epoch_work.epoch = epoch;
+            epoch_work.bump = ctx.bumps.epoch_work;
+        }
+        epoch_work.pool = epoch_work
+            .pool
+            .checked_add(amount_in_minimum_units)
+            .ok_or(CustomError::NumberOverflow)?;
+
+        emit!(EpochRewardsFunded {
+            schema_version: EVENT_SCHEMA_VERSION,
+            funder: ctx.accounts.funder.key(),
+            epoch,
+            amount: amount_in_minimum_units,
+            pool: epoch_work.pool,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Credit a server's delegators with its share of an epoch's work pool, pool x credits /
+    // total credits, once the reporting epoch is over (permissionless, once per server)
+    pub fn distribute_work_rewards(ctx: Context<DistributeWorkRewards>) -> Result<()> {
+        let clock = Clock::get()?;
+        let epoch_work = &mut ctx.accounts.epoch_work;
+        require!(clock.epoch >= epoch_work.epoch + 2, CustomError::InvalidEpoch);
+
+        let work_credits = &mut ctx.accounts.work_credits;
+        require!(!work_credits.distributed, CustomError::NothingToClaim);
+        work_credits.distributed = true;
+
+        let share = if epoch_work.total_credits > 0 {
+            (epoch_work.pool as u128 * work_credits.credits as u128
+                / epoch_work.total_credits as u128) as u64
+        } else {
+            0
+        };
+        epoch_work.distributed += share;
+
+        // Without delegators the share can't be credited, it goes to the treasury with the dust
+        let info_account = &mut ctx.accounts.info_account;
+        if info_account.delegated_weight > 0 {
+            info_account.distribute_rewards(share, clock.unix_timestamp)?;
+        } else {
+            info_account.reward_dust += share;
+        }
+
+        emit!(WorkRewardsDistributed {
+            schema_version: EVENT_SCHEMA_VERSION,
+            server: info_account.key(),
+            epoch: epoch_work.epoch,
+            credits: work_credits.credits,
+            amount: share,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Failed availability proof reported by the usage oracle, at most once per server and epoch.
+    // Counts the failure and moves config.failure_penalty_bps of the operator's pending revenue
+    // (unreleased and released) to the treasury; stake is never touched.
+    pub fn penalize_service_failure(ctx: Context<PenalizeServiceFailure>) -> Result<()> {
+        let clock = Clock::get()?;
+        let info_account = &mut ctx.accounts.info_account;
+        require!(
+            info_account.service_failures == 0 || info_account.last_failure_epoch < clock.epoch,
+            CustomError::AlreadyPenalizedThisEpoch
+        );
+        info_account.accrue_revenue(clock.unix_timestamp)?;
+
+        let pending = info_account.revenue_unreleased + info_account.revenue_released;
+        let penalty = fee_amount(pending, ctx.accounts.config.failure_penalty_bps)?;
+        if penalty > 0 {
+            let (Some(revenue_vault), Some(treasury_token_account), Some(mint)) = (
+                ctx.accounts.revenue_vault.as_ref(),
+                ctx.accounts.treasury_token_account.as_ref(),
+                ctx.accounts.mint.as_ref(),
+            ) else {
+                return err!(CustomError::TreasuryAccountRequired);
+            };
+
+            let seed_owner = info_account.seed_owner();
+            let seeds = &[
+                INFO_SEED,
+                seed_owner.as_ref(),
+                info_account.serverkey_hash.as_ref(),
+                &[info_account.bump],
+            ];
+            anchor_spl::token_interface::transfer_checked(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: revenue_vault.to_account_info(),
+                        mint: mint.to_account_info(),
+                        to: treasury_token_account.to_account_info(),
+                        authority: info_account.to_account_info(),
+                    },
+                    &[&seeds[..]],
+                ),
+                penalty,
+                mint.decimals,
+            )?;
+
+            // Unreleased revenue goes first, the operator hasn't been able to count on it yet
+            let from_unreleased = penalty.min(info_account.revenue_unreleased);
+            info_account.revenue_unreleased -= from_unreleased;
+            info_account.revenue_released -= penalty - from_unreleased;
+        }
+
+        info_account.service_failures += 1;
+        info_account.last_failure_epoch = clock.epoch;
+
+        record_audit(
+            &ctx.accounts.config,
+            ctx.accounts.audit_log.as_ref(),
+            AuditAction::ServiceFailurePenalized,
+            ctx.accounts.oracle.key(),
+            info_account.key(),
+            &clock,
+        )?;
+        emit!(ServiceFailurePenalized {
+            schema_version: EVENT_SCHEMA_VERSION,
+            server: info_account.key(),
+            epoch: clock.epoch,
+            penalty,
+            failures: info_account.service_failures,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Pay a server for its service, `amount` in base units of the server's mint. Revenue goes to
+    // the server's revenue vault and streams to the operator over config.revenue_stream_secs; a
+    // payment restarts the stream, so what is still unreleased vests over the full period again.
+    pub fn pay_server(ctx: Context<PayServer>, amount: u64) -> Result<()> {
+        require!(amount > 0, CustomError::InsufficientFunds);
+
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.sender_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.revenue_vault.to_account_info(),
+                    authority: ctx.accounts.payer.to_account_info(),
+                },
+            ),
+            amount,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        let clock = Clock::get()?;
+        let info_account = &mut ctx.accounts.info_account;
+        info_account.accrue_revenue(clock.unix_timestamp)?;
+        info_account.revenue_unreleased = info_account
+            .revenue_unreleased
+            .checked_add(amount)
+            .ok_or(CustomError::NumberOverflow)?;
+        info_account.revenue_total = info_account
+            .revenue_total
+            .checked_add(amount)
+            .ok_or(CustomError::NumberOverflow)?;
+        info_account.revenue_stream_end = clock
+            .unix_timestamp
+            .checked_add(ctx.accounts.config.revenue_stream_secs as i64)
+            .ok_or(CustomError::NumberOverflow)?;
+
+        emit!(RevenuePaid {
+            schema_version: EVENT_SCHEMA_VERSION,
+            payer: ctx.accounts.payer.key(),
+            server: info_account.key(),
+            amount,
+            stream_end: info_account.revenue_stream_end,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Pay a server `amount` base units of config.stable_mint. The operator's part goes straight
+    // to the operator in the stablecoin. The delegators' part goes to the treasury, which pays
+    // its worth in staking tokens, at the Pyth price, into the reward pool; delegator rewards stay
+    // in the token their stake is in. Only for servers staked in the staking token.
+    pub fn pay_server_stable(ctx: Context<PayServerStable>, amount: u64) -> Result<()> {
+        require!(amount > 0, CustomError::InsufficientFunds);
+        let config = &ctx.accounts.config;
+        require!(
+            config.stable_mint != Pubkey::default(),
+            CustomError::StablePaymentsDisabled
+        );
+
+        let clock = Clock::get()?;
+        let info_account = &mut ctx.accounts.info_account;
+        let delegator_share = info_account.delegator_share(amount)?;
+        let operator_share = amount - delegator_share;
+        for (to, share) in [
+            (ctx.accounts.owner_stable_account.to_account_info(), operator_share),
+            (ctx.accounts.treasury_stable_account.to_account_info(), delegator_share),
+        ] {
+            if share == 0 {
+                continue;
+            }
+            anchor_spl::token_interface::transfer_checked(
+                CpiContext::new(
+                    ctx.accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.sender_stable_account.to_account_info(),
+                        mint: ctx.accounts.stable_mint.to_account_info(),
+                        to,
+                        authority: ctx.accounts.payer.to_account_info(),
+                    },
+                ),
+                share,
+                ctx.accounts.stable_mint.decimals,
+            )?;
+        }
+
+        let mut delegator_tokens = 0;
+        if delegator_share > 0 {
+            let (price, exponent) = staking_token_price(
+                &ctx.accounts.price_update,
+                config,
+                clock.unix_timestamp,
+            )?;
+            delegator_tokens = usd_to_tokens(
+                delegator_share,
+                ctx.accounts.stable_mint.decimals,
+                price,
+                exponent,
+                ctx.accounts.mint.decimals,
+            )?;
+            let seeds = &[TREASURY_SEED, &[ctx.bumps.treasury]];
+            anchor_spl::token_interface::transfer_checked(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.treasury_token_account.to_account_info(),
+                        mint: ctx.accounts.mint.to_account_info(),
+                        to: ctx.accounts.reward_vault.to_account_info(),
+                        authority: ctx.accounts.treasury.to_account_info(),
+                    },
+                    &[&seeds[..]],
+                ),
+                delegator_tokens,
+                ctx.accounts.mint.decimals,
+            )?;
+            info_account.distribute_rewards(delegator_tokens, clock.unix_timestamp)?;
+        }
+
+        emit!(StablePaymentReceived {
+            schema_version: EVENT_SCHEMA_VERSION,
+            payer: ctx.accounts.payer.key(),
+            server: info_account.key(),
+            amount,
+            operator_share,
+            delegator_share,
+            delegator_tokens,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Escrow `amount` base units of the server's mint for `duration` seconds of service. The
+    // escrow drips to the server's revenue as settle_subscription runs, for the time the server
+    // kept heartbeating; time it was offline stays in the escrow and goes back to the client.
+    pub fn open_subscription(
+        ctx: Context<OpenSubscription>,
+        amount: u64,
+        duration: i64,
+    ) -> Result<()> {
+        require!(amount > 0, CustomError::InsufficientFunds);
+        require!(duration > 0, CustomError::InvalidArgument);
+
+        let clock = Clock::get()?;
+        let info_account = &ctx.accounts.info_account;
+        require!(
+            server_online(info_account, &ctx.accounts.config, clock.unix_timestamp),
+            CustomError::ServerOffline
+        );
+
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.sender_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.escrow.to_account_info(),
+                    authority: ctx.accounts.client.to_account_info(),
+                },
+            ),
+            amount,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        let subscription = &mut ctx.accounts.subscription;
+        subscription.version = VERSION;
+        subscription.client = ctx.accounts.client.key();
+        subscription.server = info_account.key();
+        subscription.mint = info_account.mint;
+        subscription.amount = amount;
+        subscription.paid = 0;
+        subscription.start = clock.unix_timestamp;
+        subscription.end = clock
+            .unix_timestamp
+            .checked_add(duration)
+            .ok_or(CustomError::NumberOverflow)?;
+        subscription.updated_at = clock.unix_timestamp;
+        subscription.live_secs = 0;
+        subscription.bump = ctx.bumps.subscription;
+        subscription.disputed = 0;
+
+        emit!(SubscriptionOpened {
+            schema_version: EVENT_SCHEMA_VERSION,
+            client: subscription.client,
+            server: subscription.server,
+            amount,
+            end: subscription.end,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Pay the server what a subscription owes it so far (permissionless)
+    pub fn settle_subscription(ctx: Context<SettleSubscription>) -> Result<()> {
+        let clock = Clock::get()?;
+        let accounts = ctx.accounts;
+        let owed = accounts.subscription.accrue(
+            clock.unix_timestamp,
+            live_until(&accounts.info_account, &accounts.config),
+        )?;
+        pay_subscription(
+            &accounts.subscription,
+            &mut accounts.info_account,
+            &accounts.escrow,
+            &accounts.revenue_vault,
+            &accounts.mint,
+            &accounts.token_program,
+            owed,
+        )?;
+
+        emit!(SubscriptionSettled {
+            schema_version: EVENT_SCHEMA_VERSION,
+            client: accounts.subscription.client,
+            server: accounts.subscription.server,
+            amount: owed,
+            paid: accounts.subscription.paid,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // End a subscription early or after it ran out: the server is paid what it earned and the
+    // client gets the rest of the escrow back
+    pub fn close_subscription(ctx: Context<CloseSubscription>) -> Result<()> {
+        let clock = Clock::get()?;
+        let accounts = ctx.accounts;
+        let owed = accounts.subscription.accrue(
+            clock.unix_timestamp,
+            live_until(&accounts.info_account, &accounts.config),
+        )?;
+        pay_subscription(
+            &accounts.subscription,
+            &mut accounts.info_account,
+            &accounts.escrow,
+            &accounts.revenue_vault,
+            &accounts.mint,
+            &accounts.token_program,
+            owed,
+        )?;
+
+        // Whatever is left, including tokens sent to the escrow by others, so it can be closed
+        accounts.escrow.reload()?;
+        let refund = accounts.escrow.amount;
+        let subscription = &accounts.subscription;
+        let client = subscription.client;
+        let server = subscription.server;
+        let seeds = &[
+            SUBSCRIPTION_SEED,
+            client.as_ref(),
+            server.as_ref(),
+            &[subscription.bump],
+        ];
+        if refund > 0 {
+            anchor_spl::token_interface::transfer_checked(
+                CpiContext::new_with_signer(
+                    accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: accounts.escrow.to_account_info(),
+                        mint: accounts.mint.to_account_info(),
+                        to: accounts.client_token_account.to_account_info(),
+                        authority: subscription.to_account_info(),
+                    },
+                    &[&seeds[..]],
+                ),
+                refund,
+                accounts.mint.decimals,
+            )?;
+        }
+        anchor_spl::token_interface::close_account(CpiContext::new_with_signer(
+            accounts.token_program.to_account_info(),
+            CloseAccount {
+                account: accounts.escrow.to_account_info(),
+                destination: accounts.client.to_account_info(),
+                authority: subscription.to_account_info(),
+            },
+            &[&seeds[..]],
+        ))?;
+
+        emit!(SubscriptionClosed {
+            schema_version: EVENT_SCHEMA_VERSION,
+            client,
+            server,
+            paid: subscription.paid,
+            refund,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Contest `amount` of what a subscription paid the server for a failed service period. The
+    // amount is taken out of the server's pending revenue and frozen until config.arbiter
+    // resolves the dispute, or config.dispute_timeout_secs pass and it goes back to the server.
+    pub fn open_dispute(ctx: Context<OpenDispute>, amount: u64) -> Result<()> {
+        require!(amount > 0, CustomError::InvalidArgument);
+        let subscription = &mut ctx.accounts.subscription;
+        require!(
+            amount <= subscription.paid - subscription.disputed,
+            CustomError::InsufficientFunds
+        );
+
+        let clock = Clock::get()?;
+        let info_account = &mut ctx.accounts.info_account;
+        info_account.accrue_revenue(clock.unix_timestamp)?;
+        require!(
+            amount <= info_account.revenue_unreleased + info_account.revenue_released,
+            CustomError::InsufficientFunds
+        );
+        // Like penalize_service_failure, unreleased revenue goes first
+        let from_unreleased = amount.min(info_account.revenue_unreleased);
+        info_account.revenue_unreleased -= from_unreleased;
+        info_account.revenue_released -= amount - from_unreleased;
+        info_account.revenue_disputed += amount;
+        subscription.disputed += amount;
+
+        let dispute = &mut ctx.accounts.dispute;
+        dispute.version = VERSION;
+        dispute.client = subscription.client;
+        dispute.server = info_account.key();
+        dispute.amount = amount;
+        dispute.opened_at = clock.unix_timestamp;
+        dispute.bump = ctx.bumps.dispute;
+
+        emit!(DisputeOpened {
+            schema_version: EVENT_SCHEMA_VERSION,
+            client: dispute.client,
+            server: dispute.server,
+            amount,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Arbiter's ruling: `refund` of the disputed amount goes back to the client, the rest
+    // returns to the server's released revenue
+    pub fn resolve_dispute(ctx: Context<ResolveDispute>, refund: u64) -> Result<()> {
+        let dispute = &ctx.accounts.dispute;
+        require!(refund <= dispute.amount, CustomError::InvalidArgument);
+
+        let info_account = &mut ctx.accounts.info_account;
+        if refund > 0 {
+            let seed_owner = info_account.seed_owner();
+            let seeds = &[
+                INFO_SEED,
+                seed_owner.as_ref(),
+                info_account.serverkey_hash.as_ref(),
+                &[info_account.bump],
+            ];
+            anchor_spl::token_interface::transfer_checked(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.revenue_vault.to_account_info(),
+                        mint: ctx.accounts.mint.to_account_info(),
+                        to: ctx.accounts.client_token_account.to_account_info(),
+                        authority: info_account.to_account_info(),
+                    },
+                    &[&seeds[..]],
+                ),
+                refund,
+                ctx.accounts.mint.decimals,
+            )?;
+        }
+        release_dispute_revenue(info_account, dispute.amount, refund)?;
+
+        let clock = Clock::get()?;
+        record_audit(
+            &ctx.accounts.config,
+            ctx.accounts.audit_log.as_ref(),
+            AuditAction::DisputeResolved,
+            ctx.accounts.arbiter.key(),
+            dispute.key(),
+            &clock,
+        )?;
+        emit!(DisputeResolved {
+            schema_version: EVENT_SCHEMA_VERSION,
+            client: dispute.client,
+            server: dispute.server,
+            amount: dispute.amount,
+            refund,
+            timed_out: false,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Release a dispute the arbiter left unresolved past config.dispute_timeout_secs to the
+    // server (permissionless)
+    pub fn expire_dispute(ctx: Context<ExpireDispute>) -> Result<()> {
+        let clock = Clock::get()?;
+        let dispute = &ctx.accounts.dispute;
+        require!(
+            clock.unix_timestamp
+                >= dispute.opened_at + ctx.accounts.config.dispute_timeout_secs as i64,
+            CustomError::DisputeNotExpired
+        );
+        release_dispute_revenue(&mut ctx.accounts.info_account, dispute.amount, 0)?;
+
+        emit!(DisputeResolved {
+            schema_version: EVENT_SCHEMA_VERSION,
+            client: dispute.client,
+            server: dispute.server,
+            amount: dispute.amount,
+            refund: 0,
+            timed_out: true,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Open a payment channel to a server: the client escrows `amount` base units of the server's
+    // mint and pays off-chain with cumulative vouchers (channel_voucher_message) that the server
+    // redeems with claim_channel. The server backs the channel with `collateral` of its stake
+    // once it accepts.
+    pub fn open_channel(ctx: Context<OpenChannel>, amount: u64, collateral: u64) -> Result<()> {
+        require!(amount > 0, CustomError::InsufficientFunds);
+
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.sender_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.escrow.to_account_info(),
+                    authority: ctx.accounts.client.to_account_info(),
+                },
+            ),
+            amount,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        let clock = Clock::get()?;
+        let channel = &mut ctx.accounts.channel;
+        channel.version = VERSION;
+        channel.client = ctx.accounts.client.key();
+        channel.server = ctx.accounts.info_account.key();
+        channel.mint = ctx.accounts.mint.key();
+        channel.deposit = amount;
+        channel.paid = 0;
+        channel.collateral = collateral;
+        channel.accepted = false;
+        channel.close_requested_at = 0;
+        channel.refund_owed = 0;
+        channel.opened_slot = clock.slot;
+        channel.bump = ctx.bumps.channel;
+
+        emit!(ChannelOpened {
+            schema_version: EVENT_SCHEMA_VERSION,
+            client: channel.client,
+            server: channel.server,
+            deposit: amount,
+            collateral,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Server owner locks the requested collateral from its stake, until the channel is finalized
+    pub fn accept_channel(ctx: Context<AcceptChannel>) -> Result<()> {
+        let channel = &mut ctx.accounts.channel;
+        require!(!channel.accepted, CustomError::ChannelAlreadyAccepted);
+        require!(channel.close_requested_at == 0, CustomError::ChannelClosing);
+
+        let info_account = &mut ctx.accounts.info_account;
+        let locked = info_account
+            .channel_locked
+            .checked_add(channel.collateral)
+            .ok_or(CustomError::NumberOverflow)?;
+        require!(locked <= info_account.stake, CustomError::InsufficientFunds);
+        info_account.channel_locked = locked;
+        channel.accepted = true;
+
+        let clock = Clock::get()?;
+        emit!(ChannelAccepted {
+            schema_version: EVENT_SCHEMA_VERSION,
+            client: channel.client,
+            server: channel.server,
+            collateral: channel.collateral,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Redeem the client's latest voucher for `paid` (cumulative), signed by the client in an
+    // Ed25519 instruction right before this one. The difference to what was already redeemed
+    // goes to the server's revenue. Possible until the channel is finalized.
+    pub fn claim_channel(ctx: Context<ClaimChannel>, paid: u64) -> Result<()> {
+        let channel = &ctx.accounts.channel;
+        require!(paid > channel.paid, CustomError::NothingToClaim);
+        require!(paid <= channel.deposit, CustomError::InsufficientFunds);
+        verify_ed25519_ix(
+            &ctx.accounts.instructions,
+            &channel.client,
+            &channel_voucher_message(&channel.key(), channel.opened_slot, paid),
+        )?;
+
+        let amount = paid - channel.paid;
+        let seeds = &[
+            CHANNEL_SEED,
+            channel.client.as_ref(),
+            channel.server.as_ref(),
+            &[channel.bump],
+        ];
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.escrow.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    to: ctx.accounts.revenue_vault.to_account_info(),
+                    authority: channel.to_account_info(),
+                },
+                &[&seeds[..]],
+            ),
+            amount,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        let info_account = &mut ctx.accounts.info_account;
+        info_account.revenue_released = info_account
+            .revenue_released
+            .checked_add(amount)
+            .ok_or(CustomError::NumberOverflow)?;
+        info_account.revenue_total = info_account
+            .revenue_total
+            .checked_add(amount)
+            .ok_or(CustomError::NumberOverflow)?;
+        ctx.accounts.channel.paid = paid;
+
+        let clock = Clock::get()?;
+        emit!(ChannelClaimed {
+            schema_version: EVENT_SCHEMA_VERSION,
+            client: ctx.accounts.channel.client,
+            server: ctx.accounts.channel.server,
+            amount,
+            paid,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Client starts closing the channel, which gives the server config.channel_challenge_secs to
+    // claim its latest voucher. With `refund` the client presents a refund the server signed
+    // (channel_refund_message, Ed25519 instruction right before this one), paid out of the
+    // server's locked collateral at finalization.
+    pub fn close_channel(ctx: Context<CloseChannel>, refund: Option<u64>) -> Result<()> {
+        let channel = &mut ctx.accounts.channel;
+        require!(channel.close_requested_at == 0, CustomError::ChannelClosing);
+
+        if let Some(refund) = refund {
+            require!(channel.accepted, CustomError::ChannelNotAccepted);
+            let instructions = ctx
+                .accounts
+                .instructions
+                .as_ref()
+                .ok_or(CustomError::InvalidSignature)?;
+            verify_ed25519_ix(
+                instructions,
+                &ctx.accounts.info_account.owner,
+                &channel_refund_message(&channel.key(), channel.opened_slot, refund),
+            )?;
+            channel.refund_owed = refund.min(channel.collateral);
+        }
+
+        let clock = Clock::get()?;
+        channel.close_requested_at = clock.unix_timestamp;
+
+        emit!(ChannelCloseRequested {
+            schema_version: EVENT_SCHEMA_VERSION,
+            client: channel.client,
+            server: channel.server,
+            refund_owed: channel.refund_owed,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // After the challenge period (right away for a channel the server never accepted): pay the
+    // owed refund out of the server's stake vault, unlock the collateral and return the unspent
+    // deposit to the client
+    pub fn finalize_channel(ctx: Context<FinalizeChannel>) -> Result<()> {
+        let clock = Clock::get()?;
+        let channel = &ctx.accounts.channel;
+        require!(channel.close_requested_at != 0, CustomError::ChannelNotClosing);
+        if channel.accepted {
+            let challenge_end = channel
+                .close_requested_at
+                .saturating_add(ctx.accounts.config.channel_challenge_secs as i64);
+            require!(clock.unix_timestamp >= challenge_end, CustomError::ChallengePeriodActive);
+        }
+
+        let info_account = &mut ctx.accounts.info_account;
+        let mut refund_owed = 0;
+        if channel.accepted {
+            info_account.channel_locked -= channel.collateral;
+            refund_owed = channel.refund_owed.min(info_account.stake);
+        }
+        if refund_owed > 0 {
+            let seed_owner = info_account.seed_owner();
+            let seeds = &[
+                INFO_SEED,
+                seed_owner.as_ref(),
+                info_account.serverkey_hash.as_ref(),
+                &[info_account.bump],
+            ];
+            anchor_spl::token_interface::transfer_checked(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.vault.to_account_info(),
+                        mint: ctx.accounts.mint.to_account_info(),
+                        to: ctx.accounts.client_token_account.to_account_info(),
+                        authority: info_account.to_account_info(),
+                    },
+                    &[&seeds[..]],
+                ),
+                refund_owed,
+                ctx.accounts.mint.decimals,
+            )?;
+            info_account.stake -= refund_owed;
+            info_account.total -= refund_owed;
+            let main_account = &mut ctx.accounts.main_account.load_mut()?;
+            main_account.sub_self_stake(&info_account.mint, refund_owed)?;
+            info_account.refresh_stake(main_account)?;
+            info_account.count_withdrawal(main_account, refund_owed)?;
+        }
+
+        // Whatever is left in the escrow, including tokens sent to it by others
+        let remaining = ctx.accounts.escrow.amount;
+        let seeds = &[
+            CHANNEL_SEED,
+            channel.client.as_ref(),
+            channel.server.as_ref(),
+            &[channel.bump],
+        ];
+        if remaining > 0 {
+            anchor_spl::token_interface::transfer_checked(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.escrow.to_account_info(),
+                        mint: ctx.accounts.mint.to_account_info(),
+                        to: ctx.accounts.client_token_account.to_account_info(),
+                        authority: channel.to_account_info(),
+                    },
+                    &[&seeds[..]],
+                ),
+                remaining,
+                ctx.accounts.mint.decimals,
+            )?;
+        }
+        anchor_spl::token_interface::close_account(CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            CloseAccount {
+                account: ctx.accounts.escrow.to_account_info(),
+                destination: ctx.accounts.client.to_account_info(),
+                authority: channel.to_account_info(),
+            },
+            &[&seeds[..]],
+        ))?;
+
+        emit!(ChannelFinalized {
+            schema_version: EVENT_SCHEMA_VERSION,
+            client: channel.client,
+            server: channel.server,
+            paid: channel.paid,
+            refunded: remaining,
+            collateral_paid: refund_owed,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // First half of a commit-reveal server selection for a task or audit: fixes the candidate
+    // servers (remaining_accounts) and keccak(secret). reveal_task picks one of them weighted by
+    // stake, with the secret mixed with the hash of the next produced slot, which the requester
+    // can't know when committing.
+    pub fn commit_task<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CommitTask<'info>>,
+        task_id: u64,
+        commitment: [u8; 32],
+    ) -> Result<()> {
+        let candidates = ctx.remaining_accounts;
+        require!(
+            !candidates.is_empty() && candidates.len() <= MAX_TASK_CANDIDATES,
+            CustomError::InvalidBatchSize
+        );
+
+        let task = &mut ctx.accounts.task;
+        for (i, candidate) in candidates.iter().enumerate() {
+            // Only checks that it is a server, its stake is read at reveal
+            Account::<InfoAccount>::try_from(candidate)?;
+            task.candidates[i] = candidate.key();
+        }
+
+        let clock = Clock::get()?;
+        task.version = VERSION;
+        task.requester = ctx.accounts.requester.key();
+        task.task_id = task_id;
+        task.commitment = commitment;
+        task.commit_slot = clock.slot;
+        task.candidate_count = candidates.len() as u8;
+        task.bump = ctx.bumps.task;
+
+        emit!(TaskCommitted {
+            schema_version: EVENT_SCHEMA_VERSION,
+            requester: task.requester,
+            task_id,
+            candidates: task.candidate_count,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Reveal the secret and assign the task, with the candidate servers in remaining_accounts in
+    // commit order. Must happen while the slot after the commit is still in SlotHashes (512
+    // slots).
+    pub fn reveal_task<'info>(
+        ctx: Context<'_, '_, 'info, 'info, RevealTask<'info>>,
+        secret: [u8; 32],
+    ) -> Result<()> {
+        let task = &mut ctx.accounts.task;
+        require!(task.assigned == Pubkey::default(), CustomError::TaskAlreadyAssigned);
+        require!(
+            keccak::hash(&secret).to_bytes() == task.commitment,
+            CustomError::InvalidReveal
+        );
+
+        let count = task.candidate_count as usize;
+        require!(ctx.remaining_accounts.len() == count, CustomError::InvalidBatchSize);
+        let mut stakes = Vec::with_capacity(count);
+        for (candidate, expected) in ctx.remaining_accounts.iter().zip(task.candidates.iter()) {
+            require_keys_eq!(candidate.key(), *expected, CustomError::InvalidBatchAccount);
+            // A candidate removed since the commit weighs nothing
+            let stake = Account::<InfoAccount>::try_from(candidate)
+                .map(|info| info.total)
+                .unwrap_or(0);
+            stakes.push(stake);
+        }
+        let total_stake = stakes.iter().map(|stake| *stake as u128).sum::<u128>();
+        require!(total_stake > 0, CustomError::NoActiveStake);
+
+        let slot_hash = slot_hash_after(&ctx.accounts.slot_hashes, task.commit_slot)?;
+        let randomness = keccak::hashv(&[&secret, &slot_hash, task.key().as_ref()]).to_bytes();
+
+        // Walk the cumulative stake to the drawn point
+        let draw = u128::from_le_bytes(randomness[..16].try_into().unwrap()) % total_stake;
+        let mut cumulative = 0u128;
+        let mut selected = 0;
+        for (i, stake) in stakes.iter().enumerate() {
+            cumulative += *stake as u128;
+            if draw < cumulative {
+                selected = i;
+                break;
+            }
+        }
+
+        let clock = Clock::get()?;
+        task.assigned = task.candidates[selected];
+        task.randomness = randomness;
+        task.assigned_slot = clock.slot;
+
+        emit!(TaskAssigned {
+            schema_version: EVENT_SCHEMA_VERSION,
+            requester: task.requester,
+            task_id: task.task_id,
+            server: task.assigned,
+            randomness,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    // Release the vested part of a server's revenue (permissionless). Delegators get the share
+    // their active stake has in the server total, less the operator commission, credited to the
+    // reward accumulator like fund_rewards; the operator is paid the rest. When the config sets a
+    // revenue vesting schedule the operator's part vests first (cliff, then linear), and each
+    // settlement pays what has vested so far.
+    pub fn settle_revenue(ctx: Context<SettleRevenue>) -> Result<()> {
+        let clock = Clock::get()?;
+        let info_account = &mut ctx.accounts.info_account;
+        info_account.accrue_revenue(clock.unix_timestamp)?;
+
+        let amount = info_account.revenue_released;
+        info_account.revenue_released = 0;
+
+        let delegator_share = info_account.delegator_share(amount)?;
+        let mut operator_share = amount - delegator_share;
+        // With vesting on, the operator's share joins the schedule and what has vested is paid
+        let config = &ctx.accounts.config;
+        if config.revenue_vesting_cliff_secs > 0 || config.revenue_vesting_secs > 0 {
+            operator_share = info_account.add_vesting(
+                operator_share,
+                clock.unix_timestamp,
+                config.revenue_vesting_cliff_secs,
+                config.revenue_vesting_secs,
+            )?;
+        } else {
+            // Whatever was vesting when vesting got switched off is released too
+            operator_share += info_account.vesting_amount - info_account.vesting_claimed;
+            info_account.vesting_amount = 0;
+            info_account.vesting_claimed = 0;
+        }
+        require!(amount > 0 || operator_share > 0, CustomError::NothingToClaim);
+
+        let owner = info_account.owner;
+        let serverkey_hash = info_account.serverkey_hash;
+        let seed_owner = info_account.seed_owner();
+        let seeds = &[
+            INFO_SEED,
+            seed_owner.as_ref(),
+            serverkey_hash.as_ref(),
+            &[info_account.bump],
+        ];
+        for (to, share) in [
+            (ctx.accounts.reward_vault.to_account_info(), delegator_share),
+            (ctx.accounts.owner_token_account.to_account_info(), operator_share),
+        ] {
+            if share == 0 {
+                continue;
+            }
+            anchor_spl::token_interface::transfer_checked(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.revenue_vault.to_account_info(),
+                        mint: ctx.accounts.mint.to_account_info(),
+                        to,
+                        authority: info_account.to_account_info(),
+                    },
+                    &[&seeds[..]],
+                ),
+                share,
+                ctx.accounts.mint.decimals,
+            )?;
+        }
+        if delegator_share > 0 {
+            info_account.distribute_rewards(delegator_share, clock.unix_timestamp)?;
+        }
+
+        emit!(RevenueSettled {
+            schema_version: EVENT_SCHEMA_VERSION,
+            server: info_account.key(),
+            owner,
+            amount,
+            operator_share,
+            delegator_share,
+            unreleased: info_account.revenue_unreleased,
+            vesting: info_account.vesting_amount - info_account.vesting_claimed,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+}
+
+// Realloc a program account to `new_space`, topping up rent from `payer`
+fn grow_account<'info>(
+    account: &AccountInfo<'info>,
+    payer: AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
+    new_space: usize,
+) -> Result<()> {
+    let old_space = account.data_len();
+    require!(
+        new_space > old_space
+            && new_space - old_space <= solana_program::entrypoint::MAX_PERMITTED_DATA_INCREASE,
+        CustomError::InvalidAccountSize
+    );
+
+    let rent = Rent::get()?.minimum_balance(new_space);
+    let top_up = rent.saturating_sub(account.lamports());
+    if top_up > 0 {
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                system_program,
+                anchor_lang::system_program::Transfer {
+                    from: payer,
+                    to: account.clone(),
+                },
+            ),
+            top_up,
+        )?;
+    }
+
+    account.realloc(new_space, true)?;
+    Ok(())
+}
+
+// A v1 account is owned by this program, has the expected discriminator and no version byte:
+// its first field is the `initialized` flag, which is 0 or 1
+fn check_v1_account(
+    account: &AccountInfo,
+    data: &[u8],
+    discriminator: &[u8],
+    program_id: &Pubkey,
+) -> Result<()> {
+    require_keys_eq!(*account.owner, *program_id, CustomError::InvalidAccountData);
+    require!(
+        data.len() > 8 && data[..8] == *discriminator,
+        CustomError::InvalidAccountData
+    );
+    require!(data[8] <= 1, CustomError::AlreadyMigrated);
+    Ok(())
+}
+
+// Delegation of the previous deployment, which still uses the v1 layout
+fn legacy_position_v1(
+    account: &AccountInfo,
+    legacy_program: &Pubkey,
+) -> Result<DelegatedAccountV1> {
+    let data = account.try_borrow_data()?;
+    check_v1_account(account, &data, &DelegatedAccount::DISCRIMINATOR, legacy_program)?;
+    Ok(DelegatedAccountV1::deserialize(&mut &data[8..])?)
+}
+
+// Reject Token-2022 mints whose extensions would let a third party move or freeze vault funds,
+// or make a vault receive less than the amount credited for a deposit. Runs for the staking mint
+// in initialize_main and for every other mint in approve_mint.
+fn check_mint_extensions(mint: &AccountInfo) -> Result<()> {
+    if *mint.owner != anchor_spl::token_2022::ID {
+        return Ok(());
+    }
+
+    let data = mint.try_borrow_data()?;
+    let state = StateWithExtensions::<MintState>::unpack(&data)?;
+
+    for extension in state.get_extension_types()? {
+        match extension {
+            ExtensionType::PermanentDelegate => {
+                return err!(CustomError::PermanentDelegateMint)
+            }
+            ExtensionType::NonTransferable => return err!(CustomError::NonTransferableMint),
+            ExtensionType::TransferFeeConfig => return err!(CustomError::TransferFeeMint),
+            ExtensionType::TransferHook => return err!(CustomError::TransferHookMint),
+            ExtensionType::DefaultAccountState => {
+                let default_state = state.get_extension::<DefaultAccountState>()?;
+                if default_state.state == AccountState::Frozen as u8 {
+                    return err!(CustomError::DefaultFrozenMint);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+// Protocol fee taken out of `amount`, rounded down
+fn fee_amount(amount: u64, fee_bps: u16) -> Result<u64> {
+    let fee = amount as u128 * fee_bps as u128 / MAX_BPS as u128;
+    u64::try_from(fee).map_err(|_| CustomError::NumberOverflow.into())
+}
+
+// Move a protocol fee from a program-owned token account into the treasury
+fn collect_fee<'info>(
+    token_program: AccountInfo<'info>,
+    from: AccountInfo<'info>,
+    mint: &InterfaceAccount<'info, Mint>,
+    treasury_token_account: Option<&InterfaceAccount<'info, TokenAccount>>,
+    authority: AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+    fee: u64,
+) -> Result<()> {
+    if fee == 0 {
+        return Ok(());
+    }
+
+    let treasury_token_account =
+        treasury_token_account.ok_or(CustomError::TreasuryAccountRequired)?;
+
+    anchor_spl::token_interface::transfer_checked(
+        CpiContext::new_with_signer(
+            token_program,
+            TransferChecked {
+                from,
+                mint: mint.to_account_info(),
+                to: treasury_token_account.to_account_info(),
+                authority,
+            },
+            signer_seeds,
+        ),
+        fee,
+        mint.decimals,
+    )?;
+
+    let clock = Clock::get()?;
+    emit!(ProtocolFeeCollected {
+        schema_version: EVENT_SCHEMA_VERSION,
+        mint: mint.key(),
+        treasury_token_account: treasury_token_account.key(),
+        amount: fee,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+pub fn receipt_mint_address(collateral_mint: &Pubkey, program_id: &Pubkey) -> Pubkey {
+    pda::receipt_mint(collateral_mint, program_id).0
+}
+
+// Accounts used by most instructions: the program and its singleton PDAs, the approved mints with
+// their receipt mints, and the programs they are used with
+pub fn lookup_table_addresses(main_account: &MainAccount, program_id: &Pubkey) -> Vec<Pubkey> {
+    let mut addresses = vec![
+        *program_id,
+        pda::main_account(program_id).0,
+        pda::config(program_id).0,
+        pda::treasury(program_id).0,
+        anchor_spl::token::ID,
+        anchor_spl::token_2022::ID,
+        anchor_spl::associated_token::ID,
+        anchor_lang::system_program::ID,
+    ];
+    for entry in main_account.approved_mints() {
+        addresses.push(entry.mint);
+        addresses.push(receipt_mint_address(&entry.mint, program_id));
+    }
+    addresses
+}
+
+// Add the lookup_table_addresses not in `existing` to the table
+fn extend_lookup_table_with(
+    accounts: &ManageLookupTable,
+    program_id: &Pubkey,
+    existing: &[Pubkey],
+) -> Result<()> {
+    let new_addresses: Vec<Pubkey> =
+        lookup_table_addresses(&accounts.main_account.load()?, program_id)
+            .into_iter()
+            .filter(|address| !existing.contains(address))
+            .collect();
+    if new_addresses.is_empty() {
+        return Ok(());
+    }
+
+    let ix = address_lookup_table::instruction::extend_lookup_table(
+        accounts.lookup_table.key(),
+        accounts.admin.key(),
+        Some(accounts.admin.key()),
+        new_addresses.clone(),
+    );
+    invoke(
+        &ix,
+        &[
+            accounts.lookup_table.to_account_info(),
+            accounts.admin.to_account_info(),
+            accounts.admin.to_account_info(),
+            accounts.system_program.to_account_info(),
+        ],
+    )?;
+
+    let clock = Clock::get()?;
+    emit!(LookupTableExtended {
+        schema_version: EVENT_SCHEMA_VERSION,
+        lookup_table: accounts.lookup_table.key(),
+        added: new_addresses.len() as u8,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// Mint receipt tokens 1:1 for newly delegated stake
+fn mint_receipt<'info>(
+    receipt_mint: Option<&InterfaceAccount<'info, Mint>>,
+    receipt_holder: Option<&InterfaceAccount<'info, TokenAccount>>,
+    collateral_mint: &Pubkey,
+    token_program: AccountInfo<'info>,
+    program_id: &Pubkey,
+    amount: u64,
+) -> Result<()> {
+    let receipt_mint = receipt_mint.ok_or(CustomError::ReceiptAccountsRequired)?;
+    let receipt_holder = receipt_holder.ok_or(CustomError::ReceiptAccountsRequired)?;
+
+    let (expected, bump) =
+        Pubkey::find_program_address(&[RECEIPT_SEED, collateral_mint.as_ref()], program_id);
+    require_keys_eq!(receipt_mint.key(), expected, CustomError::InvalidReceiptMint);
+
+    let seeds = &[RECEIPT_SEED, collateral_mint.as_ref(), &[bump]];
+
+    anchor_spl::token_interface::mint_to(
+        CpiContext::new_with_signer(
+            token_program,
+            MintTo {
+                mint: receipt_mint.to_account_info(),
+                to: receipt_holder.to_account_info(),
+                authority: receipt_mint.to_account_info(),
+            },
+            &[&seeds[..]],
+        ),
+        amount,
+    )
+}
+
+// Move lamports into a wSOL token account and sync its token balance
+fn wrap_sol<'info>(
+    from: AccountInfo<'info>,
+    token_account: AccountInfo<'info>,
+    lamports: u64,
+    system_program: AccountInfo<'info>,
+    token_program: AccountInfo<'info>,
+) -> Result<()> {
+    anchor_lang::system_program::transfer(
+        CpiContext::new(
+            system_program,
+            anchor_lang::system_program::Transfer {
+                from,
+                to: token_account.clone(),
+            },
+        ),
+        lamports,
+    )?;
+    anchor_spl::token_interface::sync_native(CpiContext::new(
+        token_program,
+        SyncNative {
+            account: token_account,
+        },
+    ))
+}
+
+// Whether the server account a delegation points to is gone: closed, reassigned to another
+// program, or reused for another account type. A server account that doesn't parse is still
+// live, a v1 or not yet resized one, and fails with AccountNotMigrated.
+fn server_orphaned(account: &UncheckedAccount, program_id: &Pubkey) -> Result<bool> {
+    if account.owner != program_id || account.data_is_empty() {
+        return Ok(true);
+    }
+    let data = account.try_borrow_data()?;
+    if data.len() < 8 || data[..8] != InfoAccount::DISCRIMINATOR[..] {
+        return Ok(true);
+    }
+    require!(
+        InfoAccount::try_deserialize(&mut &data[..]).is_ok(),
+        CustomError::AccountNotMigrated
+    );
+    Ok(false)
+}
+
+// Stake vault of `authority`: created on first use like init_if_needed would, an existing one
+// only has its address checked. Creation is logged as VaultCreated so indexers don't have to
+// pick it out of the associated token program's inner instructions.
+fn open_vault<'info>(
+    vault: &AccountInfo<'info>,
+    authority: AccountInfo<'info>,
+    mint: &InterfaceAccount<'info, Mint>,
+    payer: AccountInfo<'info>,
+    token_program: AccountInfo<'info>,
+    associated_token_program: AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
+) -> Result<()> {
+    require_keys_eq!(
+        vault.key(),
+        get_associated_token_address_with_program_id(authority.key, &mint.key(), token_program.key),
+        CustomError::InvalidAccountData
+    );
+    if !vault.data_is_empty() {
+        return Ok(());
+    }
+
+    let (authority_key, payer_key) = (authority.key(), payer.key());
+    anchor_spl::associated_token::create(CpiContext::new(
+        associated_token_program,
+        Create {
+            payer,
+            associated_token: vault.clone(),
+            authority,
+            mint: mint.to_account_info(),
+            system_program,
+            token_program,
+        },
+    ))?;
+
+    let clock = Clock::get()?;
+    emit!(VaultCreated {
+        schema_version: EVENT_SCHEMA_VERSION,
+        vault: vault.key(),
+        authority: authority_key,
+        mint: mint.key(),
+        payer: payer_key,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+    Ok(())
+}
+
+// Close a vault the withdrawal emptied, refunding its rent to `destination`
+fn close_empty_vault<'info>(
+    vault: &mut InterfaceAccount<'info, TokenAccount>,
+    destination: AccountInfo<'info>,
+    authority: AccountInfo<'info>,
+    token_program: AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    vault.reload()?;
+    require!(vault.amount == 0, CustomError::NonZeroBalance);
+    let (authority_key, destination_key) = (authority.key(), destination.key());
+    anchor_spl::token_interface::close_account(CpiContext::new_with_signer(
+        token_program,
+        CloseAccount {
+            account: vault.to_account_info(),
+            destination,
+            authority,
+        },
+        signer_seeds,
+    ))?;
+
+    let clock = Clock::get()?;
+    emit!(VaultClosed {
+        schema_version: EVENT_SCHEMA_VERSION,
+        vault: vault.key(),
+        authority: authority_key,
+        rent_destination: destination_key,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+    Ok(())
+}
+
+// Rent side of gc_account: closes the account's token vault if it still exists, then moves
+// `reward_bps` of the account's lamports to the caller. Returns the reward, closing the account
+// itself is left to gc_account.
+#[allow(clippy::too_many_arguments)]
+fn collect_rent<'info>(
+    account: AccountInfo<'info>,
+    vault: &UncheckedAccount<'info>,
+    mint: &Pubkey,
+    owner: AccountInfo<'info>,
+    caller: AccountInfo<'info>,
+    token_program: AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+    reward_bps: u16,
+) -> Result<u64> {
+    require_keys_eq!(
+        vault.key(),
+        get_associated_token_address_with_program_id(account.key, mint, token_program.key),
+        CustomError::InvalidAccountData
+    );
+    // Already gone when the last withdrawal closed it
+    if !vault.data_is_empty() {
+        let amount = TokenAccount::try_deserialize(&mut &vault.try_borrow_data()?[..])?.amount;
+        require!(amount == 0, CustomError::NonZeroBalance);
+        let rent_destination = owner.key();
+        anchor_spl::token_interface::close_account(CpiContext::new_with_signer(
+            token_program,
+            CloseAccount {
+                account: vault.to_account_info(),
+                destination: owner,
+                authority: account.clone(),
+            },
+            signer_seeds,
+        ))?;
+
+        let clock = Clock::get()?;
+        emit!(VaultClosed {
+            schema_version: EVENT_SCHEMA_VERSION,
+            vault: vault.key(),
+            authority: account.key(),
+            rent_destination,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+    }
+
+    let reward = fee_amount(account.lamports(), reward_bps)?;
+    **account.try_borrow_mut_lamports()? -= reward;
+    **caller.try_borrow_mut_lamports()? += reward;
+    Ok(reward)
+}
+
+// Self-stake deposit shared by deposit and batch, returns the event for the caller to emit
+fn deposit_stake(accounts: &mut Deposit, amount_in_minimum_units: u64) -> Result<TokenDeposited> {
+    log_compute_units(&accounts.config, "deposit: start");
+    let main_account = &mut accounts.main_account.load_mut()?;
+    let info_account = &mut accounts.info_account;
+
+    check_gate(
+        &accounts.config,
+        accounts.gate_account.as_ref(),
+        &accounts.owner.key(),
+    )?;
+
+    check_deposit(main_account, info_account, &accounts.config, amount_in_minimum_units)?;
+    log_compute_units(&accounts.config, "deposit: checked");
+
+    open_vault(
+        &accounts.vault,
+        info_account.to_account_info(),
+        &accounts.mint,
+        accounts.owner.to_account_info(),
+        accounts.token_program.to_account_info(),
+        accounts.associated_token_program.to_account_info(),
+        accounts.system_program.to_account_info(),
+    )?;
+    anchor_spl::token_interface::transfer_checked(
+        CpiContext::new(
+            accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: accounts.sender_token_account.to_account_info(),
+                mint: accounts.mint.to_account_info(),
+                to: accounts.vault.to_account_info(),
+                authority: accounts.owner.to_account_info(),
+            },
+        ),
+        amount_in_minimum_units,
+        accounts.mint.decimals,
+    )?;
+
+    let event = credit_self_stake(
+        main_account,
+        info_account,
+        accounts.owner.key(),
+        amount_in_minimum_units,
+        transaction_memo(accounts.instructions.as_ref())?,
+    )?;
+    log_compute_units(&accounts.config, "deposit: done");
+    Ok(event)
+}
+
+// Bookkeeping of an operator deposit whose tokens already reached the vault, shared by
+// deposit_stake and deposit_with_permit
+fn credit_self_stake(
+    main_account: &mut MainAccount,
+    info_account: &mut InfoAccount,
+    owner: Pubkey,
+    amount_in_minimum_units: u64,
+    memo: [u8; MAX_MEMO_LEN],
+) -> Result<TokenDeposited> {
+    info_account.stake += amount_in_minimum_units;
+    info_account.total += amount_in_minimum_units;
+    main_account.add_self_stake(&info_account.mint, amount_in_minimum_units)?;
+    info_account.refresh_stake(main_account)?;
+    info_account.count_deposit(main_account, amount_in_minimum_units)?;
+
+    let clock = Clock::get()?;
+    info_account.last_active_epoch = clock.epoch;
+    Ok(TokenDeposited {
+        schema_version: EVENT_SCHEMA_VERSION,
+        owner,
+        name: info_account.name,
+        amount: amount_in_minimum_units,
+        stake: info_account.stake,
+        server_total: info_account.total,
+        server_seq: info_account.next_event_seq(),
+        memo,
+        total_stake: main_account.total_stake,
+        total_users: main_account.total_users,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    })
+}
+
+// Whole tokens to base units of a mint with `decimals` decimals. Every instruction taking whole
+// tokens converts through here, an overflow is an error rather than a panic.
+pub fn to_minimum_units(amount: u64, decimals: u8) -> Result<u64> {
+    10u64
+        .checked_pow(decimals as u32)
+        .and_then(|unit| amount.checked_mul(unit))
+        .ok_or_else(|| CustomError::NumberOverflow.into())
+}
+
+// Server name as stored, shared by add_server, update_server and batch renames. Surrounding
+// whitespace is trimmed and inner runs collapse to one space, so names differing only in spacing
+// are the same name. Control characters and the invisible formatting characters that can make a
+// name display as another one (bidi overrides and isolates, zero-width joiners) are refused, as
+// is any word whose lowercase hash is in config.blocked_name_hashes. Unicode normalization
+// (NFC) needs tables too large for the program and is left to clients.
+pub fn normalize_name(name: &str, config: &ConfigAccount) -> Result<String> {
+    let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+    require!(!name.is_empty(), CustomError::InvalidName);
+    require!(name.len() <= MAX_NAME_LEN, CustomError::NameTooLong);
+    let invisible = |c: char| {
+        matches!(
+            c,
+            '\u{061C}'
+                | '\u{200B}'..='\u{200F}'
+                | '\u{202A}'..='\u{202E}'
+                | '\u{2060}'..='\u{2069}'
+                | '\u{FEFF}'
+        )
+    };
+    require!(
+        !name.chars().any(|c| c.is_control() || invisible(c)),
+        CustomError::InvalidName
+    );
+
+    if config.blocked_name_hashes.iter().any(|blocked| *blocked != [0; 32]) {
+        for word in name.split(' ') {
+            let digest = hash(word.to_lowercase().as_bytes()).to_bytes();
+            require!(
+                !config.blocked_name_hashes.contains(&digest),
+                CustomError::BlockedName
+            );
+        }
+    }
+    Ok(name)
+}
+
+// Handles are lowercase DNS-style names: labels of a-z, 0-9 and '-' joined by dots, no label
+// empty or starting or ending with '-'. They are taken as given, so each has one spelling and
+// one registry address.
+fn check_handle(handle: &str) -> Result<()> {
+    require!(
+        !handle.is_empty() && handle.len() <= MAX_HANDLE_LEN,
+        CustomError::InvalidHandle
+    );
+    require!(
+        handle.split('.').all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .bytes()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == b'-')
+        }),
+        CustomError::InvalidHandle
+    );
+    Ok(())
+}
+
+// UIs load the icon from the URI as is, so only schemes that fetch a plain resource are accepted,
+// no data: or javascript: URIs, and no spaces or control characters
+fn check_icon_uri(icon_uri: &str) -> Result<()> {
+    if icon_uri.is_empty() {
+        return Ok(());
+    }
+    require!(icon_uri.len() <= MAX_ICON_URI_LEN, CustomError::IconUriTooLong);
+    let path = ["https://", "ipfs://", "ar://"]
+        .iter()
+        .find_map(|scheme| icon_uri.strip_prefix(scheme));
+    require!(
+        path.is_some_and(|path| !path.is_empty())
+            && icon_uri.bytes().all(|c| c.is_ascii_graphic()),
+        CustomError::InvalidIconUri
+    );
+    Ok(())
+}
+
+// Burn side and payout of pool_withdraw and pool_withdraw_assets
+fn redeem_pool_shares<'info>(
+    accounts: &mut PoolWithdraw<'info>,
+    shares: u64,
+    amount: u64,
+) -> Result<()> {
+    require!(shares > 0, CustomError::InvalidArgument);
+    require!(
+        amount <= accounts.pool.idle,
+        CustomError::PoolLiquidityInsufficient
+    );
+
+    anchor_spl::token_interface::burn(
+        CpiContext::new(
+            accounts.token_program.to_account_info(),
+            Burn {
+                mint: accounts.share_mint.to_account_info(),
+                from: accounts.share_account.to_account_info(),
+                authority: accounts.owner.to_account_info(),
+            },
+        ),
+        shares,
+    )?;
+
+    let pool = &mut accounts.pool;
+    let mint = pool.mint;
+    let seeds = &[POOL_SEED, mint.as_ref(), &[pool.bump]];
+    anchor_spl::token_interface::transfer_checked(
+        CpiContext::new_with_signer(
+            accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: accounts.pool_vault.to_account_info(),
+                mint: accounts.mint.to_account_info(),
+                to: accounts.receipt_token_account.to_account_info(),
+                authority: pool.to_account_info(),
+            },
+            &[&seeds[..]],
+        ),
+        amount,
+        accounts.mint.decimals,
+    )?;
+    pool.idle -= amount;
+
+    let clock = Clock::get()?;
+    emit!(PoolWithdrawn {
+        schema_version: EVENT_SCHEMA_VERSION,
+        owner: accounts.owner.key(),
+        pool: pool.key(),
+        amount,
+        shares,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// Position PDA [INFO_SEED, owner, server] and its vault for instructions that take them from
+// remaining_accounts, both created on first use like init_if_needed would. Returns the position
+// with its bump.
+#[allow(clippy::too_many_arguments)]
+// Create an account of this program at a PDA the way Anchor's `init` does. An address that
+// already holds lamports can't be created by create_account, so it is topped up to rent exemption,
+// allocated and assigned instead, and a transfer to the address ahead of time can't block it.
+fn create_pda_account<'info>(
+    account: &AccountInfo<'info>,
+    payer: AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
+    space: usize,
+    program_id: &Pubkey,
+    seeds: &[&[u8]],
+) -> Result<()> {
+    let rent = Rent::get()?.minimum_balance(space);
+    let lamports = account.lamports();
+    if lamports == 0 {
+        return anchor_lang::system_program::create_account(
+            CpiContext::new_with_signer(
+                system_program,
+                anchor_lang::system_program::CreateAccount {
+                    from: payer,
+                    to: account.clone(),
+                },
+                &[seeds],
+            ),
+            rent,
+            space as u64,
+            program_id,
+        );
+    }
+
+    let top_up = rent.saturating_sub(lamports);
+    if top_up > 0 {
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                system_program.clone(),
+                anchor_lang::system_program::Transfer {
+                    from: payer,
+                    to: account.clone(),
+                },
+            ),
+            top_up,
+        )?;
+    }
+    anchor_lang::system_program::allocate(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            anchor_lang::system_program::Allocate {
+                account_to_allocate: account.clone(),
+            },
+            &[seeds],
+        ),
+        space as u64,
+    )?;
+    anchor_lang::system_program::assign(
+        CpiContext::new_with_signer(
+            system_program,
+            anchor_lang::system_program::Assign {
+                account_to_assign: account.clone(),
+            },
+            &[seeds],
+        ),
+        program_id,
+    )
+}
+
+fn batch_position<'info>(
+    owner: &Pubkey,
+    info_account: &Account<'info, InfoAccount>,
+    delegated_info: &'info AccountInfo<'info>,
+    vault_info: &'info AccountInfo<'info>,
+    mint: &InterfaceAccount<'info, Mint>,
+    payer: AccountInfo<'info>,
+    token_program: AccountInfo<'info>,
+    associated_token_program: AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
+    program_id: &Pubkey,
+) -> Result<(Account<'info, DelegatedAccount>, u8)> {
+    let info_key = info_account.key();
+    let (delegated_key, delegated_bump) =
+        Pubkey::find_program_address(&[INFO_SEED, owner.as_ref(), info_key.as_ref()], program_id);
+    require_keys_eq!(delegated_info.key(), delegated_key, CustomError::InvalidBatchAccount);
+    require_keys_eq!(
+        vault_info.key(),
+        get_associated_token_address_with_program_id(
+            &delegated_key,
+            &mint.key(),
+            token_program.key,
+        ),
+        CustomError::InvalidBatchAccount
+    );
+
+    if delegated_info.data_is_empty() {
+        let seeds = &[INFO_SEED, owner.as_ref(), info_key.as_ref(), &[delegated_bump]];
+        create_pda_account(
+            delegated_info,
+            payer.clone(),
+            system_program.clone(),
+            DELEGATED_ACCOUNT_SPACE,
+            program_id,
+            &seeds[..],
+        )?;
+        DelegatedAccount::default()
+            .try_serialize(&mut &mut delegated_info.try_borrow_mut_data()?[..])?;
+    }
+
+    open_vault(
+        vault_info,
+        delegated_info.clone(),
+        mint,
+        payer,
+        token_program,
+        associated_token_program,
+        system_program,
+    )?;
+
+    Ok((Account::<DelegatedAccount>::try_from(delegated_info)?, delegated_bump))
+}
+
+// Bookkeeping shared by d_deposit and d_deposit_many, the token transfer is left to the caller.
+// Returns true when this deposit opened the position.
+#[allow(clippy::too_many_arguments)]
+fn record_delegation(
+    main_account: &mut MainAccount,
+    info_account: &mut Account<InfoAccount>,
+    delegated_account: &mut DelegatedAccount,
+    config: &ConfigAccount,
+    price_update: Option<&UncheckedAccount>,
+    owner: Pubkey,
+    amount_in_minimum_units: u64,
+    decimals: u8,
+    lockup: LockupTier,
+    clock: &Clock,
+) -> Result<bool> {
+    // Self-stake goes through deposit, so it counts towards the self-bond and not the delegations
+    require_keys_neq!(owner, info_account.owner, CustomError::SelfDelegation);
+    require!(info_account.exit_announced_at == 0, CustomError::ServerExiting);
+
+    let created = !delegated_account.initialized;
+
+    if created {
+        main_account.add_user(false)?;
+        info_account.total_delegators = info_account
+            .total_delegators
+            .checked_add(1)
+            .ok_or(CustomError::NumberOverflow)?;
+        delegated_account.version = VERSION;
+        delegated_account.owner = owner;
+        delegated_account.delegator = info_account.key();
+        delegated_account.mint = info_account.mint;
+        delegated_account.initialized = true; // Mark account as initialized
+    } else {
+        require!(
+            delegated_account.owner == owner,
+            CustomError::DelegateAlreadyInitialized
+        );
+    }
+
+    activate_delegation(main_account, info_account, delegated_account, clock.epoch)?;
+    check_delegation_limits(
+        main_account,
+        info_account,
+        delegated_account,
+        config,
+        price_update,
+        amount_in_minimum_units,
+        decimals,
+    )?;
+
+    queue_delegation(
+        main_account,
+        info_account,
+        delegated_account,
+        config,
+        amount_in_minimum_units,
+        lockup,
+        clock,
+    )?;
+
+    Ok(created)
+}
+
+// Server and network minimum of a delegation deposit, and the maximum of the position it grows
+fn check_delegation_limits(
+    main_account: &MainAccount,
+    info_account: &InfoAccount,
+    delegated_account: &DelegatedAccount,
+    config: &ConfigAccount,
+    price_update: Option<&UncheckedAccount>,
+    amount_in_minimum_units: u64,
+    decimals: u8,
+) -> Result<()> {
+    main_account.check_rate_age(
+        &info_account.mint,
+        config.max_rate_age_secs,
+        Clock::get()?.unix_timestamp,
+    )?;
+    let position =
+        delegated_account.stake + delegated_account.activating_stake + amount_in_minimum_units;
+    let minimum = stake_minimum(
+        DELEGATE_MINIMUM_STAKE,
+        config.delegate_min_stake_usd,
+        config,
+        price_update,
+    )?;
+    if amount_in_minimum_units < info_account.delegate_minimum(decimals)?
+        || main_account.to_common_units(&info_account.mint, amount_in_minimum_units)? < minimum
+        || main_account.to_common_units(&info_account.mint, position)? > MAXIMUM_STAKE
+    {
+        return Err(CustomError::DelegateExceedsMaxStakeLimit.into());
+    }
+    Ok(())
+}
+
+// Stake side of record_delegation, past the limit checks. Also used by rebalance, which moves
+// stake between positions that are already open.
+fn queue_delegation(
+    main_account: &mut MainAccount,
+    info_account: &mut Account<InfoAccount>,
+    delegated_account: &mut DelegatedAccount,
+    config: &ConfigAccount,
+    amount_in_minimum_units: u64,
+    lockup: LockupTier,
+    clock: &Clock,
+) -> Result<()> {
+    let old_weight = delegated_account.reward_weight();
+    delegated_account.settle_expired_lockup(info_account, clock.unix_timestamp)?;
+
+    // Choosing a tier relocks the whole position, a longer tier upgrades it
+    delegated_account.apply_lockup(lockup, clock.unix_timestamp);
+
+    // New stake is queued and only earns rewards and counts towards the server from the next epoch
+    delegated_account.activating_stake += amount_in_minimum_units;
+    delegated_account.activation_epoch = clock.epoch + 1;
+    info_account.activating_stake += amount_in_minimum_units;
+    info_account.count_deposit(main_account, amount_in_minimum_units)?;
+
+    info_account.check_self_bond(config.self_bond_multiple)?;
+
+    info_account.update_delegated_weight(old_weight, delegated_account.reward_weight())?;
+    delegated_account.checkpoint_rewards(info_account.acc_reward_per_share)
+}
+
+// Bookkeeping shared by d_withdraw and d_withdraw_compressed, the token transfers are left to the
+// caller
+fn record_withdrawal(
+    main_account: &mut MainAccount,
+    info_account: &mut Account<InfoAccount>,
+    delegated_account: &mut DelegatedAccount,
+    amount_in_minimum_units: u64,
+    clock: &Clock,
+) -> Result<()> {
+    activate_delegation(main_account, info_account, delegated_account, clock.epoch)?;
+
+    require!(
+        amount_in_minimum_units <= delegated_account.stake + delegated_account.activating_stake,
+        CustomError::InsufficientFunds
+    );
+
+    let now = clock.unix_timestamp;
+    require!(now >= delegated_account.lock_until, CustomError::StakeLocked);
+
+    let old_weight = delegated_account.reward_weight();
+    delegated_account.settle_expired_lockup(info_account, now)?;
+
+    // Stake still waiting for activation is released first
+    let from_activating = amount_in_minimum_units.min(delegated_account.activating_stake);
+    let from_active = amount_in_minimum_units - from_activating;
+    delegated_account.activating_stake -= from_activating;
+    info_account.activating_stake -= from_activating;
+
+    info_account.total -= from_active;
+    info_account.delegated -= from_active;
+    delegated_account.stake -= from_active;
+    main_account.sub_stake(&info_account.mint, from_active)?;
+    info_account.refresh_stake(main_account)?;
+    info_account.count_withdrawal(main_account, amount_in_minimum_units)?;
+
+    info_account.update_delegated_weight(old_weight, delegated_account.reward_weight())?;
+    delegated_account.checkpoint_rewards(info_account.acc_reward_per_share)
+}
+
+// Closes a delegation for force_return_delegation and return_delegation: the whole stake, the
+// pending rewards and the rent go to the delegator. Returns the stake and rewards paid out.
+#[allow(clippy::too_many_arguments)]
+fn return_position<'info>(
+    main_account: &AccountLoader<'info, MainAccount>,
+    main_bump: u8,
+    info_account: &mut Account<'info, InfoAccount>,
+    delegated_account: &mut Account<'info, DelegatedAccount>,
+    vault: &mut Option<InterfaceAccount<'info, TokenAccount>>,
+    reward_vault: &InterfaceAccount<'info, TokenAccount>,
+    delegation_owner: AccountInfo<'info>,
+    delegation_owner_token_account: &InterfaceAccount<'info, TokenAccount>,
+    mint: &InterfaceAccount<'info, Mint>,
+    token_program: AccountInfo<'info>,
+    clock: &Clock,
+) -> Result<(u64, u64)> {
+    // The main account signs the reward transfer below, so it can't stay borrowed across it
+    let amount = delegated_account.stake + delegated_account.activating_stake;
+    {
+        let main_account = &mut main_account.load_mut()?;
+        record_withdrawal(main_account, info_account, delegated_account, amount, clock)?;
+        main_account.remove_user(false);
+    }
+    info_account.remove_delegator();
+    require!(
+        delegated_account.receipt_minted == 0 && delegated_account.referral_pending == 0,
+        CustomError::DelegationNotReturnable
+    );
+    require!(
+        !info_account.lists(&delegated_account.key(), delegated_account),
+        CustomError::DelegationLinked
+    );
+    // Bridged owners, pools and portfolios are accounts of this program, which never signs for
+    // their token accounts here. Their positions leave through their own instructions.
+    require!(*delegation_owner.owner != ID, CustomError::DelegationNotReturnable);
+
+    let owner = delegated_account.owner;
+    let server = info_account.key();
+    let seeds = &[INFO_SEED, owner.as_ref(), server.as_ref(), &[delegated_account.bump]];
+
+    // Already gone when the last withdrawal closed it, and then there is no stake left
+    if let Some(vault) = vault {
+        if amount > 0 {
+            anchor_spl::token_interface::transfer_checked(
+                CpiContext::new_with_signer(
+                    token_program.clone(),
+                    TransferChecked {
+                        from: vault.to_account_info(),
+                        mint: mint.to_account_info(),
+                        to: delegation_owner_token_account.to_account_info(),
+                        authority: delegated_account.to_account_info(),
+                    },
+                    &[&seeds[..]],
+                ),
+                amount,
+                mint.decimals,
+            )?;
+        }
+        close_empty_vault(
+            vault,
+            delegation_owner,
+            delegated_account.to_account_info(),
+            token_program.clone(),
+            &[&seeds[..]],
+        )?;
+    } else {
+        require!(amount == 0, CustomError::InvalidAccountData);
+    }
+
+    // No reward fee, the delegator didn't choose to leave
+    let rewards = delegated_account.pending_rewards;
+    if rewards > 0 {
+        delegated_account.pending_rewards = 0;
+        let main_seeds = &[MAIN_SEED, &[main_bump]];
+        anchor_spl::token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                token_program,
+                TransferChecked {
+                    from: reward_vault.to_account_info(),
+                    mint: mint.to_account_info(),
+                    to: delegation_owner_token_account.to_account_info(),
+                    authority: main_account.to_account_info(),
+                },
+                &[&main_seeds[..]],
+            ),
+            rewards,
+            mint.decimals,
+        )?;
+    }
+
+    Ok((amount, rewards))
+}
+
+// Leaf of a compressed delegation: the serialized record, bound to its index in the tree. The
+// list links at the end are left out, compressed positions aren't listed and the leaves written
+// before the links existed stay valid.
+pub fn delegation_leaf(delegation: &DelegatedAccount, leaf_index: u32) -> Result<[u8; 32]> {
+    let mut data = Vec::with_capacity(DELEGATED_ACCOUNT_SPACE);
+    delegation.try_serialize(&mut data)?;
+    data.truncate(data.len() - 2 * 32);
+    Ok(keccak::hashv(&[&leaf_index.to_le_bytes(), &data]).to_bytes())
+}
+
+// Leaf of the tenure snapshot tree for one staker
+pub fn tenure_leaf(owner: &Pubkey, points: u64, multiplier_bps: u16) -> [u8; 32] {
+    keccak::hashv(&[owner.as_ref(), &points.to_le_bytes(), &multiplier_bps.to_le_bytes()])
+        .to_bytes()
+}
+
+// Leaf of the snapshot archive for one EpochSnapshot, over its raw account data
+pub fn snapshot_leaf(data: &[u8]) -> [u8; 32] {
+    keccak::hash(data).to_bytes()
+}
+
+// Inner nodes hash their two children in sorted order, so the proof needs no left/right flags
+fn hash_pair(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    if a <= b {
+        keccak::hashv(&[a, b]).to_bytes()
+    } else {
+        keccak::hashv(&[b, a]).to_bytes()
+    }
+}
+
+fn verify_merkle_proof(proof: &[[u8; 32]], root: [u8; 32], leaf: [u8; 32]) -> bool {
+    let node = proof.iter().fold(leaf, |node, sibling| hash_pair(&node, sibling));
+    node == root
+}
+
+// The record a compressed instruction works on: the caller's current leaf, or a new position
+fn compressed_position(
+    position: Option<&CompressedDelegation>,
+    owner: &Pubkey,
+) -> Result<DelegatedAccount> {
+    match position {
+        Some(position) => {
+            require_keys_eq!(
+                position.delegation.owner,
+                *owner,
+                CustomError::Unauthorized
+            );
+            Ok(position.delegation.clone())
+        }
+        None => Ok(DelegatedAccount::default()),
+    }
+}
+
+// Write a compressed delegation to the server's tree. A new position is appended, an existing one
+// replaces its previous leaf, which the compression program verifies against the proof passed as
+// the CPI's remaining accounts. Returns the leaf index.
+fn write_compressed_delegation<'info>(
+    ctx: CpiContext<'_, '_, '_, 'info, Modify<'info>>,
+    info_account: &mut InfoAccount,
+    previous: Option<&CompressedDelegation>,
+    delegation: &DelegatedAccount,
+) -> Result<u32> {
+    let merkle_tree = ctx.accounts.merkle_tree.key();
+    let leaf_index = match previous {
+        Some(previous) => {
+            spl_account_compression::cpi::replace_leaf(
+                ctx,
+                previous.root,
+                delegation_leaf(&previous.delegation, previous.leaf_index)?,
+                delegation_leaf(delegation, previous.leaf_index)?,
+                previous.leaf_index,
+            )?;
+            previous.leaf_index
+        }
+        None => {
+            let leaf_index = info_account.delegation_leaves;
+            spl_account_compression::cpi::append(ctx, delegation_leaf(delegation, leaf_index)?)?;
+            info_account.delegation_leaves += 1;
+            leaf_index
+        }
+    };
+
+    // Indexers rebuild the leaves, and the proofs clients need, from this event
+    let clock = Clock::get()?;
+    emit!(CompressedDelegationWritten {
+        schema_version: EVENT_SCHEMA_VERSION,
+        merkle_tree,
+        leaf_index,
+        delegation: delegation.clone(),
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(leaf_index)
+}
+
+// Heartbeats are only enforced once the config sets a timeout
+fn server_online(info_account: &InfoAccount, config: &ConfigAccount, now: i64) -> bool {
+    config.heartbeat_timeout_secs == 0 || now <= live_until(info_account, config)
+}
+
+// Time up to which the server counts as online given its last heartbeat
+fn live_until(info_account: &InfoAccount, config: &ConfigAccount) -> i64 {
+    if config.heartbeat_timeout_secs == 0 {
+        return i64::MAX;
+    }
+    info_account
+        .last_heartbeat
+        .saturating_add(config.heartbeat_timeout_secs as i64)
+}
+
+// Move `amount` from a subscription escrow to the server's revenue, already vested
+fn pay_subscription<'info>(
+    subscription: &Account<'info, Subscription>,
+    info_account: &mut Account<'info, InfoAccount>,
+    escrow: &InterfaceAccount<'info, TokenAccount>,
+    revenue_vault: &InterfaceAccount<'info, TokenAccount>,
+    mint: &InterfaceAccount<'info, Mint>,
+    token_program: &Interface<'info, TokenInterface>,
+    amount: u64,
+) -> Result<()> {
+    if amount == 0 {
+        return Ok(());
+    }
+    let seeds = &[
+        SUBSCRIPTION_SEED,
+        subscription.client.as_ref(),
+        subscription.server.as_ref(),
+        &[subscription.bump],
+    ];
+    anchor_spl::token_interface::transfer_checked(
+        CpiContext::new_with_signer(
+            token_program.to_account_info(),
+            TransferChecked {
+                from: escrow.to_account_info(),
+                mint: mint.to_account_info(),
+                to: revenue_vault.to_account_info(),
+                authority: subscription.to_account_info(),
+            },
+            &[&seeds[..]],
+        ),
+        amount,
+        mint.decimals,
+    )?;
+
+    info_account.revenue_released = info_account
+        .revenue_released
+        .checked_add(amount)
+        .ok_or(CustomError::NumberOverflow)?;
+    info_account.revenue_total = info_account
+        .revenue_total
+        .checked_add(amount)
+        .ok_or(CustomError::NumberOverflow)?;
+    Ok(())
+}
+
+// What the serverkey signs to authorize an endpoint for the server
+pub fn endpoint_message(server: &Pubkey, endpoint: &str) -> Vec<u8> {
+    [b"aeronyx-endpoint".as_ref(), server.as_ref(), endpoint.as_bytes()].concat()
+}
+
+// What the serverkey signs to report its software version. Only valid during `epoch`, so an old
+// report can't be replayed later to roll the version back.
+pub fn version_message(server: &Pubkey, version: &SoftwareVersion, epoch: u64) -> Vec<u8> {
+    [
+        b"aeronyx-version".as_ref(),
+        server.as_ref(),
+        &version.major.to_le_bytes(),
+        &version.minor.to_le_bytes(),
+        &version.patch.to_le_bytes(),
+        &epoch.to_le_bytes(),
+    ]
+    .concat()
+}
+
+// Check that the server's serverkey signed `message`: a 32-byte (Ed25519) key through an Ed25519
+// instruction right before this one carrying `signature`, a 64- or 65-byte (secp256k1, raw or
+// 0x04-prefixed) key with `signature` as 64 bytes plus the recovery id, recovered on-chain.
+fn verify_serverkey_signature(
+    info_account: &InfoAccount,
+    instructions: Option<&UncheckedAccount>,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<()> {
+    let serverkey = info_account.serverkey();
+    match serverkey.len() {
+        32 => {
+            let instructions = instructions.ok_or(CustomError::InvalidSignature)?;
+            let signed = verify_ed25519_ix(
+                instructions,
+                &Pubkey::try_from(serverkey).unwrap(),
+                message,
+            )?;
+            require!(signature == signed, CustomError::InvalidSignature);
+        }
+        64 | 65 => {
+            require!(signature.len() == 65, CustomError::InvalidSignature);
+            let recovered = secp256k1_recover(
+                &keccak::hash(message).to_bytes(),
+                signature[64],
+                &signature[..64],
+            )
+            .map_err(|_| CustomError::InvalidSignature)?;
+            // Recovered keys are the 64-byte X || Y form, without the 0x04 prefix
+            require!(
+                recovered.to_bytes()[..] == serverkey[serverkey.len() - 64..]
+                    && (serverkey.len() == 64 || serverkey[0] == 4),
+                CustomError::InvalidSignature
+            );
+        }
+        _ => return err!(CustomError::InvalidSignature),
+    }
+    Ok(())
+}
+
+// Off-chain payment from the client, `paid` is the cumulative amount owed to the server
+pub fn channel_voucher_message(channel: &Pubkey, opened_slot: u64, paid: u64) -> Vec<u8> {
+    [
+        b"aeronyx-channel".as_ref(),
+        channel.as_ref(),
+        &opened_slot.to_le_bytes(),
+        &paid.to_le_bytes(),
+    ]
+    .concat()
+}
+
+// Refund the server owner promises the client, paid from its collateral if the channel is
+// closed with it
+pub fn channel_refund_message(channel: &Pubkey, opened_slot: u64, refund: u64) -> Vec<u8> {
+    [
+        b"aeronyx-refund".as_ref(),
+        channel.as_ref(),
+        &opened_slot.to_le_bytes(),
+        &refund.to_le_bytes(),
+    ]
+    .concat()
+}
+
+// What the owner signs for deposit_with_permit, `amount` in whole tokens
+pub fn permit_message(server: &Pubkey, amount: u64, nonce: u64, expiry: i64) -> Vec<u8> {
+    [
+        b"aeronyx-permit".as_ref(),
+        server.as_ref(),
+        &amount.to_le_bytes(),
+        &nonce.to_le_bytes(),
+        &expiry.to_le_bytes(),
+    ]
+    .concat()
+}
+
+// Replay protection of the owner-signed authorizations relayed for an account (permits and
+// future meta-transactions): `nonce` must be the account's next one, which is then used up
+fn consume_nonce(account: Pubkey, owner: Pubkey, next: &mut u64, nonce: u64) -> Result<()> {
+    require!(nonce == *next, CustomError::InvalidNonce);
+    *next = next.checked_add(1).ok_or(CustomError::NumberOverflow)?;
+
+    let clock = Clock::get()?;
+    emit!(NonceUsed {
+        schema_version: EVENT_SCHEMA_VERSION,
+        account,
+        owner,
+        nonce,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+    Ok(())
+}
+
+// Message the usage oracle signs for submit_usage_proof
+pub fn usage_message(server: &Pubkey, epoch: u64, report: &UsageReport) -> Result<Vec<u8>> {
+    let mut message = b"aeronyx-usage".to_vec();
+    message.extend_from_slice(server.as_ref());
+    message.extend_from_slice(&epoch.to_le_bytes());
+    report.serialize(&mut message)?;
+    Ok(message)
+}
+
+// Emitter chain, emitter address, sequence and payload of a PostedVAA account of the core bridge,
+// which only exists once the guardian signatures over the message have been verified. The
+// payload has to start with the `kind` type byte, which is stripped.
+fn parse_posted_vaa(data: &[u8], kind: u8) -> Result<(u16, [u8; 32], u64, Vec<u8>)> {
+    // b"vaa", vaa_version u8, consistency_level u8, vaa_time u32, vaa_signature_account,
+    // submission_time u32, nonce u32, sequence u64, emitter_chain u16, emitter_address, payload
+    // (u32 length)
+    require!(data.len() >= 95 && &data[..3] == b"vaa", CustomError::InvalidVaa);
+    let sequence = u64::from_le_bytes(data[49..57].try_into().unwrap());
+    let emitter_chain = u16::from_le_bytes(data[57..59].try_into().unwrap());
+    let emitter: [u8; 32] = data[59..91].try_into().unwrap();
+    let len = u32::from_le_bytes(data[91..95].try_into().unwrap()) as usize;
+    let payload = data.get(95..95 + len).ok_or(CustomError::InvalidVaa)?;
+    require!(payload.first() == Some(&kind), CustomError::InvalidVaa);
+    Ok((emitter_chain, emitter, sequence, payload[1..].to_vec()))
+}
+
+// Unfreeze `amount` of disputed revenue of which `refund` went back to the client
+fn release_dispute_revenue(
+    info_account: &mut InfoAccount,
+    amount: u64,
+    refund: u64,
+) -> Result<()> {
+    info_account.revenue_disputed -= amount;
+    info_account.revenue_released = info_account
+        .revenue_released
+        .checked_add(amount - refund)
+        .ok_or(CustomError::NumberOverflow)?;
+    Ok(())
+}
+
+// PriceUpdateV2 account of the Pyth receiver, after its discriminator
+#[derive(AnchorDeserialize)]
+struct PythPriceUpdate {
+    _write_authority: Pubkey,
+    verification_level: PythVerificationLevel,
+    price_message: PythPriceMessage,
+    _posted_slot: u64,
+}
+
+#[derive(AnchorDeserialize)]
+enum PythVerificationLevel {
+    Partial { _num_signatures: u8 },
+    Full,
+}
+
+#[derive(AnchorDeserialize)]
+struct PythPriceMessage {
+    feed_id: [u8; 32],
+    price: i64,
+    _conf: u64,
+    exponent: i32,
+    publish_time: i64,
+    _prev_publish_time: i64,
+    _ema_price: i64,
+    _ema_conf: u64,
+}
+
+// Staking token price in USD as (price, exponent), from a fully verified Pyth price update of
+// config.price_feed_id at most config.max_price_age_secs old
+fn staking_token_price(
+    price_update: &AccountInfo,
+    config: &ConfigAccount,
+    now: i64,
+) -> Result<(u64, i32)> {
+    require!(config.price_feed_id != [0; 32], CustomError::InvalidPriceFeed);
+    require_keys_eq!(*price_update.owner, PYTH_RECEIVER_ID, CustomError::InvalidPriceFeed);
+    let data = price_update.try_borrow_data()?;
+    require!(
+        data.len() > 8 && data[..8] == hash(b"account:PriceUpdateV2").to_bytes()[..8],
+        CustomError::InvalidPriceFeed
+    );
+    let update = PythPriceUpdate::deserialize(&mut &data[8..])
+        .map_err(|_| error!(CustomError::InvalidPriceFeed))?;
+    let message = update.price_message;
+    require!(
+        matches!(update.verification_level, PythVerificationLevel::Full)
+            && message.feed_id == config.price_feed_id
+            && message.price > 0,
+        CustomError::InvalidPriceFeed
+    );
+    require!(
+        now.saturating_sub(message.publish_time) <= config.max_price_age_secs as i64,
+        CustomError::StalePrice
+    );
+    Ok((message.price as u64, message.exponent))
+}
+
+// Minimum stake in staking token base units: `tokens`, or `usd` at the Pyth price when the
+// deployment sets a USD minimum
+fn stake_minimum(
+    tokens: u64,
+    usd: u64,
+    config: &ConfigAccount,
+    price_update: Option<&UncheckedAccount>,
+) -> Result<u64> {
+    if usd == 0 {
+        return Ok(tokens);
+    }
+    let price_update = price_update.ok_or(CustomError::InvalidPriceFeed)?;
+    let (price, exponent) =
+        staking_token_price(price_update, config, Clock::get()?.unix_timestamp)?;
+    usd_to_tokens(usd, USD_DECIMALS, price, exponent, STAKING_DECIMALS)
+}
+
+// `usd` base units of a stablecoin with `usd_decimals` to base units of a token with `decimals`
+// priced at price x 10^exponent USD
+pub fn usd_to_tokens(
+    usd: u64,
+    usd_decimals: u8,
+    price: u64,
+    exponent: i32,
+    decimals: u8,
+) -> Result<u64> {
+    let pow10 = |exp: u32| 10u128.checked_pow(exp).ok_or(CustomError::NumberOverflow);
+    let mut numerator = (usd as u128)
+        .checked_mul(pow10(decimals as u32)?)
+        .ok_or(CustomError::NumberOverflow)?;
+    let mut denominator = (price as u128)
+        .checked_mul(pow10(usd_decimals as u32)?)
+        .ok_or(CustomError::NumberOverflow)?;
+    if exponent < 0 {
+        numerator = numerator
+            .checked_mul(pow10(exponent.unsigned_abs())?)
+            .ok_or(CustomError::NumberOverflow)?;
+    } else {
+        denominator = denominator
+            .checked_mul(pow10(exponent as u32)?)
+            .ok_or(CustomError::NumberOverflow)?;
+    }
+    u64::try_from(numerator / denominator).map_err(|_| error!(CustomError::NumberOverflow))
+}
+
+fn write_usage(
+    usage: &mut ServerUsage,
+    info_account: &mut Account<InfoAccount>,
+    main_account: &MainAccount,
+    epoch: u64,
+    report: &UsageReport,
+    reporter: Pubkey,
+    bump: u8,
+) -> Result<()> {
+    let clock = Clock::get()?;
+    // Only finished epochs can be reported, usage of the running one is still growing
+    require!(epoch < clock.epoch, CustomError::InvalidEpoch);
+    require!(report.uptime_bps <= MAX_BPS, CustomError::InvalidBasisPoints);
+
+    let server = info_account.key();
+    // A late report of an older epoch doesn't replace the current uptime
+    if epoch >= info_account.uptime_epoch {
+        info_account.uptime_bps = report.uptime_bps;
+        info_account.uptime_epoch = epoch;
+        info_account.refresh_tier(main_account)?;
+    }
+
+    usage.version = VERSION;
+    usage.server = server;
+    usage.epoch = epoch;
+    usage.bytes_served = report.bytes_served;
+    usage.requests = report.requests;
+    usage.uptime_bps = report.uptime_bps;
+    usage.reporter = reporter;
+    usage.slot = clock.slot;
+    usage.unix_timestamp = clock.unix_timestamp;
+    usage.bump = bump;
+
+    emit!(UsageRecorded {
+        schema_version: EVENT_SCHEMA_VERSION,
+        server,
+        epoch,
+        bytes_served: report.bytes_served,
+        requests: report.requests,
+        uptime_bps: report.uptime_bps,
+        reporter,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+// While config.cpi_guard is on, the calling instruction must be a top-level one, or a CPI whose
+// outermost program, the one the user signed the transaction for, is in
+// config.cpi_allowed_programs. Programs in between are not visible in the instructions sysvar.
+fn check_cpi_origin(config: &ConfigAccount, instructions: Option<&UncheckedAccount>) -> Result<()> {
+    if !config.cpi_guard || get_stack_height() == TRANSACTION_LEVEL_STACK_HEIGHT {
+        return Ok(());
+    }
+    let instructions = instructions
+        .ok_or(CustomError::CpiNotAllowed)?
+        .to_account_info();
+    let current = load_current_index_checked(&instructions)?;
+    let caller = load_instruction_at_checked(current as usize, &instructions)?.program_id;
+    require!(
+        caller != Pubkey::default() && config.cpi_allowed_programs.contains(&caller),
+        CustomError::CpiNotAllowed
+    );
+    Ok(())
+}
+
+// While the server has a signer set, `threshold` of its keys must sign: the owner, when it is a
+// member, and any signer accounts in `signers`
+fn check_signer_set(
+    info_account: &InfoAccount,
+    owner: &Pubkey,
+    signers: &[AccountInfo],
+) -> Result<()> {
+    if info_account.signer_threshold == 0 {
+        return Ok(());
+    }
+    let set = &info_account.signers[..info_account.signer_count as usize];
+    let mut signed = [false; MAX_SERVER_SIGNERS];
+    let keys = signers.iter().filter(|account| account.is_signer).map(|account| account.key);
+    for key in std::iter::once(owner).chain(keys) {
+        if let Some(i) = set.iter().position(|member| member == key) {
+            signed[i] = true;
+        }
+    }
+    require!(
+        signed.iter().filter(|signed| **signed).count() >= info_account.signer_threshold as usize,
+        CustomError::SignerThresholdNotMet
+    );
+    Ok(())
+}
+
+// Require the instruction before the current one to be an Ed25519 program check of one
+// signature by `signer` over `message`, with all offsets pointing into that instruction.
+// Returns the verified signature.
+fn verify_ed25519_ix(
+    instructions: &AccountInfo,
+    signer: &Pubkey,
+    message: &[u8],
+) -> Result<[u8; 64]> {
+    const HEADER_LEN: usize = 16;
+
+    let current = load_current_index_checked(instructions)?;
+    require!(current > 0, CustomError::InvalidSignature);
+    let ix = load_instruction_at_checked(current as usize - 1, instructions)?;
+    require_keys_eq!(ix.program_id, ed25519_program::ID, CustomError::InvalidSignature);
+
+    let data = &ix.data;
+    require!(
+        data.len() >= HEADER_LEN && data[0] == 1,
+        CustomError::InvalidSignature
+    );
+    let read_u16 = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+    // Signature, public key and message must all come from the Ed25519 instruction itself
+    for at in [4, 8, 14] {
+        require!(read_u16(at) == u16::MAX, CustomError::InvalidSignature);
+    }
+    let signature_offset = read_u16(2) as usize;
+    let pubkey_offset = read_u16(6) as usize;
+    let message_offset = read_u16(10) as usize;
+    let message_len = read_u16(12) as usize;
+
+    require!(
+        data.get(pubkey_offset..pubkey_offset + 32) == Some(signer.as_ref()),
+        CustomError::InvalidSignature
+    );
+    require!(
+        message_len == message.len()
+            && data.get(message_offset..message_offset + message_len) == Some(message),
+        CustomError::InvalidSignature
+    );
+    let signature = data
+        .get(signature_offset..signature_offset + 64)
+        .ok_or(CustomError::InvalidSignature)?;
+    Ok(signature.try_into().unwrap())
+}
+
+// Memo attached to a deposit: the data of an SPL Memo instruction placed right before it in the
+// transaction, zero-padded. No memo without the instructions sysvar or such an instruction.
+fn transaction_memo(instructions: Option<&UncheckedAccount>) -> Result<[u8; MAX_MEMO_LEN]> {
+    let mut memo = [0; MAX_MEMO_LEN];
+    let Some(instructions) = instructions else {
+        return Ok(memo);
+    };
+    let current = load_current_index_checked(instructions)?;
+    if current == 0 {
+        return Ok(memo);
+    }
+    let ix = load_instruction_at_checked(current as usize - 1, instructions)?;
+    if ix.program_id == MEMO_PROGRAM_ID {
+        require!(ix.data.len() <= MAX_MEMO_LEN, CustomError::MemoTooLong);
+        memo[..ix.data.len()].copy_from_slice(&ix.data);
+    }
+    Ok(memo)
+}
+
+// Limits on an operator deposit, shared with validate_deposit
+fn check_deposit(
+    main_account: &MainAccount,
+    info_account: &InfoAccount,
+    config: &ConfigAccount,
+    amount_in_minimum_units: u64,
+) -> Result<()> {
+    main_account.check_rate_age(
+        &info_account.mint,
+        config.max_rate_age_secs,
+        Clock::get()?.unix_timestamp,
+    )?;
+    // Check if it exceeds the maximum stake limit
+    require!(
+        main_account
+            .to_common_units(&info_account.mint, info_account.stake + amount_in_minimum_units)?
+            <= MAXIMUM_STAKE,
+        CustomError::ExceedsMaxStakeLimit
+    );
+    Ok(())
+}
+
+// The preflight views take the sender account unchecked and report a mismatch as a result code
+fn check_sender_balance(sender: &TokenAccount, mint: &Pubkey, amount: u64) -> Result<()> {
+    require_keys_eq!(sender.mint, *mint, CustomError::InvalidMint);
+    require!(sender.amount >= amount, CustomError::InsufficientFunds);
+    Ok(())
+}
+
+// Append an entry to the audit log. Before initialize_audit_log there is no log and nothing is
+// recorded, afterwards the log account is required.
+fn record_audit(
+    config: &ConfigAccount,
+    audit_log: Option<&AccountLoader<AuditLog>>,
+    action: AuditAction,
+    actor: Pubkey,
+    target: Pubkey,
+    clock: &Clock,
+) -> Result<()> {
+    if !config.audit_log {
+        return Ok(());
+    }
+    let audit_log = audit_log.ok_or(CustomError::AuditLogRequired)?;
+    audit_log.load_mut()?.push(AuditEntry::new(action, actor, target, clock));
+    Ok(())
+}
+
+// Log the compute units left at `label` when config.compute_telemetry is set, so the cost of each
+// step can be compared across releases. Devnet builds only, the flag is refused elsewhere.
+fn log_compute_units(config: &ConfigAccount, label: &str) {
+    if cfg!(feature = "devnet") && config.compute_telemetry {
+        msg!(label);
+        sol_log_compute_units();
+    }
+}
+
+// Admin and clock of the config changes of one instruction, see ConfigValue
+struct ConfigChanges<'a> {
+    admin: Pubkey,
+    clock: &'a Clock,
+}
+
+impl ConfigChanges<'_> {
+    // Set a config field, emitting its change event when the value differs
+    fn set<T: ConfigValue>(&self, field: &mut T, value: T, param: ConfigParam) {
+        if *field != value {
+            T::emit_change(param, *field, value, self.admin, self.clock);
+            *field = value;
+        }
+    }
+}
+
+// Config field types and the change event each one emits
+trait ConfigValue: Copy + PartialEq {
+    fn emit_change(
+        param: ConfigParam,
+        old_value: Self,
+        new_value: Self,
+        admin: Pubkey,
+        clock: &Clock,
+    );
+}
+
+// exit_notice_secs, the only signed field, is never negative
+macro_rules! numeric_config_value {
+    ($($ty:ty),*) => {$(
+        impl ConfigValue for $ty {
+            fn emit_change(
+                param: ConfigParam,
+                old_value: Self,
+                new_value: Self,
+                admin: Pubkey,
+                clock: &Clock,
+            ) {
+                emit!(ConfigValueChanged {
+                    schema_version: EVENT_SCHEMA_VERSION,
+                    admin,
+                    param,
+                    old_value: old_value as u64,
+                    new_value: new_value as u64,
+                    slot: clock.slot,
+                    unix_timestamp: clock.unix_timestamp,
+                });
+            }
+        }
+    )*};
+}
+
+numeric_config_value!(u16, u64, i64);
+
+// Values longer than 32 bytes are reported as the hash() of their Borsh encoding
+macro_rules! hashed_config_value {
+    ($($ty:ty),*) => {$(
+        impl ConfigValue for $ty {
+            fn emit_change(
+                param: ConfigParam,
+                old_value: Self,
+                new_value: Self,
+                admin: Pubkey,
+                clock: &Clock,
+            ) {
+                let digest = |value: Self| {
+                    let mut data = Vec::new();
+                    value.serialize(&mut data).unwrap();
+                    hash(&data).to_bytes()
+                };
+                emit!(ConfigDataChanged {
+                    schema_version: EVENT_SCHEMA_VERSION,
+                    admin,
+                    param,
+                    old_value: digest(old_value),
+                    new_value: digest(new_value),
+                    slot: clock.slot,
+                    unix_timestamp: clock.unix_timestamp,
+                });
+            }
+        }
+    )*};
+}
+
+hashed_config_value!(
+    [u64; MAX_CAPABILITIES],
+    [[u8; 32]; MAX_BLOCKED_NAMES],
+    [Pubkey; MAX_CPI_PROGRAMS],
+    SoftwareVersion
+);
+
+impl ConfigValue for [u8; 32] {
+    fn emit_change(
+        param: ConfigParam,
+        old_value: Self,
+        new_value: Self,
+        admin: Pubkey,
+        clock: &Clock,
+    ) {
+        emit!(ConfigDataChanged {
+            schema_version: EVENT_SCHEMA_VERSION,
+            admin,
+            param,
+            old_value,
+            new_value,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+    }
+}
+
+impl ConfigValue for Pubkey {
+    fn emit_change(
+        param: ConfigParam,
+        old_value: Self,
+        new_value: Self,
+        admin: Pubkey,
+        clock: &Clock,
+    ) {
+        emit!(ConfigAddressChanged {
+            schema_version: EVENT_SCHEMA_VERSION,
+            admin,
+            param,
+            old_value,
+            new_value,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+    }
+}
+
+impl ConfigValue for bool {
+    fn emit_change(
+        param: ConfigParam,
+        old_value: Self,
+        new_value: Self,
+        admin: Pubkey,
+        clock: &Clock,
+    ) {
+        emit!(ConfigFlagToggled {
+            schema_version: EVENT_SCHEMA_VERSION,
+            admin,
+            param,
+            old_value,
+            new_value,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+        });
+    }
+}
+
+// Compliance gate: when a gate program is configured the staker must present an account at
+// PDA [ALLOWLIST_SEED, staker] of that program. This program's own allowlist entries satisfy the
+// rule, and so does any external attestation program that follows the same derivation.
+fn check_gate(
+    config: &ConfigAccount,
+    gate_account: Option<&UncheckedAccount>,
+    staker: &Pubkey,
+) -> Result<()> {
+    if config.gate_program == Pubkey::default() {
+        return Ok(());
+    }
+
+    let gate_account = gate_account.ok_or(CustomError::StakerNotApproved)?;
+    let (expected, _) = Pubkey::find_program_address(
+        &[ALLOWLIST_SEED, staker.as_ref()],
+        &config.gate_program,
+    );
+
+    require_keys_eq!(gate_account.key(), expected, CustomError::StakerNotApproved);
+    require_keys_eq!(
+        *gate_account.owner,
+        config.gate_program,
+        CustomError::StakerNotApproved
+    );
+    require!(!gate_account.data_is_empty(), CustomError::StakerNotApproved);
+
+    Ok(())
+}
+
+// Hash of the first slot produced after `slot`, from the SlotHashes sysvar. Read in place since
+// the sysvar is too large to deserialize; entries are (slot, hash) pairs, newest first, and
+// skipped slots have none.
+fn slot_hash_after(slot_hashes: &AccountInfo, slot: u64) -> Result<[u8; 32]> {
+    const ENTRY_LEN: usize = 8 + 32;
+
+    let data = slot_hashes.try_borrow_data()?;
+    let read_u64 = |at: usize| -> Option<u64> {
+        data.get(at..at + 8)
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+    };
+    let len = read_u64(0).ok_or(CustomError::InvalidAccountData)? as usize;
+    let newest = read_u64(8).ok_or(CustomError::InvalidAccountData)?;
+    require!(slot < newest, CustomError::RevealTooEarly);
+
+    // Entry i holds slot newest - i or older, so the first slot after `slot` is at this index
+    // or a newer one. Scanning towards the newest, the first entry past `slot` is the answer.
+    let start = (newest - slot - 1) as usize;
+    require!(start < len, CustomError::RevealExpired);
+    for index in (0..=start).rev() {
+        let at = 8 + index * ENTRY_LEN;
+        let entry_slot = read_u64(at).ok_or(CustomError::InvalidAccountData)?;
+        if entry_slot > slot {
+            return Ok(data[at + 8..at + ENTRY_LEN].try_into().unwrap());
+        }
+    }
+    err!(CustomError::InvalidAccountData)
+}
+
+// Churn limit: a server removed less than config.registration_cooldown_secs ago can't be
+// registered again under the same owner and serverkey. Servers never removed have no record.
+fn check_registration_cooldown(
+    config: &ConfigAccount,
+    registration_record: Option<&UncheckedAccount>,
+    program_id: &Pubkey,
+) -> Result<()> {
+    if config.registration_cooldown_secs == 0 {
+        return Ok(());
+    }
+    let record = registration_record.ok_or(CustomError::RegistrationRecordRequired)?;
+    if record.owner != program_id || record.data_is_empty() {
+        return Ok(());
+    }
+    let record = RegistrationRecord::try_deserialize(&mut &record.try_borrow_data()?[..])?;
+    require!(
+        Clock::get()?.unix_timestamp
+            >= record.closed_at.saturating_add(config.registration_cooldown_secs as i64),
+        CustomError::RegistrationCooldown
+    );
+    Ok(())
+}
+
+// NFT-gated registration: while config.license_collection is set, the registrant must hold a
+// token whose Token Metadata is in that collection, verified by the collection authority
+fn check_license(
+    config: &ConfigAccount,
+    license_token_account: Option<&InterfaceAccount<TokenAccount>>,
+    license_metadata: Option<&Account<MetadataAccount>>,
+    registrant: &Pubkey,
+) -> Result<()> {
+    if config.license_collection == Pubkey::default() {
+        return Ok(());
+    }
+
+    let token_account = license_token_account.ok_or(CustomError::LicenseRequired)?;
+    let metadata = license_metadata.ok_or(CustomError::LicenseRequired)?;
+    require_keys_eq!(token_account.owner, *registrant, CustomError::LicenseRequired);
+    require!(token_account.amount > 0, CustomError::LicenseRequired);
+    // Account checked the Token Metadata owner, which only stores the PDA's own mint here
+    require_keys_eq!(metadata.mint, token_account.mint, CustomError::LicenseRequired);
+    require!(
+        matches!(
+            &metadata.collection,
+            Some(collection) if collection.verified && collection.key == config.license_collection
+        ),
+        CustomError::LicenseRequired
+    );
+
+    Ok(())
+}
+
+// Promote stake queued in an earlier epoch. Activation is lazy: it happens the next time the
+// position is touched, crank_rewards can be used to apply it right after the epoch boundary.
+fn activate_delegation(
+    main_account: &mut MainAccount,
+    info_account: &mut Account<InfoAccount>,
+    delegated_account: &mut DelegatedAccount,
+    epoch: u64,
+) -> Result<()> {
+    if delegated_account.activating_stake == 0 || epoch < delegated_account.activation_epoch {
+        return Ok(());
+    }
+
+    delegated_account.settle_rewards(info_account.acc_reward_per_share)?;
+    let old_weight = delegated_account.reward_weight();
+
+    let amount = delegated_account.activating_stake;
+    delegated_account.activating_stake = 0;
+    delegated_account.stake += amount;
+    info_account.activating_stake -= amount;
+    info_account.total += amount;
+    info_account.delegated += amount;
+    main_account.add_stake(&info_account.mint, amount)?;
+    info_account.refresh_stake(main_account)?;
+
+    info_account.update_delegated_weight(old_weight, delegated_account.reward_weight())?;
+    delegated_account.checkpoint_rewards(info_account.acc_reward_per_share)?;
+
+    let clock = Clock::get()?;
+    emit!(DelegationActivated {
+        schema_version: EVENT_SCHEMA_VERSION,
+        owner: delegated_account.owner,
+        delegator: info_account.key(),
+        amount,
+        epoch,
+        slot: clock.slot,
+        unix_timestamp: clock.unix_timestamp,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct InitializeMain<'info> {
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + std::mem::size_of::<MainAccount>(),
+        seeds = [MAIN_SEED], 
+        bump
+    )]
+    pub main_account: AccountLoader<'info, MainAccount>,
+    // Only needed when the config overrides the staking mint
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Option<Account<'info, ConfigAccount>>,
+    // The staking mint, checked in the handler against the config override
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+#[instruction(serverkey: Vec<u8>)]
+pub struct AddServer<'info> {
+    #[account(mut)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    // Required when the config has a gate program set, see `check_gate`
+    /// CHECK: owner and address are verified against the configured gate program
+    pub gate_account: Option<UncheckedAccount<'info>>,
+
+    // Required when the config sets a USD minimum, see `stake_minimum`
+    /// CHECK: Pyth PriceUpdateV2, owner and contents are verified in staking_token_price
+    pub price_update: Option<UncheckedAccount<'info>>,
+
+    // Required for new servers when the config sets a license collection, see `check_license`
+    pub license_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    pub license_metadata: Option<Account<'info, MetadataAccount>>,
+
+    // Required for new servers while config.registration_cooldown_secs is set, even when it
+    // doesn't exist yet, see `check_registration_cooldown`
+    /// CHECK: address is checked here, owner and contents in check_registration_cooldown
+    #[account(
+        seeds = [
+            REGISTRATION_SEED,
+            owner.key().as_ref(),
+            &hash(serverkey.as_ref()).to_bytes(),
+        ],
+        bump
+    )]
+    pub registration_record: Option<UncheckedAccount<'info>>,
+
+    // PDA account for storing data
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = INFO_ACCOUNT_SPACE,
+        seeds = [
+            INFO_SEED,        // seed prefix
+            owner.key().as_ref(), // Use caller's public key as seed
+            &hash(serverkey.as_ref()).to_bytes(),
+        ],
+        bump
+    )]
+    pub info_account: Box<Account<'info, InfoAccount>>, // PDA for storing name, boxed to keep it off the stack
+
+    // Transfer account
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = owner,
+        associated_token::token_program = token_program,
+    )]
+    pub sender_token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    // PDA account for staking in contract
+    /// CHECK: ATA of info_account for mint, created or checked in open_vault
+    #[account(mut)]
+    pub vault: UncheckedAccount<'info>,
+
+    // Any collateral mint approved in the main account
+    #[account(
+        constraint = main_account.load()?.mint_class(&mint.key()) == Some(CollateralClass::Primary) @ CustomError::InvalidMint
+    )]
+    pub mint: Box<InterfaceAccount<'info, Mint>>, // Collateral mint of the server
+
+    // Bond accounts, required for new servers when the deployment runs in dual-token mode.
+    // The bond vault is the info_account's ATA of the bond mint, created by the client beforehand.
+    pub bond_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(mut)]
+    pub bond_sender_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut)]
+    pub bond_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    // Mutable for add_server_sol, which wraps the collateral from the owner's lamports
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    // Pays rent for the server account and its vault, e.g. an onboarding service sponsoring new
+    // operators. The rent is refunded to the owner when the server is removed.
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    // Token Program
+    pub token_program: Interface<'info, TokenInterface>,
+
+    // Associated Token Program
+    pub associated_token_program: Program<'info, AssociatedToken>,
+
+    // System Program
+    pub system_program: Program<'info, System>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct UpdateServer<'info> {
+    #[account(
+        mut,
+        has_one = owner
+    )]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(address = info_account.mint @ CustomError::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ServerExit<'info> {
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(mut, has_one = owner)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    pub owner: Signer<'info>,
+}
+
+// Operational updates that move no funds, open to the owner and to the operator key
+#[derive(Accounts)]
+pub struct OperateServer<'info> {
+    #[account(
+        mut,
+        constraint = info_account.is_operator(&authority.key()) @ CustomError::Unauthorized
+    )]
+    pub info_account: Account<'info, InfoAccount>,
+
+    pub authority: Signer<'info>, // Owner or operator key
+}
+
+#[derive(Accounts)]
+pub struct Recovery<'info> {
+    #[account(
+        mut,
+        constraint = info_account.backup_key != Pubkey::default()
+            && info_account.backup_key == backup_key.key() @ CustomError::Unauthorized,
+    )]
+    pub info_account: Account<'info, InfoAccount>,
+    pub backup_key: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetGuardians<'info> {
+    #[account(has_one = owner)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = GUARDIAN_SET_SPACE,
+        seeds = [GUARDIAN_SEED, info_account.key().as_ref()],
+        bump
+    )]
+    pub guardian_set: Account<'info, GuardianSet>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateGuardians<'info> {
+    #[account(has_one = owner)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        mut,
+        seeds = [GUARDIAN_SEED, info_account.key().as_ref()],
+        bump = guardian_set.bump
+    )]
+    pub guardian_set: Account<'info, GuardianSet>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveGuardians<'info> {
+    #[account(has_one = owner)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        mut,
+        close = owner,
+        seeds = [GUARDIAN_SEED, info_account.key().as_ref()],
+        bump = guardian_set.bump
+    )]
+    pub guardian_set: Account<'info, GuardianSet>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct GuardianVote<'info> {
+    #[account(
+        mut,
+        seeds = [GUARDIAN_SEED, guardian_set.server.as_ref()],
+        bump = guardian_set.bump
+    )]
+    pub guardian_set: Account<'info, GuardianSet>,
+    pub guardian: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct GuardianRecovery<'info> {
+    #[account(mut, address = guardian_set.server @ CustomError::InvalidAccountData)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        mut,
+        seeds = [GUARDIAN_SEED, guardian_set.server.as_ref()],
+        bump = guardian_set.bump
+    )]
+    pub guardian_set: Account<'info, GuardianSet>,
+
+    #[account(address = guardian_set.proposed_owner @ CustomError::Unauthorized)]
+    pub new_owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(handle: String)]
+pub struct ClaimHandle<'info> {
+    #[account(has_one = owner)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    // Fails as already in use when the handle is taken
+    #[account(
+        init,
+        payer = owner,
+        space = NODE_HANDLE_SPACE,
+        seeds = [HANDLE_SEED, &hash(handle.as_bytes()).to_bytes()],
+        bump
+    )]
+    pub node_handle: Account<'info, NodeHandle>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct TransferHandle<'info> {
+    #[account(has_one = owner, address = node_handle.server @ CustomError::InvalidAccountData)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        mut,
+        seeds = [HANDLE_SEED, node_handle.handle_hash.as_ref()],
+        bump = node_handle.bump
+    )]
+    pub node_handle: Account<'info, NodeHandle>,
+
+    #[account(constraint = new_info_account.owner == new_owner.key() @ CustomError::Unauthorized)]
+    pub new_info_account: Account<'info, InfoAccount>,
+
+    pub owner: Signer<'info>,
+    pub new_owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ReleaseHandle<'info> {
+    /// CHECK: the server the handle points to, empty once it was removed, see release_handle
+    #[account(address = node_handle.server @ CustomError::InvalidAccountData)]
+    pub info_account: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        close = signer,
+        seeds = [HANDLE_SEED, node_handle.handle_hash.as_ref()],
+        bump = node_handle.bump
+    )]
+    pub node_handle: Account<'info, NodeHandle>,
+
+    #[account(mut)]
+    pub signer: Signer<'info>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct UpdateServerName<'info> {
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>, // Blocked name hashes, see normalize_name
+
+    // Exchange rate of the mint, the name change fee is set in common units
+    #[account(seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(
+        mut,
+        has_one = owner
+    )]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(address = info_account.mint @ CustomError::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    // Fee accounts, only required for a rename while config.name_change_fee is set
+    #[account(
+        mut,
+        constraint = owner_token_account.mint == mint.key() @ CustomError::InvalidMint,
+    )]
+    pub owner_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    /// CHECK: data-less PDA that owns the treasury token accounts
+    #[account(seeds = [TREASURY_SEED], bump)]
+    pub treasury: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = treasury,
+        associated_token::token_program = token_program,
+    )]
+    pub treasury_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct RemoveServer<'info> {
+    #[account(mut)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(
+        mut,
+        close = owner,
+        has_one = owner,
+        constraint = info_account.total == 0 @ CustomError::NonZeroBalance,
+        // Checked apart from `total` so a drifted split can't hide stake
+        constraint = info_account.stake == 0 && info_account.delegated == 0
+            @ CustomError::NonZeroBalance,
+        // Delegators leave first, with d_withdraw or force_return_delegation
+        constraint = info_account.total_delegators == 0 @ CustomError::DelegatorsRemaining,
+        constraint = info_account.activating_stake == 0 @ CustomError::DelegatorsRemaining,
+        constraint = info_account.revenue_unreleased == 0
+            && info_account.revenue_released == 0
+            && info_account.vesting_amount == info_account.vesting_claimed
+            && info_account.revenue_disputed == 0
+            @ CustomError::NonZeroBalance,
+        // LST collateral is withdrawn with withdraw_lst and dust swept with treasury_sweep_dust
+        // first, the server is the only record of either
+        constraint = info_account.restaked == 0 && info_account.reward_dust == 0
+            @ CustomError::NonZeroBalance,
+        // The bond vault can only be emptied through withdraw_bond on a live server
+        constraint = info_account.bond == 0 @ CustomError::NonZeroBalance,
+        seeds = [
+            INFO_SEED,        // seed prefix
+            info_account.seed_owner().as_ref(), // Registrant, see seed_owner
+            info_account.serverkey_hash.as_ref(),
+        ],
+        bump = info_account.bump
+    )]
+    pub info_account: Account<'info, InfoAccount>,
+
+    // Remembers when this server was closed, see check_registration_cooldown. Keyed like the
+    // server address, by the registrant.
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = REGISTRATION_RECORD_SPACE,
+        seeds = [
+            REGISTRATION_SEED,
+            info_account.seed_owner().as_ref(),
+            info_account.serverkey_hash.as_ref(),
+        ],
+        bump
+    )]
+    pub registration_record: Account<'info, RegistrationRecord>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = info_account,
+        associated_token::token_program = token_program,
+    )]
+    pub vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        address = info_account.mint @ CustomError::InvalidMint
+    )]
+    pub mint: InterfaceAccount<'info, Mint>, // Collateral mint of the server
+
+    // Only needed for calls through CPI while config.cpi_guard is on
+    /// CHECK: instructions sysvar
+    #[account(address = solana_program::sysvar::instructions::ID)]
+    pub instructions: Option<UncheckedAccount<'info>>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>, // System Program
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct Deposit<'info> {
+    #[account(mut)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    // Required when the config has a gate program set, see `check_gate`
+    /// CHECK: owner and address are verified against the configured gate program
+    pub gate_account: Option<UncheckedAccount<'info>>,
+
+    #[account(
+        mut,
+        has_one = owner,
+    )]
+    pub info_account: Account<'info, InfoAccount>, // PDA for storing name
+
+    // Recreated when a full withdrawal closed it
+    /// CHECK: ATA of info_account for mint, created or checked in open_vault
+    #[account(mut)]
+    pub vault: UncheckedAccount<'info>,
+
+    #[account(
+        address = info_account.mint @ CustomError::InvalidMint
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    // Transfer account
+    #[account(
+        mut,
+        constraint = sender_token_account.mint == mint.key() @ CustomError::InvalidMint,  
+    )]
+    pub sender_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    // Only needed to tag the deposit with a memo, see `transaction_memo`
+    /// CHECK: instructions sysvar
+    #[account(address = solana_program::sysvar::instructions::ID)]
+    pub instructions: Option<UncheckedAccount<'info>>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct DepositWithPermit<'info> {
+    #[account(mut)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    // Required when the config has a gate program set, see `check_gate`
+    /// CHECK: owner and address are verified against the configured gate program
+    pub gate_account: Option<UncheckedAccount<'info>>,
+
+    #[account(mut, has_one = owner)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    // Recreated when a full withdrawal closed it
+    /// CHECK: ATA of info_account for mint, created or checked in open_vault
+    #[account(mut)]
+    pub vault: UncheckedAccount<'info>,
+
+    #[account(
+        address = info_account.mint @ CustomError::InvalidMint
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    // Any account of the owner that approved the permit authority, never someone else's
+    #[account(
+        mut,
+        constraint = sender_token_account.mint == mint.key() @ CustomError::InvalidMint,
+        constraint = sender_token_account.owner == owner.key() @ CustomError::Unauthorized,
+    )]
+    pub sender_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: data-less PDA, the SPL delegate owners approve for permit deposits
+    #[account(seeds = [PERMIT_SEED], bump)]
+    pub permit_authority: UncheckedAccount<'info>,
+
+    /// CHECK: authorizes through the Ed25519 permit signature instead of signing
+    pub owner: UncheckedAccount<'info>,
+    // Submits the transaction, pays its fees and any rent
+    #[account(mut)]
+    pub relayer: Signer<'info>,
+    /// CHECK: instructions sysvar, read for the Ed25519 signature check
+    #[account(address = solana_program::sysvar::instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+#[instruction(beneficiary: Pubkey)]
+pub struct DepositFor<'info> {
+    #[account(mut)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    // Required when the config has a gate program set, see `check_gate`
+    /// CHECK: owner and address are verified against the configured gate program
+    pub gate_account: Option<UncheckedAccount<'info>>,
+
+    #[account(
+        mut,
+        constraint = info_account.owner == beneficiary @ CustomError::Unauthorized,
+    )]
+    pub info_account: Account<'info, InfoAccount>,
+
+    // Recreated when a full withdrawal closed it
+    /// CHECK: ATA of info_account for mint, created or checked in open_vault
+    #[account(mut)]
+    pub vault: UncheckedAccount<'info>,
+
+    #[account(
+        address = info_account.mint @ CustomError::InvalidMint
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    // The funder's account, it signs the transfer
+    #[account(
+        mut,
+        constraint = sender_token_account.mint == mint.key() @ CustomError::InvalidMint,
+    )]
+    pub sender_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    // Only needed to tag the deposit with a memo, see `transaction_memo`
+    /// CHECK: instructions sysvar
+    #[account(address = solana_program::sysvar::instructions::ID)]
+    pub instructions: Option<UncheckedAccount<'info>>,
+
+    #[account(mut)]
+    pub funder: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+#[instruction(beneficiary: Pubkey)]
+pub struct DelegatedDepositFor<'info> {
+    #[account(mut)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    // Required when the config has a gate program set, see `check_gate`
+    /// CHECK: owner and address are verified against the configured gate program
+    pub gate_account: Option<UncheckedAccount<'info>>,
+
+    // Required when the config sets a USD minimum, see `stake_minimum`
+    /// CHECK: Pyth PriceUpdateV2, owner and contents are verified in staking_token_price
+    pub price_update: Option<UncheckedAccount<'info>>,
+
+    #[account(mut)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    // The beneficiary's position, the same PDA its own d_deposit uses
+    #[account(
+        init_if_needed,
+        payer = funder,
+        space = DELEGATED_ACCOUNT_SPACE,
+        seeds = [
+            INFO_SEED,
+            beneficiary.as_ref(),
+            info_account.key().as_ref(),
+        ],
+        bump
+    )]
+    pub delegated_account: Account<'info, DelegatedAccount>,
+
+    /// CHECK: ATA of delegated_account for mint, created or checked in open_vault
+    #[account(mut)]
+    pub vault: UncheckedAccount<'info>,
+
+    #[account(
+        address = info_account.mint @ CustomError::InvalidMint
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    // The funder's account, it signs the transfer
+    #[account(
+        mut,
+        constraint = sender_token_account.mint == mint.key() @ CustomError::InvalidMint,
+    )]
+    pub sender_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    // Liquid receipt token accounts, required while config.receipt_tokens is on. Receipts are
+    // burned on withdrawal, so they must reach the beneficiary.
+    #[account(mut)]
+    pub receipt_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(
+        mut,
+        constraint = receipt_holder_account.owner == beneficiary @ CustomError::Unauthorized,
+    )]
+    pub receipt_holder_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    // Only needed to tag the deposit with a memo, see `transaction_memo`
+    /// CHECK: instructions sysvar
+    #[account(address = solana_program::sysvar::instructions::ID)]
+    pub instructions: Option<UncheckedAccount<'info>>,
+
+    #[account(mut)]
+    pub funder: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CreateStandingOrder<'info> {
+    pub info_account: Account<'info, InfoAccount>,
+
+    // Executions add to this position, the owner opens it with a first d_deposit
+    #[account(
+        has_one = owner,
+        seeds = [
+            INFO_SEED,
+            owner.key().as_ref(),
+            info_account.key().as_ref(),
+        ],
+        bump = delegated_account.bump
+    )]
+    pub delegated_account: Account<'info, DelegatedAccount>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = STANDING_ORDER_SPACE,
+        seeds = [STANDING_ORDER_SEED, owner.key().as_ref(), info_account.key().as_ref()],
+        bump
+    )]
+    pub standing_order: Account<'info, StandingOrder>,
+
+    #[account(address = info_account.mint @ CustomError::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        constraint = funding_account.mint == mint.key() @ CustomError::InvalidMint,
+        constraint = funding_account.owner == owner.key() @ CustomError::Unauthorized,
+    )]
+    pub funding_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct ExecuteStandingOrder<'info> {
+    #[account(mut)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    // Required when the config has a gate program set, see `check_gate`
+    /// CHECK: owner and address are verified against the configured gate program
+    pub gate_account: Option<UncheckedAccount<'info>>,
+
+    // Required when the config sets a USD minimum, see `stake_minimum`
+    /// CHECK: Pyth PriceUpdateV2, owner and contents are verified in staking_token_price
+    pub price_update: Option<UncheckedAccount<'info>>,
+
+    #[account(mut, address = standing_order.server @ CustomError::InvalidAccountData)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        mut,
+        seeds = [
+            STANDING_ORDER_SEED,
+            standing_order.owner.as_ref(),
+            standing_order.server.as_ref(),
+        ],
+        bump = standing_order.bump
+    )]
+    pub standing_order: Account<'info, StandingOrder>,
+
+    #[account(
+        mut,
+        seeds = [
+            INFO_SEED,
+            standing_order.owner.as_ref(),
+            info_account.key().as_ref(),
+        ],
+        bump = delegated_account.bump
+    )]
+    pub delegated_account: Account<'info, DelegatedAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = delegated_account,
+        associated_token::token_program = token_program,
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = info_account.mint @ CustomError::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut, address = standing_order.funding_account @ CustomError::InvalidAccountData)]
+    pub funding_account: InterfaceAccount<'info, TokenAccount>,
+
+    // Liquid receipt token accounts, required while config.receipt_tokens is on
+    #[account(mut)]
+    pub receipt_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(
+        mut,
+        constraint = receipt_holder_account.owner == standing_order.owner
+            @ CustomError::Unauthorized,
+    )]
+    pub receipt_holder_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub keeper: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct CancelStandingOrder<'info> {
+    #[account(
+        mut,
+        close = owner,
+        has_one = owner,
+        seeds = [STANDING_ORDER_SEED, owner.key().as_ref(), standing_order.server.as_ref()],
+        bump = standing_order.bump
+    )]
+    pub standing_order: Account<'info, StandingOrder>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CreateDelegationIntent<'info> {
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = DELEGATION_INTENT_SPACE,
+        seeds = [INTENT_SEED, owner.key().as_ref(), info_account.key().as_ref()],
+        bump
+    )]
+    pub delegation_intent: Account<'info, DelegationIntent>,
+
+    #[account(address = info_account.mint @ CustomError::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        constraint = funding_account.mint == mint.key() @ CustomError::InvalidMint,
+        constraint = funding_account.owner == owner.key() @ CustomError::Unauthorized,
+    )]
+    pub funding_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct ExecuteDelegationIntent<'info> {
+    #[account(mut)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    // Required when the config has a gate program set, see `check_gate`
+    /// CHECK: owner and address are verified against the configured gate program
+    pub gate_account: Option<UncheckedAccount<'info>>,
+
+    // Required when the config sets a USD minimum, see `stake_minimum`
+    /// CHECK: Pyth PriceUpdateV2, owner and contents are verified in staking_token_price
+    pub price_update: Option<UncheckedAccount<'info>>,
+
+    #[account(mut, address = delegation_intent.server @ CustomError::InvalidAccountData)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        mut,
+        close = owner,
+        has_one = owner,
+        seeds = [
+            INTENT_SEED,
+            delegation_intent.owner.as_ref(),
+            delegation_intent.server.as_ref(),
+        ],
+        bump = delegation_intent.bump
+    )]
+    pub delegation_intent: Account<'info, DelegationIntent>,
+
+    #[account(
+        init_if_needed,
+        payer = executor,
+        space = DELEGATED_ACCOUNT_SPACE,
+        seeds = [
+            INFO_SEED,
+            delegation_intent.owner.as_ref(),
+            info_account.key().as_ref(),
+        ],
+        bump
+    )]
+    pub delegated_account: Account<'info, DelegatedAccount>,
+
+    /// CHECK: ATA of delegated_account for mint, created or checked in open_vault
+    #[account(mut)]
+    pub vault: UncheckedAccount<'info>,
+
+    #[account(address = info_account.mint @ CustomError::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut, address = delegation_intent.funding_account @ CustomError::InvalidAccountData)]
+    pub funding_account: InterfaceAccount<'info, TokenAccount>,
+
+    // Liquid receipt token accounts, required while config.receipt_tokens is on
+    #[account(mut)]
+    pub receipt_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(
+        mut,
+        constraint = receipt_holder_account.owner == delegation_intent.owner
+            @ CustomError::Unauthorized,
+    )]
+    pub receipt_holder_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: the intent's owner, receives its rent
+    #[account(mut)]
+    pub owner: UncheckedAccount<'info>,
+
+    // The server operator or a keeper, pays for a new position
+    #[account(mut)]
+    pub executor: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelDelegationIntent<'info> {
+    #[account(
+        mut,
+        close = owner,
+        has_one = owner,
+        seeds = [INTENT_SEED, owner.key().as_ref(), delegation_intent.server.as_ref()],
+        bump = delegation_intent.bump
+    )]
+    pub delegation_intent: Account<'info, DelegationIntent>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct DelegatedDeposit<'info> {
+    #[account(mut)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    // Required when the config has a gate program set, see `check_gate`
+    /// CHECK: owner and address are verified against the configured gate program
+    pub gate_account: Option<UncheckedAccount<'info>>,
+
+    // Required when the config sets a USD minimum, see `stake_minimum`
+    /// CHECK: Pyth PriceUpdateV2, owner and contents are verified in staking_token_price
+    pub price_update: Option<UncheckedAccount<'info>>,
+
+    #[account(mut)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = DELEGATED_ACCOUNT_SPACE,
+        seeds = [
+            INFO_SEED,
+            owner.key().as_ref(),
+            info_account.key().as_ref(),
+        ],
+        bump
+    )]
+    pub delegated_account: Account<'info, DelegatedAccount>, // PDA account for staking in contract
+
+    /// CHECK: ATA of delegated_account for mint, created or checked in open_vault
+    #[account(mut)]
+    pub vault: UncheckedAccount<'info>,
+
+    #[account(
+        address = info_account.mint @ CustomError::InvalidMint
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    // Transfer account
+    #[account(
+        mut,
+        constraint = sender_token_account.mint == mint.key() @ CustomError::InvalidMint,  
+    )]
+    pub sender_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    // Server owner's ATA, required while the server charges an entry fee
+    #[account(
+        mut,
+        constraint = operator_token_account.key()
+            == get_associated_token_address_with_program_id(
+                &info_account.owner,
+                &mint.key(),
+                &token_program.key(),
+            ) @ CustomError::InvalidAccountData,
+    )]
+    pub operator_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    // Liquid receipt token accounts, required while config.receipt_tokens is on
+    #[account(mut)]
+    pub receipt_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(mut)]
+    pub receipt_holder_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    // Only needed to tag the deposit with a memo, see `transaction_memo`
+    /// CHECK: instructions sysvar
+    #[account(address = solana_program::sysvar::instructions::ID)]
+    pub instructions: Option<UncheckedAccount<'info>>,
+
+    // Owner may be a wallet or a program PDA signing through CPI (invoke_signed)
+    pub owner: Signer<'info>,
+    // Pays rent for new accounts, a PDA owner usually can't fund system transfers itself
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(sequence: u64, foreign_owner: [u8; 32])]
+pub struct DelegatedDepositBridged<'info> {
+    #[account(mut)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    // Required when the config has a gate program set, see `check_gate`
+    /// CHECK: owner and address are verified against the configured gate program
+    pub gate_account: Option<UncheckedAccount<'info>>,
+
+    // Required when the config sets a USD minimum, see `stake_minimum`
+    /// CHECK: Pyth PriceUpdateV2, owner and contents are verified in staking_token_price
+    pub price_update: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: contents are parsed and checked in d_deposit_bridged
+    #[account(owner = config.wormhole_bridge @ CustomError::InvalidVaa)]
+    pub posted_vaa: UncheckedAccount<'info>,
+
+    // Fails when the message was already credited
+    #[account(
+        init,
+        payer = payer,
+        space = BRIDGED_DELEGATION_SPACE,
+        seeds = [
+            BRIDGED_SEED,
+            config.bridge_emitter_chain.to_le_bytes().as_ref(),
+            sequence.to_le_bytes().as_ref(),
+        ],
+        bump
+    )]
+    pub bridged_delegation: Account<'info, BridgedDelegation>,
+
+    // Owns the delegations of the foreign address, created with its first one
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = FOREIGN_OWNER_SPACE,
+        seeds = [
+            FOREIGN_OWNER_SEED,
+            config.bridge_emitter_chain.to_le_bytes().as_ref(),
+            foreign_owner.as_ref(),
+        ],
+        bump
+    )]
+    pub foreign_owner_account: Account<'info, ForeignOwner>,
+
+    #[account(mut)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = DELEGATED_ACCOUNT_SPACE,
+        seeds = [
+            INFO_SEED,
+            foreign_owner_account.key().as_ref(),
+            info_account.key().as_ref(),
+        ],
+        bump
+    )]
+    pub delegated_account: Account<'info, DelegatedAccount>,
+
+    /// CHECK: ATA of delegated_account for mint, created or checked in open_vault
+    #[account(mut)]
+    pub vault: UncheckedAccount<'info>,
+
+    #[account(address = info_account.mint @ CustomError::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = sender_token_account.mint == mint.key() @ CustomError::InvalidMint,
+    )]
+    pub sender_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub funder: Signer<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(sequence: u64, foreign_owner: [u8; 32])]
+pub struct DelegatedWithdrawBridged<'info> {
+    #[account(mut, seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    /// CHECK: contents are parsed and checked in d_withdraw_bridged
+    #[account(owner = config.wormhole_bridge @ CustomError::InvalidVaa)]
+    pub posted_vaa: UncheckedAccount<'info>,
+
+    // Fails when the message was already used
+    #[account(
+        init,
+        payer = payer,
+        space = BRIDGED_DELEGATION_SPACE,
+        seeds = [
+            BRIDGED_SEED,
+            config.bridge_emitter_chain.to_le_bytes().as_ref(),
+            sequence.to_le_bytes().as_ref(),
+        ],
+        bump
+    )]
+    pub bridged_delegation: Account<'info, BridgedDelegation>,
+
+    #[account(
+        seeds = [
+            FOREIGN_OWNER_SEED,
+            config.bridge_emitter_chain.to_le_bytes().as_ref(),
+            foreign_owner.as_ref(),
+        ],
+        bump = foreign_owner_account.bump
+    )]
+    pub foreign_owner_account: Account<'info, ForeignOwner>,
+
+    #[account(mut)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        mut,
+        seeds = [
+            INFO_SEED,
+            foreign_owner_account.key().as_ref(),
+            info_account.key().as_ref(),
+        ],
+        bump = delegated_account.bump
+    )]
+    pub delegated_account: Account<'info, DelegatedAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = delegated_account,
+        associated_token::token_program = token_program,
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = main_account,
+        associated_token::token_program = token_program,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = info_account.mint @ CustomError::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    // Owned by the message's recipient, checked in d_withdraw_bridged
+    #[account(
+        mut,
+        constraint = recipient_token_account.mint == mint.key() @ CustomError::InvalidMint,
+    )]
+    pub recipient_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    // Treasury PDA and its token account, only required while the matching fee is switched on
+    /// CHECK: data-less PDA that owns the treasury token accounts
+    #[account(seeds = [TREASURY_SEED], bump)]
+    pub treasury: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = treasury,
+        associated_token::token_program = token_program,
+    )]
+    pub treasury_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>, // Anyone, normally the relayer
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct DelegatedDepositMany<'info> {
+    #[account(mut)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    // Required when the config has a gate program set, see `check_gate`
+    /// CHECK: owner and address are verified against the configured gate program
+    pub gate_account: Option<UncheckedAccount<'info>>,
+
+    // Required when the config sets a USD minimum, see `stake_minimum`
+    /// CHECK: Pyth PriceUpdateV2, owner and contents are verified in staking_token_price
+    pub price_update: Option<UncheckedAccount<'info>>,
+
+    #[account(
+        constraint = main_account.load()?.mint_class(&mint.key()) == Some(CollateralClass::Primary) @ CustomError::InvalidMint
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = sender_token_account.mint == mint.key() @ CustomError::InvalidMint,
+    )]
+    pub sender_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    // Liquid receipt token accounts, required while config.receipt_tokens is on
+    #[account(mut)]
+    pub receipt_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(mut)]
+    pub receipt_holder_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub owner: Signer<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Rebalance<'info> {
+    #[account(mut)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(
+        has_one = owner,
+        seeds = [PORTFOLIO_SEED, owner.key().as_ref()],
+        bump = portfolio.bump
+    )]
+    pub portfolio: Account<'info, Portfolio>,
+
+    // Shared by every position, checked against each server's mint
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct CreatePortfolio<'info> {
+    #[account(
+        init,
+        payer = owner,
+        space = PORTFOLIO_SPACE,
+        seeds = [PORTFOLIO_SEED, owner.key().as_ref()],
+        bump
+    )]
+    pub portfolio: Account<'info, Portfolio>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct PortfolioDeposit<'info> {
+    #[account(mut)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    // Required when the config has a gate program set, see `check_gate`
+    /// CHECK: owner and address are verified against the configured gate program
+    pub gate_account: Option<UncheckedAccount<'info>>,
+
+    // Required when the config sets a USD minimum, see `stake_minimum`
+    /// CHECK: Pyth PriceUpdateV2, owner and contents are verified in staking_token_price
+    pub price_update: Option<UncheckedAccount<'info>>,
+
+    #[account(
+        mut,
+        has_one = owner,
+        seeds = [PORTFOLIO_SEED, owner.key().as_ref()],
+        bump = portfolio.bump
+    )]
+    pub portfolio: Account<'info, Portfolio>,
+
+    #[account(
+        constraint = main_account.load()?.mint_class(&mint.key()) == Some(CollateralClass::Primary) @ CustomError::InvalidMint
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = sender_token_account.mint == mint.key() @ CustomError::InvalidMint,
+    )]
+    pub sender_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub owner: Signer<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct PortfolioWithdraw<'info> {
+    #[account(mut)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(
+        mut,
+        has_one = owner,
+        seeds = [PORTFOLIO_SEED, owner.key().as_ref()],
+        bump = portfolio.bump
+    )]
+    pub portfolio: Account<'info, Portfolio>,
+
+    #[account(mut)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        mut,
+        constraint = delegated_account.owner == portfolio.key() @ CustomError::Unauthorized,
+        seeds = [INFO_SEED, portfolio.key().as_ref(), info_account.key().as_ref()],
+        bump = delegated_account.bump
+    )]
+    pub delegated_account: Account<'info, DelegatedAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = delegated_account,
+        associated_token::token_program = token_program,
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        associated_token::mint = mint,
+        associated_token::authority = owner,
+        associated_token::token_program = token_program,
+    )]
+    pub receipt_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = info_account.mint @ CustomError::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    // Treasury PDA and its token account, only required while the matching fee is switched on
+    /// CHECK: data-less PDA that owns the treasury token accounts
+    #[account(seeds = [TREASURY_SEED], bump)]
+    pub treasury: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = treasury,
+        associated_token::token_program = token_program,
+    )]
+    pub treasury_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializePool<'info> {
+    #[account(
+        has_one = admin @ CustomError::Unauthorized,
+        seeds = [CONFIG_SEED],
+        bump
+    )]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(
+        constraint = main_account.load()?.mint_class(&mint.key()) == Some(CollateralClass::Primary) @ CustomError::InvalidMint
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = DELEGATION_POOL_SPACE,
+        seeds = [POOL_SEED, mint.key().as_ref()],
+        bump
+    )]
+    pub pool: Account<'info, DelegationPool>,
+
+    // Same decimals as the collateral, shares start out 1:1 with base units
+    #[account(
+        init,
+        payer = admin,
+        seeds = [POOL_SHARE_SEED, pool.key().as_ref()],
+        bump,
+        mint::decimals = mint.decimals,
+        mint::authority = pool,
+        mint::token_program = token_program,
+    )]
+    pub share_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init,
+        payer = admin,
+        associated_token::mint = mint,
+        associated_token::authority = pool,
+        associated_token::token_program = token_program,
+    )]
+    pub pool_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PoolDeposit<'info> {
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    // Required when the config has a gate program set, see `check_gate`
+    /// CHECK: owner and address are verified against the configured gate program
+    pub gate_account: Option<UncheckedAccount<'info>>,
+
+    #[account(
+        mut,
+        has_one = mint,
+        has_one = share_mint,
+        seeds = [POOL_SEED, mint.key().as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, DelegationPool>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = pool,
+        associated_token::token_program = token_program,
+    )]
+    pub pool_vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub share_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = sender_token_account.mint == mint.key() @ CustomError::InvalidMint,
+    )]
+    pub sender_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = share_mint,
+        associated_token::authority = owner,
+        associated_token::token_program = token_program,
+    )]
+    pub share_account: InterfaceAccount<'info, TokenAccount>,
+
+    // Owner may be a wallet or a program PDA signing through CPI (invoke_signed)
+    pub owner: Signer<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PoolWithdraw<'info> {
+    #[account(
+        mut,
+        has_one = mint,
+        has_one = share_mint,
+        seeds = [POOL_SEED, mint.key().as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, DelegationPool>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = pool,
+        associated_token::token_program = token_program,
+    )]
+    pub pool_vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(mut)]
+    pub share_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = share_account.mint == share_mint.key() @ CustomError::InvalidMint,
+    )]
+    pub share_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = mint,
+        associated_token::authority = owner,
+        associated_token::token_program = token_program,
+    )]
+    pub receipt_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    // Owner may be a wallet or a program PDA signing through CPI (invoke_signed)
+    pub owner: Signer<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct PoolDelegate<'info> {
+    #[account(mut)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    // Required when the config sets a USD minimum, see `stake_minimum`
+    /// CHECK: Pyth PriceUpdateV2, owner and contents are verified in staking_token_price
+    pub price_update: Option<UncheckedAccount<'info>>,
+
+    #[account(
+        mut,
+        has_one = manager @ CustomError::Unauthorized,
+        has_one = mint,
+        seeds = [POOL_SEED, mint.key().as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, DelegationPool>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = pool,
+        associated_token::token_program = token_program,
+    )]
+    pub pool_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut, constraint = info_account.mint == mint.key() @ CustomError::InvalidMint)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = manager,
+        space = DELEGATED_ACCOUNT_SPACE,
+        seeds = [INFO_SEED, pool.key().as_ref(), info_account.key().as_ref()],
+        bump
+    )]
+    pub delegated_account: Account<'info, DelegatedAccount>,
+
+    /// CHECK: ATA of delegated_account for mint, created or checked in open_vault
+    #[account(mut)]
+    pub vault: UncheckedAccount<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut)]
+    pub manager: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct PoolUndelegate<'info> {
+    #[account(mut)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(
+        mut,
+        has_one = manager @ CustomError::Unauthorized,
+        has_one = mint,
+        seeds = [POOL_SEED, mint.key().as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, DelegationPool>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = pool,
+        associated_token::token_program = token_program,
+    )]
+    pub pool_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        mut,
+        seeds = [INFO_SEED, pool.key().as_ref(), info_account.key().as_ref()],
+        bump = delegated_account.bump
+    )]
+    pub delegated_account: Account<'info, DelegatedAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = delegated_account,
+        associated_token::token_program = token_program,
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    // Treasury PDA and its token account, only required while the matching fee is switched on
+    /// CHECK: data-less PDA that owns the treasury token accounts
+    #[account(seeds = [TREASURY_SEED], bump)]
+    pub treasury: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = treasury,
+        associated_token::token_program = token_program,
+    )]
+    pub treasury_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub manager: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct GetPoolBalance<'info> {
+    #[account(has_one = share_mint)]
+    pub pool: Account<'info, DelegationPool>,
+    pub share_mint: InterfaceAccount<'info, Mint>,
+
+    // The holder's share account, left out for the pool totals only
+    #[account(constraint = share_account.mint == share_mint.key() @ CustomError::InvalidMint)]
+    pub share_account: Option<InterfaceAccount<'info, TokenAccount>>,
+}
+
+#[derive(Accounts)]
+pub struct SyncPool<'info> {
+    #[account(mut, has_one = share_mint)]
+    pub pool: Account<'info, DelegationPool>,
+    pub share_mint: InterfaceAccount<'info, Mint>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct Withdraw<'info> {
+    #[account(mut)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(
+        mut,
+        has_one = owner,
+        seeds = [
+            INFO_SEED,        // seed prefix
+            info_account.seed_owner().as_ref(), // Registrant, see seed_owner
+            info_account.serverkey_hash.as_ref(),
+        ],
+        bump = info_account.bump
+    )]
+    pub info_account: Account<'info, InfoAccount>, // PDA for storing name
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = info_account,
+        associated_token::token_program = token_program,
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    // Here, if there's no related ata account, the contract automatically creates or updates the account to accept tokens. The address of the ata account is easy to derive using @solana/spl-token's getAssociatedTokenAddress
+    #[account(
+        init_if_needed,
+        payer = owner,
+        associated_token::mint = mint,
+        associated_token::authority = owner,
+        associated_token::token_program = token_program,
+    )]
+    pub receipt_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    // Paid instead of receipt_token_account while the server has a withdrawal whitelist
+    #[account(
+        mut,
+        constraint = destination.mint == mint.key() @ CustomError::InvalidMint,
+    )]
+    pub destination: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        address = info_account.mint @ CustomError::InvalidMint
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    // Treasury PDA and its token account, only required while the matching fee is switched on
+    /// CHECK: data-less PDA that owns the treasury token accounts
+    #[account(seeds = [TREASURY_SEED], bump)]
+    pub treasury: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = treasury,
+        associated_token::token_program = token_program,
+    )]
+    pub treasury_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    // Only needed for calls through CPI while config.cpi_guard is on
+    /// CHECK: instructions sysvar
+    #[account(address = solana_program::sysvar::instructions::ID)]
+    pub instructions: Option<UncheckedAccount<'info>>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct DelegatedWithdraw<'info> {
+    #[account(mut)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(mut)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        mut,
+        has_one = owner,
+        seeds = [
+            INFO_SEED,
+            owner.key().as_ref(),
+            info_account.key().as_ref(),
+        ],
+        bump = delegated_account.bump
+    )]
+    pub delegated_account: Account<'info, DelegatedAccount>, // PDA account for staking in contract
+
+    // Must be empty, a position with auto-renewal on stays locked until it is disabled
+    /// CHECK: only its emptiness is read
+    #[account(
+        seeds = [RENEWAL_SEED, delegated_account.key().as_ref()],
+        bump,
+        constraint = lockup_renewal.data_is_empty() @ CustomError::LockupRenewalActive
+    )]
+    pub lockup_renewal: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = delegated_account,
+        associated_token::token_program = token_program,
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    // Here, if there's no related ata account, the contract automatically creates or updates the account to accept tokens. The address of the ata account is easy to derive using @solana/spl-token's getAssociatedTokenAddress
+    // The ATA program accepts off-curve authorities, so PDA owners get a receipt account too
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = mint,
+        associated_token::authority = owner,
+        associated_token::token_program = token_program,
+    )]
+    pub receipt_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        address = info_account.mint @ CustomError::InvalidMint
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    // Liquid receipt token accounts, required while config.receipt_tokens is on
+    #[account(mut)]
+    pub receipt_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(mut)]
+    pub receipt_holder_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    // Treasury PDA and its token account, only required while the matching fee is switched on
+    /// CHECK: data-less PDA that owns the treasury token accounts
+    #[account(seeds = [TREASURY_SEED], bump)]
+    pub treasury: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = treasury,
+        associated_token::token_program = token_program,
+    )]
+    pub treasury_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    // Owner may be a wallet or a program PDA signing through CPI (invoke_signed)
+    pub owner: Signer<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitDelegationTree<'info> {
+    #[account(
+        mut,
+        has_one = owner,
+        seeds = [
+            INFO_SEED,
+            info_account.seed_owner().as_ref(),
+            info_account.serverkey_hash.as_ref(),
+        ],
+        bump = info_account.bump
+    )]
+    pub info_account: Account<'info, InfoAccount>,
+
+    /// CHECK: data-less PDA that owns the delegation tree and the pooled vault
+    #[account(seeds = [TREE_SEED, info_account.key().as_ref()], bump)]
+    pub tree_authority: UncheckedAccount<'info>,
+
+    /// CHECK: allocated by the client, initialized by the compression program
+    #[account(mut)]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    pub owner: Signer<'info>,
+    pub compression_program: Program<'info, SplAccountCompression>,
+    pub noop_program: Program<'info, Noop>,
+}
+
+#[derive(Accounts)]
+pub struct CompressedDelegatedDeposit<'info> {
+    #[account(mut, seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    // Required when the config has a gate program set, see `check_gate`
+    /// CHECK: owner and address are verified against the configured gate program
+    pub gate_account: Option<UncheckedAccount<'info>>,
+
+    // Required when the config sets a USD minimum, see `stake_minimum`
+    /// CHECK: Pyth PriceUpdateV2, owner and contents are verified in staking_token_price
+    pub price_update: Option<UncheckedAccount<'info>>,
+
+    #[account(mut)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    /// CHECK: data-less PDA that owns the delegation tree and the pooled vault
+    #[account(seeds = [TREE_SEED, info_account.key().as_ref()], bump)]
+    pub tree_authority: UncheckedAccount<'info>,
+
+    /// CHECK: validated by the compression program
+    #[account(mut, address = info_account.delegation_tree @ CustomError::InvalidDelegationTree)]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    /// CHECK: ATA of tree_authority for mint, created or checked in open_vault
+    #[account(mut)]
+    pub pool_vault: UncheckedAccount<'info>,
+
+    #[account(
+        address = info_account.mint @ CustomError::InvalidMint
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = sender_token_account.mint == mint.key() @ CustomError::InvalidMint,
+    )]
+    pub sender_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub owner: Signer<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub compression_program: Program<'info, SplAccountCompression>,
+    pub noop_program: Program<'info, Noop>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CompressedDelegatedWithdraw<'info> {
+    #[account(mut, seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(mut)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    /// CHECK: data-less PDA that owns the delegation tree and the pooled vault
+    #[account(seeds = [TREE_SEED, info_account.key().as_ref()], bump)]
+    pub tree_authority: UncheckedAccount<'info>,
+
+    /// CHECK: validated by the compression program
+    #[account(mut, address = info_account.delegation_tree @ CustomError::InvalidDelegationTree)]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = tree_authority,
+        associated_token::token_program = token_program,
+    )]
+    pub pool_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = mint,
+        associated_token::authority = owner,
+        associated_token::token_program = token_program,
+    )]
+    pub receipt_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        address = info_account.mint @ CustomError::InvalidMint
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    // Treasury PDA and its token account, only required while the matching fee is switched on
+    /// CHECK: data-less PDA that owns the treasury token accounts
+    #[account(seeds = [TREASURY_SEED], bump)]
+    pub treasury: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = treasury,
+        associated_token::token_program = token_program,
+    )]
+    pub treasury_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub owner: Signer<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub compression_program: Program<'info, SplAccountCompression>,
+    pub noop_program: Program<'info, Noop>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimCompressedRewards<'info> {
+    #[account(seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(mut)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    /// CHECK: data-less PDA that owns the delegation tree and the pooled vault
+    #[account(seeds = [TREE_SEED, info_account.key().as_ref()], bump)]
+    pub tree_authority: UncheckedAccount<'info>,
+
+    /// CHECK: validated by the compression program
+    #[account(mut, address = info_account.delegation_tree @ CustomError::InvalidDelegationTree)]
+    pub merkle_tree: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = main_account,
+        associated_token::token_program = token_program,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = mint,
+        associated_token::authority = owner,
+        associated_token::token_program = token_program,
+    )]
+    pub receipt_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        address = info_account.mint @ CustomError::InvalidMint
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    // Treasury PDA and its token account, only required while the matching fee is switched on
+    /// CHECK: data-less PDA that owns the treasury token accounts
+    #[account(seeds = [TREASURY_SEED], bump)]
+    pub treasury: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = treasury,
+        associated_token::token_program = token_program,
+    )]
+    pub treasury_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub owner: Signer<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub compression_program: Program<'info, SplAccountCompression>,
+    pub noop_program: Program<'info, Noop>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct RemoveDelegatedAccount<'info> {
+    #[account(mut)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+    #[account(mut)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        mut,
+        close = owner,
+        has_one = owner,
+        constraint = delegated_account.stake == 0 @ CustomError::NonZeroBalance,  // Can only close account when stake is 0
+        constraint = delegated_account.activating_stake == 0 @ CustomError::NonZeroBalance,
+        // Unclaimed rewards are paid from the reward vault to this position, claim them first
+        constraint = delegated_account.pending_rewards == 0 @ CustomError::NonZeroBalance,
+        constraint = !info_account.lists(&delegated_account.key(), &delegated_account)
+            @ CustomError::DelegationLinked,
+        seeds = [
+            INFO_SEED,        // seed prefix
+            owner.key().as_ref(), // Use caller's public key as seed
+            info_account.key().as_ref(),
+        ],
+        bump = delegated_account.bump
+    )]
+    pub delegated_account: Account<'info, DelegatedAccount>,
+
+    // Tokens sent to the vault outside of d_deposit are not part of the stake, see sweep_excess
+    #[account(
+        mut,
+        constraint = vault.amount == 0 @ CustomError::VaultNotEmpty,
+        associated_token::mint = mint,
+        associated_token::authority = delegated_account,
+        associated_token::token_program = token_program,
+    )]
+    pub vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        address = info_account.mint @ CustomError::InvalidMint
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+    // Rent from the closed accounts is credited back to the owner, which works for PDA owners as well
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct ForceReturnDelegation<'info> {
+    #[account(mut, seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(mut, has_one = owner)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        mut,
+        close = delegation_owner,
+        seeds = [
+            INFO_SEED,
+            delegation_owner.key().as_ref(),
+            info_account.key().as_ref(),
+        ],
+        bump = delegated_account.bump
+    )]
+    pub delegated_account: Account<'info, DelegatedAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = delegated_account,
+        associated_token::token_program = token_program,
+    )]
+    pub vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = main_account,
+        associated_token::token_program = token_program,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: the delegator, receives the stake, the rewards and the rent
+    #[account(mut, address = delegated_account.owner @ CustomError::InvalidAccountData)]
+    pub delegation_owner: UncheckedAccount<'info>,
+
+    // The operator pays for the delegator's token account when it doesn't exist
+    #[account(
+        init_if_needed,
+        payer = owner,
+        associated_token::mint = mint,
+        associated_token::authority = delegation_owner,
+        associated_token::token_program = token_program,
+    )]
+    pub delegation_owner_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        address = info_account.mint @ CustomError::InvalidMint
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>, // Server owner
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+// ForceReturnDelegation without the server owner, checked against the exit notice instead
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct ReturnDelegation<'info> {
+    #[account(mut, seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(mut)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        mut,
+        close = delegation_owner,
+        seeds = [
+            INFO_SEED,
+            delegation_owner.key().as_ref(),
+            info_account.key().as_ref(),
+        ],
+        bump = delegated_account.bump
+    )]
+    pub delegated_account: Account<'info, DelegatedAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = delegated_account,
+        associated_token::token_program = token_program,
+    )]
+    pub vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = main_account,
+        associated_token::token_program = token_program,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: the delegator, receives the stake, the rewards and the rent
+    #[account(mut, address = delegated_account.owner @ CustomError::InvalidAccountData)]
+    pub delegation_owner: UncheckedAccount<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = mint,
+        associated_token::authority = delegation_owner,
+        associated_token::token_program = token_program,
+    )]
+    pub delegation_owner_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        address = info_account.mint @ CustomError::InvalidMint
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>, // Anyone, e.g. the leaving operator or a keeper
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct GcAccount<'info> {
+    #[account(mut)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(mut)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    // Collects this delegation of `info_account` instead of the server
+    #[account(
+        mut,
+        constraint = delegated_account.delegator == info_account.key()
+            @ CustomError::InvalidAccountData
+    )]
+    pub delegated_account: Option<Account<'info, DelegatedAccount>>,
+
+    /// CHECK: the ATA of the collected account, checked and closed in collect_rent when it exists
+    #[account(mut)]
+    pub vault: UncheckedAccount<'info>,
+
+    /// CHECK: receives the remaining rent, checked against the owner of the collected account
+    #[account(mut)]
+    pub owner: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub caller: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[cfg_attr(feature = "event-cpi", event_cpi)]
+#[derive(Accounts)]
+pub struct RecoverDelegation<'info> {
+    #[account(mut)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    /// CHECK: the server the delegation points to, only used for the seeds and the orphan check
+    #[account(address = delegated_account.delegator @ CustomError::InvalidAccountData)]
+    pub info_account: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        close = owner,
+        has_one = owner,
+        has_one = mint @ CustomError::InvalidMint,
+        seeds = [
+            INFO_SEED,
+            owner.key().as_ref(),
+            info_account.key().as_ref(),
+        ],
+        bump = delegated_account.bump
+    )]
+    pub delegated_account: Account<'info, DelegatedAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = delegated_account,
+        associated_token::token_program = token_program,
+    )]
+    pub vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        associated_token::mint = mint,
+        associated_token::authority = owner,
+        associated_token::token_program = token_program,
+    )]
+    pub receipt_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    // Liquid receipt token accounts, required while the position has receipts outstanding
+    #[account(mut)]
+    pub receipt_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(mut)]
+    pub receipt_holder_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + ConfigAccount::INIT_SPACE,
+        seeds = [CONFIG_SEED],
+        bump
+    )]
+    pub config: Account<'info, ConfigAccount>,
+
+    // Only the program's upgrade authority can create the config and become its admin
+    #[account(
+        constraint = program.programdata_address()? == Some(program_data.key())
+            @ CustomError::Unauthorized
+    )]
+    pub program: Program<'info, crate::program::StakingContract>,
+    #[account(
+        constraint = program_data.upgrade_authority_address == Some(admin.key())
+            @ CustomError::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    #[account(
+        mut,
+        has_one = admin @ CustomError::Unauthorized,
+        seeds = [CONFIG_SEED],
+        bump
+    )]
+    pub config: Account<'info, ConfigAccount>,
+
+    /// CHECK: only its emptiness is read, the staking mint is fixed once it exists
+    #[account(seeds = [MAIN_SEED], bump)]
+    pub main_account: UncheckedAccount<'info>,
+
+    // Required once the audit log is initialized, see record_audit
+    #[account(mut, seeds = [AUDIT_LOG_SEED], bump)]
+    pub audit_log: Option<AccountLoader<'info, AuditLog>>,
+
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeAuditLog<'info> {
+    #[account(
+        mut,
+        has_one = admin @ CustomError::Unauthorized,
+        seeds = [CONFIG_SEED],
+        bump
+    )]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + std::mem::size_of::<AuditLog>(),
+        seeds = [AUDIT_LOG_SEED],
+        bump
+    )]
+    pub audit_log: AccountLoader<'info, AuditLog>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DepositLst<'info> {
+    #[account(mut, seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(
+        mut,
+        has_one = owner,
+    )]
+    pub info_account: Account<'info, InfoAccount>,
+
+    // Separate vault per LST mint, next to the server's stake vault
+    #[account(
+        init_if_needed,
+        payer = owner,
+        associated_token::mint = mint,
+        associated_token::authority = info_account,
+        associated_token::token_program = token_program,
+    )]
+    pub lst_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        constraint = main_account.load()?.mint_class(&mint.key()) == Some(CollateralClass::LiquidStaking) @ CustomError::InvalidMint
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = sender_token_account.mint == mint.key() @ CustomError::InvalidMint,
+    )]
+    pub sender_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawLst<'info> {
+    #[account(mut, seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(
+        mut,
+        has_one = owner,
+        seeds = [
+            INFO_SEED,
+            info_account.seed_owner().as_ref(),
+            info_account.serverkey_hash.as_ref(),
+        ],
+        bump = info_account.bump
+    )]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = info_account,
+        associated_token::token_program = token_program,
+    )]
+    pub lst_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        associated_token::mint = mint,
+        associated_token::authority = owner,
+        associated_token::token_program = token_program,
+    )]
+    pub receipt_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = info_account.restaked_mint @ CustomError::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DepositBond<'info> {
+    #[account(mut, seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(
+        mut,
+        has_one = owner,
+    )]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        associated_token::mint = mint,
+        associated_token::authority = info_account,
+        associated_token::token_program = token_program,
+    )]
+    pub bond_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = config.bond_mint @ CustomError::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = sender_token_account.mint == mint.key() @ CustomError::InvalidMint,
+    )]
+    pub sender_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawBond<'info> {
+    #[account(mut, seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(
+        mut,
+        has_one = owner,
+        seeds = [
+            INFO_SEED,
+            info_account.seed_owner().as_ref(),
+            info_account.serverkey_hash.as_ref(),
+        ],
+        bump = info_account.bump
+    )]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = info_account,
+        associated_token::token_program = token_program,
+    )]
+    pub bond_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        associated_token::mint = mint,
+        associated_token::authority = owner,
+        associated_token::token_program = token_program,
+    )]
+    pub receipt_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = config.bond_mint @ CustomError::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ManageLookupTable<'info> {
+    #[account(
+        has_one = admin @ CustomError::Unauthorized,
+        seeds = [CONFIG_SEED],
+        bump
+    )]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    /// CHECK: created and validated by the address lookup table program
+    #[account(mut)]
+    pub lookup_table: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    /// CHECK: fixed program address
+    #[account(address = address_lookup_table::program::ID)]
+    pub address_lookup_table_program: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(epoch: u64)]
+pub struct TakeEpochSnapshot<'info> {
+    #[account(seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + EpochSnapshot::INIT_SPACE,
+        seeds = [SNAPSHOT_SEED, epoch.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub snapshot: Account<'info, EpochSnapshot>,
+
+    // Snapshot of epoch - 1, to emit the EpochSummary. Left out when that epoch has none, or one
+    // taken before the snapshots carried the lifetime counters.
+    #[account(
+        seeds = [SNAPSHOT_SEED, epoch.saturating_sub(1).to_le_bytes().as_ref()],
+        bump = previous_snapshot.bump
+    )]
+    pub previous_snapshot: Option<Account<'info, EpochSnapshot>>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(day: u32)]
+pub struct UpdateDailyStats<'info> {
+    #[account(seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = DAILY_STATS_SPACE,
+        seeds = [DAILY_STATS_SEED, day.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub daily_stats: Account<'info, DailyStats>,
+
+    // Only read by the call opening the day, stake_delta is 0 without it
+    #[account(
+        seeds = [DAILY_STATS_SEED, day.saturating_sub(1).to_le_bytes().as_ref()],
+        bump = previous_day.bump
+    )]
+    pub previous_day: Option<Account<'info, DailyStats>>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ArchiveSnapshots<'info> {
+    #[account(
+        has_one = admin @ CustomError::Unauthorized,
+        seeds = [CONFIG_SEED],
+        bump
+    )]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = admin,
+        space = SNAPSHOT_ARCHIVE_SPACE,
+        seeds = [ARCHIVE_SEED],
+        bump
+    )]
+    pub archive: Account<'info, SnapshotArchive>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ResizeAccount<'info> {
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    /// CHECK: program ownership, discriminator and owner field are verified in resize_account
+    #[account(mut)]
+    pub account: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateAccount<'info> {
+    /// CHECK: ownership, discriminator, layout and address are verified by the migration
+    #[account(mut)]
+    pub account: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateInfoAccount<'info> {
+    #[account(mut, seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    /// CHECK: ownership, discriminator, layout and address are verified by the migration
+    #[account(mut)]
+    pub account: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateDelegatedAccount<'info> {
+    pub info_account: Account<'info, InfoAccount>,
+
+    /// CHECK: ownership, discriminator, layout and address are verified by the migration
+    #[account(mut)]
+    pub account: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateFromV0<'info> {
+    #[account(mut)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    // Required when the config sets a USD minimum, see `stake_minimum`
+    /// CHECK: Pyth PriceUpdateV2, owner and contents are verified in staking_token_price
+    pub price_update: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: the previous deployment, as configured
+    #[account(
+        executable,
+        constraint = config.legacy_program != Pubkey::default()
+            && legacy_program.key() == config.legacy_program @ CustomError::InvalidLegacyProgram
+    )]
+    pub legacy_program: UncheckedAccount<'info>,
+
+    /// CHECK: v1 server account of the legacy program, owner, layout and address are verified
+    /// in the handler
+    pub legacy_server: UncheckedAccount<'info>,
+
+    /// CHECK: v1 delegation of the legacy program, owner, layout and address are verified in
+    /// the handler
+    #[account(mut)]
+    pub legacy_position: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = DELEGATED_ACCOUNT_SPACE,
+        seeds = [INFO_SEED, owner.key().as_ref(), info_account.key().as_ref()],
+        bump
+    )]
+    pub delegated_account: Account<'info, DelegatedAccount>,
+
+    /// CHECK: ATA of delegated_account for mint, created or checked in open_vault
+    #[account(mut)]
+    pub vault: UncheckedAccount<'info>,
+
+    // v1 only staked the specified mint
+    #[account(
+        address = SPECIFIED_MINT @ CustomError::InvalidMint,
+        constraint = info_account.mint == mint.key() @ CustomError::InvalidMint
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    // Receives the legacy withdrawal and funds the new position
+    #[account(
+        mut,
+        constraint = owner_token_account.mint == mint.key() @ CustomError::InvalidMint,
+        constraint = owner_token_account.owner == owner.key() @ CustomError::Unauthorized,
+    )]
+    pub owner_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub owner: Signer<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(partner_program: Pubkey)]
+pub struct SetPartnerAdapter<'info> {
+    #[account(
+        has_one = admin @ CustomError::Unauthorized,
+        seeds = [CONFIG_SEED],
+        bump
+    )]
+    pub config: Account<'info, ConfigAccount>,
+
+    // Required once the audit log is initialized, see record_audit
+    #[account(mut, seeds = [AUDIT_LOG_SEED], bump)]
+    pub audit_log: Option<AccountLoader<'info, AuditLog>>,
+
+    #[account(seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = admin,
+        space = PARTNER_ADAPTER_SPACE,
+        seeds = [PARTNER_ADAPTER_SEED, partner_program.as_ref()],
+        bump
+    )]
+    pub partner_adapter: Account<'info, PartnerAdapter>,
+
+    /// CHECK: ATA of partner_adapter for mint, created or checked in open_vault
+    #[account(mut)]
+    pub adapter_vault: UncheckedAccount<'info>,
+
+    // Credits are delegations, so only primary collateral qualifies
+    #[account(
+        constraint = main_account.load()?.mint_class(&mint.key()) == Some(CollateralClass::Primary) @ CustomError::InvalidMint
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ImportPartnerPosition<'info> {
+    #[account(mut)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    // Required when the config sets a USD minimum, see `stake_minimum`
+    /// CHECK: Pyth PriceUpdateV2, owner and contents are verified in staking_token_price
+    pub price_update: Option<UncheckedAccount<'info>>,
+
+    #[account(
+        mut,
+        has_one = mint,
+        seeds = [PARTNER_ADAPTER_SEED, partner_adapter.partner_program.as_ref()],
+        bump = partner_adapter.bump
+    )]
+    pub partner_adapter: Account<'info, PartnerAdapter>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = partner_adapter,
+        associated_token::token_program = token_program,
+    )]
+    pub adapter_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: position account of the partner program, owner and layout are verified against
+    /// the adapter in the handler
+    pub partner_position: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = PARTNER_IMPORT_SPACE,
+        seeds = [PARTNER_IMPORT_SEED, partner_position.key().as_ref()],
+        bump
+    )]
+    pub partner_import: Account<'info, PartnerImport>,
+
+    #[account(mut, constraint = info_account.mint == mint.key() @ CustomError::InvalidMint)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = DELEGATED_ACCOUNT_SPACE,
+        seeds = [INFO_SEED, owner.key().as_ref(), info_account.key().as_ref()],
+        bump
+    )]
+    pub delegated_account: Account<'info, DelegatedAccount>,
+
+    /// CHECK: ATA of delegated_account for mint, created or checked in open_vault
+    #[account(mut)]
+    pub vault: UncheckedAccount<'info>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    pub owner: Signer<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimTenureCredit<'info> {
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = TENURE_CREDIT_SPACE,
+        seeds = [TENURE_SEED, owner.key().as_ref()],
+        bump
+    )]
+    pub tenure_credit: Account<'info, TenureCredit>,
+
+    #[account(
+        constraint = mint.key() == config.staking_mint() @ CustomError::InvalidMint,
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    /// CHECK: data-less PDA that owns the treasury token accounts
+    #[account(seeds = [TREASURY_SEED], bump)]
+    pub treasury: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = treasury,
+        associated_token::token_program = token_program,
+    )]
+    pub treasury_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        associated_token::mint = mint,
+        associated_token::authority = owner,
+        associated_token::token_program = token_program,
+    )]
+    pub owner_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct TreasuryTransfer<'info> {
+    #[account(
+        has_one = admin @ CustomError::Unauthorized,
+        seeds = [CONFIG_SEED],
+        bump
+    )]
+    pub config: Account<'info, ConfigAccount>,
+
+    // Required once the audit log is initialized, see record_audit
+    #[account(mut, seeds = [AUDIT_LOG_SEED], bump)]
+    pub audit_log: Option<AccountLoader<'info, AuditLog>>,
+
+    /// CHECK: data-less PDA that owns the treasury token accounts
+    #[account(seeds = [TREASURY_SEED], bump)]
+    pub treasury: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = treasury,
+        associated_token::token_program = token_program,
+    )]
+    pub treasury_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = destination_token_account.mint == mint.key() @ CustomError::InvalidMint,
+    )]
+    pub destination_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    pub admin: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct TreasurySweepDust<'info> {
+    #[account(seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(mut)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = main_account,
+        associated_token::token_program = token_program,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        address = info_account.mint @ CustomError::InvalidMint
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    /// CHECK: data-less PDA that owns the treasury token accounts
+    #[account(seeds = [TREASURY_SEED], bump)]
+    pub treasury: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = treasury,
+        associated_token::token_program = token_program,
+    )]
+    pub treasury_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct SweepExcess<'info> {
+    #[account(seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(mut)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    // Sweeps this delegation's vault instead of the server's
+    #[account(
+        constraint = delegated_account.delegator == info_account.key()
+            @ CustomError::InvalidAccountData
+    )]
+    pub delegated_account: Option<Account<'info, DelegatedAccount>>,
+
+    // Checked in the handler to be the ATA of the server or of the delegation
+    #[account(mut)]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    // Required while the server has delegated stake
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = main_account,
+        associated_token::token_program = token_program,
+    )]
+    pub reward_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    // Required while the server has no delegated stake
+    #[account(
+        mut,
+        constraint = owner_token_account.owner == info_account.owner @ CustomError::Unauthorized,
+        constraint = owner_token_account.mint == mint.key() @ CustomError::InvalidMint,
+    )]
+    pub owner_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        address = info_account.mint @ CustomError::InvalidMint
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeReceiptMint<'info> {
+    #[account(
+        has_one = admin @ CustomError::Unauthorized,
+        seeds = [CONFIG_SEED],
+        bump
+    )]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(
+        constraint = main_account.load()?.mint_class(&mint.key()) == Some(CollateralClass::Primary) @ CustomError::InvalidMint
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    // Same decimals as the collateral so receipts map 1:1 to base units
+    #[account(
+        init,
+        payer = admin,
+        seeds = [RECEIPT_SEED, mint.key().as_ref()],
+        bump,
+        mint::decimals = mint.decimals,
+        mint::authority = receipt_mint,
+        mint::token_program = token_program,
+    )]
+    pub receipt_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetReceiptMetadata<'info> {
+    #[account(
+        has_one = admin @ CustomError::Unauthorized,
+        seeds = [CONFIG_SEED],
+        bump
+    )]
+    pub config: Account<'info, ConfigAccount>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        seeds = [RECEIPT_SEED, mint.key().as_ref()],
+        bump
+    )]
+    pub receipt_mint: InterfaceAccount<'info, Mint>,
+
+    /// CHECK: Metadata PDA of the receipt mint, created and validated by the Token Metadata program
+    #[account(
+        mut,
+        seeds = [b"metadata", token_metadata_program.key().as_ref(), receipt_mint.key().as_ref()],
+        bump,
+        seeds::program = token_metadata_program.key()
+    )]
+    pub metadata: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub token_metadata_program: Program<'info, Metadata>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct ApproveMint<'info> {
+    #[account(
+        has_one = admin @ CustomError::Unauthorized,
+        seeds = [CONFIG_SEED],
+        bump
+    )]
+    pub config: Account<'info, ConfigAccount>,
+
+    // Required once the audit log is initialized, see record_audit
+    #[account(mut, seeds = [AUDIT_LOG_SEED], bump)]
+    pub audit_log: Option<AccountLoader<'info, AuditLog>>,
+
+    #[account(mut, seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateExchangeRate<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump,
+        constraint = config.rate_oracle == oracle.key() @ CustomError::Unauthorized
+    )]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(mut, seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    pub oracle: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(staker: Pubkey)]
+pub struct AddToAllowlist<'info> {
+    #[account(
+        has_one = admin @ CustomError::Unauthorized,
+        seeds = [CONFIG_SEED],
+        bump
+    )]
+    pub config: Account<'info, ConfigAccount>,
+
+    // Required once the audit log is initialized, see record_audit
+    #[account(mut, seeds = [AUDIT_LOG_SEED], bump)]
+    pub audit_log: Option<AccountLoader<'info, AuditLog>>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + AllowlistEntry::INIT_SPACE,
+        seeds = [ALLOWLIST_SEED, staker.as_ref()],
+        bump
+    )]
+    pub allowlist_entry: Account<'info, AllowlistEntry>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveFromAllowlist<'info> {
+    #[account(
+        has_one = admin @ CustomError::Unauthorized,
+        seeds = [CONFIG_SEED],
+        bump
+    )]
+    pub config: Account<'info, ConfigAccount>,
+
+    // Required once the audit log is initialized, see record_audit
+    #[account(mut, seeds = [AUDIT_LOG_SEED], bump)]
+    pub audit_log: Option<AccountLoader<'info, AuditLog>>,
+
+    #[account(
+        mut,
+        close = admin,
+        seeds = [ALLOWLIST_SEED, allowlist_entry.staker.as_ref()],
+        bump
+    )]
+    pub allowlist_entry: Account<'info, AllowlistEntry>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct FundRewards<'info> {
+    #[account(seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(mut)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    // Reward pool shared by all servers, owned by the main PDA
+    #[account(
+        init_if_needed,
+        payer = funder,
+        associated_token::mint = mint,
+        associated_token::authority = main_account,
+        associated_token::token_program = token_program,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        address = info_account.mint @ CustomError::InvalidMint
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = sender_token_account.mint == mint.key() @ CustomError::InvalidMint,
+    )]
+    pub sender_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub funder: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimRewards<'info> {
+    #[account(seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        mut,
+        has_one = owner,
+        seeds = [
+            INFO_SEED,
+            owner.key().as_ref(),
+            info_account.key().as_ref(),
+        ],
+        bump = delegated_account.bump
+    )]
+    pub delegated_account: Account<'info, DelegatedAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = main_account,
+        associated_token::token_program = token_program,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = mint,
+        associated_token::authority = owner,
+        associated_token::token_program = token_program,
+    )]
+    pub receipt_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        address = info_account.mint @ CustomError::InvalidMint
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    // Treasury PDA and its token account, only required while the matching fee is switched on
+    /// CHECK: data-less PDA that owns the treasury token accounts
+    #[account(seeds = [TREASURY_SEED], bump)]
+    pub treasury: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = treasury,
+        associated_token::token_program = token_program,
+    )]
+    pub treasury_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub owner: Signer<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateDelegation<'info> {
+    #[account(
+        mut,
+        has_one = owner,
+    )]
+    pub delegated_account: Account<'info, DelegatedAccount>,
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct EnableLockupRenewal<'info> {
+    #[account(has_one = owner)]
+    pub delegated_account: Account<'info, DelegatedAccount>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = LOCKUP_RENEWAL_SPACE,
+        seeds = [RENEWAL_SEED, delegated_account.key().as_ref()],
+        bump
+    )]
+    pub lockup_renewal: Account<'info, LockupRenewal>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DisableLockupRenewal<'info> {
+    #[account(mut, address = delegated_account.delegator @ CustomError::InvalidAccountData)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(mut, has_one = owner)]
+    pub delegated_account: Account<'info, DelegatedAccount>,
+
+    #[account(
+        mut,
+        close = owner,
+        seeds = [RENEWAL_SEED, delegated_account.key().as_ref()],
+        bump = lockup_renewal.bump
+    )]
+    pub lockup_renewal: Account<'info, LockupRenewal>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RenewLockup<'info> {
+    #[account(mut, address = delegated_account.delegator @ CustomError::InvalidAccountData)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(mut)]
+    pub delegated_account: Account<'info, DelegatedAccount>,
+
+    #[account(
+        mut,
+        seeds = [RENEWAL_SEED, delegated_account.key().as_ref()],
+        bump = lockup_renewal.bump
+    )]
+    pub lockup_renewal: Account<'info, LockupRenewal>,
+}
+
+#[derive(Accounts)]
+pub struct SettleLockup<'info> {
+    #[account(mut, seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(mut, address = delegated_account.delegator @ CustomError::InvalidAccountData)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(mut)]
+    pub delegated_account: Account<'info, DelegatedAccount>,
+
+    // Must be empty, a lock with renewal on rolls over instead of running out
+    /// CHECK: only its emptiness is read
+    #[account(
+        seeds = [RENEWAL_SEED, delegated_account.key().as_ref()],
+        bump,
+        constraint = lockup_renewal.data_is_empty() @ CustomError::LockupRenewalActive
+    )]
+    pub lockup_renewal: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct LinkDelegation<'info> {
+    #[account(mut, address = delegated_account.delegator @ CustomError::InvalidAccountData)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(mut)]
+    pub delegated_account: Account<'info, DelegatedAccount>,
+
+    #[account(mut)]
+    pub tail: Option<Account<'info, DelegatedAccount>>,
+}
+
+#[derive(Accounts)]
+pub struct UnlinkDelegation<'info> {
+    #[account(mut, address = delegated_account.delegator @ CustomError::InvalidAccountData)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(mut)]
+    pub delegated_account: Account<'info, DelegatedAccount>,
+
+    #[account(mut)]
+    pub prev: Option<Account<'info, DelegatedAccount>>,
+    #[account(mut)]
+    pub next: Option<Account<'info, DelegatedAccount>>,
+
+    pub caller: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct GetNetworkStats<'info> {
+    #[account(seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+}
+
+#[derive(Accounts)]
+pub struct GetServerInfo<'info> {
+    #[account(seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    pub info_account: Account<'info, InfoAccount>,
+
+    // Absent once the owner closed the emptied vault
+    #[account(
+        associated_token::mint = mint,
+        associated_token::authority = info_account,
+        associated_token::token_program = token_program,
+    )]
+    pub vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(address = info_account.mint @ CustomError::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        seeds = [
+            INFO_SEED,
+            delegated_account.owner.as_ref(),
+            info_account.key().as_ref(),
+        ],
+        bump = delegated_account.bump
+    )]
+    pub delegated_account: Option<Account<'info, DelegatedAccount>>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(owner: Pubkey)]
+pub struct GetStakeOf<'info> {
+    pub info_account: Account<'info, InfoAccount>,
+
+    // The owner's position in the server, left out for the server owner or when there is none
+    #[account(
+        seeds = [
+            INFO_SEED,
+            owner.as_ref(),
+            info_account.key().as_ref(),
+        ],
+        bump = delegated_account.bump
+    )]
+    pub delegated_account: Option<Account<'info, DelegatedAccount>>,
+}
+
+#[derive(Accounts)]
+pub struct ValidateDeposit<'info> {
+    #[account(seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    /// CHECK: verified by check_gate like in deposit
+    pub gate_account: Option<UncheckedAccount<'info>>,
+
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(address = info_account.mint @ CustomError::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    pub sender_token_account: InterfaceAccount<'info, TokenAccount>,
+}
+
+#[derive(Accounts)]
+#[instruction(owner: Pubkey)]
+pub struct ValidateDelegatedDeposit<'info> {
+    #[account(seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    /// CHECK: verified by check_gate like in d_deposit
+    pub gate_account: Option<UncheckedAccount<'info>>,
+
+    // Required when the config sets a USD minimum, see `stake_minimum`
+    /// CHECK: Pyth PriceUpdateV2, owner and contents are verified in staking_token_price
+    pub price_update: Option<UncheckedAccount<'info>>,
+
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        seeds = [
+            INFO_SEED,
+            owner.as_ref(),
+            info_account.key().as_ref(),
+        ],
+        bump = delegated_account.bump
+    )]
+    pub delegated_account: Option<Account<'info, DelegatedAccount>>,
+
+    #[account(address = info_account.mint @ CustomError::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    pub sender_token_account: InterfaceAccount<'info, TokenAccount>,
+}
+
+#[derive(Accounts)]
+#[instruction(owner: Pubkey)]
+pub struct GetQuote<'info> {
+    #[account(seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    // Required for delegation deposits when the config sets a USD minimum, see `stake_minimum`
+    /// CHECK: Pyth PriceUpdateV2, owner and contents are verified in staking_token_price
+    pub price_update: Option<UncheckedAccount<'info>>,
+
+    pub info_account: Account<'info, InfoAccount>,
+
+    // The owner's position in the server, left out for the server owner or before the first
+    // delegation
+    #[account(
+        seeds = [
+            INFO_SEED,
+            owner.as_ref(),
+            info_account.key().as_ref(),
+        ],
+        bump = delegated_account.bump
+    )]
+    pub delegated_account: Option<Account<'info, DelegatedAccount>>,
+
+    #[account(address = info_account.mint @ CustomError::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+}
+
+#[derive(Accounts)]
+#[instruction(owner: Pubkey)]
+pub struct Diagnose<'info> {
+    #[account(seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    /// CHECK: verified by check_gate like in deposit and d_deposit
+    pub gate_account: Option<UncheckedAccount<'info>>,
+
+    // Required for delegation deposits when the config sets a USD minimum, see `stake_minimum`
+    /// CHECK: Pyth PriceUpdateV2, owner and contents are verified in staking_token_price
+    pub price_update: Option<UncheckedAccount<'info>>,
+
+    pub info_account: Account<'info, InfoAccount>,
+
+    // The owner's position in the server, left out for the server owner or before the first
+    // delegation
+    #[account(
+        seeds = [
+            INFO_SEED,
+            owner.as_ref(),
+            info_account.key().as_ref(),
+        ],
+        bump = delegated_account.bump
+    )]
+    pub delegated_account: Option<Account<'info, DelegatedAccount>>,
+
+    #[account(address = info_account.mint @ CustomError::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    pub sender_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+}
+
+#[derive(Accounts)]
+pub struct Reconcile<'info> {
+    #[account(mut, seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(mut)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        associated_token::mint = mint,
+        associated_token::authority = info_account,
+        associated_token::token_program = token_program,
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = info_account.mint @ CustomError::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct RecountTotals<'info> {
+    #[account(
+        has_one = admin @ CustomError::Unauthorized,
+        seeds = [CONFIG_SEED],
+        bump
+    )]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = admin,
+        space = RECOUNT_SPACE,
+        seeds = [RECOUNT_SEED],
+        bump
+    )]
+    pub recount: Account<'info, Recount>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct FinishRecount<'info> {
+    #[account(
+        has_one = admin @ CustomError::Unauthorized,
+        seeds = [CONFIG_SEED],
+        bump
+    )]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(mut, seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(mut, close = admin, seeds = [RECOUNT_SEED], bump = recount.bump)]
+    pub recount: Account<'info, Recount>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct FlagServer<'info> {
+    #[account(mut)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        mut,
+        has_one = owner,
+        seeds = [
+            INFO_SEED,
+            owner.key().as_ref(),
+            info_account.key().as_ref(),
+        ],
+        bump = delegated_account.bump
+    )]
+    pub delegated_account: Account<'info, DelegatedAccount>,
+
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CrankRewards<'info> {
+    #[account(mut, seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(mut)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        mut,
+        seeds = [
+            INFO_SEED,
+            delegated_account.owner.as_ref(),
+            info_account.key().as_ref(),
+        ],
+        bump = delegated_account.bump
+    )]
+    pub delegated_account: Account<'info, DelegatedAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = delegated_account,
+        associated_token::token_program = token_program,
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = main_account,
+        associated_token::token_program = token_program,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        address = info_account.mint @ CustomError::InvalidMint
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimReferralRewards<'info> {
+    #[account(seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        mut,
+        has_one = referrer @ CustomError::InvalidReferrer,
+        seeds = [
+            INFO_SEED,
+            delegated_account.owner.as_ref(),
+            info_account.key().as_ref(),
+        ],
+        bump = delegated_account.bump
+    )]
+    pub delegated_account: Account<'info, DelegatedAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = main_account,
+        associated_token::token_program = token_program,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = referrer,
+        associated_token::mint = mint,
+        associated_token::authority = referrer,
+        associated_token::token_program = token_program,
+    )]
+    pub receipt_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        address = info_account.mint @ CustomError::InvalidMint
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    // Treasury PDA and its token account, only required while the matching fee is switched on
+    /// CHECK: data-less PDA that owns the treasury token accounts
+    #[account(seeds = [TREASURY_SEED], bump)]
+    pub treasury: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = treasury,
+        associated_token::token_program = token_program,
+    )]
+    pub treasury_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub referrer: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(epoch: u64)]
+pub struct ReportUsage<'info> {
+    #[account(seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump,
+        constraint = config.usage_oracle == oracle.key() @ CustomError::Unauthorized
+    )]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(mut)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        init,
+        payer = oracle,
+        space = SERVER_USAGE_SPACE,
+        seeds = [USAGE_SEED, info_account.key().as_ref(), epoch.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub usage: Account<'info, ServerUsage>,
+
+    #[account(mut)]
+    pub oracle: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(epoch: u64)]
+pub struct SubmitUsageProof<'info> {
+    #[account(seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(mut, has_one = owner)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = SERVER_USAGE_SPACE,
+        seeds = [USAGE_SEED, info_account.key().as_ref(), epoch.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub usage: Account<'info, ServerUsage>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    /// CHECK: instructions sysvar, read for the Ed25519 signature check
+    #[account(address = solana_program::sysvar::instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(epoch: u64)]
+pub struct SelectActiveSet<'info> {
+    #[account(seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = ACTIVE_SET_SPACE,
+        seeds = [ACTIVE_SET_SEED, epoch.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub active_set: Account<'info, ActiveSet>,
+
+    /// CHECK: active set of the previous epoch, may not exist
+    #[account(seeds = [ACTIVE_SET_SEED, epoch.saturating_sub(1).to_le_bytes().as_ref()], bump)]
+    pub previous_set: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(epoch: u64)]
+pub struct ReportWorkCredits<'info> {
+    #[account(seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump,
+        constraint = config.usage_oracle == oracle.key() @ CustomError::Unauthorized
+    )]
+    pub config: Account<'info, ConfigAccount>,
+
+    pub info_account: Account<'info, InfoAccount>,
+
+    // Required when config.active_set_size is set
+    #[account(
+        seeds = [ACTIVE_SET_SEED, epoch.to_le_bytes().as_ref()],
+        bump = active_set.bump
+    )]
+    pub active_set: Option<Account<'info, ActiveSet>>,
+
+    #[account(
+        init_if_needed,
+        payer = oracle,
+        space = EPOCH_WORK_SPACE,
+        seeds = [WORK_SEED, epoch.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub epoch_work: Account<'info, EpochWork>,
+
+    #[account(
+        init,
+        payer = oracle,
+        space = WORK_CREDITS_SPACE,
+        seeds = [WORK_SEED, epoch.to_le_bytes().as_ref(), info_account.key().as_ref()],
+        bump
+    )]
+    pub work_credits: Account<'info, WorkCredits>,
+
+    #[account(mut)]
+    pub oracle: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(epoch: u64)]
+pub struct FundEpochRewards<'info> {
+    #[account(seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = funder,
+        space = EPOCH_WORK_SPACE,
+        seeds = [WORK_SEED, epoch.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub epoch_work: Account<'info, EpochWork>,
+
+    // Reward pool shared with fund_rewards
+    #[account(
+        init_if_needed,
+        payer = funder,
+        associated_token::mint = mint,
+        associated_token::authority = main_account,
+        associated_token::token_program = token_program,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = sender_token_account.mint == mint.key() @ CustomError::InvalidMint,
+    )]
+    pub sender_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub funder: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DistributeWorkRewards<'info> {
+    #[account(
+        mut,
+        seeds = [WORK_SEED, epoch_work.epoch.to_le_bytes().as_ref()],
+        bump = epoch_work.bump
+    )]
+    pub epoch_work: Account<'info, EpochWork>,
+
+    #[account(
+        mut,
+        seeds = [
+            WORK_SEED,
+            epoch_work.epoch.to_le_bytes().as_ref(),
+            work_credits.server.as_ref(),
+        ],
+        bump = work_credits.bump
+    )]
+    pub work_credits: Account<'info, WorkCredits>,
+
+    #[account(mut, address = work_credits.server @ CustomError::InvalidAccountData)]
+    pub info_account: Account<'info, InfoAccount>,
+}
+
+#[derive(Accounts)]
+pub struct AttestStake<'info> {
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(mut)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    /// CHECK: core bridge config, read for the message fee
+    #[account(mut, seeds = [WORMHOLE_BRIDGE_SEED], bump, seeds::program = config.wormhole_bridge)]
+    pub wormhole_bridge: UncheckedAccount<'info>,
+
+    /// CHECK: created by the core bridge
+    #[account(
+        mut,
+        seeds = [
+            ATTESTATION_SEED,
+            info_account.key().as_ref(),
+            info_account.attestations.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub wormhole_message: UncheckedAccount<'info>,
+
+    /// CHECK: data-less PDA, the emitter address of the program's messages
+    #[account(seeds = [EMITTER_SEED], bump)]
+    pub wormhole_emitter: UncheckedAccount<'info>,
+
+    /// CHECK: emitter sequence, created and checked by the core bridge
+    #[account(
+        mut,
+        seeds = [WORMHOLE_SEQUENCE_SEED, wormhole_emitter.key().as_ref()],
+        bump,
+        seeds::program = config.wormhole_bridge
+    )]
+    pub wormhole_sequence: UncheckedAccount<'info>,
+
+    /// CHECK: core bridge fee collector
+    #[account(
+        mut,
+        seeds = [WORMHOLE_FEE_COLLECTOR_SEED],
+        bump,
+        seeds::program = config.wormhole_bridge
+    )]
+    pub wormhole_fee_collector: UncheckedAccount<'info>,
+
+    /// CHECK: the configured core bridge
+    #[account(executable, address = config.wormhole_bridge)]
+    pub wormhole_program: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub clock: Sysvar<'info, Clock>,
+    pub rent: Sysvar<'info, Rent>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct BindEndpoint<'info> {
+    #[account(
+        mut,
+        constraint = info_account.is_operator(&authority.key()) @ CustomError::Unauthorized
+    )]
+    pub info_account: Account<'info, InfoAccount>,
+
+    pub authority: Signer<'info>, // Owner or operator key
+    /// CHECK: instructions sysvar, only needed for Ed25519 serverkeys
+    #[account(address = solana_program::sysvar::instructions::ID)]
+    pub instructions: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct ReportVersion<'info> {
+    #[account(mut)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    /// CHECK: instructions sysvar, only needed for Ed25519 serverkeys
+    #[account(address = solana_program::sysvar::instructions::ID)]
+    pub instructions: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct SetCapabilities<'info> {
+    #[account(seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(mut, has_one = owner)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct PenalizeServiceFailure<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump,
+        constraint = config.usage_oracle == oracle.key() @ CustomError::Unauthorized
+    )]
+    pub config: Account<'info, ConfigAccount>,
+
+    // Required once the audit log is initialized, see record_audit
+    #[account(mut, seeds = [AUDIT_LOG_SEED], bump)]
+    pub audit_log: Option<AccountLoader<'info, AuditLog>>,
+
+    #[account(mut)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    // Revenue accounts are only needed when there is pending revenue to dock
+    #[account(
+        mut,
+        seeds = [REVENUE_SEED, info_account.key().as_ref()],
+        bump,
+        token::mint = info_account.mint,
+        token::authority = info_account,
+        token::token_program = token_program,
+    )]
+    pub revenue_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(address = info_account.mint @ CustomError::InvalidMint)]
+    pub mint: Option<InterfaceAccount<'info, Mint>>,
+
+    /// CHECK: data-less PDA that owns the treasury token accounts
+    #[account(seeds = [TREASURY_SEED], bump)]
+    pub treasury: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        associated_token::mint = info_account.mint,
+        associated_token::authority = treasury,
+        associated_token::token_program = token_program,
+    )]
+    pub treasury_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub oracle: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct PayServer<'info> {
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(mut)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    // Kept apart from the stake vault, which is the server's ATA for the same mint
+    #[account(
+        init_if_needed,
+        payer = payer,
+        seeds = [REVENUE_SEED, info_account.key().as_ref()],
+        bump,
+        token::mint = mint,
+        token::authority = info_account,
+        token::token_program = token_program,
+    )]
+    pub revenue_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = info_account.mint @ CustomError::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = sender_token_account.mint == mint.key() @ CustomError::InvalidMint,
+    )]
+    pub sender_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PayServerStable<'info> {
+    #[account(seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(mut)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        address = info_account.mint @ CustomError::InvalidMint,
+        constraint = mint.key() == config.staking_mint() @ CustomError::InvalidMint,
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(address = config.stable_mint @ CustomError::InvalidMint)]
+    pub stable_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = sender_stable_account.mint == stable_mint.key() @ CustomError::InvalidMint,
+    )]
+    pub sender_stable_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = owner_stable_account.owner == info_account.owner @ CustomError::Unauthorized,
+        constraint = owner_stable_account.mint == stable_mint.key() @ CustomError::InvalidMint,
+    )]
+    pub owner_stable_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: data-less PDA that owns the treasury token accounts
+    #[account(seeds = [TREASURY_SEED], bump)]
+    pub treasury: UncheckedAccount<'info>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = stable_mint,
+        associated_token::authority = treasury,
+        associated_token::token_program = token_program,
+    )]
+    pub treasury_stable_account: InterfaceAccount<'info, TokenAccount>,
+    // Pays the delegators' share in staking tokens
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = treasury,
+        associated_token::token_program = token_program,
+    )]
+    pub treasury_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = mint,
+        associated_token::authority = main_account,
+        associated_token::token_program = token_program,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: Pyth PriceUpdateV2, owner and contents are verified in staking_token_price
+    pub price_update: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct OpenSubscription<'info> {
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        init,
+        payer = client,
+        space = SUBSCRIPTION_SPACE,
+        seeds = [SUBSCRIPTION_SEED, client.key().as_ref(), info_account.key().as_ref()],
+        bump
+    )]
+    pub subscription: Account<'info, Subscription>,
+
+    #[account(
+        init,
+        payer = client,
+        associated_token::mint = mint,
+        associated_token::authority = subscription,
+        associated_token::token_program = token_program,
+    )]
+    pub escrow: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = info_account.mint @ CustomError::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = sender_token_account.mint == mint.key() @ CustomError::InvalidMint,
+    )]
+    pub sender_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub client: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SettleSubscription<'info> {
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(mut, address = subscription.server @ CustomError::InvalidAccountData)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        mut,
+        seeds = [SUBSCRIPTION_SEED, subscription.client.as_ref(), subscription.server.as_ref()],
+        bump = subscription.bump
+    )]
+    pub subscription: Account<'info, Subscription>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = subscription,
+        associated_token::token_program = token_program,
+    )]
+    pub escrow: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        seeds = [REVENUE_SEED, info_account.key().as_ref()],
+        bump,
+        token::mint = mint,
+        token::authority = info_account,
+        token::token_program = token_program,
+    )]
+    pub revenue_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = subscription.mint @ CustomError::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct OpenDispute<'info> {
+    #[account(mut, address = subscription.server @ CustomError::InvalidAccountData)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        mut,
+        has_one = client,
+        seeds = [SUBSCRIPTION_SEED, client.key().as_ref(), subscription.server.as_ref()],
+        bump = subscription.bump
+    )]
+    pub subscription: Account<'info, Subscription>,
+
+    // One open dispute per client and server
+    #[account(
+        init,
+        payer = client,
+        space = DISPUTE_SPACE,
+        seeds = [DISPUTE_SEED, client.key().as_ref(), info_account.key().as_ref()],
+        bump
+    )]
+    pub dispute: Account<'info, Dispute>,
+
+    #[account(mut)]
+    pub client: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ResolveDispute<'info> {
+    #[account(
+        seeds = [CONFIG_SEED],
+        bump,
+        constraint = config.arbiter == arbiter.key() @ CustomError::Unauthorized
+    )]
+    pub config: Account<'info, ConfigAccount>,
+
+    // Required once the audit log is initialized, see record_audit
+    #[account(mut, seeds = [AUDIT_LOG_SEED], bump)]
+    pub audit_log: Option<AccountLoader<'info, AuditLog>>,
+
+    #[account(mut, address = dispute.server @ CustomError::InvalidAccountData)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        mut,
+        close = client,
+        has_one = client,
+        seeds = [DISPUTE_SEED, dispute.client.as_ref(), dispute.server.as_ref()],
+        bump = dispute.bump
+    )]
+    pub dispute: Account<'info, Dispute>,
+
+    #[account(
+        mut,
+        seeds = [REVENUE_SEED, info_account.key().as_ref()],
+        bump,
+        token::mint = mint,
+        token::authority = info_account,
+        token::token_program = token_program,
+    )]
+    pub revenue_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = info_account.mint @ CustomError::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = client_token_account.owner == dispute.client @ CustomError::Unauthorized,
+        constraint = client_token_account.mint == mint.key() @ CustomError::InvalidMint,
+    )]
+    pub client_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: receives the dispute account rent, checked against dispute.client
+    #[account(mut)]
+    pub client: UncheckedAccount<'info>,
+    pub arbiter: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ExpireDispute<'info> {
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(mut, address = dispute.server @ CustomError::InvalidAccountData)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        mut,
+        close = client,
+        has_one = client,
+        seeds = [DISPUTE_SEED, dispute.client.as_ref(), dispute.server.as_ref()],
+        bump = dispute.bump
+    )]
+    pub dispute: Account<'info, Dispute>,
+
+    /// CHECK: receives the dispute account rent, checked against dispute.client
+    #[account(mut)]
+    pub client: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseSubscription<'info> {
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(mut, address = subscription.server @ CustomError::InvalidAccountData)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        mut,
+        close = client,
+        has_one = client,
+        seeds = [SUBSCRIPTION_SEED, client.key().as_ref(), subscription.server.as_ref()],
+        bump = subscription.bump
+    )]
+    pub subscription: Account<'info, Subscription>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = subscription,
+        associated_token::token_program = token_program,
+    )]
+    pub escrow: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = client,
+        seeds = [REVENUE_SEED, info_account.key().as_ref()],
+        bump,
+        token::mint = mint,
+        token::authority = info_account,
+        token::token_program = token_program,
+    )]
+    pub revenue_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = subscription.mint @ CustomError::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = client_token_account.mint == mint.key() @ CustomError::InvalidMint,
+    )]
+    pub client_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub client: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct OpenChannel<'info> {
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        init,
+        payer = client,
+        space = CHANNEL_SPACE,
+        seeds = [CHANNEL_SEED, client.key().as_ref(), info_account.key().as_ref()],
+        bump
+    )]
+    pub channel: Account<'info, Channel>,
+
+    #[account(
+        init,
+        payer = client,
+        associated_token::mint = mint,
+        associated_token::authority = channel,
+        associated_token::token_program = token_program,
+    )]
+    pub escrow: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = info_account.mint @ CustomError::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = sender_token_account.mint == mint.key() @ CustomError::InvalidMint,
+    )]
+    pub sender_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub client: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptChannel<'info> {
+    #[account(mut, has_one = owner)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        mut,
+        constraint = channel.server == info_account.key() @ CustomError::InvalidAccountData,
+    )]
+    pub channel: Account<'info, Channel>,
+
+    pub owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimChannel<'info> {
+    #[account(mut, has_one = owner)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        mut,
+        constraint = channel.server == info_account.key() @ CustomError::InvalidAccountData,
+    )]
+    pub channel: Account<'info, Channel>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = channel,
+        associated_token::token_program = token_program,
+    )]
+    pub escrow: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        seeds = [REVENUE_SEED, info_account.key().as_ref()],
+        bump,
+        token::mint = mint,
+        token::authority = info_account,
+        token::token_program = token_program,
+    )]
+    pub revenue_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = channel.mint @ CustomError::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    /// CHECK: instructions sysvar, read for the Ed25519 signature check
+    #[account(address = solana_program::sysvar::instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseChannel<'info> {
+    #[account(address = channel.server @ CustomError::InvalidAccountData)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(mut, has_one = client)]
+    pub channel: Account<'info, Channel>,
+
+    pub client: Signer<'info>,
+    /// CHECK: instructions sysvar, only needed with a refund voucher
+    #[account(address = solana_program::sysvar::instructions::ID)]
+    pub instructions: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct FinalizeChannel<'info> {
+    #[account(mut, seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(mut, address = channel.server @ CustomError::InvalidAccountData)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        mut,
+        close = client,
+        has_one = client,
+        seeds = [CHANNEL_SEED, client.key().as_ref(), channel.server.as_ref()],
+        bump = channel.bump
+    )]
+    pub channel: Account<'info, Channel>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = channel,
+        associated_token::token_program = token_program,
+    )]
+    pub escrow: InterfaceAccount<'info, TokenAccount>,
+
+    // Server stake vault, source of the collateral refund
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = info_account,
+        associated_token::token_program = token_program,
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = channel.mint @ CustomError::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = client_token_account.mint == mint.key() @ CustomError::InvalidMint,
+    )]
+    pub client_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub client: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
 
 #[derive(Accounts)]
-pub struct RemoveServer<'info> {
+#[instruction(task_id: u64)]
+pub struct CommitTask<'info> {
+    #[account(
+        init,
+        payer = requester,
+        space = TASK_SPACE,
+        seeds = [TASK_SEED, requester.key().as_ref(), task_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub task: Account<'info, Task>,
+
+    #[account(mut)]
+    pub requester: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RevealTask<'info> {
+    #[account(
+        mut,
+        has_one = requester,
+        seeds = [TASK_SEED, requester.key().as_ref(), task.task_id.to_le_bytes().as_ref()],
+        bump = task.bump
+    )]
+    pub task: Account<'info, Task>,
+
+    pub requester: Signer<'info>,
+    /// CHECK: SlotHashes sysvar, parsed by slot_hash_after
+    #[account(address = solana_program::sysvar::slot_hashes::ID)]
+    pub slot_hashes: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SettleRevenue<'info> {
+    #[account(seeds = [MAIN_SEED], bump)]
+    pub main_account: AccountLoader<'info, MainAccount>,
+
+    #[account(seeds = [CONFIG_SEED], bump)]
+    pub config: Account<'info, ConfigAccount>,
+
+    #[account(mut)]
+    pub info_account: Account<'info, InfoAccount>,
+
+    #[account(
+        mut,
+        seeds = [REVENUE_SEED, info_account.key().as_ref()],
+        bump,
+        token::mint = mint,
+        token::authority = info_account,
+        token::token_program = token_program,
+    )]
+    pub revenue_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = info_account.mint @ CustomError::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = owner_token_account.owner == info_account.owner @ CustomError::Unauthorized,
+        constraint = owner_token_account.mint == mint.key() @ CustomError::InvalidMint,
+    )]
+    pub owner_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    // Reward pool of fund_rewards, receives the delegators' share
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = mint,
+        associated_token::authority = main_account,
+        associated_token::token_program = token_program,
+    )]
+    pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
     #[account(mut)]
-    pub main_account: Account<'info, MainAccount>,
+    pub payer: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct ConfigParams {
+    pub referral_bps: Option<u16>,
+    pub self_bond_multiple: Option<u16>,
+    pub gate_program: Option<Pubkey>,
+    pub bond_mint: Option<Pubkey>,
+    pub bond_minimum: Option<u64>,
+    pub bond_maximum: Option<u64>,
+    pub receipt_tokens: Option<bool>,
+    pub rate_oracle: Option<Pubkey>,
+    pub withdraw_fee_bps: Option<u16>,
+    pub reward_fee_bps: Option<u16>,
+    pub staking_mint: Option<Pubkey>,
+    pub usage_oracle: Option<Pubkey>,
+    pub revenue_stream_secs: Option<u64>,
+    pub heartbeat_timeout_secs: Option<u64>,
+    pub channel_challenge_secs: Option<u64>,
+    pub failure_penalty_bps: Option<u16>,
+    pub capability_minimums: Option<[u64; MAX_CAPABILITIES]>,
+    pub active_set_size: Option<u16>,
+    pub active_set_rotation_bps: Option<u16>,
+    pub jail_cooldown_epochs: Option<u64>,
+    pub wormhole_bridge: Option<Pubkey>,
+    pub attestation_threshold_bps: Option<u16>,
+    pub bridge_emitter_chain: Option<u16>,
+    pub bridge_emitter: Option<[u8; 32]>,
+    pub stable_mint: Option<Pubkey>,
+    pub price_feed_id: Option<[u8; 32]>,
+    pub max_price_age_secs: Option<u64>,
+    pub min_stake_usd: Option<u64>,
+    pub delegate_min_stake_usd: Option<u64>,
+    pub revenue_vesting_cliff_secs: Option<u64>,
+    pub revenue_vesting_secs: Option<u64>,
+    pub arbiter: Option<Pubkey>,
+    pub dispute_timeout_secs: Option<u64>,
+    pub gc_inactive_epochs: Option<u64>,
+    pub gc_reward_bps: Option<u16>,
+    pub blocked_name_hashes: Option<[[u8; 32]; MAX_BLOCKED_NAMES]>,
+    pub cpi_guard: Option<bool>,
+    pub cpi_allowed_programs: Option<[Pubkey; MAX_CPI_PROGRAMS]>,
+    pub direct_registration: Option<bool>,
+    pub license_collection: Option<Pubkey>,
+    pub registration_cooldown_secs: Option<u64>,
+    pub contact_key: Option<[u8; 32]>,
+    pub min_software_version: Option<SoftwareVersion>,
+    pub legacy_program: Option<Pubkey>,
+    pub tenure_root: Option<[u8; 32]>,
+    pub name_change_fee: Option<u64>,
+    pub exit_notice_secs: Option<i64>,
+    pub compute_telemetry: Option<bool>,
+    pub max_rate_age_secs: Option<u64>,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct ConfigAccount {
+    pub version: u8,
+    pub admin: Pubkey,
+    pub referral_bps: u16, // Share of delegator rewards paid to the referrer
+    pub self_bond_multiple: u16, // Max server total as a multiple of the operator stake, 0 disables
+    pub gate_program: Pubkey, // Compliance gate for stakers, Pubkey::default() disables
+    pub bond_mint: Pubkey, // Secondary bond asset for dual-token mode, Pubkey::default() disables
+    pub bond_minimum: u64, // Bond limits in base units of the bond mint, a 0 maximum is unbounded
+    pub bond_maximum: u64,
+    pub receipt_tokens: bool, // Mint liquid receipt tokens on delegation, burn them on withdrawal
+    pub rate_oracle: Pubkey, // Signer allowed to publish exchange rates for non-staking mints
+    pub withdraw_fee_bps: u16, // Protocol fee on withdrawn stake, paid to the treasury
+    pub reward_fee_bps: u16,   // Protocol fee on claimed rewards, paid to the treasury
+    // Staking token registered by initialize_main, Pubkey::default() keeps SPECIFIED_MINT. Only
+    // read at initialization, the main account is the reference afterwards.
+    pub staking_mint: Pubkey,
+    pub usage_oracle: Pubkey, // Attests per-epoch server usage, Pubkey::default() disables
+    pub revenue_stream_secs: u64, // Period over which server revenue vests, 0 releases it at once
+    pub heartbeat_timeout_secs: u64, // A server is offline this long after its last heartbeat, 0 disables
+    pub channel_challenge_secs: u64, // Time a server has to claim its last voucher once a channel closes
+    pub failure_penalty_bps: u16, // Share of pending revenue docked per failed availability proof
+    // Minimum total server stake for each capability bit, in staking token base units
+    pub capability_minimums: [u64; MAX_CAPABILITIES],
+    pub active_set_size: u16, // Servers per epoch active set, at most MAX_ACTIVE_SET, 0 disables
+    // Share of the active set reserved for servers that weren't in the previous epoch's set
+    pub active_set_rotation_bps: u16,
+    pub jail_cooldown_epochs: u64, // Epochs a penalized server stays out of the active set
+    pub wormhole_bridge: Pubkey, // Wormhole core bridge program, Pubkey::default() disables
+    // Change of a server total, relative to its last attestation, that can be attested again
+    pub attestation_threshold_bps: u16,
+    // AeroNyx contract on another chain whose Wormhole messages credit bridged delegations,
+    // zero disables
+    pub bridge_emitter_chain: u16,
+    pub bridge_emitter: [u8; 32],
+    pub stable_mint: Pubkey, // Stablecoin accepted by pay_server_stable, Pubkey::default() disables
+    pub price_feed_id: [u8; 32], // Pyth feed of the staking token in USD
+    pub max_price_age_secs: u64,
+    // Minimum self and delegated stake in USD (USD_DECIMALS), replacing MINIMUM_STAKE and
+    // DELEGATE_MINIMUM_STAKE when set
+    pub min_stake_usd: u64,
+    pub delegate_min_stake_usd: u64,
+    // Vesting of the operator's settled revenue, a 0 period pays it out at once
+    pub revenue_vesting_cliff_secs: u64,
+    pub revenue_vesting_secs: u64,
+    pub arbiter: Pubkey, // Resolves client disputes, may be a governance PDA
+    pub dispute_timeout_secs: u64, // After which an unresolved dispute releases to the server
+    pub gc_inactive_epochs: u64, // Idle epochs before empty accounts can be collected, 0 = off
+    pub gc_reward_bps: u16, // Share of the closed account's rent paid to the gc_account caller
+    // hash() of lowercase words refused in server names, see normalize_name. Unused slots are zero.
+    pub blocked_name_hashes: [[u8; 32]; MAX_BLOCKED_NAMES],
+    // Restricts withdraw and remove_server to top-level calls and CPIs from these programs, see
+    // check_cpi_origin. Unused slots are Pubkey::default().
+    pub cpi_guard: bool,
+    pub cpi_allowed_programs: [Pubkey; MAX_CPI_PROGRAMS],
+    pub direct_registration: bool, // add_server only as a top-level instruction, never through CPI
+    pub license_collection: Pubkey, // Operator-license NFT collection, default when not gated
+    pub audit_log: bool, // Set by initialize_audit_log, from then on admin actions must be logged
+    pub registration_cooldown_secs: u64, // Before a removed server can register again, 0 for none
+    // X25519 key operators encrypt their contact details to, held by the admin and arbiter. All
+    // zero when not set.
+    pub contact_key: [u8; 32],
+    // Servers reporting an older version, or none, earn no work credits. 0.0.0 disables.
+    pub min_software_version: SoftwareVersion,
+    pub legacy_program: Pubkey, // Previous deployment migrate_from_v0 pulls from, default disables
+    pub tenure_root: [u8; 32], // Pre-launch stakers snapshot for claim_tenure_credit, zero if none
+    pub name_change_fee: u64, // Common units paid to the treasury per update_server rename
+    // Between announce_exit and a withdrawal leaving a server with delegations under
+    // MINIMUM_STAKE, 0 for none
+    pub exit_notice_secs: i64,
+    pub compute_telemetry: bool, // Log remaining compute units in the stake paths, devnet only
+    // Oldest exchange rate stake in another mint is admitted at, 0 for no limit
+    pub max_rate_age_secs: u64,
+}
+
+impl ConfigAccount {
+    pub fn staking_mint(&self) -> Pubkey {
+        if self.staking_mint == Pubkey::default() {
+            SPECIFIED_MINT
+        } else {
+            self.staking_mint
+        }
+    }
+
+    // Capability bits whose minimum stake a server with `value` total stake meets
+    pub fn capabilities_met(&self, value: u64) -> u8 {
+        self.capability_minimums
+            .iter()
+            .enumerate()
+            .filter(|(_, minimum)| value >= **minimum)
+            .fold(0, |flags, (i, _)| flags | 1 << i)
+    }
+}
+
+// A compressed delegation as last written to its server's tree, with the root the client's proof
+// was computed against
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct CompressedDelegation {
+    pub root: [u8; 32],
+    pub leaf_index: u32,
+    pub delegation: DelegatedAccount,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct AllowlistEntry {
+    pub version: u8,
+    pub staker: Pubkey,
+    pub approved_by: Pubkey,
+}
+
+// Global totals at the first crank of an epoch, PDA [SNAPSHOT_SEED, epoch (u64 LE)]
+#[account]
+#[derive(InitSpace)]
+pub struct EpochSnapshot {
+    pub version: u8,
+    pub epoch: u64,
+    pub total_stake: u64,
+    pub total_self_stake: u64,
+    pub total_delegated: u64,
+    pub total_users: u32,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+    pub bump: u8,
+    // MainAccount lifetime counters at the time of the snapshot, see EpochSummary
+    pub total_deposited: u64,
+    pub total_withdrawn: u64,
+    pub servers_opened: u32,
+    pub servers_closed: u32,
+}
+
+pub const ACTIVE_SET_SPACE: usize = 8 + ActiveSet::INIT_SPACE;
+
+// Servers selected for an epoch, PDA [ACTIVE_SET_SEED, epoch (u64 LE)]. The first `count`
+// entries are used, in no particular order.
+#[account]
+#[derive(InitSpace)]
+pub struct ActiveSet {
+    pub version: u8,
+    pub epoch: u64,
+    pub size: u16, // config.active_set_size when the set was started
+    pub count: u16,
+    pub max_incumbents: u16, // Slots members of the previous epoch's set may hold
+    pub incumbents: u16,
+    pub entries: [ActiveSetEntry; MAX_ACTIVE_SET],
+    pub bump: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct ActiveSetEntry {
+    pub server: Pubkey,
+    pub score: u64, // Total stake in staking token base units x reputation
+    pub incumbent: bool, // Also in the previous epoch's set
+}
+
+impl ActiveSet {
+    pub fn entries(&self) -> &[ActiveSetEntry] {
+        &self.entries[..self.count as usize]
+    }
+
+    pub fn contains(&self, server: &Pubkey) -> bool {
+        self.entries().iter().any(|entry| entry.server == *server)
+    }
+
+    // Add a server while there is room, otherwise replace the lowest score if it is beaten. Once
+    // the incumbents are at max_incumbents, an incumbent can only replace another incumbent.
+    // Returns whether the server made it in.
+    pub fn insert(&mut self, server: Pubkey, score: u64, incumbent: bool) -> bool {
+        if self.contains(&server) {
+            return false;
+        }
+        let entry = ActiveSetEntry {
+            server,
+            score,
+            incumbent,
+        };
+        let incumbents_full = incumbent && self.incumbents >= self.max_incumbents;
+        let size = (self.size as usize).min(MAX_ACTIVE_SET);
+        if !incumbents_full && (self.count as usize) < size {
+            self.entries[self.count as usize] = entry;
+            self.count += 1;
+            self.incumbents += incumbent as u16;
+            return true;
+        }
+        let lowest = self
+            .entries()
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| !incumbents_full || entry.incumbent)
+            .min_by_key(|(_, entry)| entry.score)
+            .map(|(index, entry)| (index, *entry));
+        match lowest {
+            Some((index, lowest)) if lowest.score < score => {
+                self.incumbents = self.incumbents + incumbent as u16 - lowest.incumbent as u16;
+                self.entries[index] = entry;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+pub const EPOCH_WORK_SPACE: usize = 8 + EpochWork::INIT_SPACE;
+pub const WORK_CREDITS_SPACE: usize = 8 + WorkCredits::INIT_SPACE;
+pub const BRIDGED_DELEGATION_SPACE: usize = 8 + BridgedDelegation::INIT_SPACE;
+pub const FOREIGN_OWNER_SPACE: usize = 8 + ForeignOwner::INIT_SPACE;
+
+// Used bridged delegation or withdrawal message, PDA [BRIDGED_SEED, emitter chain (u16 LE),
+// sequence (u64 LE)]
+#[account]
+#[derive(InitSpace)]
+pub struct BridgedDelegation {
+    pub version: u8,
+    pub emitter_chain: u16,
+    pub sequence: u64,
+    pub server: Pubkey,
+    pub owner: Pubkey, // Foreign owner PDA
+    pub amount: u64,
+    pub bump: u8,
+}
+
+// Stand-in owner of the bridged delegations of `address` on Wormhole chain `emitter_chain`, PDA
+// [FOREIGN_OWNER_SEED, emitter chain (u16 LE), address]. Its positions only move through
+// guardian-verified messages, see d_withdraw_bridged.
+#[account]
+#[derive(InitSpace)]
+pub struct ForeignOwner {
+    pub version: u8,
+    pub emitter_chain: u16,
+    pub address: [u8; 32],
+    pub bump: u8,
+}
+
+// Work reward pool of an epoch, PDA [WORK_SEED, epoch (u64 LE)]. Paid out of the reward vault
+// to servers in proportion to their credits.
+#[account]
+#[derive(InitSpace)]
+pub struct EpochWork {
+    pub version: u8,
+    pub epoch: u64,
+    pub total_credits: u64,
+    pub servers: u32, // Servers with reported credits
+    pub pool: u64, // Staking token base units funded for the epoch
+    pub distributed: u64,
+    pub bump: u8,
+}
+
+// Credits of one server in an epoch, PDA [WORK_SEED, epoch (u64 LE), server]
+#[account]
+#[derive(InitSpace)]
+pub struct WorkCredits {
+    pub version: u8,
+    pub server: Pubkey,
+    pub epoch: u64,
+    pub credits: u64,
+    pub distributed: bool,
+    pub bump: u8,
+}
+
+pub const SERVER_USAGE_SPACE: usize = 8 + ServerUsage::INIT_SPACE;
+
+pub const CHANNEL_SPACE: usize = 8 + Channel::INIT_SPACE;
+
+// Payment channel from a client to a server, PDA [CHANNEL_SEED, client, server]. The deposit
+// sits in the channel's ATA, `collateral` is locked from the server's stake once accepted.
+#[account]
+#[derive(InitSpace)]
+pub struct Channel {
+    pub version: u8,
+    pub client: Pubkey,
+    pub server: Pubkey,
+    pub mint: Pubkey,
+    pub deposit: u64,
+    pub paid: u64, // Cumulative amount redeemed by the server
+    pub collateral: u64,
+    pub accepted: bool,
+    pub close_requested_at: i64, // 0 while open
+    pub refund_owed: u64, // Server-signed refund presented at close, capped at the collateral
+    // Part of the signed messages, so vouchers of an earlier channel at the same address are void
+    pub opened_slot: u64,
+    pub bump: u8,
+}
+
+pub const TASK_SPACE: usize = 8 + Task::INIT_SPACE;
+
+// Commit-reveal server selection, PDA [TASK_SEED, requester, task_id (u64 LE)]. `assigned`
+// and `randomness` let anyone recompute the draw.
+#[account]
+#[derive(InitSpace)]
+pub struct Task {
+    pub version: u8,
+    pub requester: Pubkey,
+    pub task_id: u64,
+    pub commitment: [u8; 32], // keccak(secret)
+    pub commit_slot: u64,
+    pub candidate_count: u8,
+    pub candidates: [Pubkey; MAX_TASK_CANDIDATES],
+    pub assigned: Pubkey, // Pubkey::default() until revealed
+    pub randomness: [u8; 32], // keccak(secret, hash of the first slot after commit_slot, task)
+    pub assigned_slot: u64,
+    pub bump: u8,
+}
+
+pub const DISPUTE_SPACE: usize = 8 + Dispute::INIT_SPACE;
+pub const REGISTRATION_RECORD_SPACE: usize = 8 + RegistrationRecord::INIT_SPACE;
+pub const RECOUNT_SPACE: usize = 8 + Recount::INIT_SPACE;
+pub const STANDING_ORDER_SPACE: usize = 8 + StandingOrder::INIT_SPACE;
+pub const DELEGATION_INTENT_SPACE: usize = 8 + DelegationIntent::INIT_SPACE;
+pub const DAILY_STATS_SPACE: usize = 8 + DailyStats::INIT_SPACE;
+pub const SNAPSHOT_ARCHIVE_SPACE: usize = 8 + SnapshotArchive::INIT_SPACE;
+pub const LOCKUP_RENEWAL_SPACE: usize = 8 + LockupRenewal::INIT_SPACE;
+pub const GUARDIAN_SET_SPACE: usize = 8 + GuardianSet::INIT_SPACE;
+pub const PORTFOLIO_SPACE: usize = 8 + Portfolio::INIT_SPACE;
+pub const DELEGATION_POOL_SPACE: usize = 8 + DelegationPool::INIT_SPACE;
+pub const NODE_HANDLE_SPACE: usize = 8 + NodeHandle::INIT_SPACE;
+pub const PARTNER_ADAPTER_SPACE: usize = 8 + PartnerAdapter::INIT_SPACE;
+pub const PARTNER_IMPORT_SPACE: usize = 8 + PartnerImport::INIT_SPACE;
+pub const TENURE_CREDIT_SPACE: usize = 8 + TenureCredit::INIT_SPACE;
+
+// Client's contest of a server's revenue, PDA [DISPUTE_SEED, client, server]
+#[account]
+#[derive(InitSpace)]
+pub struct Dispute {
+    pub version: u8,
+    pub client: Pubkey,
+    pub server: Pubkey,
+    pub amount: u64, // Frozen in the server's revenue_disputed
+    pub opened_at: i64,
+    pub bump: u8,
+}
+
+// Last removal of a server, PDA [REGISTRATION_SEED, owner, serverkey hash]. Outlives the server
+// account so re-registration can be held off, see check_registration_cooldown.
+#[account]
+#[derive(InitSpace)]
+pub struct RegistrationRecord {
+    pub version: u8,
+    pub closed_at: i64,
+    pub closes: u32, // Times this server was removed
+    pub bump: u8,
+}
+
+// Running totals of a recount pass, PDA [RECOUNT_SEED]. Stake arrays follow the order of the
+// main account's mint_totals.
+#[account]
+#[derive(Default, InitSpace)]
+pub struct Recount {
+    pub version: u8,
+    pub cursor: Pubkey, // Last server counted
+    pub users: u32,
+    pub servers: u32,
+    pub total_stake: [u64; MAX_APPROVED_MINTS],
+    pub self_stake: [u64; MAX_APPROVED_MINTS],
+    pub bump: u8,
+}
+
+// Network figures of one UTC day, PDA [DAILY_STATS_SEED, day (u32 LE)], written by
+// update_daily_stats
+#[account]
+#[derive(Default, InitSpace)]
+pub struct DailyStats {
+    pub version: u8,
+    pub day: u32, // Days since the Unix epoch
+    pub total_stake: u64, // Main account total_stake when the record was opened
+    pub stake_delta: i64, // Change of total_stake since the previous day's record
+    pub opened_at: i64,
+    pub active_servers: u32, // Servers the day's calls found online
+    pub cursor: Pubkey, // Last server counted
+    pub bump: u8,
+}
+
+// Epoch snapshots folded by archive_snapshots, PDA [ARCHIVE_SEED]. Leaves are appended to an
+// incremental Merkle tree of depth ARCHIVE_DEPTH, hashed like verify_merkle_proof with empty
+// subtrees hashing up from zero leaves. `branch` keeps, per height, the left node still waiting
+// for its right sibling, so the root is updated without storing the leaves.
+#[account]
+#[derive(InitSpace)]
+pub struct SnapshotArchive {
+    pub version: u8,
+    pub count: u64, // Leaves appended, leaf i is the i-th snapshot archived
+    pub first_epoch: u64,
+    pub last_epoch: u64,
+    pub root: [u8; 32],
+    pub branch: [[u8; 32]; ARCHIVE_DEPTH],
+    pub bump: u8,
+}
+
+impl SnapshotArchive {
+    fn append(&mut self, leaf: [u8; 32]) -> Result<()> {
+        require!(self.count < 1u64 << ARCHIVE_DEPTH, CustomError::ArchiveFull);
+        let mut node = leaf;
+        let mut size = self.count;
+        for height in 0..ARCHIVE_DEPTH {
+            if size & 1 == 0 {
+                self.branch[height] = node;
+                break;
+            }
+            node = hash_pair(&self.branch[height], &node);
+            size >>= 1;
+        }
+        self.count += 1;
+        self.root = self.compute_root();
+        Ok(())
+    }
+
+    fn compute_root(&self) -> [u8; 32] {
+        let mut node = [0u8; 32];
+        let mut zero = [0u8; 32];
+        let mut size = self.count;
+        for height in 0..ARCHIVE_DEPTH {
+            node = if size & 1 == 1 {
+                hash_pair(&self.branch[height], &node)
+            } else {
+                hash_pair(&node, &zero)
+            };
+            zero = hash_pair(&zero, &zero);
+            size >>= 1;
+        }
+        node
+    }
+}
+
+// Recurring delegation, PDA [STANDING_ORDER_SEED, owner, server]. The owner approves the order
+// as SPL delegate of `funding_account` for as much as it wants to be drawn in total, and any
+// keeper can run execute_standing_order once every `interval_secs`.
+#[account]
+#[derive(InitSpace)]
+pub struct StandingOrder {
+    pub version: u8,
+    pub owner: Pubkey,
+    pub server: Pubkey,
+    pub funding_account: Pubkey,
+    pub amount: u64, // Per execution, in base units
+    pub interval_secs: i64,
+    pub next_execution: i64, // Earliest unix timestamp of the next execution
+    pub lockup: LockupTier,
+    pub executions: u32,
+    pub bump: u8,
+}
+
+impl StandingOrder {
+    // Move to the first period after `now`, periods missed while no keeper ran are skipped
+    // rather than caught up in one go
+    pub fn advance(&mut self, now: i64) -> Result<()> {
+        let behind = now - self.next_execution;
+        self.next_execution = now
+            .checked_sub(behind % self.interval_secs)
+            .and_then(|start| start.checked_add(self.interval_secs))
+            .ok_or(CustomError::NumberOverflow)?;
+        self.executions = self.executions.saturating_add(1);
+        Ok(())
+    }
+}
+
+// One-off delegation signed up for in advance, PDA [INTENT_SEED, owner, server]. The owner
+// approves the intent as SPL delegate of `funding_account` for `amount`, and the server or any
+// keeper runs execute_delegation_intent once before `expires_at`, which closes it.
+#[account]
+#[derive(InitSpace)]
+pub struct DelegationIntent {
+    pub version: u8,
+    pub owner: Pubkey,
+    pub server: Pubkey,
+    pub funding_account: Pubkey,
+    pub amount: u64, // Base units
+    pub expires_at: i64,
+    pub lockup: LockupTier,
+    pub bump: u8,
+}
+
+// Auto-renewal opt-in of a locked delegation, PDA [RENEWAL_SEED, delegation]. Its existence is
+// the flag, DelegatedAccount has no room for one since it is also the compressed leaf layout.
+// While it exists the lock rolls over to a new term of `lockup` at each expiry and d_withdraw is
+// refused. Compressed positions can't opt in.
+#[account]
+#[derive(InitSpace)]
+pub struct LockupRenewal {
+    pub version: u8,
+    pub delegation: Pubkey,
+    pub lockup: LockupTier,
+    pub renewed_from: i64, // Expiry the last roll-over started from, 0 before the first one
+    pub renewed_until: i64, // Expiry that roll-over set
+    pub bump: u8,
+}
+
+impl LockupRenewal {
+    // Roll an expired lock over into the term that covers `now`, terms missed while nobody
+    // cranked are skipped. Returns whether the lock moved.
+    pub fn roll_over(&mut self, delegation: &mut DelegatedAccount, now: i64) -> Result<bool> {
+        if now < delegation.lock_until {
+            return Ok(false);
+        }
+        let duration = self.lockup.duration();
+        let missed = (now - delegation.lock_until) / duration;
+        self.renewed_from = missed
+            .checked_mul(duration)
+            .and_then(|elapsed| delegation.lock_until.checked_add(elapsed))
+            .ok_or(CustomError::NumberOverflow)?;
+        self.renewed_until = self
+            .renewed_from
+            .checked_add(duration)
+            .ok_or(CustomError::NumberOverflow)?;
+        delegation.lockup = delegation.lockup.max(self.lockup);
+        delegation.lock_until = self.renewed_until;
+        Ok(true)
+    }
+}
+
+// M-of-N recovery guardians of a server, PDA [GUARDIAN_SEED, server]. Keyed by the server rather
+// than its owner so the set survives an ownership change. Each guardian holds one vote for a
+// new owner, `votes` lines up with `guardians`.
+#[account]
+#[derive(InitSpace)]
+pub struct GuardianSet {
+    pub version: u8,
+    pub server: Pubkey,
+    pub guardians: [Pubkey; MAX_GUARDIANS],
+    pub guardian_count: u8,
+    pub threshold: u8,
+    pub votes: [Pubkey; MAX_GUARDIANS], // Pubkey::default() for no vote
+    pub proposed_owner: Pubkey, // Key `threshold` guardians agree on, default until then
+    pub approved_at: i64, // When proposed_owner reached the threshold, 0 without one
+    pub bump: u8,
+}
+
+impl GuardianSet {
+    pub fn guardian_index(&self, key: &Pubkey) -> Option<usize> {
+        self.guardians[..self.guardian_count as usize]
+            .iter()
+            .position(|guardian| guardian == key)
+    }
+
+    pub fn approvals(&self, new_owner: &Pubkey) -> u8 {
+        if *new_owner == Pubkey::default() {
+            return 0;
+        }
+        self.votes.iter().filter(|vote| *vote == new_owner).count() as u8
+    }
+
+    pub fn clear_votes(&mut self) {
+        self.votes = [Pubkey::default(); MAX_GUARDIANS];
+        self.proposed_owner = Pubkey::default();
+        self.approved_at = 0;
+    }
+}
+
+// Delegations managed as one, PDA [PORTFOLIO_SEED, owner]. The PDA is the owner of its positions,
+// PDAs [INFO_SEED, portfolio, server], so they are only reachable through the portfolio_*
+// instructions signed by `owner`.
+#[account]
+#[derive(InitSpace)]
+pub struct Portfolio {
+    pub version: u8,
+    pub owner: Pubkey,
+    pub servers: [Pubkey; MAX_PORTFOLIO_POSITIONS], // Servers with an open position
+    pub position_count: u8,
+    pub bump: u8,
+}
+
+impl Portfolio {
+    pub fn positions(&self) -> &[Pubkey] {
+        &self.servers[..self.position_count as usize]
+    }
+
+    pub fn add_position(&mut self, server: Pubkey) -> Result<()> {
+        let count = self.position_count as usize;
+        require!(count < MAX_PORTFOLIO_POSITIONS, CustomError::PortfolioFull);
+        self.servers[count] = server;
+        self.position_count += 1;
+        Ok(())
+    }
+
+    // Swap-remove, the order of the positions carries no meaning
+    pub fn remove_position(&mut self, server: &Pubkey) {
+        if let Some(index) = self.positions().iter().position(|key| key == server) {
+            let last = self.position_count as usize - 1;
+            self.servers[index] = self.servers[last];
+            self.servers[last] = Pubkey::default();
+            self.position_count -= 1;
+        }
+    }
+}
+
+// Pooled delegation of one primary mint, PDA [POOL_SEED, mint]. Holders of any size buy shares
+// of `share_mint` and the manager delegates the aggregate, positions [INFO_SEED, pool, server].
+// Only Gold tier servers qualify. The share price is (idle + delegated) / share supply, with
+// `delegated` as of the last sync_pool.
+#[account]
+#[derive(InitSpace)]
+pub struct DelegationPool {
+    pub version: u8,
+    pub mint: Pubkey,
+    pub share_mint: Pubkey, // PDA [POOL_SHARE_SEED, pool], minted by the pool
+    pub manager: Pubkey,
+    pub idle: u64, // Base units in the pool vault, donations to the vault are not counted
+    pub delegated: u64,
+    pub servers: [Pubkey; MAX_POOL_POSITIONS],
+    pub position_count: u8,
+    pub synced_at: i64,
+    pub bump: u8,
+}
+
+impl DelegationPool {
+    pub fn positions(&self) -> &[Pubkey] {
+        &self.servers[..self.position_count as usize]
+    }
+
+    pub fn add_position(&mut self, server: Pubkey) -> Result<()> {
+        let count = self.position_count as usize;
+        require!(count < MAX_POOL_POSITIONS, CustomError::PoolFull);
+        self.servers[count] = server;
+        self.position_count += 1;
+        Ok(())
+    }
+
+    pub fn total_assets(&self) -> Result<u64> {
+        self.idle
+            .checked_add(self.delegated)
+            .ok_or(CustomError::NumberOverflow.into())
+    }
+
+    // Shares for `amount` base units, 1:1 into an empty pool. Rounds down, in the pool's favour.
+    pub fn shares_for(&self, amount: u64, share_supply: u64) -> Result<u64> {
+        let total_assets = self.total_assets()?;
+        if share_supply == 0 || total_assets == 0 {
+            return Ok(amount);
+        }
+        u64::try_from(amount as u128 * share_supply as u128 / total_assets as u128)
+            .map_err(|_| CustomError::NumberOverflow.into())
+    }
+
+    // Shares that pay out `amount` base units. Rounds up, in the pool's favour.
+    pub fn shares_to_withdraw(&self, amount: u64, share_supply: u64) -> Result<u64> {
+        let total_assets = self.total_assets()?;
+        require!(total_assets > 0, CustomError::PoolLiquidityInsufficient);
+        u64::try_from(
+            (amount as u128 * share_supply as u128).div_ceil(total_assets as u128),
+        )
+        .map_err(|_| CustomError::NumberOverflow.into())
+    }
+
+    // Base units `shares` redeem for. Rounds down, in the pool's favour.
+    pub fn assets_for(&self, shares: u64, share_supply: u64) -> Result<u64> {
+        if share_supply == 0 {
+            return Ok(0);
+        }
+        u64::try_from(shares as u128 * self.total_assets()? as u128 / share_supply as u128)
+            .map_err(|_| CustomError::NumberOverflow.into())
+    }
+}
+
+// Registry entry of a unique node handle, PDA [HANDLE_SEED, hash(handle)]. The owner of `server`
+// controls it, so it follows the server through ownership recovery.
+#[account]
+#[derive(InitSpace)]
+pub struct NodeHandle {
+    pub version: u8,
+    pub server: Pubkey,
+    pub handle_hash: [u8; 32], // Seed of this PDA
+    pub handle: [u8; MAX_HANDLE_LEN], // Zero padded
+    pub handle_len: u8,
+    pub bump: u8,
+}
+
+// Admin-approved reader of a partner staking program's positions, PDA
+// [PARTNER_ADAPTER_SEED, partner_program]. Its ATA for `mint` holds the partner-funded bonus.
+#[account]
+#[derive(InitSpace)]
+pub struct PartnerAdapter {
+    pub version: u8,
+    pub partner_program: Pubkey,
+    pub mint: Pubkey, // Collateral the credits are delegated in
+    pub position_discriminator: [u8; 8], // First bytes of the partner's position accounts
+    pub owner_offset: u16, // Byte offset of the position owner's pubkey
+    pub amount_offset: u16, // Byte offset of the staked amount, a little-endian u64
+    pub bonus_bps: u16, // Credit per partner base unit, 10,000 is 1:1
+    pub max_credit: u64, // Per import, base units of `mint`
+    pub enabled: bool,
+    pub total_credited: u64,
+    pub bump: u8,
+    pub lockup: LockupTier, // Credits are locked in this tier, never None
+}
+
+// Receipt of an import_partner_position, PDA [PARTNER_IMPORT_SEED, partner_position]. Its
+// existence is what makes the import one-time, whoever owns the position later.
+#[account]
+#[derive(InitSpace)]
+pub struct PartnerImport {
+    pub version: u8,
+    pub adapter: Pubkey,
+    pub owner: Pubkey,
+    pub partner_position: Pubkey,
+    pub partner_stake: u64, // As read from the partner position
+    pub credited: u64,
+    pub delegated_account: Pubkey,
+    pub unix_timestamp: i64,
+    pub bump: u8,
+}
+
+// Loyalty credit of a pre-launch staker, PDA [TENURE_SEED, owner], created by its one claim
+#[account]
+#[derive(InitSpace)]
+pub struct TenureCredit {
+    pub version: u8,
+    pub owner: Pubkey,
+    pub points: u64,
+    pub multiplier_bps: u16, // 10,000 is 1x
+    pub root: [u8; 32], // Snapshot root the claim was proven against
+    pub claimed_at: i64,
+    pub credited: u64, // Staking token base units paid, points x multiplier
+    pub bump: u8,
+}
+
+// Ring buffer of the last AUDIT_LOG_LEN admin and oracle actions, PDA [AUDIT_LOG_SEED], so the
+// trail doesn't depend on how long RPC nodes keep transaction logs. Zero-copy like MainAccount,
+// appending only writes one entry.
+#[account(zero_copy)]
+pub struct AuditLog {
+    pub head: u64, // Entries ever written, the next one goes to entries[head % AUDIT_LOG_LEN]
+    pub entries: [AuditEntry; AUDIT_LOG_LEN],
+}
+
+#[zero_copy]
+pub struct AuditEntry {
+    pub actor: Pubkey,
+    pub target: Pubkey, // Account acted on: the config, a mint, a staker, a server, a dispute...
+    pub slot: u64,
+    pub unix_timestamp: i64,
+    pub action: u8, // AuditAction
+    pub _padding: [u8; 7],
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    AuditLogInitialized,
+    ConfigUpdated,
+    MintApproved,
+    TreasuryTransfer,
+    AllowlistAdded,
+    AllowlistRemoved,
+    ServiceFailurePenalized,
+    DisputeResolved,
+    PartnerAdapterUpdated,
+}
+
+// Config field named by the Config*Changed events, in update_config order
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigParam {
+    ReferralBps,
+    SelfBondMultiple,
+    GateProgram,
+    WithdrawFeeBps,
+    RewardFeeBps,
+    RateOracle,
+    ReceiptTokens,
+    StakingMint,
+    UsageOracle,
+    RevenueStreamSecs,
+    HeartbeatTimeoutSecs,
+    ChannelChallengeSecs,
+    FailurePenaltyBps,
+    CapabilityMinimums,
+    ActiveSetSize,
+    ActiveSetRotationBps,
+    JailCooldownEpochs,
+    WormholeBridge,
+    AttestationThresholdBps,
+    BridgeEmitterChain,
+    BridgeEmitter,
+    StableMint,
+    PriceFeedId,
+    MaxPriceAgeSecs,
+    MinStakeUsd,
+    DelegateMinStakeUsd,
+    RevenueVestingCliffSecs,
+    RevenueVestingSecs,
+    Arbiter,
+    DisputeTimeoutSecs,
+    GcInactiveEpochs,
+    GcRewardBps,
+    BlockedNameHashes,
+    CpiGuard,
+    CpiAllowedPrograms,
+    DirectRegistration,
+    LicenseCollection,
+    RegistrationCooldownSecs,
+    ContactKey,
+    MinSoftwareVersion,
+    LegacyProgram,
+    TenureRoot,
+    NameChangeFee,
+    ExitNoticeSecs,
+    BondMint,
+    BondMinimum,
+    BondMaximum,
+    AuditLog,
+    ComputeTelemetry,
+    MaxRateAgeSecs,
+}
+
+impl AuditEntry {
+    pub fn new(action: AuditAction, actor: Pubkey, target: Pubkey, clock: &Clock) -> Self {
+        Self {
+            actor,
+            target,
+            slot: clock.slot,
+            unix_timestamp: clock.unix_timestamp,
+            action: action as u8,
+            _padding: [0; 7],
+        }
+    }
+}
+
+impl AuditLog {
+    pub fn push(&mut self, entry: AuditEntry) {
+        self.entries[(self.head % AUDIT_LOG_LEN as u64) as usize] = entry;
+        self.head += 1;
+    }
+
+    // Written entries, oldest first
+    pub fn recent(&self) -> impl Iterator<Item = &AuditEntry> {
+        let len = self.head.min(AUDIT_LOG_LEN as u64) as usize;
+        let start = (self.head - len as u64) % AUDIT_LOG_LEN as u64;
+        (0..len).map(move |i| &self.entries[(start as usize + i) % AUDIT_LOG_LEN])
+    }
+}
+
+pub const SUBSCRIPTION_SPACE: usize = 8 + Subscription::INIT_SPACE;
+
+// Prepaid service from a client to a server, PDA [SUBSCRIPTION_SEED, client, server]. The
+// escrow is the subscription's ATA for `mint`.
+#[account]
+#[derive(InitSpace)]
+pub struct Subscription {
+    pub version: u8,
+    pub client: Pubkey,
+    pub server: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64, // Escrowed at opening, in base units
+    pub paid: u64, // Already moved to the server's revenue
+    pub start: i64,
+    pub end: i64,
+    pub updated_at: i64, // Time up to which the subscription has been accounted
+    pub live_secs: i64, // Accounted seconds during which the server was online
+    pub bump: u8,
+    pub disputed: u64, // Part of `paid` the client has contested, see open_dispute
+}
+
+impl Subscription {
+    // Account for the time since the last update, of which only the part before `live_until`
+    // is paid for. Returns the amount newly owed to the server.
+    pub fn accrue(&mut self, now: i64, live_until: i64) -> Result<u64> {
+        let until = now.min(self.end);
+        if until <= self.updated_at {
+            return Ok(0);
+        }
+        let live_end = live_until.clamp(self.updated_at, until);
+        self.live_secs += live_end - self.updated_at;
+        self.updated_at = until;
+
+        let earned = (self.amount as u128 * self.live_secs as u128
+            / (self.end - self.start) as u128) as u64;
+        let owed = earned - self.paid;
+        self.paid = earned;
+        Ok(owed)
+    }
+}
+
+// Work done by a server over one epoch, PDA [USAGE_SEED, server, epoch (u64 LE)]
+#[account]
+#[derive(InitSpace)]
+pub struct ServerUsage {
+    pub version: u8,
+    pub server: Pubkey,
+    pub epoch: u64,
+    pub bytes_served: u64,
+    pub requests: u64,
+    pub uptime_bps: u16, // Share of the epoch the server was reachable
+    pub reporter: Pubkey, // Usage oracle that attested the record
+    pub slot: u64,
+    pub unix_timestamp: i64,
+    pub bump: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct UsageReport {
+    pub bytes_served: u64,
+    pub requests: u64,
+    pub uptime_bps: u16,
+}
+
+// Account layouts before versioning, read by the migrate_*_to_v2 instructions
+pub const MAIN_ACCOUNT_V1_SPACE: usize = 8 + 8 + 4 + 1;
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct MainAccountV1 {
+    pub total_stake: u64,
+    pub total_users: u32,
+    pub initialized: bool,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct InfoAccountV1 {
+    pub initialized: bool,
+    pub owner: Pubkey,
+    pub stake: u64,
+    pub total: u64,
+    pub total_delegators: u32,
+    pub name: String,
+    pub serverkey: Vec<u8>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct DelegatedAccountV1 {
+    pub initialized: bool,
+    pub delegator: Pubkey,
+    pub owner: Pubkey,
+    pub stake: u64,
+}
+
+// serde only derives arrays of up to 32 elements, longer byte arrays go through this
+#[cfg(feature = "serde")]
+mod serde_byte_array {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        bytes: &[u8; N],
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(bytes)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> std::result::Result<[u8; N], D::Error> {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        bytes
+            .try_into()
+            .map_err(|_| serde::de::Error::custom("invalid byte array length"))
+    }
+}
+
+// Zero-copy: read in place instead of Borsh-deserialized, fields are fixed size and padded
+// explicitly so the layout has no implicit gaps
+#[account(zero_copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MainAccount {
+    pub total_stake: u64,
+    pub total_users: u32,
+    pub initialized: u8,
+    pub mint_count: u8, // Number of used entries in mint_totals
+    pub version: u8,
+    pub _padding: [u8; 1],
+    // Split of total_stake between operator self-stake and delegations, same units
+    pub total_self_stake: u64,
+    pub total_delegated: u64,
+    pub total_servers: u32, // Part of total_users that are servers, the rest are delegations
+    pub _padding2: [u8; 4],
+    // Approved collateral mints with the active stake held in each
+    pub mint_totals: [MintTotal; MAX_APPROVED_MINTS],
+    // Number of servers per STAKE_BUCKET_BOUNDS range of their total stake, servers are counted
+    // from their first stake change after this was added
+    pub servers_by_stake: [u32; STAKE_BUCKETS],
+    // Lifetime stake deposited into and withdrawn from servers, self stake and delegations, in
+    // the same units as total_stake. Bonds and restaked LSTs are not included.
+    pub total_deposited: u64,
+    pub total_withdrawn: u64,
+    // Lifetime server registrations and removals, total_servers is their difference
+    pub servers_opened: u32,
+    pub servers_closed: u32,
+}
+
+#[zero_copy]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MintTotal {
+    pub mint: Pubkey,
+    pub total_stake: u64,
+    pub self_stake: u64, // Part of total_stake staked by operators, the rest is delegated
+    pub rate: u64, // Staking token value of one base unit, scaled by RATE_PRECISION, 0 until published
+    pub rate_updated_at: i64,
+    pub class: u8, // CollateralClass
+    pub decimals: u8, // Checked against the expected value on approval, 0 if migrated from v1
+    pub _padding: [u8; 6],
+}
+
+impl MintTotal {
+    pub fn new(
+        mint: Pubkey,
+        class: CollateralClass,
+        decimals: u8,
+        rate: u64,
+        rate_updated_at: i64,
+    ) -> Self {
+        Self {
+            mint,
+            total_stake: 0,
+            self_stake: 0,
+            rate,
+            rate_updated_at,
+            class: class as u8,
+            decimals,
+            _padding: [0; 6],
+        }
+    }
+
+    pub fn class(&self) -> CollateralClass {
+        match self.class {
+            1 => CollateralClass::LiquidStaking,
+            2 => CollateralClass::Bond,
+            _ => CollateralClass::Primary,
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CollateralClass {
+    #[default]
+    Primary, // Server stake and delegations
+    LiquidStaking, // Extra LST collateral held next to the server stake
+    Bond, // Secondary asset required in dual-token mode
+}
+
+impl MainAccount {
+    pub fn approved_mints(&self) -> &[MintTotal] {
+        &self.mint_totals[..self.mint_count as usize]
+    }
+
+    fn entry_mut(&mut self, mint: &Pubkey) -> Result<&mut MintTotal> {
+        let count = self.mint_count as usize;
+        self.mint_totals[..count]
+            .iter_mut()
+            .find(|entry| entry.mint == *mint)
+            .ok_or(CustomError::InvalidMint.into())
+    }
+
+    pub fn add_mint(&mut self, entry: MintTotal) -> Result<()> {
+        let count = self.mint_count as usize;
+        require!(count < MAX_APPROVED_MINTS, CustomError::TooManyMints);
+        self.mint_totals[count] = entry;
+        self.mint_count += 1;
+        Ok(())
+    }
+
+    pub fn is_mint_approved(&self, mint: &Pubkey) -> bool {
+        self.approved_mints().iter().any(|entry| entry.mint == *mint)
+    }
+
+    pub fn mint_class(&self, mint: &Pubkey) -> Option<CollateralClass> {
+        self.approved_mints()
+            .iter()
+            .find(|entry| entry.mint == *mint)
+            .map(|entry| entry.class())
+    }
+
+    // Value of `amount` base units of `mint` in base units of the staking token
+    pub fn to_common_units(&self, mint: &Pubkey, amount: u64) -> Result<u64> {
+        let rate = self.rate(mint)?;
+        let value = amount as u128 * rate as u128 / RATE_PRECISION as u128;
+        u64::try_from(value).map_err(|_| CustomError::NumberOverflow.into())
+    }
+
+    // Base units of `mint` worth `value` base units of the staking token
+    pub fn from_common_units(&self, mint: &Pubkey, value: u64) -> Result<u64> {
+        let rate = self.rate(mint)?;
+        let amount = value as u128 * RATE_PRECISION as u128 / rate as u128;
+        u64::try_from(amount).map_err(|_| CustomError::NumberOverflow.into())
+    }
+
+    fn rate(&self, mint: &Pubkey) -> Result<u64> {
+        let entry = self
+            .approved_mints()
+            .iter()
+            .find(|entry| entry.mint == *mint)
+            .ok_or(CustomError::InvalidMint)?;
+        require!(entry.rate > 0, CustomError::ExchangeRateNotSet);
+        Ok(entry.rate)
+    }
+
+    // Reject a rate published more than `max_age_secs` before `now`, 0 for no limit. The staking
+    // token's rate is fixed and never ages.
+    pub fn check_rate_age(&self, mint: &Pubkey, max_age_secs: u64, now: i64) -> Result<()> {
+        if max_age_secs == 0 || *mint == self.approved_mints()[0].mint {
+            return Ok(());
+        }
+        let entry = self
+            .approved_mints()
+            .iter()
+            .find(|entry| entry.mint == *mint)
+            .ok_or(CustomError::InvalidMint)?;
+        require!(
+            now.saturating_sub(entry.rate_updated_at) <= max_age_secs as i64,
+            CustomError::ExchangeRateStale
+        );
+        Ok(())
+    }
+
+    pub fn set_rate(&mut self, mint: &Pubkey, rate: u64, now: i64) -> Result<()> {
+        let entry = self.entry_mut(mint)?;
+        entry.rate = rate;
+        entry.rate_updated_at = now;
+        self.refresh_total_stake()
+    }
+
+    // User and server counters. A removal saturates at zero instead of failing, so a drifted
+    // counter can't keep anyone from leaving; recount repairs the drift.
+    pub fn add_user(&mut self, server: bool) -> Result<()> {
+        self.total_users = self
+            .total_users
+            .checked_add(1)
+            .ok_or(CustomError::NumberOverflow)?;
+        if server {
+            self.total_servers = self
+                .total_servers
+                .checked_add(1)
+                .ok_or(CustomError::NumberOverflow)?;
+            self.servers_opened = self.servers_opened.saturating_add(1);
+        }
+        Ok(())
+    }
+
+    pub fn remove_user(&mut self, server: bool) {
+        self.total_users = self.total_users.saturating_sub(1);
+        if server {
+            self.total_servers = self.total_servers.saturating_sub(1);
+            self.servers_closed = self.servers_closed.saturating_add(1);
+        }
+    }
+
+    // Lifetime flow counters, never decreasing. They saturate so they can't fail a transfer.
+    pub fn count_deposit(&mut self, mint: &Pubkey, amount: u64) -> Result<()> {
+        let value = self.to_common_units(mint, amount)?;
+        self.total_deposited = self.total_deposited.saturating_add(value);
+        Ok(())
+    }
+
+    pub fn count_withdrawal(&mut self, mint: &Pubkey, amount: u64) -> Result<()> {
+        let value = self.to_common_units(mint, amount)?;
+        self.total_withdrawn = self.total_withdrawn.saturating_add(value);
+        Ok(())
+    }
+
+    // Move a server between servers_by_stake buckets, given as index + 1 with 0 for none.
+    // Saturates like remove_user.
+    pub fn move_stake_bucket(&mut self, from: u8, to: u8) {
+        if from == to {
+            return;
+        }
+        if let Some(count) = from.checked_sub(1).map(|i| &mut self.servers_by_stake[i as usize]) {
+            *count = count.saturating_sub(1);
+        }
+        if let Some(count) = to.checked_sub(1).map(|i| &mut self.servers_by_stake[i as usize]) {
+            *count = count.saturating_add(1);
+        }
+    }
+
+    // Replace the counters and the per-mint totals with a finished recount
+    pub fn apply_recount(&mut self, recount: &Recount) -> Result<()> {
+        self.total_users = recount.users;
+        self.total_servers = recount.servers;
+        let count = self.mint_count as usize;
+        for (i, entry) in self.mint_totals[..count].iter_mut().enumerate() {
+            entry.total_stake = recount.total_stake[i];
+            entry.self_stake = recount.self_stake[i];
+        }
+        self.refresh_total_stake()
+    }
+
+    // Keep the global and the per-mint totals in step
+    pub fn add_stake(&mut self, mint: &Pubkey, amount: u64) -> Result<()> {
+        let entry = self.entry_mut(mint)?;
+        entry.total_stake = entry
+            .total_stake
+            .checked_add(amount)
+            .ok_or(CustomError::NumberOverflow)?;
+        self.refresh_total_stake()
+    }
+
+    pub fn sub_stake(&mut self, mint: &Pubkey, amount: u64) -> Result<()> {
+        let entry = self.entry_mut(mint)?;
+        entry.total_stake = entry
+            .total_stake
+            .checked_sub(amount)
+            .ok_or(CustomError::NumberOverflow)?;
+        self.refresh_total_stake()
+    }
+
+    // Same as add_stake/sub_stake for the operator's own stake
+    pub fn add_self_stake(&mut self, mint: &Pubkey, amount: u64) -> Result<()> {
+        let entry = self.entry_mut(mint)?;
+        entry.self_stake = entry
+            .self_stake
+            .checked_add(amount)
+            .ok_or(CustomError::NumberOverflow)?;
+        self.add_stake(mint, amount)
+    }
+
+    pub fn sub_self_stake(&mut self, mint: &Pubkey, amount: u64) -> Result<()> {
+        let entry = self.entry_mut(mint)?;
+        entry.self_stake = entry
+            .self_stake
+            .checked_sub(amount)
+            .ok_or(CustomError::NumberOverflow)?;
+        self.sub_stake(mint, amount)
+    }
+
+    // total_stake is the primary collateral valued in the staking token at the current rates,
+    // recomputed rather than accumulated so rate changes never leave it drifting
+    fn refresh_total_stake(&mut self) -> Result<()> {
+        let mut self_stake: u64 = 0;
+        let mut delegated: u64 = 0;
+        for entry in self
+            .approved_mints()
+            .iter()
+            .filter(|entry| entry.class() == CollateralClass::Primary && entry.total_stake > 0)
+        {
+            self_stake = self_stake
+                .checked_add(self.to_common_units(&entry.mint, entry.self_stake)?)
+                .ok_or(CustomError::NumberOverflow)?;
+            delegated = delegated
+                .checked_add(
+                    self.to_common_units(&entry.mint, entry.total_stake - entry.self_stake)?,
+                )
+                .ok_or(CustomError::NumberOverflow)?;
+        }
+        self.total_self_stake = self_stake;
+        self.total_delegated = delegated;
+        self.total_stake = self_stake
+            .checked_add(delegated)
+            .ok_or(CustomError::NumberOverflow)?;
+        Ok(())
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InfoAccount {
+    pub version: u8, // First so v1 accounts, which start with `initialized`, are told apart
+    pub initialized: bool,
+    // Fields used in memcmp filters come first, at the INFO_*_OFFSET offsets
+    pub owner: Pubkey,
+    pub mint: Pubkey, // Collateral mint of the server and its delegations
+    #[cfg_attr(feature = "serde", serde(with = "serde_byte_array"))]
+    pub serverkey: [u8; MAX_SERVERKEY_LEN],
+    pub serverkey_len: u8,
+    pub serverkey_hash: [u8; 32], // hash(serverkey), cached for PDA seed derivation
+    pub bump: u8, // Canonical PDA bump, stored at registration
+    pub stake: u64,
+    pub total: u64,
+    pub total_delegators: u32,
+    pub name: [u8; MAX_NAME_LEN], // UTF-8, zero padded
+    pub name_len: u8,
+    pub acc_reward_per_share: u128, // Rewards per unit of delegated weight, scaled by REWARD_PRECISION
+    pub delegate_minimum: u64, // Server policy in base units, 0 falls back to the network minimum
+    pub delegated_weight: u64, // Sum of the lockup-boosted reward weights of all delegations
+    pub activating_stake: u64, // Delegated stake queued for the next epoch, not yet in `total`
+    pub complaints: u32, // Number of delegator flags received, input for reputation
+    pub restaked_mint: Pubkey, // LST held as extra collateral, see deposit_lst
+    pub restaked: u64,
+    pub bond: u64, // Secondary bond held in the bond vault, in base units of config.bond_mint
+    pub reward_dust: u64, // Funded rewards lost to rounding, swept to the treasury
+    pub delegated: u64, // Active delegated stake, total = stake + delegated
+    pub delegation_tree: Pubkey, // Merkle tree of compressed delegations, default when not set up
+    pub delegation_leaves: u32, // Leaves appended to delegation_tree, the next leaf index
+    // Service revenue held in the revenue vault, see pay_server. `revenue_unreleased` vests
+    // linearly from `revenue_updated_at` to `revenue_stream_end` into `revenue_released`.
+    pub revenue_unreleased: u64,
+    pub revenue_released: u64, // Vested, paid to the operator by the next settle_revenue
+    pub revenue_updated_at: i64,
+    pub revenue_stream_end: i64,
+    pub revenue_total: u64, // Lifetime revenue paid to the server
+    pub commission_bps: u16, // Operator cut of the delegators' share of settled revenue
+    pub last_heartbeat: i64, // Unix timestamp of the operator's last heartbeat
+    pub channel_locked: u64, // Part of `stake` backing open payment channels, not withdrawable
+    pub service_failures: u32, // Failed availability proofs, see penalize_service_failure
+    pub last_failure_epoch: u64,
+    pub capabilities: u8, // CAPABILITY_* flags declared by the operator
+    pub uptime_bps: u16, // From the latest usage record, of epoch `uptime_epoch`
+    pub uptime_epoch: u64,
+    pub tier: ServiceTier, // Recomputed by refresh_tier whenever stake or uptime change
+    pub pricing: Pricing, // Published by the operator with update_pricing
+    pub endpoint: [u8; MAX_ENDPOINT_LEN], // Network address signed by the serverkey, zero padded
+    pub endpoint_len: u8,
+    pub attested_total: u64, // `total` in the last Wormhole stake attestation
+    pub attestations: u64,
+    // Operator revenue vesting in the revenue vault, see settle_revenue. Nothing is claimable
+    // before `vesting_cliff_end`, then `vesting_amount` releases linearly from `vesting_start` to
+    // `vesting_end`.
+    pub vesting_amount: u64,
+    pub vesting_claimed: u64,
+    pub vesting_start: i64,
+    pub vesting_cliff_end: i64,
+    pub vesting_end: i64,
+    pub revenue_disputed: u64, // Pending revenue frozen by open disputes, in the revenue vault
+    pub last_active_epoch: u64, // Last registration, self-stake change or heartbeat, see gc_account
+    pub nonce: u64, // Next nonce of the owner's relayed authorizations, see consume_nonce
+    // Token accounts withdraw may pay to, unused slots are Pubkey::default(). Once set, changes
+    // wait WHITELIST_CHANGE_DELAY_SECS after an announcement, see set_withdrawal_whitelist.
+    pub withdrawal_whitelist: [Pubkey; MAX_WITHDRAWAL_ADDRESSES],
+    pub whitelist_unlocks_at: i64, // 0 without an announced change
+    // Owner the PDA was derived from, set when ownership first moves, see seed_owner
+    pub registrant: Pubkey,
+    pub backup_key: Pubkey, // May claim the server after a recovery delay, default when unset
+    pub recovery_announced_at: i64, // 0 without a pending recovery
+    // Logo for delegation UIs, an https, ipfs or ar URI, zero padded. See set_icon_uri.
+    #[cfg_attr(feature = "serde", serde(with = "serde_byte_array"))]
+    pub icon_uri: [u8; MAX_ICON_URI_LEN],
+    pub icon_uri_len: u8,
+    // Operator contact details encrypted to config.contact_key, opaque to the program and zero
+    // padded. Lets the admin and arbiter reach the operator during incidents, see set_contact.
+    #[cfg_attr(feature = "serde", serde(with = "serde_byte_array"))]
+    pub contact: [u8; MAX_CONTACT_LEN],
+    pub contact_len: u8,
+    pub software_version: SoftwareVersion, // Last reported by the node, see report_version
+    pub version_reported_at: i64,
+    // Client idempotency key of the registration, 0 when none was given. add_server with the same
+    // nonce on the existing server succeeds without depositing again.
+    pub registration_nonce: u64,
+    // M-of-N signer set for institutionally operated nodes, see set_signer_set. While the
+    // threshold is non-zero, withdrawals and removal need that many of these keys to sign.
+    pub signers: [Pubkey; MAX_SERVER_SIGNERS],
+    pub signer_count: u8,
+    pub signer_threshold: u8, // 0 when the owner key alone is enough
+    // Hot key for day-to-day operation, see OperateServer. It never controls funds, default
+    // when not set.
+    pub operator_key: Pubkey,
+    pub exit_announced_at: i64, // 0 unless the owner announced leaving, see announce_exit
+    pub entry_fee_bps: u16, // Share of each d_deposit paid to the owner, see set_entry_fee
+    pub entry_fees_collected: u64, // Base units of the server mint, lifetime
+    pub stake_bucket: u8, // Index + 1 of its MainAccount.servers_by_stake bucket, 0 if not counted
+    // Lifetime stake deposited and withdrawn, self stake and delegations, base units of `mint`
+    pub total_deposited: u64,
+    pub total_withdrawn: u64,
+    pub withdrawals_frozen_until: i64, // Set when ownership moves, see reassign_owner
+    // Latest reward distributions, the oldest overwritten first at `reward_history_next`. Lets a
+    // lock that ran out stop earning its boost where it expired, see settle_expired_lockup.
+    pub reward_history: [RewardCheckpoint; REWARD_HISTORY_LEN],
+    pub reward_history_next: u8,
+    // Ends of the list of the server's delegations, default while it is empty. See
+    // link_delegation.
+    pub delegations_head: Pubkey,
+    pub delegations_tail: Pubkey,
+    pub linked_delegations: u32,
+    pub event_seq: u64, // Number of the server's last deposit or withdrawal event
+}
+
+impl InfoAccount {
+    // Saturating like MainAccount::remove_user
+    pub fn remove_delegator(&mut self) {
+        self.total_delegators = self.total_delegators.saturating_sub(1);
+    }
+
+    // Number the server's next deposit or withdrawal event. The numbers are consecutive per
+    // server, so an indexer that lost an event to RPC log truncation sees the gap.
+    pub fn next_event_seq(&mut self) -> u64 {
+        self.event_seq = self.event_seq.wrapping_add(1);
+        self.event_seq
+    }
+
+    // Whether the delegation at `key` is in the server's list. Every entry but the head has a
+    // `prev`.
+    pub fn lists(&self, key: &Pubkey, delegation: &DelegatedAccount) -> bool {
+        delegation.prev != Pubkey::default() || self.delegations_head == *key
+    }
+
+    // Owner key in the PDA seeds. The address never changes, so after a recovery the seeds keep
+    // using the key the server was registered with.
+    pub fn seed_owner(&self) -> Pubkey {
+        if self.registrant == Pubkey::default() {
+            self.owner
+        } else {
+            self.registrant
+        }
+    }
+
+    // Hand the server to `owner`, see claim_recovery and claim_guardian_recovery. Settings the
+    // lost key made for its own safety, the backup key and the whitelist, are dropped, and so is
+    // the operator key it named. Withdrawals then wait the WHITELIST_CHANGE_DELAY_SECS the
+    // dropped whitelist would have imposed.
+    pub fn reassign_owner(&mut self, owner: Pubkey, now: i64) {
+        self.registrant = self.seed_owner();
+        self.owner = owner;
+        self.operator_key = Pubkey::default();
+        self.backup_key = Pubkey::default();
+        self.recovery_announced_at = 0;
+        self.withdrawal_whitelist = [Pubkey::default(); MAX_WITHDRAWAL_ADDRESSES];
+        self.whitelist_unlocks_at = 0;
+        self.withdrawals_frozen_until = now.saturating_add(WHITELIST_CHANGE_DELAY_SECS);
+    }
+
+    pub fn withdrawals_frozen(&self, now: i64) -> bool {
+        now < self.withdrawals_frozen_until
+    }
+
+    pub fn has_withdrawal_whitelist(&self) -> bool {
+        self.withdrawal_whitelist
+            .iter()
+            .any(|address| *address != Pubkey::default())
+    }
+
+    pub fn serverkey(&self) -> &[u8] {
+        &self.serverkey[..self.serverkey_len as usize]
+    }
+
+    // Whether `key` may run OperateServer instructions
+    pub fn is_operator(&self, key: &Pubkey) -> bool {
+        *key == self.owner || (self.operator_key != Pubkey::default() && *key == self.operator_key)
+    }
+
+    pub fn name(&self) -> &[u8] {
+        &self.name[..self.name_len as usize]
+    }
+
+    pub fn endpoint(&self) -> &[u8] {
+        &self.endpoint[..self.endpoint_len as usize]
+    }
+
+    pub fn icon_uri(&self) -> &[u8] {
+        &self.icon_uri[..self.icon_uri_len as usize]
+    }
+
+    pub fn contact(&self) -> &[u8] {
+        &self.contact[..self.contact_len as usize]
+    }
+
+    pub fn set_name(&mut self, name: &str) -> Result<()> {
+        require!(name.len() <= MAX_NAME_LEN, CustomError::NameTooLong);
+        self.name = [0; MAX_NAME_LEN];
+        self.name[..name.len()].copy_from_slice(name.as_bytes());
+        self.name_len = name.len() as u8;
+        Ok(())
+    }
+
+    // Callers check the length against MAX_SERVERKEY_LEN
+    pub fn set_serverkey(&mut self, serverkey: &[u8]) {
+        self.serverkey = [0; MAX_SERVERKEY_LEN];
+        self.serverkey[..serverkey.len()].copy_from_slice(serverkey);
+        self.serverkey_len = serverkey.len() as u8;
+    }
+
+    // Effective minimum for a single delegated deposit into this server
+    // `amount` in whole tokens, returns it in base units
+    pub fn set_delegate_minimum(&mut self, amount: u64, decimals: u8) -> Result<u64> {
+        let amount_in_minimum_units = to_minimum_units(amount, decimals)?;
+        require!(
+            amount_in_minimum_units >= to_minimum_units(DELEGATE_MINIMUM_STAKE_TOKENS, decimals)?,
+            CustomError::DelegateMinimumTooLow
+        );
+        self.delegate_minimum = amount_in_minimum_units;
+        Ok(amount_in_minimum_units)
+    }
+
+    pub fn delegate_minimum(&self, decimals: u8) -> Result<u64> {
+        Ok(self
+            .delegate_minimum
+            .max(to_minimum_units(DELEGATE_MINIMUM_STAKE_TOKENS, decimals)?))
+    }
+
+    // Part of `amount` of service revenue owed to the delegators: their share of the total stake,
+    // less the operator's commission
+    pub fn delegator_share(&self, amount: u64) -> Result<u64> {
+        if self.delegated_weight == 0 || self.total == 0 {
+            return Ok(0);
+        }
+        let gross = (amount as u128 * self.delegated as u128 / self.total as u128) as u64;
+        Ok(gross - fee_amount(gross, self.commission_bps)?)
+    }
+
+    // Credit rewards already in the reward vault to this server's delegators
+    pub fn distribute_rewards(&mut self, amount: u64, now: i64) -> Result<()> {
+        require!(self.delegated_weight > 0, CustomError::NoDelegatedStake);
+
+        let next = self.reward_history_next as usize;
+        self.reward_history[next] = RewardCheckpoint {
+            unix_timestamp: now,
+            acc_reward_per_share: self.acc_reward_per_share,
+        };
+        self.reward_history_next = ((next + 1) % REWARD_HISTORY_LEN) as u8;
+
+        let increment = (amount as u128)
+            .checked_mul(REWARD_PRECISION)
+            .ok_or(CustomError::NumberOverflow)?
+            / self.delegated_weight as u128;
+        self.acc_reward_per_share = self
+            .acc_reward_per_share
+            .checked_add(increment)
+            .ok_or(CustomError::NumberOverflow)?;
+
+        // The rounded-off remainder can never be claimed, keep it for treasury_sweep_dust
+        let distributed = (increment * self.delegated_weight as u128 / REWARD_PRECISION) as u64;
+        self.reward_dust += amount - distributed;
+
+        Ok(())
+    }
+
+    // acc_reward_per_share as it stood at `at`: before the first remembered distribution after
+    // it, the current value when there is none. None once the history no longer reaches back to
+    // `at`, the full ring's oldest distribution being later.
+    pub fn reward_per_share_at(&self, at: i64) -> Option<u128> {
+        let oldest = self.reward_history_next as usize;
+        let wrapped = self.reward_history[oldest].unix_timestamp != 0;
+        match (0..REWARD_HISTORY_LEN)
+            .map(|i| &self.reward_history[(oldest + i) % REWARD_HISTORY_LEN])
+            .position(|checkpoint| checkpoint.unix_timestamp > at)
+        {
+            Some(0) if wrapped => None,
+            Some(i) => {
+                Some(self.reward_history[(oldest + i) % REWARD_HISTORY_LEN].acc_reward_per_share)
+            }
+            None => Some(self.acc_reward_per_share),
+        }
+    }
+
+    // Delegated plus self stake (including queued stake) must stay within `multiple` x self stake
+    pub fn check_self_bond(&self, multiple: u16) -> Result<()> {
+        if multiple == 0 {
+            return Ok(());
+        }
+        let total = self.total as u128 + self.activating_stake as u128;
+        require!(
+            total <= self.stake as u128 * multiple as u128,
+            CustomError::SelfBondRatioExceeded
+        );
+        Ok(())
+    }
+
+    // Self stake under MINIMUM_STAKE with delegations open is only allowed `notice` seconds after
+    // announce_exit
+    pub fn check_exit_notice(
+        &self,
+        main_account: &MainAccount,
+        notice: i64,
+        now: i64,
+    ) -> Result<()> {
+        if notice == 0
+            || self.total_delegators == 0
+            || main_account.to_common_units(&self.mint, self.stake)? >= MINIMUM_STAKE
+        {
+            return Ok(());
+        }
+        require!(
+            self.exit_announced_at != 0 && now >= self.exit_announced_at.saturating_add(notice),
+            CustomError::ExitNoticeRequired
+        );
+        Ok(())
+    }
+
+    // Uptime of the latest usage record (full before the first one) less FAILURE_REPUTATION_BPS
+    // per service failure
+    pub fn reputation_bps(&self) -> u16 {
+        let uptime = if self.uptime_epoch == 0 && self.uptime_bps == 0 {
+            MAX_BPS
+        } else {
+            self.uptime_bps
+        };
+        let penalty = (self.service_failures as u64 * FAILURE_REPUTATION_BPS as u64)
+            .min(MAX_BPS as u64) as u16;
+        uptime.saturating_sub(penalty)
+    }
+
+    // Stake of the server's mint coming in or going out, on the server and the global counters
+    pub fn count_deposit(&mut self, main_account: &mut MainAccount, amount: u64) -> Result<()> {
+        self.total_deposited = self.total_deposited.saturating_add(amount);
+        main_account.count_deposit(&self.mint, amount)
+    }
+
+    pub fn count_withdrawal(&mut self, main_account: &mut MainAccount, amount: u64) -> Result<()> {
+        self.total_withdrawn = self.total_withdrawn.saturating_add(amount);
+        main_account.count_withdrawal(&self.mint, amount)
+    }
+
+    // After a change to the server's stake: the tier, and the histogram bucket it is counted in
+    pub fn refresh_stake(&mut self, main_account: &mut MainAccount) -> Result<()> {
+        self.refresh_tier(main_account)?;
+        let total = main_account.to_common_units(&self.mint, self.total)?;
+        let bucket = STAKE_BUCKET_BOUNDS.iter().filter(|bound| total >= **bound).count() as u8 + 1;
+        main_account.move_stake_bucket(self.stake_bucket, bucket);
+        self.stake_bucket = bucket;
+        Ok(())
+    }
+
+    pub fn refresh_tier(&mut self, main_account: &MainAccount) -> Result<()> {
+        let self_stake = main_account.to_common_units(&self.mint, self.stake)?;
+        let total = main_account.to_common_units(&self.mint, self.total)?;
+        self.tier = if self_stake < MINIMUM_STAKE {
+            ServiceTier::None
+        } else if total >= GOLD_TIER_STAKE && self.uptime_bps >= GOLD_TIER_UPTIME_BPS {
+            ServiceTier::Gold
+        } else if total >= SILVER_TIER_STAKE && self.uptime_bps >= SILVER_TIER_UPTIME_BPS {
+            ServiceTier::Silver
+        } else {
+            ServiceTier::Bronze
+        };
+        Ok(())
+    }
+
+    // Move the part of the revenue stream that has vested by `now` to revenue_released
+    pub fn accrue_revenue(&mut self, now: i64) -> Result<()> {
+        let vested = if now >= self.revenue_stream_end {
+            self.revenue_unreleased
+        } else if now > self.revenue_updated_at {
+            (self.revenue_unreleased as u128 * (now - self.revenue_updated_at) as u128
+                / (self.revenue_stream_end - self.revenue_updated_at) as u128) as u64
+        } else {
+            0
+        };
+        self.revenue_unreleased -= vested;
+        self.revenue_released = self
+            .revenue_released
+            .checked_add(vested)
+            .ok_or(CustomError::NumberOverflow)?;
+        self.revenue_updated_at = now;
+        Ok(())
+    }
+
+    // Vested and not yet claimed operator revenue at `now`
+    pub fn claimable_vesting(&self, now: i64) -> u64 {
+        let vested = if now < self.vesting_cliff_end {
+            0
+        } else if now >= self.vesting_end {
+            self.vesting_amount
+        } else {
+            (self.vesting_amount as u128 * (now - self.vesting_start) as u128
+                / (self.vesting_end - self.vesting_start) as u128) as u64
+        };
+        vested.saturating_sub(self.vesting_claimed)
+    }
+
+    // Claim what has vested (returned) and add `amount` to the schedule. The unvested rest and
+    // `amount` vest together from a start weighted by the two, so new revenue doesn't restart the
+    // cliff of older revenue.
+    pub fn add_vesting(
+        &mut self,
+        amount: u64,
+        now: i64,
+        cliff_secs: u64,
+        secs: u64,
+    ) -> Result<u64> {
+        let claimable = self.claimable_vesting(now);
+        self.vesting_claimed += claimable;
+        if amount == 0 {
+            return Ok(claimable);
+        }
+
+        let unvested = self.vesting_amount - self.vesting_claimed;
+        let total = unvested.checked_add(amount).ok_or(CustomError::NumberOverflow)?;
+        let start = if unvested == 0 {
+            now
+        } else {
+            self.vesting_start
+                + ((now - self.vesting_start) as i128 * amount as i128 / total as i128) as i64
+        };
+        self.vesting_amount = total;
+        self.vesting_claimed = 0;
+        self.vesting_start = start;
+        self.vesting_cliff_end = start
+            .checked_add(cliff_secs as i64)
+            .ok_or(CustomError::NumberOverflow)?;
+        self.vesting_end = start
+            .checked_add(cliff_secs.max(secs) as i64)
+            .ok_or(CustomError::NumberOverflow)?;
+        Ok(claimable)
+    }
+
+    pub fn update_delegated_weight(&mut self, old_weight: u64, new_weight: u64) -> Result<()> {
+        self.delegated_weight = self
+            .delegated_weight
+            .checked_sub(old_weight)
+            .and_then(|weight| weight.checked_add(new_weight))
+            .ok_or(CustomError::NumberOverflow)?;
+        Ok(())
+    }
+}
+
+#[account]
+#[derive(Default, InitSpace)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DelegatedAccount {
+    pub version: u8, // First so v1 accounts, which start with `initialized`, are told apart
+    pub initialized: bool,
+    // Fields used in memcmp filters come first, at the DELEGATED_*_OFFSET offsets
+    pub delegator: Pubkey, // Server info account
+    pub owner: Pubkey,
+    pub mint: Pubkey,
+    pub stake: u64,
+    pub referrer: Pubkey, // Pubkey::default() when the delegation was not referred
+    pub referral_bps: u16,
+    pub reward_debt: u128,
+    pub pending_rewards: u64,
+    pub referral_pending: u64,
+    pub auto_compound: bool, // Reward crank restakes into the vault instead of leaving rewards claimable
+    pub lockup: LockupTier,
+    pub lock_until: i64, // d_withdraw is rejected before this unix timestamp
+    pub activating_stake: u64, // Deposited but not yet active, excluded from `stake` and rewards
+    pub activation_epoch: u64,
+    pub last_flag_epoch: Option<u64>,
+    pub receipt_minted: u64, // Receipt tokens outstanding for this position
+    pub bump: u8, // Canonical PDA bump, stored when the position is opened
+    // Neighbours in the server's delegation list, default at the ends and while not linked. Not
+    // part of the compressed leaf, see delegation_leaf.
+    pub prev: Pubkey,
+    pub next: Pubkey,
+}
+
+// Sub-operations of `batch`, all against the server of the Deposit accounts
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub enum BatchOp {
+    Deposit { amount: u64 }, // Whole tokens, as in deposit
+    UpdateName { name: String },
+    SetDelegateMinimum { amount: u64 }, // Whole tokens
+}
+
+// Arguments of set_partner_adapter, see PartnerAdapter
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct PartnerAdapterParams {
+    pub position_discriminator: [u8; 8],
+    pub owner_offset: u16,
+    pub amount_offset: u16,
+    pub bonus_bps: u16,
+    pub max_credit: u64,
+    pub enabled: bool,
+    pub lockup: LockupTier,
+}
+
+// Operation priced by `quote` or checked by `diagnose`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub enum QuoteKind {
+    Deposit { lockup: LockupTier }, // The lockup only applies to delegations
+    Withdraw,
+}
+
+// Return data of get_network_stats, stake amounts in staking token base units
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct NetworkStats {
+    pub total_stake: u64,
+    pub total_self_stake: u64,
+    pub total_delegated: u64,
+    pub total_users: u32,
+    pub total_servers: u32,
+    pub total_delegations: u32,
+    pub average_stake_per_server: u64, // total_stake / total_servers
+    pub delegation_ratio_bps: u16, // Share of total_stake that is delegated
+}
+
+// Return data of get_server_info, amounts in base units of `mint`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct ServerInfo {
+    pub server: Pubkey,
+    pub owner: Pubkey,
+    pub mint: Pubkey,
+    pub stake: u64,
+    pub delegated: u64,
+    pub total: u64,
+    pub activating_stake: u64,
+    pub total_delegators: u32,
+    pub vault_balance: u64, // 0 when the vault was closed
+    pub status: ServerStatus,
+    pub complaints: u32,
+    pub pending_rewards: u64, // Claimable by the passed delegation, 0 without one
+    pub capabilities: u8, // Declared capabilities whose minimum stake the server still meets
+    pub tier: ServiceTier,
+    pub pricing: Pricing,
+}
+
+// Type byte leading the payload of each bridge emitter message
+pub const BRIDGE_DELEGATION_MESSAGE: u8 = 1;
+pub const BRIDGE_WITHDRAWAL_MESSAGE: u8 = 2;
+
+// Payload of the bridge emitter's delegation messages after BRIDGE_DELEGATION_MESSAGE, Borsh
+// encoded. `amount` is in base units of the server's mint.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct BridgedDelegationMessage {
+    pub server: Pubkey,
+    pub foreign_owner: [u8; 32],
+    pub amount: u64,
+    pub lockup: LockupTier,
+}
+
+// Payload of the bridge emitter's withdrawal messages after BRIDGE_WITHDRAWAL_MESSAGE, Borsh
+// encoded
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct BridgedWithdrawalMessage {
+    pub server: Pubkey,
+    pub foreign_owner: [u8; 32],
+    pub amount: u64, // Base units of the server's mint
+    pub recipient: Pubkey, // Owner of the token account paid on Solana
+}
+
+// Payload of the Wormhole messages posted by attest_stake, Borsh encoded. Amounts are in base
+// units of `mint`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct StakeAttestation {
+    pub version: u8,
+    pub server: Pubkey,
+    pub owner: Pubkey,
+    pub serverkey_hash: [u8; 32],
+    pub mint: Pubkey,
+    pub stake: u64,
+    pub delegated: u64,
+    pub total: u64,
+    pub tier: ServiceTier,
+    pub epoch: u64,
+    pub slot: u64,
+}
+
+// acc_reward_per_share just before a distribution made at `unix_timestamp`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default)]
+#[derive(InitSpace)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RewardCheckpoint {
+    pub unix_timestamp: i64,
+    pub acc_reward_per_share: u128,
+}
+
+// Node software version, compared major first, then minor, then patch
+#[derive(
+    AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Default,
+)]
+#[derive(InitSpace)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SoftwareVersion {
+    pub major: u16,
+    pub minor: u16,
+    pub patch: u16,
+}
+
+// Resource prices of a server in base units of its mint, 0 when not offered
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, InitSpace)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pricing {
+    pub per_gb: u64,
+    pub per_hour: u64,
+    pub updated_at: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, InitSpace)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ServiceTier {
+    #[default]
+    None, // Self stake below MINIMUM_STAKE
+    Bronze,
+    Silver, // SILVER_TIER_STAKE total and SILVER_TIER_UPTIME_BPS uptime
+    Gold,   // GOLD_TIER_STAKE total and GOLD_TIER_UPTIME_BPS uptime
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ServerStatus {
+    Inactive,     // No self stake left
+    BelowMinimum, // Self stake under MINIMUM_STAKE, e.g. after an exchange rate drop
+    OverBonded,   // Delegations exceed the self-bond ratio, new delegations are rejected
+    Active,
+}
+
+// Return data of get_pool_balance, amounts in base units of `mint`. The share price only moves
+// with sync_pool, so a caller can convert with total_assets / share_supply until then.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct PoolBalance {
+    pub pool: Pubkey,
+    pub mint: Pubkey,
+    pub share_mint: Pubkey,
+    pub total_assets: u64,
+    pub share_supply: u64,
+    pub idle: u64, // Upper bound of what can be withdrawn right now
+    pub synced_at: i64,
+    pub owner: Pubkey, // Pubkey::default() without a share account
+    pub shares: u64,
+    pub assets: u64,
+    pub max_withdraw: u64,
+}
+
+// Return data of get_stake_of, amounts in base units of `mint`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct StakeOf {
+    pub server: Pubkey,
+    pub owner: Pubkey,
+    pub mint: Pubkey,
+    pub stake: u64, // Active stake, the only part that counts towards the server
+    pub activating_stake: u64,
+    pub lock_until: i64, // 0 without a lockup
+    pub server_total: u64,
+}
+
+// Return data of validate_deposit and validate_d_deposit
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct PreflightResult {
+    pub valid: bool,
+    // Code the instruction would fail with: a CustomError or Anchor constraint code, or the
+    // runtime error value for program errors. 0 when valid.
+    pub error_code: u32,
+}
+
+impl From<Result<()>> for PreflightResult {
+    fn from(check: Result<()>) -> Self {
+        let error_code = match check {
+            Ok(()) => 0,
+            Err(Error::AnchorError(error)) => error.error_code_number,
+            Err(Error::ProgramError(error)) => u64::from(error.program_error) as u32,
+        };
+        Self {
+            valid: error_code == 0,
+            error_code,
+        }
+    }
+}
+
+// Return data of quote, amounts in base units of `mint`. The balances are the ones the quoted
+// operation leaves behind, for the operator stake or the owner's delegation.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct Quote {
+    pub server: Pubkey,
+    pub owner: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub fee: u64, // Withdrawal fee kept by the treasury, or the server's entry fee on a d_deposit
+    pub net_amount: u64, // What reaches the vault, or the owner's wallet on a withdrawal
+    pub stake: u64,
+    pub activating_stake: u64,
+    pub lock_until: i64, // 0 without a lockup
+    pub server_total: u64,
+}
+
+// Return data of diagnose: the codes of the rules the operation would break, in the order it
+// checks them, each as PreflightResult reports it. Empty when the operation would go through.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct Diagnosis {
+    pub violations: Vec<u32>,
+}
+
+impl Diagnosis {
+    fn check(&mut self, check: Result<()>) {
+        let result = PreflightResult::from(check);
+        if !result.valid {
+            self.violations.push(result.error_code);
+        }
+    }
+
+    fn require(&mut self, condition: bool, error: CustomError) {
+        if !condition {
+            self.check(Err(error.into()));
+        }
+    }
+}
+
+#[derive(
+    AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Default,
+)]
+#[derive(InitSpace)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LockupTier {
+    #[default]
+    None,
+    ThreeMonths,
+    SixMonths,
+    TwelveMonths,
+}
+
+impl LockupTier {
+    pub fn duration(&self) -> i64 {
+        const DAY: i64 = 24 * 60 * 60;
+        match self {
+            LockupTier::None => 0,
+            LockupTier::ThreeMonths => 90 * DAY,
+            LockupTier::SixMonths => 180 * DAY,
+            LockupTier::TwelveMonths => 365 * DAY,
+        }
+    }
+
+    // Reward multiplier in basis points, 10,000 is 1x
+    pub fn multiplier_bps(&self) -> u16 {
+        match self {
+            LockupTier::None => 10_000,
+            LockupTier::ThreeMonths => 11_000,
+            LockupTier::SixMonths => 12_500,
+            LockupTier::TwelveMonths => 15_000,
+        }
+    }
+}
+
+impl DelegatedAccount {
+    // Stake boosted by the lockup multiplier, used to share rewards
+    pub fn reward_weight(&self) -> u64 {
+        (self.stake as u128 * self.lockup.multiplier_bps() as u128 / MAX_BPS as u128) as u64
+    }
+
+    // Lock the position for the chosen tier, never shortening an active lock. The whole position
+    // earns the boost of the tier it keeps, so choosing a tier locks it for that tier's duration.
+    // A top-up without a tier joins the lock as it stands.
+    pub fn apply_lockup(&mut self, tier: LockupTier, now: i64) {
+        self.expire_lockup(now);
+        if tier == LockupTier::None {
+            return;
+        }
+        if tier > self.lockup {
+            self.lockup = tier;
+        }
+        self.lock_until = self.lock_until.max(now + self.lockup.duration());
+    }
+
+    // Settle rewards and drop the boost of a lock that ran out. Distributions up to `lock_until`
+    // are earned at the boosted weight, later ones at the plain stake. The boost the server still
+    // counted for those later ones goes to its reward dust, nobody earned it.
+    pub fn settle_expired_lockup(
+        &mut self,
+        info_account: &mut InfoAccount,
+        now: i64,
+    ) -> Result<()> {
+        if self.lockup != LockupTier::None && now >= self.lock_until {
+            let weight = self.reward_weight() as u128;
+            // Not before the last checkpoint, which may already be past the expiry. Once the
+            // history has moved past the expiry the checkpoint is all that is known, so the boost
+            // stops there.
+            let mut expired_at = 0;
+            if weight > 0 {
+                expired_at = self
+                    .reward_debt
+                    .checked_mul(REWARD_PRECISION)
+                    .ok_or(CustomError::NumberOverflow)?
+                    .div_ceil(weight);
+            }
+            if let Some(at_expiry) = info_account.reward_per_share_at(self.lock_until) {
+                expired_at = expired_at.max(at_expiry);
+            }
+            let expired_at = expired_at.min(info_account.acc_reward_per_share);
+            self.settle_rewards(expired_at)?;
+            self.lockup = LockupTier::None;
+
+            let unearned = (weight - self.reward_weight() as u128)
+                .checked_mul(info_account.acc_reward_per_share - expired_at)
+                .ok_or(CustomError::NumberOverflow)?
+                / REWARD_PRECISION;
+            info_account.reward_dust = info_account
+                .reward_dust
+                .checked_add(u64::try_from(unearned).map_err(|_| CustomError::NumberOverflow)?)
+                .ok_or(CustomError::NumberOverflow)?;
+            self.checkpoint_rewards(expired_at)?;
+        }
+        self.settle_rewards(info_account.acc_reward_per_share)
+    }
+
+    pub fn expire_lockup(&mut self, now: i64) {
+        if self.lockup != LockupTier::None && now >= self.lock_until {
+            self.lockup = LockupTier::None;
+        }
+    }
+
+    // Move rewards accrued since the last checkpoint into the claimable balances
+    pub fn settle_rewards(&mut self, acc_reward_per_share: u128) -> Result<()> {
+        let accrued = (self.reward_weight() as u128)
+            .checked_mul(acc_reward_per_share)
+            .ok_or(CustomError::NumberOverflow)?
+            / REWARD_PRECISION;
+        let earned = accrued
+            .checked_sub(self.reward_debt)
+            .ok_or(CustomError::NumberOverflow)?;
+
+        let referral = if self.referrer != Pubkey::default() {
+            earned * self.referral_bps as u128 / MAX_BPS as u128
+        } else {
+            0
+        };
+
+        self.pending_rewards = self
+            .pending_rewards
+            .checked_add(u64::try_from(earned - referral).map_err(|_| CustomError::NumberOverflow)?)
+            .ok_or(CustomError::NumberOverflow)?;
+        self.referral_pending = self
+            .referral_pending
+            .checked_add(u64::try_from(referral).map_err(|_| CustomError::NumberOverflow)?)
+            .ok_or(CustomError::NumberOverflow)?;
+        self.reward_debt = accrued;
+
+        Ok(())
+    }
+
+    // Reset the reward baseline after the stake changed
+    pub fn checkpoint_rewards(&mut self, acc_reward_per_share: u128) -> Result<()> {
+        self.reward_debt = (self.reward_weight() as u128)
+            .checked_mul(acc_reward_per_share)
+            .ok_or(CustomError::NumberOverflow)?
+            / REWARD_PRECISION;
+        Ok(())
+    }
+}
+
+#[event]
+pub struct MainAccountInitialized {
+    pub schema_version: u8,
+    pub admin: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ConfigUpdated {
+    pub schema_version: u8,
+    pub admin: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+// Numeric parameter, bps, seconds, epochs or amounts
+#[event]
+pub struct ConfigValueChanged {
+    pub schema_version: u8,
+    pub admin: Pubkey,
+    pub param: ConfigParam,
+    pub old_value: u64,
+    pub new_value: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+// Role, program or mint address, Pubkey::default() when unset
+#[event]
+pub struct ConfigAddressChanged {
+    pub schema_version: u8,
+    pub admin: Pubkey,
+    pub param: ConfigParam,
+    pub old_value: Pubkey,
+    pub new_value: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ConfigFlagToggled {
+    pub schema_version: u8,
+    pub admin: Pubkey,
+    pub param: ConfigParam,
+    pub old_value: bool,
+    pub new_value: bool,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+// 32-byte value, hash() of the Borsh encoding for larger ones
+#[event]
+pub struct ConfigDataChanged {
+    pub schema_version: u8,
+    pub admin: Pubkey,
+    pub param: ConfigParam,
+    pub old_value: [u8; 32],
+    pub new_value: [u8; 32],
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct MintApproved {
+    pub schema_version: u8,
+    pub admin: Pubkey,
+    pub mint: Pubkey,
+    pub class: CollateralClass,
+    pub decimals: u8,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct UsageRecorded {
+    pub schema_version: u8,
+    #[index]
+    pub server: Pubkey,
+    pub epoch: u64,
+    pub bytes_served: u64,
+    pub requests: u64,
+    pub uptime_bps: u16,
+    pub reporter: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct EntryFeePaid {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey, // Delegator
+    pub server: Pubkey,
+    pub fee: u64, // Base units, already taken off the staked amount
+    pub entry_fee_bps: u16,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct EntryFeeUpdated {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub server: Pubkey,
+    pub entry_fee_bps: u16,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ExitCancelled {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub server: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ExitAnnounced {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub server: Pubkey,
+    pub withdrawable_at: i64, // Earliest withdrawal below the minimum with delegations open
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct DelegationIntentCancelled {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub server: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct DelegationIntentExecuted {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub server: Pubkey,
+    pub executor: Pubkey,
+    pub amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct DelegationIntentCreated {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub server: Pubkey,
+    pub amount: u64,
+    pub expires_at: i64,
+    pub lockup: LockupTier,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct OperatorKeySet {
+    pub schema_version: u8,
+    #[index]
+    pub server: Pubkey,
+    pub owner: Pubkey,
+    pub operator_key: Pubkey, // Default when removed
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct SignerSetUpdated {
+    pub schema_version: u8,
+    #[index]
+    pub server: Pubkey,
+    pub owner: Pubkey,
+    pub signers: Vec<Pubkey>, // Empty when the set was removed
+    pub threshold: u8,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct TenureCreditClaimed {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub points: u64,
+    pub multiplier_bps: u16,
+    pub credited: u64,
+    pub root: [u8; 32],
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct PartnerPositionImported {
+    pub schema_version: u8,
+    #[index]
+    pub adapter: Pubkey,
+    pub owner: Pubkey,
+    pub partner_position: Pubkey,
+    pub delegator: Pubkey,
+    pub partner_stake: u64,
+    pub credited: u64,
+    pub total_stake: u64, // Main account totals after the operation
+    pub total_users: u32,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct PartnerAdapterUpdated {
+    pub schema_version: u8,
+    #[index]
+    pub adapter: Pubkey,
+    pub partner_program: Pubkey,
+    pub mint: Pubkey,
+    pub bonus_bps: u16,
+    pub max_credit: u64,
+    pub enabled: bool,
+    pub lockup: LockupTier,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct PositionMigrated {
+    pub schema_version: u8,
+    #[index]
+    pub legacy_position: Pubkey,
+    pub delegated_account: Pubkey,
+    pub owner: Pubkey,
+    pub delegator: Pubkey,
+    pub amount: u64,
+    pub stake: u64,
+    pub total_stake: u64, // Main account totals after the operation
+    pub total_users: u32,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct SoftwareVersionReported {
+    pub schema_version: u8,
+    #[index]
+    pub server: Pubkey,
+    pub version: SoftwareVersion,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ContactUpdated {
+    pub schema_version: u8,
+    #[index]
+    pub server: Pubkey,
+    pub contact_len: u8, // 0 when the contact was removed
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct IconUriUpdated {
+    pub schema_version: u8,
+    #[index]
+    pub server: Pubkey,
+    pub icon_uri: String, // Empty when the icon was removed
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct HandleSet {
+    pub schema_version: u8,
+    #[index]
+    pub node_handle: Pubkey,
+    pub handle: [u8; MAX_HANDLE_LEN],
+    pub handle_len: u8,
+    pub server: Pubkey, // Pubkey::default() when the handle was released
+    pub owner: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct VaultCreated {
+    pub schema_version: u8,
+    #[index]
+    pub vault: Pubkey,
+    pub authority: Pubkey,
+    pub mint: Pubkey,
+    pub payer: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct VaultClosed {
+    pub schema_version: u8,
+    #[index]
+    pub vault: Pubkey,
+    pub authority: Pubkey,
+    pub rent_destination: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct PoolInitialized {
+    pub schema_version: u8,
+    #[index]
+    pub pool: Pubkey,
+    pub mint: Pubkey,
+    pub share_mint: Pubkey,
+    pub manager: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct PoolDeposited {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub pool: Pubkey,
+    pub amount: u64,
+    pub shares: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct PoolWithdrawn {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub pool: Pubkey,
+    pub amount: u64,
+    pub shares: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct PoolSynced {
+    pub schema_version: u8,
+    #[index]
+    pub pool: Pubkey,
+    pub idle: u64,
+    pub delegated: u64,
+    pub share_supply: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct PortfolioRebalanced {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub portfolio: Pubkey,
+    pub weights: Vec<u16>,
+    pub moved: u64, // Base units that changed position
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct PortfolioCreated {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub portfolio: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct GuardiansSet {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub server: Pubkey,
+    pub guardians: Vec<Pubkey>, // Empty when the set was removed
+    pub threshold: u8,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct GuardianRecoveryApproved {
+    pub schema_version: u8,
+    #[index]
+    pub server: Pubkey,
+    pub guardian: Pubkey,
+    pub new_owner: Pubkey, // Pubkey::default() when the guardian withdrew its vote
+    pub approvals: u8,
+    pub proposed_owner: Pubkey,
+    pub approved_at: i64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct GuardianRecoveryCancelled {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub server: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct BackupKeySet {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub server: Pubkey,
+    pub backup_key: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct RecoveryAnnounced {
+    pub schema_version: u8,
+    #[index]
+    pub server: Pubkey,
+    pub owner: Pubkey,
+    pub backup_key: Pubkey,
+    pub claimable_at: i64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct RecoveryCancelled {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub server: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    #[account(
-        mut,
-        close = owner,
-        has_one = owner,
-        constraint = info_account.total == 0 @ CustomError::NonZeroBalance,
-        seeds = [
-            INFO_SEED,        // seed prefix
-            owner.key().as_ref(), // Use caller's public key as seed
-            &hash(info_account.serverkey.as_ref()).to_bytes(),
-        ],
-        bump,     
-    )]
-    pub info_account: Account<'info, InfoAccount>,
+#[event]
+pub struct OwnershipRecovered {
+    pub schema_version: u8,
+    #[index]
+    pub server: Pubkey,
+    pub previous_owner: Pubkey,
+    pub owner: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    #[account(
-        mut,
-        associated_token::mint = mint,
-        associated_token::authority = info_account,
-        associated_token::token_program = token_program,
-    )]
-    pub vault: Account<'info, TokenAccount>,
+#[event]
+pub struct WhitelistChangeAnnounced {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub server: Pubkey,
+    pub unlocks_at: i64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    #[account(
-        address = Pubkey::from_str(SPECIFIED_MINT).unwrap() @ CustomError::InvalidMint
-    )]
-    pub mint: Account<'info, Mint>, // Hardcoded specified token
+#[event]
+pub struct WithdrawalWhitelistSet {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub server: Pubkey,
+    pub addresses: Vec<Pubkey>,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    #[account(mut)]
-    pub owner: Signer<'info>,
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub system_program: Program<'info, System>, // System Program
+#[event]
+pub struct LockupRenewalUpdated {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub delegated_account: Pubkey,
+    pub enabled: bool,
+    pub lockup: LockupTier,
+    pub lock_until: i64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
 
-#[derive(Accounts)]
-pub struct Deposit<'info> {
-    #[account(mut)]
-    pub main_account: Account<'info, MainAccount>,
+#[event]
+pub struct LockupSettled {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub delegated_account: Pubkey,
+    pub lock_until: i64, // When the lock ran out
+    pub old_weight: u64, // Reward weight before and after dropping the boost
+    pub new_weight: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    #[account(
-        mut,
-        has_one = owner,
-    )]
-    pub info_account: Account<'info, InfoAccount>, // PDA for storing name
+#[event]
+pub struct DelegationListUpdated {
+    pub schema_version: u8,
+    #[index]
+    pub server: Pubkey,
+    pub delegated_account: Pubkey,
+    pub linked: bool, // false when unlinked
+    pub prev: Pubkey, // Neighbours it was linked between
+    pub next: Pubkey,
+    pub linked_delegations: u32,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    #[account(
-        mut,
-        associated_token::mint = mint,
-        associated_token::authority = info_account,
-        associated_token::token_program = token_program,
-    )]
-    pub vault: Account<'info, TokenAccount>,
+#[event]
+pub struct LockupRenewed {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub delegated_account: Pubkey,
+    pub lockup: LockupTier,
+    pub lock_until: i64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    #[account(
-        address = Pubkey::from_str(SPECIFIED_MINT).unwrap() @ CustomError::InvalidMint
-    )]
-    pub mint: Account<'info, Mint>,
+#[event]
+pub struct StandingOrderCreated {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub server: Pubkey,
+    pub amount: u64,
+    pub interval_secs: i64,
+    pub next_execution: i64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    // Transfer account
-    #[account(
-        mut,
-        constraint = sender_token_account.mint == mint.key() @ CustomError::InvalidMint,  
-    )]
-    pub sender_token_account: Account<'info, TokenAccount>,
+#[event]
+pub struct StandingOrderExecuted {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub server: Pubkey,
+    pub keeper: Pubkey,
+    pub amount: u64,
+    pub executions: u32,
+    pub next_execution: i64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    #[account(mut)]
-    pub owner: Signer<'info>,
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub system_program: Program<'info, System>,
+#[event]
+pub struct StandingOrderCancelled {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub server: Pubkey,
+    pub executions: u32,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
 
-#[derive(Accounts)]
-pub struct DelegatedDeposit<'info> {
-    #[account(mut)]
-    pub main_account: Account<'info, MainAccount>,
+#[event]
+pub struct DepositFunded {
+    pub schema_version: u8,
+    #[index]
+    pub funder: Pubkey,
+    pub beneficiary: Pubkey,
+    pub account: Pubkey, // Server for deposit_for, delegation for d_deposit_for
+    pub amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    #[account(mut)]
-    pub info_account: Account<'info, InfoAccount>,
+#[event]
+pub struct TotalsRecounted {
+    pub schema_version: u8,
+    pub total_users: u32,
+    pub total_servers: u32,
+    pub total_stake: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    #[account(
-        init_if_needed,
-        payer = owner,
-        space = 8 + 1 + 32 + 32 + 8,
-        seeds = [
-            INFO_SEED,
-            owner.key().as_ref(),
-            info_account.key().as_ref(),
-        ],
-        bump
-    )]
-    pub delegated_account: Account<'info, DelegatedAccount>, // PDA account for staking in contract
+#[event]
+pub struct NonceUsed {
+    pub schema_version: u8,
+    #[index]
+    pub account: Pubkey, // Server whose nonce was used
+    pub owner: Pubkey, // Signer of the authorization
+    pub nonce: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    #[account(
-        init_if_needed,  
-        payer = owner,
-        associated_token::mint = mint,
-        associated_token::authority = delegated_account,
-        associated_token::token_program = token_program,
-    )]
-    pub vault: Account<'info, TokenAccount>,
+#[event]
+pub struct DelegationReturned {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey, // Delegator
+    pub server: Pubkey,
+    pub amount: u64, // Stake returned, active and queued
+    pub rewards: u64, // Pending rewards paid out with it
+    pub total_stake: u64, // Main account totals after the operation
+    pub total_users: u32,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct DelegationRecovered {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub server: Pubkey, // Orphaned server account
+    pub amount: u64, // Whole vault balance returned to the owner
+    pub forfeited_rewards: u64, // Pending rewards that were owed by the server's reward vault
+    pub total_stake: u64, // Main account totals after the operation
+    pub total_users: u32,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ExcessSwept {
+    pub schema_version: u8,
+    pub server: Pubkey,
+    pub vault: Pubkey,
+    pub amount: u64, // Vault balance above the recorded stake
+    pub to_rewards: bool, // Credited to the delegators, otherwise returned to the server owner
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct AccountCollected {
+    pub schema_version: u8,
+    #[index]
+    pub account: Pubkey, // Closed server or delegation
+    pub owner: Pubkey,
+    pub caller: Pubkey,
+    pub reward: u64, // Lamports of rent paid to the caller
+    pub total_stake: u64, // Main account totals after the operation
+    pub total_users: u32,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct DisputeOpened {
+    pub schema_version: u8,
+    #[index]
+    pub client: Pubkey,
+    pub server: Pubkey,
+    pub amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct DisputeResolved {
+    pub schema_version: u8,
+    #[index]
+    pub client: Pubkey,
+    pub server: Pubkey,
+    pub amount: u64,
+    pub refund: u64, // Returned to the client, the rest went back to the server
+    pub timed_out: bool,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct StablePaymentReceived {
+    pub schema_version: u8,
+    pub payer: Pubkey,
+    #[index]
+    pub server: Pubkey,
+    pub amount: u64, // Stablecoin base units
+    pub operator_share: u64,
+    pub delegator_share: u64,
+    pub delegator_tokens: u64, // Staking token base units paid to the reward pool
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct BridgedDelegationCredited {
+    pub schema_version: u8,
+    #[index]
+    pub server: Pubkey,
+    pub emitter_chain: u16,
+    pub sequence: u64,
+    pub foreign_owner: [u8; 32],
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct BridgedWithdrawalPaid {
+    pub schema_version: u8,
+    #[index]
+    pub server: Pubkey,
+    pub emitter_chain: u16,
+    pub sequence: u64,
+    pub foreign_owner: [u8; 32],
+    pub owner: Pubkey, // Foreign owner PDA
+    pub recipient: Pubkey,
+    pub amount: u64, // Stake withdrawn, the withdrawal fee included
+    pub rewards: u64, // Pending rewards paid with it, the reward fee included
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct StakeAttested {
+    pub schema_version: u8,
+    #[index]
+    pub server: Pubkey,
+    pub index: u64, // Attestation number of the server
+    pub message: Pubkey,
+    pub total: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct EndpointBound {
+    pub schema_version: u8,
+    #[index]
+    pub server: Pubkey,
+    pub endpoint: String,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct PricingUpdated {
+    pub schema_version: u8,
+    #[index]
+    pub server: Pubkey,
+    pub mint: Pubkey,
+    pub per_gb: u64,
+    pub per_hour: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct CapabilitiesUpdated {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub server: Pubkey,
+    pub capabilities: u8,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ServiceFailurePenalized {
+    pub schema_version: u8,
+    #[index]
+    pub server: Pubkey,
+    pub epoch: u64,
+    pub penalty: u64, // Pending revenue moved to the treasury
+    pub failures: u32,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ActiveSetUpdated {
+    pub schema_version: u8,
+    pub epoch: u64,
+    pub added: u8, // Servers of this call that made it into the set
+    pub count: u16,
+    pub incumbents: u16,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct WorkCreditsReported {
+    pub schema_version: u8,
+    #[index]
+    pub server: Pubkey,
+    pub epoch: u64,
+    pub credits: u64,
+    pub total_credits: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct EpochRewardsFunded {
+    pub schema_version: u8,
+    pub funder: Pubkey,
+    pub epoch: u64,
+    pub amount: u64,
+    pub pool: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct WorkRewardsDistributed {
+    pub schema_version: u8,
+    #[index]
+    pub server: Pubkey,
+    pub epoch: u64,
+    pub credits: u64,
+    pub amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ChannelOpened {
+    pub schema_version: u8,
+    #[index]
+    pub client: Pubkey,
+    pub server: Pubkey,
+    pub deposit: u64,
+    pub collateral: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ChannelAccepted {
+    pub schema_version: u8,
+    #[index]
+    pub client: Pubkey,
+    pub server: Pubkey,
+    pub collateral: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ChannelClaimed {
+    pub schema_version: u8,
+    #[index]
+    pub client: Pubkey,
+    pub server: Pubkey,
+    pub amount: u64,
+    pub paid: u64, // Cumulative
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ChannelCloseRequested {
+    pub schema_version: u8,
+    #[index]
+    pub client: Pubkey,
+    pub server: Pubkey,
+    pub refund_owed: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ChannelFinalized {
+    pub schema_version: u8,
+    #[index]
+    pub client: Pubkey,
+    pub server: Pubkey,
+    pub paid: u64,
+    pub refunded: u64, // Unspent deposit returned to the client
+    pub collateral_paid: u64, // Refund taken from the server's stake
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct TaskCommitted {
+    pub schema_version: u8,
+    #[index]
+    pub requester: Pubkey,
+    pub task_id: u64,
+    pub candidates: u8,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct TaskAssigned {
+    pub schema_version: u8,
+    #[index]
+    pub requester: Pubkey,
+    pub task_id: u64,
+    pub server: Pubkey,
+    pub randomness: [u8; 32],
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ServerHeartbeat {
+    pub schema_version: u8,
+    #[index]
+    pub server: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct SubscriptionOpened {
+    pub schema_version: u8,
+    #[index]
+    pub client: Pubkey,
+    pub server: Pubkey,
+    pub amount: u64,
+    pub end: i64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct SubscriptionSettled {
+    pub schema_version: u8,
+    #[index]
+    pub client: Pubkey,
+    pub server: Pubkey,
+    pub amount: u64,
+    pub paid: u64, // Total paid to the server so far
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct SubscriptionClosed {
+    pub schema_version: u8,
+    #[index]
+    pub client: Pubkey,
+    pub server: Pubkey,
+    pub paid: u64,
+    pub refund: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct CommissionUpdated {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub server: Pubkey,
+    pub commission_bps: u16,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct RevenuePaid {
+    pub schema_version: u8,
+    pub payer: Pubkey,
+    #[index]
+    pub server: Pubkey,
+    pub amount: u64,
+    pub stream_end: i64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    #[account(
-        address = Pubkey::from_str(SPECIFIED_MINT).unwrap() @ CustomError::InvalidMint
-    )]
-    pub mint: Account<'info, Mint>,
+#[event]
+pub struct RevenueSettled {
+    pub schema_version: u8,
+    #[index]
+    pub server: Pubkey,
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub operator_share: u64,
+    pub delegator_share: u64, // Credited to the delegators' reward accumulator
+    pub unreleased: u64, // Still streaming after this settlement
+    pub vesting: u64, // Operator revenue still vesting, operator_share is what was paid out
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    // Transfer account
-    #[account(
-        mut,
-        constraint = sender_token_account.mint == mint.key() @ CustomError::InvalidMint,  
-    )]
-    pub sender_token_account: Account<'info, TokenAccount>,
+#[event]
+pub struct AccountingReconciled {
+    pub schema_version: u8,
+    #[index]
+    pub server: Pubkey,
+    pub recorded_total: u64,
+    pub computed_total: u64,
+    pub vault_shortfall: u64, // Base units the vaults hold below their records
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    #[account(mut)]
-    pub owner: Signer<'info>,
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub system_program: Program<'info, System>,
+#[event]
+pub struct DelegationTreeInitialized {
+    pub schema_version: u8,
+    pub server: Pubkey,
+    pub merkle_tree: Pubkey,
+    pub max_depth: u32,
+    pub max_buffer_size: u32,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
 
-#[derive(Accounts)]
-pub struct Withdraw<'info> {
-    #[account(mut)]
-    pub main_account: Account<'info, MainAccount>,
+#[event]
+pub struct CompressedDelegationWritten {
+    pub schema_version: u8,
+    #[index]
+    pub merkle_tree: Pubkey,
+    pub leaf_index: u32,
+    pub delegation: DelegatedAccount,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    #[account(
-        mut,
-        has_one = owner,
-        seeds = [
-            INFO_SEED,        // seed prefix
-            owner.key().as_ref(), // Use caller's public key as seed
-            &hash(info_account.serverkey.as_ref()).to_bytes(),
-        ],
-        bump
-    )]
-    pub info_account: Account<'info, InfoAccount>, // PDA for storing name
-    #[account(
-        mut,
-        associated_token::mint = mint,
-        associated_token::authority = info_account,
-        associated_token::token_program = token_program,
-    )]
-    pub vault: Account<'info, TokenAccount>,
+#[event]
+pub struct LookupTableExtended {
+    pub schema_version: u8,
+    pub lookup_table: Pubkey,
+    pub added: u8,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    // Here, if there's no related ata account, the contract automatically creates or updates the account to accept tokens. The address of the ata account is easy to derive using @solana/spl-token's getAssociatedTokenAddress
-    #[account(
-        init_if_needed,
-        payer = owner,
-        associated_token::mint = mint,
-        associated_token::authority = owner,
-        associated_token::token_program = token_program,
-    )]
-    pub receipt_token_account: Account<'info, TokenAccount>,
+// Activity over the epoch between two consecutive snapshots, pre-aggregated for dashboards
+#[event]
+pub struct EpochSummary {
+    pub schema_version: u8,
+    pub epoch: u64, // The finished epoch, the one before the snapshot emitting this
+    pub inflow: u64, // Stake deposited, in the same units as total_stake
+    pub outflow: u64, // Stake withdrawn, same units
+    pub new_servers: u32,
+    pub closed_servers: u32,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    #[account(
-        address = Pubkey::from_str(SPECIFIED_MINT).unwrap() @ CustomError::InvalidMint
-    )]
-    pub mint: Account<'info, Mint>,
+#[event]
+pub struct DailyStatsUpdated {
+    pub schema_version: u8,
+    pub day: u32,
+    pub total_stake: u64,
+    pub stake_delta: i64,
+    pub active_servers: u32, // So far, later calls of the day may add to it
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    #[account(mut)]
-    pub owner: Signer<'info>,
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub system_program: Program<'info, System>,
+#[event]
+pub struct SnapshotArchived {
+    pub schema_version: u8,
+    pub epoch: u64,
+    pub index: u64, // Leaf position in the archive tree
+    pub leaf: [u8; 32],
+    pub root: [u8; 32], // Archive root after this leaf
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
 
-#[derive(Accounts)]
-pub struct DelegatedWithdraw<'info> {
-    #[account(mut)]
-    pub main_account: Account<'info, MainAccount>,
+#[event]
+pub struct EpochSnapshotTaken {
+    pub schema_version: u8,
+    pub epoch: u64,
+    pub total_stake: u64,
+    pub total_delegated: u64,
+    pub total_users: u32,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    #[account(mut)]
-    pub info_account: Account<'info, InfoAccount>,
+#[event]
+pub struct AccountMigrated {
+    pub schema_version: u8,
+    pub account: Pubkey,
+    pub version: u8,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    #[account(
-        mut,
-        has_one = owner,
-        seeds = [
-            INFO_SEED,
-            owner.key().as_ref(),
-            info_account.key().as_ref(),
-        ],
-        bump
-    )]
-    pub delegated_account: Account<'info, DelegatedAccount>, // PDA account for staking in contract
+#[event]
+pub struct AccountResized {
+    pub schema_version: u8,
+    pub account: Pubkey,
+    pub authority: Pubkey,
+    pub old_space: u32,
+    pub new_space: u32,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    #[account(
-        mut,
-        associated_token::mint = mint,
-        associated_token::authority = delegated_account,
-        associated_token::token_program = token_program,
-    )]
-    pub vault: Account<'info, TokenAccount>,
+#[event]
+pub struct DonationReceived {
+    pub schema_version: u8,
+    pub donor: Pubkey,
+    pub server: Pubkey,
+    pub servers: u8,
+    pub amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    // Here, if there's no related ata account, the contract automatically creates or updates the account to accept tokens. The address of the ata account is easy to derive using @solana/spl-token's getAssociatedTokenAddress
-    #[account(
-        init_if_needed,
-        payer = owner,
-        associated_token::mint = mint,
-        associated_token::authority = owner,
-        associated_token::token_program = token_program,
-    )]
-    pub receipt_token_account: Account<'info, TokenAccount>,
+#[event]
+pub struct TreasuryTransferred {
+    pub schema_version: u8,
+    pub admin: Pubkey,
+    pub mint: Pubkey,
+    pub destination: Pubkey,
+    pub amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    #[account(
-        address = Pubkey::from_str(SPECIFIED_MINT).unwrap() @ CustomError::InvalidMint
-    )]
-    pub mint: Account<'info, Mint>,
+#[event]
+pub struct DustSwept {
+    pub schema_version: u8,
+    pub server: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    #[account(mut)]
-    pub owner: Signer<'info>,
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub system_program: Program<'info, System>,
+#[event]
+pub struct ProtocolFeeCollected {
+    pub schema_version: u8,
+    pub mint: Pubkey,
+    pub treasury_token_account: Pubkey,
+    pub amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
 
-#[derive(Accounts)]
-pub struct RemoveDelegatedAccount<'info> {
-    #[account(mut)]
-    pub main_account: Account<'info, MainAccount>,
-    #[account(mut)]
-    pub info_account: Account<'info, InfoAccount>,
+#[event]
+pub struct ExchangeRateUpdated {
+    pub schema_version: u8,
+    pub mint: Pubkey,
+    pub rate: u64,
+    pub total_stake: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    #[account(
-        mut,
-        close = owner,
-        has_one = owner,
-        constraint = delegated_account.stake == 0 @ CustomError::NonZeroBalance,  // Can only close account when stake is 0
-        seeds = [
-            INFO_SEED,        // seed prefix
-            owner.key().as_ref(), // Use caller's public key as seed
-            info_account.key().as_ref(),
-        ],
-        bump,     
-    )]
-    pub delegated_account: Account<'info, DelegatedAccount>,
+#[event]
+pub struct ReceiptMintInitialized {
+    pub schema_version: u8,
+    pub mint: Pubkey,
+    pub receipt_mint: Pubkey,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    #[account(
-        mut,
-        associated_token::mint = mint,
-        associated_token::authority = delegated_account,
-        associated_token::token_program = token_program,
-    )]
-    pub vault: Account<'info, TokenAccount>,
+#[event]
+pub struct BondDeposited {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub server: Pubkey,
+    pub amount: u64,
+    pub bond: u64,
+    pub total_stake: u64, // Main account totals after the operation
+    pub total_users: u32,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
 
-    #[account(
-        address = Pubkey::from_str(SPECIFIED_MINT).unwrap() @ CustomError::InvalidMint
-    )]
-    pub mint: Account<'info, Mint>,
-    #[account(mut)]
-    pub owner: Signer<'info>,
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub system_program: Program<'info, System>,
+#[event]
+pub struct BondWithdrawn {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub server: Pubkey,
+    pub amount: u64,
+    pub bond: u64,
+    pub total_stake: u64, // Main account totals after the operation
+    pub total_users: u32,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
 
-#[account]
-pub struct MainAccount {
-    pub total_stake: u64,
+#[event]
+pub struct LstDeposited {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub server: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub restaked: u64,
+    pub total_stake: u64, // Main account totals after the operation
     pub total_users: u32,
-    pub initialized: bool,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
 
-#[account]
-pub struct InfoAccount {
-    pub initialized: bool,
+#[event]
+pub struct LstWithdrawn {
+    pub schema_version: u8,
+    #[index]
     pub owner: Pubkey,
-    pub stake: u64,
-    pub total: u64,
-    pub total_delegators: u32,
-    pub name: String,
-    pub serverkey: Vec<u8>,
+    pub server: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub restaked: u64,
+    pub total_stake: u64, // Main account totals after the operation
+    pub total_users: u32,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
 
-#[account]
-pub struct DelegatedAccount {
-    pub initialized: bool,
+#[event]
+pub struct AllowlistUpdated {
+    pub schema_version: u8,
+    #[index]
+    pub staker: Pubkey,
+    pub allowed: bool,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct RewardsFunded {
+    pub schema_version: u8,
+    #[index]
+    pub funder: Pubkey,
+    pub server: Pubkey,
+    pub amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct RewardsClaimed {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub delegator: Pubkey,
+    pub amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct DelegationActivated {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
     pub delegator: Pubkey,
+    pub amount: u64,
+    pub epoch: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ServerFlagged {
+    pub schema_version: u8,
+    #[index]
     pub owner: Pubkey,
+    pub server: Pubkey,
+    pub reason_code: u8,
     pub stake: u64,
+    pub complaints: u32,
+    pub epoch: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
 
 #[event]
-pub struct MainAccountInitialized {
-    pub admin: Pubkey,
+pub struct AutoCompoundUpdated {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub delegated_account: Pubkey,
+    pub enabled: bool,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct RewardsCranked {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub delegator: Pubkey,
+    pub restaked: u64,
+    pub claimable: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct ReferralRewardsClaimed {
+    pub schema_version: u8,
+    #[index]
+    pub referrer: Pubkey,
+    pub delegated_account: Pubkey,
+    pub amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
 
 #[event]
 pub struct ServerAdded {
+    pub schema_version: u8,
     #[index]
     pub owner: Pubkey,
-    pub name: String,
+    pub name: [u8; MAX_NAME_LEN],
     pub amount: u64,
-    pub serverkey: Vec<u8>,
+    pub serverkey: [u8; MAX_SERVERKEY_LEN],
+    pub serverkey_len: u8,
+    pub total_stake: u64, // Main account totals after the operation
+    pub total_users: u32,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
 
 #[event]
 pub struct ServerUpdated {
+    pub schema_version: u8,
     #[index]
     pub owner: Pubkey,
-    pub name: String,
+    pub name: [u8; MAX_NAME_LEN],
     pub amount: u64,
-    pub serverkey: Vec<u8>,
+    pub serverkey: [u8; MAX_SERVERKEY_LEN],
+    pub serverkey_len: u8,
+    pub slot: u64,
+    pub unix_timestamp: i64,
+}
+
+#[event]
+pub struct DelegateMinimumUpdated {
+    pub schema_version: u8,
+    #[index]
+    pub owner: Pubkey,
+    pub server: Pubkey,
+    pub amount: u64,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
 
 #[event]
 pub struct ServerRemoved {
+    pub schema_version: u8,
     #[index]
     pub owner: Pubkey,
-    pub name: String,
-    pub serverkey: Vec<u8>,
+    pub name: [u8; MAX_NAME_LEN],
+    pub serverkey: [u8; MAX_SERVERKEY_LEN],
+    pub serverkey_len: u8,
+    pub total_stake: u64, // Main account totals after the operation
+    pub total_users: u32,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
 
 #[event]
 pub struct DelegatedRemoved {
+    pub schema_version: u8,
     #[index]
     pub owner: Pubkey,
     pub delegator: Pubkey,
+    pub total_stake: u64, // Main account totals after the operation
+    pub total_users: u32,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
 
 #[event]
 pub struct TokenDeposited {
+    pub schema_version: u8,
     #[index]
     pub owner: Pubkey,
-    pub name: String,
+    pub name: [u8; MAX_NAME_LEN],
     pub amount: u64,
+    pub stake: u64,
+    pub server_total: u64,
+    pub server_seq: u64, // The server's event_seq, see next_event_seq
+    pub memo: [u8; MAX_MEMO_LEN], // Zero-padded, see transaction_memo
+    pub total_stake: u64, // Main account totals after the operation
+    pub total_users: u32,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
 
 #[event]
 pub struct TokenDelegatedDeposited {
+    pub schema_version: u8,
     #[index]
     pub owner: Pubkey,
     pub delegator: Pubkey,
     pub delegator_owner: Pubkey,
     pub amount: u64,
+    pub stake: u64,
+    pub activating_stake: u64,
+    pub server_total: u64,
+    pub server_seq: u64, // The server's event_seq, see next_event_seq
+    pub memo: [u8; MAX_MEMO_LEN], // Zero-padded, see transaction_memo
+    pub total_stake: u64, // Main account totals after the operation
+    pub total_users: u32,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
 
 #[event]
 pub struct TokenWithdrawn {
+    pub schema_version: u8,
     #[index]
     pub owner: Pubkey,
-    pub name: String,
+    pub name: [u8; MAX_NAME_LEN],
     pub amount: u64,
+    pub fee: u64,
+    pub stake: u64,
+    pub server_total: u64,
+    pub server_seq: u64, // The server's event_seq, see next_event_seq
+    pub total_stake: u64, // Main account totals after the operation
+    pub total_users: u32,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
 
 #[event]
 pub struct DelegatedTokenWithdrawn {
+    pub schema_version: u8,
     #[index]
     pub owner: Pubkey,
     pub delegator: Pubkey,
     pub delegator_owner: Pubkey,
     pub amount: u64,
+    pub fee: u64,
+    pub stake: u64,
+    pub activating_stake: u64,
+    pub server_total: u64,
+    pub server_seq: u64, // The server's event_seq, see next_event_seq
+    pub total_stake: u64, // Main account totals after the operation
+    pub total_users: u32,
+    pub slot: u64,
+    pub unix_timestamp: i64,
 }
 
 #[error_code]
@@ -863,4 +19969,250 @@ pub enum CustomError {
     VaultNotEmpty,
     #[msg("Only owner can update server name.")]
     OnlyOwnwer,
+    #[msg("A delegation cannot refer itself.")]
+    InvalidReferrer,
+    #[msg("Basis points must not exceed 10,000.")]
+    InvalidBasisPoints,
+    #[msg("The server has no delegated stake to reward.")]
+    NoDelegatedStake,
+    #[msg("There are no rewards to claim.")]
+    NothingToClaim,
+    #[msg("The server delegate minimum cannot be lower than 500 tokens.")]
+    DelegateMinimumTooLow,
+    #[msg("The delegated stake is still locked.")]
+    StakeLocked,
+    #[msg("A batch must hold between 1 and its maximum number of entries, with matching accounts.")]
+    InvalidBatchSize,
+    #[msg("A batch account does not match the expected PDA or vault address.")]
+    InvalidBatchAccount,
+    #[msg("Only delegations with active stake can flag a server.")]
+    NoActiveStake,
+    #[msg("This delegation already flagged the server in the current epoch.")]
+    AlreadyFlaggedThisEpoch,
+    #[msg("The server total would exceed the allowed multiple of the operator's own stake.")]
+    SelfBondRatioExceeded,
+    #[msg("The staker is not approved by the configured compliance gate.")]
+    StakerNotApproved,
+    #[msg("The mint is already an approved collateral mint.")]
+    MintAlreadyApproved,
+    #[msg("The maximum number of approved collateral mints has been reached.")]
+    TooManyMints,
+    #[msg("SOL staking requires the server collateral mint to be wSOL.")]
+    NotNativeMint,
+    #[msg("Dual-token mode requires the bond accounts when registering a server.")]
+    BondRequired,
+    #[msg("The bond vault must be the server's associated account of the bond mint.")]
+    InvalidBondVault,
+    #[msg("The bond must stay within the configured minimum and maximum.")]
+    BondOutOfRange,
+    #[msg("Receipt token accounts are required for this delegation.")]
+    ReceiptAccountsRequired,
+    #[msg("The receipt mint does not belong to the collateral mint.")]
+    InvalidReceiptMint,
+    #[msg("Mints with a permanent delegate cannot be approved.")]
+    PermanentDelegateMint,
+    #[msg("Non-transferable mints cannot be approved.")]
+    NonTransferableMint,
+    #[msg("Mints whose accounts are frozen by default cannot be approved.")]
+    DefaultFrozenMint,
+    #[msg("No exchange rate has been published for this mint.")]
+    ExchangeRateNotSet,
+    #[msg("Invalid exchange rate.")]
+    InvalidExchangeRate,
+    #[msg("The treasury token account is required while protocol fees are on.")]
+    TreasuryAccountRequired,
+    #[msg("The account is not a server or delegation account of this program.")]
+    InvalidAccountData,
+    #[msg("Accounts can only grow, by at most 10 KiB per instruction.")]
+    InvalidAccountSize,
+    #[msg("The account must be migrated to the current layout first.")]
+    AccountNotMigrated,
+    #[msg("The account is already on the current layout.")]
+    AlreadyMigrated,
+    #[msg("Snapshots can only be taken for the current epoch.")]
+    InvalidEpoch,
+    #[msg("The lookup table is not the expected address lookup table.")]
+    InvalidLookupTable,
+    #[msg("The server already has a delegation tree.")]
+    DelegationTreeExists,
+    #[msg("The Merkle tree is not the server's delegation tree.")]
+    InvalidDelegationTree,
+    #[msg("Servers with compressed delegations can't be reconciled.")]
+    ReconcileUnsupported,
+    #[msg("This build has no default staking token, set config.staking_mint first.")]
+    StakingMintNotSet,
+    #[msg("The preceding Ed25519 instruction doesn't carry the expected signature.")]
+    InvalidSignature,
+    #[msg("The server hasn't sent a heartbeat within the configured timeout.")]
+    ServerOffline,
+    #[msg("The task already has a server assigned.")]
+    TaskAlreadyAssigned,
+    #[msg("The revealed secret doesn't match the commitment.")]
+    InvalidReveal,
+    #[msg("The slot after the commitment hasn't been produced yet.")]
+    RevealTooEarly,
+    #[msg("The slot after the commitment is no longer in SlotHashes, commit again.")]
+    RevealExpired,
+    #[msg("Part of the stake backs open payment channels.")]
+    ChannelCollateralLocked,
+    #[msg("The channel was already accepted.")]
+    ChannelAlreadyAccepted,
+    #[msg("The server hasn't accepted the channel.")]
+    ChannelNotAccepted,
+    #[msg("The channel is closing.")]
+    ChannelClosing,
+    #[msg("The channel hasn't been closed by the client.")]
+    ChannelNotClosing,
+    #[msg("The server can still claim vouchers on this channel.")]
+    ChallengePeriodActive,
+    #[msg("The server was already penalized this epoch.")]
+    AlreadyPenalizedThisEpoch,
+    #[msg("The server's stake is below the minimum of a requested capability.")]
+    CapabilityStakeTooLow,
+    #[msg("Endpoints are limited to 64 bytes.")]
+    EndpointTooLong,
+    #[msg("Active sets are disabled in the config.")]
+    ActiveSetDisabled,
+    #[msg("The server isn't in the epoch's active set.")]
+    NotInActiveSet,
+    #[msg("Wormhole stake attestations are disabled in the config.")]
+    AttestationsDisabled,
+    #[msg("The stake hasn't changed enough since the last attestation.")]
+    StakeChangeTooSmall,
+    #[msg("Bridged delegations are disabled in the config.")]
+    BridgedDepositsDisabled,
+    #[msg("Invalid or unexpected Wormhole message.")]
+    InvalidVaa,
+    #[msg("Stablecoin payments are disabled in the config.")]
+    StablePaymentsDisabled,
+    #[msg("Invalid or unverified price feed.")]
+    InvalidPriceFeed,
+    #[msg("The price is too old.")]
+    StalePrice,
+    #[msg("The dispute timeout hasn't passed yet.")]
+    DisputeNotExpired,
+    #[msg("Account garbage collection is disabled.")]
+    GcDisabled,
+    #[msg("The account has been active too recently to be collected.")]
+    AccountStillActive,
+    #[msg("The reward vault or the server owner's token account is required.")]
+    SweepDestinationRequired,
+    #[msg("Server names can't be empty or contain control or invisible formatting characters.")]
+    InvalidName,
+    #[msg("The server name contains a blocked word.")]
+    BlockedName,
+    #[msg("Operators can't delegate to their own server, use deposit instead.")]
+    SelfDelegation,
+    #[msg("This instruction can't be called through CPI by this program.")]
+    CpiNotAllowed,
+    #[msg("Registration requires holding an operator license NFT of the configured collection.")]
+    LicenseRequired,
+    #[msg("The audit log account is required once the audit log is initialized.")]
+    AuditLogRequired,
+    #[msg("The server account still exists, use d_withdraw.")]
+    ServerNotOrphaned,
+    #[msg("The server still has delegators, they have to withdraw or be returned first.")]
+    DelegatorsRemaining,
+    #[msg("The delegation has receipts or referral rewards outstanding.")]
+    DelegationNotReturnable,
+    #[msg("The deposit permit has expired.")]
+    PermitExpired,
+    #[msg("The authorization nonce is not the account's next nonce.")]
+    InvalidNonce,
+    #[msg("This server was removed too recently to register again.")]
+    RegistrationCooldown,
+    #[msg("The registration record account is required while a registration cooldown is set.")]
+    RegistrationRecordRequired,
+    #[msg("The deposit memo is longer than MAX_MEMO_LEN bytes.")]
+    MemoTooLong,
+    #[msg("The mint's decimals don't match the expected value.")]
+    MintDecimalsMismatch,
+    #[msg("The standing order's next execution isn't due yet.")]
+    StandingOrderNotDue,
+    #[msg("Auto-renewal is on for this position, disable it before withdrawing.")]
+    LockupRenewalActive,
+    #[msg("The position has no active lockup to renew.")]
+    NoActiveLockup,
+    #[msg("At most MAX_WITHDRAWAL_ADDRESSES withdrawal addresses can be whitelisted.")]
+    TooManyWithdrawalAddresses,
+    #[msg("The withdrawal whitelist can only change after an announced delay has passed.")]
+    WhitelistLocked,
+    #[msg("Withdrawals of this server must go to a whitelisted token account.")]
+    WithdrawalAddressNotAllowed,
+    #[msg("No recovery has been announced for this server.")]
+    NoPendingRecovery,
+    #[msg("The recovery delay has not passed yet.")]
+    RecoveryPending,
+    #[msg("Guardians must be distinct keys other than the owner, with 1 <= threshold <= count.")]
+    InvalidGuardianSet,
+    #[msg("The signer is not a guardian of this server.")]
+    NotAGuardian,
+    #[msg("The portfolio already holds MAX_PORTFOLIO_POSITIONS positions.")]
+    PortfolioFull,
+    #[msg("The distribution weights must add up to 10,000 basis points.")]
+    InvalidDistribution,
+    #[msg("The pool already holds MAX_POOL_POSITIONS positions.")]
+    PoolFull,
+    #[msg("The pool's undelegated balance doesn't cover this amount.")]
+    PoolLiquidityInsufficient,
+    #[msg("Pools only delegate to Gold tier servers.")]
+    PoolServerNotEligible,
+    #[msg("Handles are lowercase letters, digits and dashes in dot-separated labels.")]
+    InvalidHandle,
+    #[msg("Icon URIs are limited to 128 bytes.")]
+    IconUriTooLong,
+    #[msg("Icon URIs must be https, ipfs or ar URIs without spaces or control characters.")]
+    InvalidIconUri,
+    #[msg("Encrypted contacts are limited to 192 bytes.")]
+    ContactTooLong,
+    #[msg("The server runs software below the network's minimum version.")]
+    SoftwareOutdated,
+    #[msg("Legacy migration is disabled or the program is not the configured deployment.")]
+    InvalidLegacyProgram,
+    #[msg("The legacy withdrawal did not empty the position into the owner's token account.")]
+    LegacyPositionNotEmptied,
+    #[msg("The partner adapter is disabled.")]
+    PartnerAdapterDisabled,
+    #[msg("The account is not a position of the owner in the partner program.")]
+    InvalidPartnerPosition,
+    #[msg("No tenure snapshot has been published.")]
+    TenureClaimsClosed,
+    #[msg("The Merkle proof does not match the published root.")]
+    InvalidMerkleProof,
+    #[msg("Renaming requires the owner and treasury token accounts while a name change fee is set.")]
+    NameFeeAccountsRequired,
+    #[msg("Signer sets hold up to 5 distinct keys and a threshold between 1 and their size.")]
+    InvalidSignerSet,
+    #[msg("Not enough keys of the server's signer set signed.")]
+    SignerThresholdNotMet,
+    #[msg("The delegation intent has expired.")]
+    IntentExpired,
+    #[msg("Withdrawing below the minimum with delegations open needs an exit notice that has run out.")]
+    ExitNoticeRequired,
+    #[msg("No exit has been announced for this server.")]
+    NoExitAnnounced,
+    #[msg("The server has announced its exit and takes no new delegations.")]
+    ServerExiting,
+    #[msg("The server charges an entry fee, its owner's token account is required.")]
+    OperatorTokenAccountRequired,
+    #[msg("Daily stats can only be updated for the current UTC day.")]
+    InvalidDay,
+    #[msg("The epoch snapshot is still within the retention period.")]
+    SnapshotTooRecent,
+    #[msg("The snapshot archive is full.")]
+    ArchiveFull,
+    #[msg("Mints with a transfer fee cannot be approved.")]
+    TransferFeeMint,
+    #[msg("Mints with a transfer hook cannot be approved.")]
+    TransferHookMint,
+    #[msg("Withdrawals are frozen after a change of ownership.")]
+    WithdrawalsFrozen,
+    #[msg("The exchange rate of the mint is older than the configured maximum age.")]
+    ExchangeRateStale,
+    #[msg("The staking mint can't change once the main account is initialized.")]
+    StakingMintFixed,
+    #[msg("The delegation is in its server's list, unlink it first.")]
+    DelegationLinked,
+    #[msg("The delegation is not in its server's list.")]
+    DelegationNotLinked,
 }
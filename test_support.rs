@@ -0,0 +1,506 @@
+// Localnet harness for integrators writing scenario tests. The program is loaded from its SBF
+// build (`anchor build` or `cargo build-sbf`) into solana-program-test, next to a 9-decimal mint
+// at SPECIFIED_MINT whose authority the fixture holds, so every collateral check runs as on
+// mainnet.
+//
+//     let mut fixture = Fixture::new().await;
+//     let operator = fixture.actor(5_000).await;
+//     let server = fixture.add_server(&operator, b"key", "node", 1_000).await.unwrap();
+//     let delegator = fixture.actor(1_000).await;
+//     fixture.d_deposit(&delegator, server, 500, LockupTier::None).await.unwrap();
+//     fixture.next_epoch().await;
+//
+// Amounts are whole tokens, like the instructions themselves.
+use super::*;
+use anchor_lang::AccountDeserialize;
+use anchor_spl::associated_token::spl_associated_token_account::instruction as ata_instruction;
+use anchor_spl::token::spl_token;
+use solana_program::instruction::Instruction;
+use solana_program::bpf_loader_upgradeable;
+use solana_program::program_pack::Pack;
+use solana_program::system_instruction;
+use solana_program_test::{find_file, read_file, BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::account::Account as SolanaAccount;
+use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::transaction::Transaction;
+
+pub const DECIMALS: u8 = 9;
+// SOL given to every actor for fees and rent
+pub const ACTOR_LAMPORTS: u64 = 10_000_000_000;
+
+pub struct Fixture {
+    pub context: ProgramTestContext,
+    pub mint_authority: Keypair,
+    pub admin: Keypair,
+}
+
+impl Fixture {
+    // Program with its main and config accounts initialized, `admin` holds the config
+    pub async fn new() -> Self {
+        let mut fixture = Self::uninitialized().await;
+        let admin = fixture.admin.pubkey();
+        let ixs = [
+            client::initialize_config_ix(admin),
+            client::initialize_main_ix(admin, true, SPECIFIED_MINT, DECIMALS),
+        ];
+        let admin = fixture.admin.insecure_clone();
+        fixture
+            .process(&ixs, &[&admin])
+            .await
+            .expect("initialize config and main account");
+        fixture
+    }
+
+    // Program and mint only, for tests of the initialization itself
+    pub async fn uninitialized() -> Self {
+        let mint_authority = Keypair::new();
+        let admin = Keypair::new();
+
+        // Deployed through the upgradeable loader with `admin` as upgrade authority, which
+        // initialize_config requires
+        let mut program_test = ProgramTest::default();
+        program_test.prefer_bpf(true);
+        let program_data = pda::program_data(&ID).0;
+        let elf = read_file(find_file("staking_contract.so").expect("program build"));
+        // UpgradeableLoaderState::Program, bincode encoded
+        let mut program = 2u32.to_le_bytes().to_vec();
+        program.extend_from_slice(program_data.as_ref());
+        program_test.add_account(
+            ID,
+            SolanaAccount {
+                lamports: 1_000_000_000,
+                data: program,
+                owner: bpf_loader_upgradeable::ID,
+                executable: true,
+                ..SolanaAccount::default()
+            },
+        );
+        // UpgradeableLoaderState::ProgramData { slot: 0, upgrade_authority_address }, then the ELF
+        let mut data = 3u32.to_le_bytes().to_vec();
+        data.extend_from_slice(&0u64.to_le_bytes());
+        data.push(1);
+        data.extend_from_slice(admin.pubkey().as_ref());
+        data.extend_from_slice(&elf);
+        program_test.add_account(
+            program_data,
+            SolanaAccount {
+                lamports: 1_000_000_000,
+                data,
+                owner: bpf_loader_upgradeable::ID,
+                ..SolanaAccount::default()
+            },
+        );
+
+        let mut mint_data = vec![0; spl_token::state::Mint::LEN];
+        spl_token::state::Mint::pack(
+            spl_token::state::Mint {
+                mint_authority: Some(mint_authority.pubkey()).into(),
+                supply: 0,
+                decimals: DECIMALS,
+                is_initialized: true,
+                freeze_authority: None.into(),
+            },
+            &mut mint_data,
+        )
+        .expect("pack mint");
+        program_test.add_account(
+            SPECIFIED_MINT,
+            SolanaAccount {
+                lamports: 1_000_000_000,
+                data: mint_data,
+                owner: spl_token::ID,
+                ..SolanaAccount::default()
+            },
+        );
+        program_test.add_account(
+            admin.pubkey(),
+            SolanaAccount::new(ACTOR_LAMPORTS, 0, &solana_program::system_program::ID),
+        );
+
+        Self {
+            context: program_test.start_with_context().await,
+            mint_authority,
+            admin,
+        }
+    }
+
+    // New wallet with SOL and `tokens` whole tokens in its associated token account
+    pub async fn actor(&mut self, tokens: u64) -> Keypair {
+        let actor = Keypair::new();
+        let payer = self.context.payer.insecure_clone();
+        let mint_authority = self.mint_authority.insecure_clone();
+        let ixs = [
+            system_instruction::transfer(&payer.pubkey(), &actor.pubkey(), ACTOR_LAMPORTS),
+            ata_instruction::create_associated_token_account(
+                &payer.pubkey(),
+                &actor.pubkey(),
+                &SPECIFIED_MINT,
+                &spl_token::ID,
+            ),
+            spl_token::instruction::mint_to(
+                &spl_token::ID,
+                &SPECIFIED_MINT,
+                &self.token_account(&actor.pubkey()),
+                &mint_authority.pubkey(),
+                &[],
+                to_minimum_units(tokens, DECIMALS).expect("token amount"),
+            )
+            .expect("mint_to"),
+        ];
+        self.process(&ixs, &[&mint_authority])
+            .await
+            .expect("fund actor");
+        actor
+    }
+
+    // Send `ixs` in one transaction paid by the context payer, `signers` are the extra signers
+    pub async fn process(
+        &mut self,
+        ixs: &[Instruction],
+        signers: &[&Keypair],
+    ) -> std::result::Result<(), BanksClientError> {
+        let blockhash = self
+            .context
+            .get_new_latest_blockhash()
+            .await
+            .map_err(BanksClientError::from)?;
+        let mut all_signers = vec![&self.context.payer];
+        all_signers.extend_from_slice(signers);
+        let transaction = Transaction::new_signed_with_payer(
+            ixs,
+            Some(&self.context.payer.pubkey()),
+            &all_signers,
+            blockhash,
+        );
+        self.context
+            .banks_client
+            .process_transaction(transaction)
+            .await
+    }
+
+    // Move to the first slot of the next epoch, which activates queued delegations
+    pub async fn next_epoch(&mut self) {
+        let clock: Clock = self
+            .context
+            .banks_client
+            .get_sysvar()
+            .await
+            .expect("clock");
+        let slots_per_epoch = self.context.genesis_config().epoch_schedule.slots_per_epoch;
+        self.context
+            .warp_to_slot(clock.slot + slots_per_epoch)
+            .expect("warp");
+    }
+
+    // Move the clock `secs` seconds forward, for lockups and notice periods
+    pub async fn warp_time(&mut self, secs: i64) {
+        let mut clock: Clock = self
+            .context
+            .banks_client
+            .get_sysvar()
+            .await
+            .expect("clock");
+        clock.unix_timestamp += secs;
+        self.context.set_sysvar(&clock);
+    }
+
+    // Instruction helpers, one per staking operation. The program-side errors come back as
+    // BanksClientError so tests can assert on failures.
+
+    pub async fn add_server(
+        &mut self,
+        owner: &Keypair,
+        serverkey: &[u8],
+        name: &str,
+        amount: u64,
+    ) -> std::result::Result<Pubkey, BanksClientError> {
+        let ix = client::add_server_ix(
+            owner.pubkey(),
+            serverkey.to_vec(),
+            name.to_string(),
+            amount,
+            SPECIFIED_MINT,
+            spl_token::ID,
+        );
+        self.process(&[ix], &[owner]).await?;
+        Ok(pda::server(&owner.pubkey(), serverkey, &ID).0)
+    }
+
+    pub async fn deposit(
+        &mut self,
+        owner: &Keypair,
+        serverkey: &[u8],
+        amount: u64,
+    ) -> std::result::Result<(), BanksClientError> {
+        let ix =
+            client::deposit_ix(owner.pubkey(), serverkey, amount, SPECIFIED_MINT, spl_token::ID);
+        self.process(&[ix], &[owner]).await
+    }
+
+    pub async fn withdraw(
+        &mut self,
+        owner: &Keypair,
+        serverkey: &[u8],
+        amount: u64,
+        close_vault: bool,
+    ) -> std::result::Result<(), BanksClientError> {
+        let protocol_fees = self.config().await.withdraw_fee_bps > 0;
+        let ix = client::withdraw_ix(
+            owner.pubkey(),
+            serverkey,
+            amount,
+            close_vault,
+            SPECIFIED_MINT,
+            spl_token::ID,
+            protocol_fees,
+        );
+        self.process(&[ix], &[owner]).await
+    }
+
+    pub async fn remove_server(
+        &mut self,
+        owner: &Keypair,
+        serverkey: &[u8],
+    ) -> std::result::Result<(), BanksClientError> {
+        let ix = client::remove_server_ix(owner.pubkey(), serverkey, SPECIFIED_MINT, spl_token::ID);
+        self.process(&[ix], &[owner]).await
+    }
+
+    pub async fn d_deposit(
+        &mut self,
+        owner: &Keypair,
+        server: Pubkey,
+        amount: u64,
+        lockup: LockupTier,
+    ) -> std::result::Result<(), BanksClientError> {
+        let receipts = self.config().await.receipt_tokens;
+        let ix = client::d_deposit_ix(
+            owner.pubkey(),
+            server,
+            amount,
+            None,
+            lockup,
+            SPECIFIED_MINT,
+            spl_token::ID,
+            receipts,
+        );
+        self.process(&[ix], &[owner]).await
+    }
+
+    // Delegate `amount` whole tokens to each server in one d_deposit_many
+    pub async fn d_deposit_many(
+        &mut self,
+        owner: &Keypair,
+        deposits: &[(Pubkey, u64)],
+        lockup: LockupTier,
+    ) -> std::result::Result<(), BanksClientError> {
+        let receipts = self.config().await.receipt_tokens;
+        let ix = client::d_deposit_many_ix(
+            owner.pubkey(),
+            deposits,
+            lockup,
+            SPECIFIED_MINT,
+            spl_token::ID,
+            receipts,
+        );
+        self.process(&[ix], &[owner]).await
+    }
+
+    pub async fn d_withdraw(
+        &mut self,
+        owner: &Keypair,
+        server: Pubkey,
+        amount: u64,
+        close_vault: bool,
+    ) -> std::result::Result<(), BanksClientError> {
+        let config = self.config().await;
+        let ix = client::d_withdraw_ix(
+            owner.pubkey(),
+            server,
+            amount,
+            close_vault,
+            SPECIFIED_MINT,
+            spl_token::ID,
+            config.receipt_tokens,
+            config.withdraw_fee_bps > 0,
+        );
+        self.process(&[ix], &[owner]).await
+    }
+
+    pub async fn d_remove(
+        &mut self,
+        owner: &Keypair,
+        server: Pubkey,
+    ) -> std::result::Result<(), BanksClientError> {
+        let ix = client::d_remove_ix(owner.pubkey(), server, SPECIFIED_MINT, spl_token::ID);
+        self.process(&[ix], &[owner]).await
+    }
+
+    pub async fn fund_rewards(
+        &mut self,
+        funder: &Keypair,
+        server: Pubkey,
+        amount: u64,
+    ) -> std::result::Result<(), BanksClientError> {
+        let ix =
+            client::fund_rewards_ix(funder.pubkey(), server, amount, SPECIFIED_MINT, spl_token::ID);
+        self.process(&[ix], &[funder]).await
+    }
+
+    pub async fn claim_rewards(
+        &mut self,
+        owner: &Keypair,
+        server: Pubkey,
+    ) -> std::result::Result<(), BanksClientError> {
+        let protocol_fees = self.config().await.reward_fee_bps > 0;
+        let ix = client::claim_rewards_ix(
+            owner.pubkey(),
+            server,
+            SPECIFIED_MINT,
+            spl_token::ID,
+            protocol_fees,
+        );
+        self.process(&[ix], &[owner]).await
+    }
+
+    pub async fn settle_lockup(
+        &mut self,
+        delegation_owner: &Pubkey,
+        server: Pubkey,
+    ) -> std::result::Result<(), BanksClientError> {
+        let ix = client::settle_lockup_ix(*delegation_owner, server);
+        self.process(&[ix], &[]).await
+    }
+
+    // Append the delegation of `delegation_owner` to the server's list
+    pub async fn link_delegation(
+        &mut self,
+        delegation_owner: &Pubkey,
+        server: Pubkey,
+    ) -> std::result::Result<(), BanksClientError> {
+        let delegated_account = pda::delegation(delegation_owner, &server, &ID).0;
+        let tail = Some(self.server(server).await.delegations_tail)
+            .filter(|tail| *tail != Pubkey::default());
+        let ix = client::link_delegation_ix(server, delegated_account, tail);
+        self.process(&[ix], &[]).await
+    }
+
+    pub async fn unlink_delegation(
+        &mut self,
+        caller: &Keypair,
+        delegation_owner: &Pubkey,
+        server: Pubkey,
+    ) -> std::result::Result<(), BanksClientError> {
+        let delegated_account = pda::delegation(delegation_owner, &server, &ID).0;
+        let delegation = self.account::<DelegatedAccount>(&delegated_account).await;
+        let neighbour = |key: Pubkey| Some(key).filter(|key| *key != Pubkey::default());
+        let ix = client::unlink_delegation_ix(
+            caller.pubkey(),
+            server,
+            delegated_account,
+            neighbour(delegation.prev),
+            neighbour(delegation.next),
+        );
+        self.process(&[ix], &[caller]).await
+    }
+
+    pub async fn announce_exit(
+        &mut self,
+        owner: &Keypair,
+        serverkey: &[u8],
+    ) -> std::result::Result<(), BanksClientError> {
+        let ix = client::announce_exit_ix(owner.pubkey(), serverkey);
+        self.process(&[ix], &[owner]).await
+    }
+
+    pub async fn return_delegation(
+        &mut self,
+        payer: &Keypair,
+        delegation_owner: &Pubkey,
+        server: Pubkey,
+    ) -> std::result::Result<(), BanksClientError> {
+        let ix = client::return_delegation_ix(
+            payer.pubkey(),
+            *delegation_owner,
+            server,
+            SPECIFIED_MINT,
+            spl_token::ID,
+        );
+        self.process(&[ix], &[payer]).await
+    }
+
+    pub async fn update_config(
+        &mut self,
+        params: ConfigParams,
+    ) -> std::result::Result<(), BanksClientError> {
+        let admin = self.admin.insecure_clone();
+        let ix = client::update_config_ix(admin.pubkey(), params);
+        self.process(&[ix], &[&admin]).await
+    }
+
+    // State readers
+
+    pub async fn main_account(&mut self) -> MainAccount {
+        let data = self.account_data(&pda::main_account(&ID).0).await;
+        *bytemuck::from_bytes(&data[8..8 + std::mem::size_of::<MainAccount>()])
+    }
+
+    pub async fn config(&mut self) -> ConfigAccount {
+        self.account(&pda::config(&ID).0).await
+    }
+
+    pub async fn server(&mut self, server: Pubkey) -> InfoAccount {
+        self.account(&server).await
+    }
+
+    pub async fn delegation(&mut self, owner: &Pubkey, server: &Pubkey) -> DelegatedAccount {
+        self.account(&pda::delegation(owner, server, &ID).0).await
+    }
+
+    // Associated token account of `owner` for the staking mint
+    pub fn token_account(&self, owner: &Pubkey) -> Pubkey {
+        get_associated_token_address_with_program_id(owner, &SPECIFIED_MINT, &spl_token::ID)
+    }
+
+    // Balance in base units, 0 for a missing account
+    pub async fn token_balance(&mut self, token_account: &Pubkey) -> u64 {
+        match self
+            .context
+            .banks_client
+            .get_account(*token_account)
+            .await
+            .expect("get account")
+        {
+            Some(account) => {
+                spl_token::state::Account::unpack(&account.data)
+                    .expect("token account")
+                    .amount
+            }
+            None => 0,
+        }
+    }
+
+    pub async fn account<T: AccountDeserialize>(&mut self, address: &Pubkey) -> T {
+        let data = self.account_data(address).await;
+        T::try_deserialize(&mut data.as_slice()).expect("deserialize account")
+    }
+
+    pub async fn account_exists(&mut self, address: &Pubkey) -> bool {
+        self.context
+            .banks_client
+            .get_account(*address)
+            .await
+            .expect("get account")
+            .is_some()
+    }
+
+    async fn account_data(&mut self, address: &Pubkey) -> Vec<u8> {
+        self.context
+            .banks_client
+            .get_account(*address)
+            .await
+            .expect("get account")
+            .unwrap_or_else(|| panic!("account {} not found", address))
+            .data
+    }
+}
@@ -0,0 +1,120 @@
+// Initialization of the config and main accounts on the localnet fixture: who may create them and
+// what they accept.
+//
+//     cargo test-sbf --features test-support --test initialization
+#![cfg(feature = "test-support")]
+
+use anchor_spl::token_2022::spl_token_2022;
+use solana_program::program_option::COption;
+use solana_program_test::BanksClientError;
+use solana_sdk::account::Account;
+use solana_sdk::instruction::InstructionError;
+use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::transaction::TransactionError;
+use spl_token_2022::extension::permanent_delegate::PermanentDelegate;
+use spl_token_2022::extension::{BaseStateWithExtensionsMut, ExtensionType, StateWithExtensionsMut};
+use spl_token_2022::state::Mint;
+use staking_contract::test_support::{Fixture, DECIMALS};
+use staking_contract::{client, pda, ConfigParams, CustomError, ID, SPECIFIED_MINT};
+
+fn assert_error(result: Result<(), BanksClientError>, error: CustomError) {
+    match result.expect_err("instruction should fail").unwrap() {
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => {
+            assert_eq!(code, u32::from(error))
+        }
+        other => panic!("unexpected error {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn config_requires_upgrade_authority() {
+    let mut fixture = Fixture::uninitialized().await;
+    let intruder = Keypair::new();
+    let payer = fixture.context.payer.insecure_clone();
+    let fund = solana_sdk::system_instruction::transfer(
+        &payer.pubkey(),
+        &intruder.pubkey(),
+        1_000_000_000,
+    );
+    fixture.process(&[fund], &[]).await.unwrap();
+
+    assert_error(
+        fixture
+            .process(&[client::initialize_config_ix(intruder.pubkey())], &[&intruder])
+            .await,
+        CustomError::Unauthorized,
+    );
+    assert!(!fixture.account_exists(&pda::config(&ID).0).await);
+
+    let admin = fixture.admin.insecure_clone();
+    fixture
+        .process(&[client::initialize_config_ix(admin.pubkey())], &[&admin])
+        .await
+        .unwrap();
+    assert_eq!(fixture.config().await.admin, admin.pubkey());
+}
+
+#[tokio::test]
+async fn staking_mint_fixed_after_main() {
+    let mut fixture = Fixture::new().await;
+    let before = fixture.config().await.staking_mint;
+
+    assert_error(
+        fixture
+            .update_config(ConfigParams {
+                staking_mint: Some(Keypair::new().pubkey()),
+                ..ConfigParams::default()
+            })
+            .await,
+        CustomError::StakingMintFixed,
+    );
+    assert_eq!(fixture.config().await.staking_mint, before);
+}
+
+#[tokio::test]
+async fn staking_mint_extensions_screened() {
+    let mut fixture = Fixture::uninitialized().await;
+
+    // The staking mint turned into a Token-2022 mint whose permanent delegate could drain vaults
+    let len = ExtensionType::try_calculate_account_len::<Mint>(&[ExtensionType::PermanentDelegate])
+        .unwrap();
+    let mut data = vec![0; len];
+    let mut state = StateWithExtensionsMut::<Mint>::unpack_uninitialized(&mut data).unwrap();
+    state.init_extension::<PermanentDelegate>(true).unwrap().delegate =
+        Some(Keypair::new().pubkey()).try_into().unwrap();
+    state.base = Mint {
+        mint_authority: COption::Some(fixture.mint_authority.pubkey()),
+        supply: 0,
+        decimals: DECIMALS,
+        is_initialized: true,
+        freeze_authority: COption::None,
+    };
+    state.pack_base();
+    state.init_account_type().unwrap();
+    fixture.context.set_account(
+        &SPECIFIED_MINT,
+        &Account {
+            lamports: 1_000_000_000,
+            data,
+            owner: spl_token_2022::ID,
+            ..Account::default()
+        }
+        .into(),
+    );
+
+    let admin = fixture.admin.insecure_clone();
+    fixture
+        .process(&[client::initialize_config_ix(admin.pubkey())], &[&admin])
+        .await
+        .unwrap();
+    assert_error(
+        fixture
+            .process(
+                &[client::initialize_main_ix(admin.pubkey(), true, SPECIFIED_MINT, DECIMALS)],
+                &[&admin],
+            )
+            .await,
+        CustomError::PermanentDelegateMint,
+    );
+    assert!(!fixture.account_exists(&pda::main_account(&ID).0).await);
+}
@@ -0,0 +1,224 @@
+// Random sequences of staking operations against the localnet fixture, checking after every step
+// that the records and the vaults agree and that the main account totals are the sums of the
+// server accounts. Rejected operations are fine, they must just leave everything consistent.
+//
+//     cargo test-sbf --features test-support --test invariants
+#![cfg(feature = "test-support")]
+
+use proptest::prelude::*;
+use solana_sdk::signature::{Keypair, Signer};
+use staking_contract::test_support::Fixture;
+use staking_contract::{pda, to_minimum_units, LockupTier, ID};
+
+const OPERATORS: usize = 3;
+const DELEGATORS: usize = 4;
+// Balances leave room for the 10,000 token limit to be crossed
+const OPERATOR_TOKENS: u64 = 12_000;
+const DELEGATOR_TOKENS: u64 = 12_000;
+
+#[derive(Clone, Debug)]
+enum Op {
+    AddServer { operator: usize, amount: u64 },
+    Deposit { operator: usize, amount: u64 },
+    Withdraw { operator: usize, amount: u64 },
+    DDeposit { delegator: usize, operator: usize, amount: u64, lockup: LockupTier },
+    DWithdraw { delegator: usize, operator: usize, amount: u64 },
+    NextEpoch,
+}
+
+fn lockup() -> impl Strategy<Value = LockupTier> {
+    prop_oneof![Just(LockupTier::None), Just(LockupTier::ThreeMonths)]
+}
+
+// Amounts cluster around the 500 / 1,000 / 10,000 token limits so boundaries get exercised
+fn amount() -> impl Strategy<Value = u64> {
+    prop_oneof![0..12_000u64, Just(499u64), Just(500), Just(1_000), Just(10_000), Just(10_001)]
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        (0..OPERATORS, amount()).prop_map(|(operator, amount)| Op::AddServer { operator, amount }),
+        (0..OPERATORS, amount()).prop_map(|(operator, amount)| Op::Deposit { operator, amount }),
+        (0..OPERATORS, amount()).prop_map(|(operator, amount)| Op::Withdraw { operator, amount }),
+        (0..DELEGATORS, 0..OPERATORS, amount(), lockup()).prop_map(
+            |(delegator, operator, amount, lockup)| Op::DDeposit {
+                delegator,
+                operator,
+                amount,
+                lockup,
+            }
+        ),
+        (0..DELEGATORS, 0..OPERATORS, amount()).prop_map(|(delegator, operator, amount)| {
+            Op::DWithdraw {
+                delegator,
+                operator,
+                amount,
+            }
+        }),
+        Just(Op::NextEpoch),
+    ]
+}
+
+fn serverkey(operator: usize) -> Vec<u8> {
+    vec![operator as u8 + 1; 32]
+}
+
+struct World {
+    fixture: Fixture,
+    operators: Vec<Keypair>,
+    delegators: Vec<Keypair>,
+}
+
+impl World {
+    async fn new() -> Self {
+        let mut fixture = Fixture::new().await;
+        let mut operators = Vec::new();
+        for _ in 0..OPERATORS {
+            operators.push(fixture.actor(OPERATOR_TOKENS).await);
+        }
+        let mut delegators = Vec::new();
+        for _ in 0..DELEGATORS {
+            delegators.push(fixture.actor(DELEGATOR_TOKENS).await);
+        }
+        Self {
+            fixture,
+            operators,
+            delegators,
+        }
+    }
+
+    fn server(&self, operator: usize) -> solana_sdk::pubkey::Pubkey {
+        pda::server(&self.operators[operator].pubkey(), &serverkey(operator), &ID).0
+    }
+
+    async fn apply(&mut self, op: &Op) {
+        let fixture = &mut self.fixture;
+        // Program errors are expected for out-of-range amounts, the invariants decide
+        let _ = match *op {
+            Op::AddServer { operator, amount } => fixture
+                .add_server(&self.operators[operator], &serverkey(operator), "node", amount)
+                .await
+                .map(|_| ()),
+            Op::Deposit { operator, amount } => {
+                fixture
+                    .deposit(&self.operators[operator], &serverkey(operator), amount)
+                    .await
+            }
+            Op::Withdraw { operator, amount } => {
+                fixture
+                    .withdraw(&self.operators[operator], &serverkey(operator), amount, false)
+                    .await
+            }
+            Op::DDeposit {
+                delegator,
+                operator,
+                amount,
+                lockup,
+            } => {
+                let server =
+                    pda::server(&self.operators[operator].pubkey(), &serverkey(operator), &ID).0;
+                fixture
+                    .d_deposit(&self.delegators[delegator], server, amount, lockup)
+                    .await
+            }
+            Op::DWithdraw {
+                delegator,
+                operator,
+                amount,
+            } => {
+                let server =
+                    pda::server(&self.operators[operator].pubkey(), &serverkey(operator), &ID).0;
+                fixture
+                    .d_withdraw(&self.delegators[delegator], server, amount, false)
+                    .await
+            }
+            Op::NextEpoch => {
+                fixture.next_epoch().await;
+                Ok(())
+            }
+        };
+    }
+
+    async fn check_invariants(&mut self) {
+        let mut total_self_stake = 0u64;
+        let mut total_delegated = 0u64;
+        let mut in_vaults = 0u64;
+
+        for operator in 0..OPERATORS {
+            let server = self.server(operator);
+            if !self.fixture.account_exists(&server).await {
+                continue;
+            }
+            let info = self.fixture.server(server).await;
+            let vault = self.fixture.token_account(&server);
+            let vault_balance = self.fixture.token_balance(&vault).await;
+            assert_eq!(vault_balance, info.stake, "server vault holds the operator stake");
+            assert_eq!(info.total, info.stake + info.delegated, "server total split");
+            in_vaults += vault_balance;
+
+            let mut positions = 0u64;
+            for delegator in 0..DELEGATORS {
+                let owner = self.delegators[delegator].pubkey();
+                let address = pda::delegation(&owner, &server, &ID).0;
+                if !self.fixture.account_exists(&address).await {
+                    continue;
+                }
+                let delegation = self.fixture.delegation(&owner, &server).await;
+                let vault = self.fixture.token_account(&address);
+                let vault_balance = self.fixture.token_balance(&vault).await;
+                assert_eq!(
+                    vault_balance,
+                    delegation.stake + delegation.activating_stake,
+                    "delegation vault holds the active and queued stake"
+                );
+                positions += vault_balance;
+                in_vaults += vault_balance;
+            }
+            // Activation is lazy, a position may still count as activating on the server side
+            assert_eq!(
+                info.delegated + info.activating_stake,
+                positions,
+                "server delegated and activating stake match its positions"
+            );
+
+            total_self_stake += info.stake;
+            total_delegated += info.delegated;
+        }
+
+        // Single primary mint at the 1:1 default rate, so common units are base units
+        let main = self.fixture.main_account().await;
+        assert_eq!(main.total_self_stake, total_self_stake, "main self stake");
+        assert_eq!(main.total_delegated, total_delegated, "main delegated stake");
+        assert_eq!(main.total_stake, total_self_stake + total_delegated, "main total");
+
+        // No fees are configured, so tokens only move between wallets and vaults
+        let mut in_wallets = 0u64;
+        for actor in self.operators.iter().chain(self.delegators.iter()) {
+            let account = self.fixture.token_account(&actor.pubkey());
+            in_wallets += self.fixture.token_balance(&account).await;
+        }
+        let minted = to_minimum_units(
+            OPERATOR_TOKENS * OPERATORS as u64 + DELEGATOR_TOKENS * DELEGATORS as u64,
+            9,
+        )
+        .unwrap();
+        assert_eq!(in_wallets + in_vaults, minted, "tokens are conserved");
+    }
+}
+
+proptest! {
+    // Every case boots a fresh bank, keep the count low and the sequences long instead
+    #![proptest_config(ProptestConfig::with_cases(16))]
+
+    #[test]
+    fn accounting_invariants_hold(ops in prop::collection::vec(op(), 1..40)) {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let mut world = World::new().await;
+            for op in &ops {
+                world.apply(op).await;
+                world.check_invariants().await;
+            }
+        });
+    }
+}
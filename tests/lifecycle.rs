@@ -0,0 +1,516 @@
+// End-to-end scenarios on the localnet fixture: the server and delegation lifecycles from
+// registration to closing, and the stake limits at their boundaries.
+//
+//     cargo test-sbf --features test-support --test lifecycle
+#![cfg(feature = "test-support")]
+
+use solana_program_test::BanksClientError;
+use solana_sdk::instruction::InstructionError;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signer;
+use solana_sdk::system_instruction;
+use solana_sdk::transaction::TransactionError;
+use staking_contract::test_support::Fixture;
+use staking_contract::{pda, to_minimum_units, CustomError, DelegatedAccount, LockupTier, ID};
+
+const SERVERKEY: &[u8] = &[7; 32];
+const DAY: i64 = 24 * 60 * 60;
+
+fn tokens(amount: u64) -> u64 {
+    to_minimum_units(amount, 9).unwrap()
+}
+
+fn assert_error(result: Result<(), BanksClientError>, error: CustomError) {
+    match result.expect_err("instruction should fail").unwrap() {
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => {
+            assert_eq!(code, u32::from(error))
+        }
+        other => panic!("unexpected error {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn server_lifecycle() {
+    let mut fixture = Fixture::new().await;
+    let operator = fixture.actor(5_000).await;
+    let wallet = fixture.token_account(&operator.pubkey());
+
+    let server = fixture
+        .add_server(&operator, SERVERKEY, "node-1", 1_000)
+        .await
+        .unwrap();
+    let info = fixture.server(server).await;
+    assert_eq!(info.owner, operator.pubkey());
+    assert_eq!(info.stake, tokens(1_000));
+    assert_eq!(info.total, tokens(1_000));
+    assert_eq!(fixture.token_balance(&wallet).await, tokens(4_000));
+
+    fixture.deposit(&operator, SERVERKEY, 2_000).await.unwrap();
+    assert_eq!(fixture.server(server).await.stake, tokens(3_000));
+    assert_eq!(fixture.main_account().await.total_self_stake, tokens(3_000));
+
+    // A server holding stake can't be removed
+    assert_error(
+        fixture.remove_server(&operator, SERVERKEY).await,
+        CustomError::NonZeroBalance,
+    );
+
+    // The vault is left open here, remove_server closes it together with the server
+    fixture.withdraw(&operator, SERVERKEY, 3_000, false).await.unwrap();
+    assert_eq!(fixture.server(server).await.total, 0);
+
+    fixture.remove_server(&operator, SERVERKEY).await.unwrap();
+    assert!(!fixture.account_exists(&server).await);
+    assert!(!fixture.account_exists(&fixture.token_account(&server)).await);
+    assert_eq!(fixture.token_balance(&wallet).await, tokens(5_000));
+
+    let main = fixture.main_account().await;
+    assert_eq!(main.total_stake, 0);
+    assert_eq!(main.total_users, 0);
+    assert_eq!(main.total_servers, 0);
+}
+
+#[tokio::test]
+async fn delegation_lifecycle() {
+    let mut fixture = Fixture::new().await;
+    let operator = fixture.actor(1_000).await;
+    let delegator = fixture.actor(2_000).await;
+    let wallet = fixture.token_account(&delegator.pubkey());
+    let server = fixture
+        .add_server(&operator, SERVERKEY, "node-1", 1_000)
+        .await
+        .unwrap();
+
+    fixture
+        .d_deposit(&delegator, server, 1_000, LockupTier::None)
+        .await
+        .unwrap();
+    let delegation = fixture.delegation(&delegator.pubkey(), &server).await;
+    assert_eq!(delegation.stake, 0);
+    assert_eq!(delegation.activating_stake, tokens(1_000));
+    let info = fixture.server(server).await;
+    assert_eq!(info.total_delegators, 1);
+    assert_eq!(info.total, tokens(1_000), "queued stake doesn't count yet");
+
+    // Topping up in the next epoch activates the queued stake first
+    fixture.next_epoch().await;
+    fixture
+        .d_deposit(&delegator, server, 500, LockupTier::None)
+        .await
+        .unwrap();
+    let delegation = fixture.delegation(&delegator.pubkey(), &server).await;
+    assert_eq!(delegation.stake, tokens(1_000));
+    assert_eq!(delegation.activating_stake, tokens(500));
+    assert_eq!(fixture.server(server).await.delegated, tokens(1_000));
+
+    // The operator can't leave while delegations are open
+    assert_error(
+        fixture.remove_server(&operator, SERVERKEY).await,
+        CustomError::NonZeroBalance,
+    );
+
+    // Rewards go to the only delegator, less the rounding dust
+    let funder = fixture.actor(100).await;
+    fixture.fund_rewards(&funder, server, 100).await.unwrap();
+    let before = fixture.token_balance(&wallet).await;
+    fixture.claim_rewards(&delegator, server).await.unwrap();
+    let dust = fixture.server(server).await.reward_dust;
+    assert_eq!(fixture.token_balance(&wallet).await - before, tokens(100) - dust);
+
+    // Full exit: queued and active stake come back, then the position closes
+    fixture.d_withdraw(&delegator, server, 1_500, false).await.unwrap();
+    assert_eq!(
+        fixture.token_balance(&wallet).await,
+        tokens(2_000) + tokens(100) - dust
+    );
+    fixture.d_remove(&delegator, server).await.unwrap();
+    let address = pda::delegation(&delegator.pubkey(), &server, &ID).0;
+    assert!(!fixture.account_exists(&address).await);
+
+    let info = fixture.server(server).await;
+    assert_eq!(info.total_delegators, 0);
+    assert_eq!(info.total, tokens(1_000));
+    assert_eq!(info.activating_stake, 0);
+
+    fixture.withdraw(&operator, SERVERKEY, 1_000, false).await.unwrap();
+    fixture.remove_server(&operator, SERVERKEY).await.unwrap();
+    assert_eq!(fixture.main_account().await.total_users, 0);
+}
+
+#[tokio::test]
+async fn locked_delegation_exit() {
+    let mut fixture = Fixture::new().await;
+    let operator = fixture.actor(1_000).await;
+    let delegator = fixture.actor(500).await;
+    let server = fixture
+        .add_server(&operator, SERVERKEY, "node-1", 1_000)
+        .await
+        .unwrap();
+
+    fixture
+        .d_deposit(&delegator, server, 500, LockupTier::ThreeMonths)
+        .await
+        .unwrap();
+    assert_error(
+        fixture.d_withdraw(&delegator, server, 500, false).await,
+        CustomError::StakeLocked,
+    );
+}
+
+#[tokio::test]
+async fn top_up_without_tier_keeps_lock() {
+    let mut fixture = Fixture::new().await;
+    let operator = fixture.actor(1_000).await;
+    let delegator = fixture.actor(1_500).await;
+    let server = fixture
+        .add_server(&operator, SERVERKEY, "node-1", 1_000)
+        .await
+        .unwrap();
+    fixture
+        .d_deposit(&delegator, server, 500, LockupTier::ThreeMonths)
+        .await
+        .unwrap();
+    let lock_until = fixture.delegation(&delegator.pubkey(), &server).await.lock_until;
+
+    fixture.warp_time(10 * DAY).await;
+    fixture
+        .d_deposit(&delegator, server, 500, LockupTier::None)
+        .await
+        .unwrap();
+    let delegation = fixture.delegation(&delegator.pubkey(), &server).await;
+    assert_eq!(delegation.lock_until, lock_until);
+    assert_eq!(delegation.lockup, LockupTier::ThreeMonths);
+
+    // Choosing the tier again relocks from now
+    fixture
+        .d_deposit(&delegator, server, 500, LockupTier::ThreeMonths)
+        .await
+        .unwrap();
+    let delegation = fixture.delegation(&delegator.pubkey(), &server).await;
+    assert!(delegation.lock_until >= lock_until + 10 * DAY);
+}
+
+#[tokio::test]
+async fn expired_boost_settled_by_anyone() {
+    let mut fixture = Fixture::new().await;
+    let operator = fixture.actor(1_000).await;
+    let delegator = fixture.actor(1_000).await;
+    let server = fixture
+        .add_server(&operator, SERVERKEY, "node-1", 1_000)
+        .await
+        .unwrap();
+    fixture
+        .d_deposit(&delegator, server, 1_000, LockupTier::ThreeMonths)
+        .await
+        .unwrap();
+    fixture.next_epoch().await;
+
+    assert_error(
+        fixture.settle_lockup(&delegator.pubkey(), server).await,
+        CustomError::StakeLocked,
+    );
+
+    fixture.warp_time(91 * DAY).await;
+    fixture.settle_lockup(&delegator.pubkey(), server).await.unwrap();
+    let delegation = fixture.delegation(&delegator.pubkey(), &server).await;
+    assert_eq!(delegation.lockup, LockupTier::None);
+    assert_eq!(delegation.stake, tokens(1_000));
+    assert_eq!(fixture.server(server).await.delegated_weight, tokens(1_000));
+
+    assert_error(
+        fixture.settle_lockup(&delegator.pubkey(), server).await,
+        CustomError::InvalidArgument,
+    );
+}
+
+#[tokio::test]
+async fn expired_boost_past_reward_history() {
+    let mut fixture = Fixture::new().await;
+    let operator = fixture.actor(1_000).await;
+    let delegator = fixture.actor(1_500).await;
+    let funder = fixture.actor(300).await;
+    let server = fixture
+        .add_server(&operator, SERVERKEY, "node-1", 1_000)
+        .await
+        .unwrap();
+    fixture
+        .d_deposit(&delegator, server, 1_000, LockupTier::ThreeMonths)
+        .await
+        .unwrap();
+    // The top-up activates the locked stake, the position's last checkpoint
+    fixture.next_epoch().await;
+    fixture
+        .d_deposit(&delegator, server, 500, LockupTier::None)
+        .await
+        .unwrap();
+    assert_eq!(fixture.server(server).await.delegated_weight, tokens(1_100));
+    fixture.fund_rewards(&funder, server, 110).await.unwrap();
+
+    // More distributions after the expiry than the server remembers
+    fixture.warp_time(91 * DAY).await;
+    for _ in 0..17 {
+        fixture.fund_rewards(&funder, server, 10).await.unwrap();
+    }
+    fixture.settle_lockup(&delegator.pubkey(), server).await.unwrap();
+
+    // The boost stops at the last checkpoint instead of running on, what the server paid out
+    // for it went to the reward dust
+    let pending = fixture
+        .delegation(&delegator.pubkey(), &server)
+        .await
+        .pending_rewards;
+    let dust = fixture.server(server).await.reward_dust;
+    assert!(pending < tokens(255));
+    assert!((pending + dust).abs_diff(tokens(280)) <= 20);
+}
+
+#[tokio::test]
+async fn batch_delegation_into_prefunded_position() {
+    let mut fixture = Fixture::new().await;
+    let operators = [fixture.actor(1_000).await, fixture.actor(1_000).await];
+    let delegator = fixture.actor(1_000).await;
+    let mut servers = vec![];
+    let registrations = [([7u8; 32], "node-1"), ([8; 32], "node-2")];
+    for (operator, (serverkey, name)) in operators.iter().zip(registrations) {
+        let server = fixture
+            .add_server(operator, &serverkey, name, 1_000)
+            .await
+            .unwrap();
+        servers.push(server);
+    }
+
+    // Lamports sent to a position address ahead of time must not block opening it
+    let position = pda::delegation(&delegator.pubkey(), &servers[1], &ID).0;
+    let payer = fixture.context.payer.insecure_clone();
+    let grief = system_instruction::transfer(&payer.pubkey(), &position, 1_000_000);
+    fixture.process(&[grief], &[]).await.unwrap();
+
+    fixture
+        .d_deposit_many(
+            &delegator,
+            &[(servers[0], 500), (servers[1], 500)],
+            LockupTier::None,
+        )
+        .await
+        .unwrap();
+    for server in &servers {
+        let delegation = fixture.delegation(&delegator.pubkey(), server).await;
+        assert_eq!(delegation.owner, delegator.pubkey());
+        assert_eq!(delegation.activating_stake, tokens(500));
+    }
+}
+
+#[tokio::test]
+async fn server_event_seq() {
+    let mut fixture = Fixture::new().await;
+    let operator = fixture.actor(1_500).await;
+    let delegator = fixture.actor(500).await;
+    let server = fixture
+        .add_server(&operator, SERVERKEY, "node-1", 1_000)
+        .await
+        .unwrap();
+    let seq = fixture.server(server).await.event_seq;
+
+    // Every deposit and withdrawal takes the next number, delegations included
+    fixture.deposit(&operator, SERVERKEY, 500).await.unwrap();
+    fixture
+        .d_deposit(&delegator, server, 500, LockupTier::None)
+        .await
+        .unwrap();
+    fixture.d_withdraw(&delegator, server, 500, false).await.unwrap();
+    assert_eq!(fixture.server(server).await.event_seq, seq + 3);
+
+    // Failed operations don't use one up
+    assert_error(
+        fixture.d_withdraw(&delegator, server, 500, false).await,
+        CustomError::InsufficientFunds,
+    );
+    assert_eq!(fixture.server(server).await.event_seq, seq + 3);
+}
+
+// Delegations of `server` in list order, walked from the head along `next`
+async fn listed(fixture: &mut Fixture, server: Pubkey) -> Vec<Pubkey> {
+    let mut keys = vec![];
+    let mut key = fixture.server(server).await.delegations_head;
+    while key != Pubkey::default() {
+        keys.push(key);
+        key = fixture.account::<DelegatedAccount>(&key).await.next;
+    }
+    keys
+}
+
+#[tokio::test]
+async fn delegation_list() {
+    let mut fixture = Fixture::new().await;
+    let operator = fixture.actor(1_000).await;
+    let server = fixture
+        .add_server(&operator, SERVERKEY, "node-1", 1_000)
+        .await
+        .unwrap();
+    let mut delegators = vec![];
+    for _ in 0..3 {
+        let delegator = fixture.actor(500).await;
+        fixture
+            .d_deposit(&delegator, server, 500, LockupTier::None)
+            .await
+            .unwrap();
+        fixture.link_delegation(&delegator.pubkey(), server).await.unwrap();
+        delegators.push(delegator);
+    }
+    let positions: Vec<Pubkey> = delegators
+        .iter()
+        .map(|delegator| pda::delegation(&delegator.pubkey(), &server, &ID).0)
+        .collect();
+    assert_eq!(listed(&mut fixture, server).await, positions);
+    assert_eq!(fixture.server(server).await.linked_delegations, 3);
+    assert_error(
+        fixture.link_delegation(&delegators[0].pubkey(), server).await,
+        CustomError::DelegationLinked,
+    );
+
+    // Strangers can't take a funded position out
+    let intruder = fixture.actor(0).await;
+    assert_error(
+        fixture
+            .unlink_delegation(&intruder, &delegators[1].pubkey(), server)
+            .await,
+        CustomError::Unauthorized,
+    );
+
+    // A listed position can't close, once empty anyone may unlink it
+    let last = &delegators[2];
+    fixture.d_withdraw(last, server, 500, false).await.unwrap();
+    assert_error(fixture.d_remove(last, server).await, CustomError::DelegationLinked);
+    fixture
+        .unlink_delegation(&intruder, &last.pubkey(), server)
+        .await
+        .unwrap();
+    fixture.d_remove(last, server).await.unwrap();
+
+    let middle = &delegators[1];
+    fixture
+        .unlink_delegation(middle, &middle.pubkey(), server)
+        .await
+        .unwrap();
+    assert_eq!(listed(&mut fixture, server).await, positions[..1]);
+    let info = fixture.server(server).await;
+    assert_eq!(info.delegations_tail, positions[0]);
+    assert_eq!(info.linked_delegations, 1);
+    let unlinked = fixture.delegation(&middle.pubkey(), &server).await;
+    assert_eq!((unlinked.prev, unlinked.next), (Pubkey::default(), Pubkey::default()));
+}
+
+#[tokio::test]
+async fn locked_delegation_not_returned() {
+    let mut fixture = Fixture::new().await;
+    let operator = fixture.actor(1_000).await;
+    let delegator = fixture.actor(500).await;
+    let keeper = fixture.actor(0).await;
+    let server = fixture
+        .add_server(&operator, SERVERKEY, "node-1", 1_000)
+        .await
+        .unwrap();
+    fixture
+        .d_deposit(&delegator, server, 500, LockupTier::ThreeMonths)
+        .await
+        .unwrap();
+
+    // The exit notice has run out, the lockup hasn't
+    fixture.announce_exit(&operator, SERVERKEY).await.unwrap();
+    assert_error(
+        fixture
+            .return_delegation(&keeper, &delegator.pubkey(), server)
+            .await,
+        CustomError::StakeLocked,
+    );
+    let delegation = fixture.delegation(&delegator.pubkey(), &server).await;
+    assert_eq!(delegation.activating_stake, tokens(500));
+    assert!(delegation.lock_until > 0);
+}
+
+#[tokio::test]
+async fn server_stake_limits() {
+    let mut fixture = Fixture::new().await;
+    let operator = fixture.actor(20_000).await;
+
+    assert_error(
+        fixture
+            .add_server(&operator, &[1; 32], "low", 999)
+            .await
+            .map(|_| ()),
+        CustomError::MoreThan1000FewerThan10000,
+    );
+    assert_error(
+        fixture
+            .add_server(&operator, &[2; 32], "high", 10_001)
+            .await
+            .map(|_| ()),
+        CustomError::MoreThan1000FewerThan10000,
+    );
+
+    fixture
+        .add_server(&operator, &[3; 32], "min", 1_000)
+        .await
+        .unwrap();
+    fixture
+        .add_server(&operator, &[4; 32], "max", 10_000)
+        .await
+        .unwrap();
+
+    // Deposits can fill a server up to the maximum, not past it
+    fixture.deposit(&operator, &[3; 32], 9_000).await.unwrap();
+    assert_error(
+        fixture.deposit(&operator, &[3; 32], 1).await,
+        CustomError::ExceedsMaxStakeLimit,
+    );
+    assert_error(
+        fixture.withdraw(&operator, &[3; 32], 10_001, false).await,
+        CustomError::InsufficientFunds,
+    );
+}
+
+#[tokio::test]
+async fn delegation_stake_limits() {
+    let mut fixture = Fixture::new().await;
+    let operator = fixture.actor(2_000).await;
+    let delegator = fixture.actor(20_000).await;
+    let server = fixture
+        .add_server(&operator, SERVERKEY, "node-1", 2_000)
+        .await
+        .unwrap();
+
+    assert_error(
+        fixture
+            .d_deposit(&delegator, server, 499, LockupTier::None)
+            .await,
+        CustomError::DelegateExceedsMaxStakeLimit,
+    );
+    fixture
+        .d_deposit(&delegator, server, 500, LockupTier::None)
+        .await
+        .unwrap();
+    fixture
+        .d_deposit(&delegator, server, 9_500, LockupTier::None)
+        .await
+        .unwrap();
+    // The position is at 10,000 tokens, activating stake included
+    assert_error(
+        fixture
+            .d_deposit(&delegator, server, 500, LockupTier::None)
+            .await,
+        CustomError::DelegateExceedsMaxStakeLimit,
+    );
+
+    // Delegations are capped at self_bond_multiple x the operator stake
+    let second = fixture.actor(20_000).await;
+    fixture
+        .d_deposit(&second, server, 8_000, LockupTier::None)
+        .await
+        .unwrap();
+    assert_error(
+        fixture
+            .d_deposit(&second, server, 500, LockupTier::None)
+            .await,
+        CustomError::SelfBondRatioExceeded,
+    );
+}